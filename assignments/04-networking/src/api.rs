@@ -0,0 +1,1141 @@
+use std::str::FromStr;
+
+use uuid::Uuid;
+
+use crate::db::AspirinEatsDb;
+use crate::error::AspirinEatsError;
+use crate::food::{Customer, CustomerRequest, Ingredient, Order, OrderRequest, OrderStatus};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::inventory::StockUpdate;
+use crate::metrics;
+use crate::openapi;
+use crate::reports::{Range, Report};
+use crate::store::OrderStore;
+use crate::webhooks::{self, Webhook, WebhookRequest};
+
+/// Message returned for requests to the root of the API
+pub const WELCOME_MESSAGE: &str = "Welcome to Aspirin Eats!";
+
+/// Get the internal ID of the order with the given public ID. Paths only ever name orders by
+/// their public ID, so internal IDs cannot be guessed to walk through other orders
+pub fn order_id(store: &dyn OrderStore, uuid: Uuid) -> Result<i64, AspirinEatsError> {
+    store.get_order_id(&uuid)?.ok_or(AspirinEatsError::NotFound)
+}
+
+/// Look up the internal ID of the customer with the given public ID
+fn customer_id(db: &AspirinEatsDb, uuid: Uuid) -> Result<i64, AspirinEatsError> {
+    db.get_customer_id(&uuid)?.ok_or(AspirinEatsError::NotFound)
+}
+
+/// Enum that represents every resource the origin server knows about
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Route {
+    /// `/`
+    Root,
+    /// `/orders`
+    Orders,
+    /// `/orders/stream`
+    OrdersStream,
+    /// `/orders/socket`
+    OrdersSocket,
+    /// `/orders/{id}`
+    Order(Uuid),
+    /// `/orders/{id}/status`
+    OrderStatus(Uuid),
+    /// `/orders/{id}/stream`
+    OrderStream(Uuid),
+    /// `/orders/{id}/restore`
+    OrderRestore(Uuid),
+    /// `/customers`
+    Customers,
+    /// `/customers/{id}`
+    Customer(Uuid),
+    /// `/customers/{id}/orders`
+    CustomerOrders(Uuid),
+    /// `/inventory`
+    Inventory,
+    /// `/inventory/low`
+    LowStock,
+    /// `/inventory/{ingredient}`
+    InventoryItem(Ingredient),
+    /// `/couriers`
+    Couriers,
+    /// `/webhooks`
+    Webhooks,
+    /// `/webhooks/{id}`
+    Webhook(i64),
+    /// `/webhooks/dead-letters`
+    DeadLetters,
+    /// `/reports/sales`
+    SalesReport,
+    /// `/reports/items`
+    ItemsReport,
+    /// `/reports/hourly`
+    HourlyReport,
+    /// `/metrics`
+    Metrics,
+    /// `/openapi.json`
+    OpenApi,
+}
+
+impl Route {
+    /// Path template of the route, used to label metrics without one series per order
+    pub fn template(&self) -> &'static str {
+        match self {
+            Route::Root => "/",
+            Route::Orders => "/orders",
+            Route::OrdersStream => "/orders/stream",
+            Route::OrdersSocket => "/orders/socket",
+            Route::Order(_) => "/orders/{id}",
+            Route::OrderStatus(_) => "/orders/{id}/status",
+            Route::OrderStream(_) => "/orders/{id}/stream",
+            Route::OrderRestore(_) => "/orders/{id}/restore",
+            Route::Customers => "/customers",
+            Route::Customer(_) => "/customers/{id}",
+            Route::CustomerOrders(_) => "/customers/{id}/orders",
+            Route::Inventory => "/inventory",
+            Route::LowStock => "/inventory/low",
+            Route::InventoryItem(_) => "/inventory/{ingredient}",
+            Route::Couriers => "/couriers",
+            Route::Webhooks => "/webhooks",
+            Route::Webhook(_) => "/webhooks/{id}",
+            Route::DeadLetters => "/webhooks/dead-letters",
+            Route::SalesReport => "/reports/sales",
+            Route::ItemsReport => "/reports/items",
+            Route::HourlyReport => "/reports/hourly",
+            Route::Metrics => "/metrics",
+            Route::OpenApi => "/openapi.json",
+        }
+    }
+}
+
+/// Kind of body an endpoint accepts or returns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Payload {
+    /// A single `Order` as JSON
+    Order,
+    /// A JSON array of `Order`s
+    Orders,
+    /// An `OrderRequest` as JSON
+    OrderRequest,
+    /// An `OrderStatus` as JSON
+    OrderStatus,
+    /// A single `Customer` as JSON
+    Customer,
+    /// A `CustomerRequest` as JSON
+    CustomerRequest,
+    /// A JSON array of `InventoryItem`s
+    Inventory,
+    /// A single `InventoryItem` as JSON
+    InventoryItem,
+    /// A `StockUpdate` as JSON
+    StockUpdate,
+    /// A JSON array of `Courier`s
+    Couriers,
+    /// A single `Webhook` as JSON
+    Webhook,
+    /// A JSON array of `Webhook`s
+    Webhooks,
+    /// A `WebhookRequest` as JSON
+    WebhookRequest,
+    /// A JSON array of `DeadLetter`s
+    DeadLetters,
+    /// A `SalesReport` as JSON, or its daily totals as CSV
+    SalesReport,
+    /// An `ItemsReport` as JSON, or its counts as CSV
+    ItemsReport,
+    /// An `HourlyReport` as JSON, or its hourly totals as CSV
+    HourlyReport,
+    /// A plain text message
+    Text,
+    /// A Server-Sent Events stream of order changes
+    EventStream,
+    /// A WebSocket carrying `ClientMessage`s and `ServerMessage`s as JSON text
+    WebSocket,
+    /// Metrics in the Prometheus text format
+    Metrics,
+    /// A JSON document
+    Json,
+}
+
+/// Description of a single endpoint
+#[derive(Debug)]
+pub struct Endpoint {
+    pub method: &'static str,
+    /// Path template, as returned by `Route::template`
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub request: Option<Payload>,
+    /// Status code and body of a successful response
+    pub response: (u16, Payload),
+}
+
+/// Every endpoint served by the origin. Used to generate the OpenAPI document
+pub const ENDPOINTS: &[Endpoint] = &[
+    Endpoint {
+        method: "GET",
+        path: "/",
+        summary: "Welcome message",
+        request: None,
+        response: (200, Payload::Text),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/orders",
+        summary: "List all orders",
+        request: None,
+        response: (200, Payload::Orders),
+    },
+    Endpoint {
+        method: "POST",
+        path: "/orders",
+        summary: "Place a new order",
+        request: Some(Payload::OrderRequest),
+        response: (201, Payload::Order),
+    },
+    Endpoint {
+        method: "DELETE",
+        path: "/orders",
+        summary: "Remove all orders",
+        request: None,
+        response: (200, Payload::Text),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/orders/stream",
+        summary: "Stream changes to every order",
+        request: None,
+        response: (200, Payload::EventStream),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/orders/socket",
+        summary: "Open a WebSocket to watch orders change and change their status",
+        request: None,
+        response: (101, Payload::WebSocket),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/orders/{id}",
+        summary: "Get an order",
+        request: None,
+        response: (200, Payload::Order),
+    },
+    Endpoint {
+        method: "DELETE",
+        path: "/orders/{id}",
+        summary: "Remove an order",
+        request: None,
+        response: (200, Payload::Text),
+    },
+    Endpoint {
+        method: "PUT",
+        path: "/orders/{id}/status",
+        summary: "Change the status of an order",
+        request: Some(Payload::OrderStatus),
+        response: (200, Payload::Order),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/orders/{id}/stream",
+        summary: "Stream changes to one order",
+        request: None,
+        response: (200, Payload::EventStream),
+    },
+    Endpoint {
+        method: "POST",
+        path: "/orders/{id}/restore",
+        summary: "Restore a removed order",
+        request: None,
+        response: (200, Payload::Order),
+    },
+    Endpoint {
+        method: "POST",
+        path: "/customers",
+        summary: "Create a customer, or get the existing one with the same name and contact",
+        request: Some(Payload::CustomerRequest),
+        response: (201, Payload::Customer),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/customers/{id}",
+        summary: "Get a customer",
+        request: None,
+        response: (200, Payload::Customer),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/customers/{id}/orders",
+        summary: "List the orders placed by a customer",
+        request: None,
+        response: (200, Payload::Orders),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/inventory",
+        summary: "List the stock of every ingredient",
+        request: None,
+        response: (200, Payload::Inventory),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/inventory/low",
+        summary: "List the ingredients at or below their low stock threshold",
+        request: None,
+        response: (200, Payload::Inventory),
+    },
+    Endpoint {
+        method: "PUT",
+        path: "/inventory/{ingredient}",
+        summary: "Set the stock of an ingredient",
+        request: Some(Payload::StockUpdate),
+        response: (200, Payload::InventoryItem),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/couriers",
+        summary: "List couriers and the deliveries assigned to them",
+        request: None,
+        response: (200, Payload::Couriers),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/webhooks",
+        summary: "List webhook subscriptions, without their secrets or what they cover",
+        request: None,
+        response: (200, Payload::Webhooks),
+    },
+    Endpoint {
+        method: "POST",
+        path: "/webhooks",
+        summary: "Subscribe to order events. The response is the only one with the secret",
+        request: Some(Payload::WebhookRequest),
+        response: (201, Payload::Webhook),
+    },
+    Endpoint {
+        method: "DELETE",
+        path: "/webhooks/{id}",
+        summary: "Remove a webhook subscription and its dead letters",
+        request: None,
+        response: (200, Payload::Text),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/webhooks/dead-letters",
+        summary: "List webhook deliveries that were given up on",
+        request: None,
+        response: (200, Payload::DeadLetters),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/reports/sales",
+        summary: "Revenue, orders per status and daily totals",
+        request: None,
+        response: (200, Payload::SalesReport),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/reports/items",
+        summary: "Most ordered menu items and toppings",
+        request: None,
+        response: (200, Payload::ItemsReport),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/reports/hourly",
+        summary: "Orders and revenue per hour of the day",
+        request: None,
+        response: (200, Payload::HourlyReport),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/metrics",
+        summary: "Prometheus metrics",
+        request: None,
+        response: (200, Payload::Metrics),
+    },
+    Endpoint {
+        method: "GET",
+        path: "/openapi.json",
+        summary: "This OpenAPI document",
+        request: None,
+        response: (200, Payload::Json),
+    },
+];
+
+/// Path template for a raw request path, or `unmatched` if no route serves it
+pub fn route_template(path: &str) -> &'static str {
+    Route::from_str(path).map_or("unmatched", |route| route.template())
+}
+
+impl FromStr for Route {
+    type Err = AspirinEatsError;
+
+    /// Match a request path (without query string) to a Route
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        match segments.as_slice() {
+            [] => Ok(Route::Root),
+            ["inventory"] => Ok(Route::Inventory),
+            ["inventory", "low"] => Ok(Route::LowStock),
+            ["inventory", name] => Ingredient::from_name(name)
+                .map(Route::InventoryItem)
+                .ok_or(AspirinEatsError::NotFound),
+            ["couriers"] => Ok(Route::Couriers),
+            ["webhooks"] => Ok(Route::Webhooks),
+            ["webhooks", "dead-letters"] => Ok(Route::DeadLetters),
+            ["webhooks", id] => Ok(Route::Webhook(parse_id(id)?)),
+            ["reports", "sales"] => Ok(Route::SalesReport),
+            ["reports", "items"] => Ok(Route::ItemsReport),
+            ["reports", "hourly"] => Ok(Route::HourlyReport),
+            ["metrics"] => Ok(Route::Metrics),
+            ["openapi.json"] => Ok(Route::OpenApi),
+            ["orders"] => Ok(Route::Orders),
+            ["orders", "stream"] => Ok(Route::OrdersStream),
+            ["orders", "socket"] => Ok(Route::OrdersSocket),
+            ["orders", key] => Ok(Route::Order(parse_uuid(key)?)),
+            ["orders", key, "status"] => Ok(Route::OrderStatus(parse_uuid(key)?)),
+            ["orders", key, "stream"] => Ok(Route::OrderStream(parse_uuid(key)?)),
+            ["orders", key, "restore"] => Ok(Route::OrderRestore(parse_uuid(key)?)),
+            ["customers"] => Ok(Route::Customers),
+            ["customers", key] => Ok(Route::Customer(parse_uuid(key)?)),
+            ["customers", key, "orders"] => Ok(Route::CustomerOrders(parse_uuid(key)?)),
+            _ => Err(AspirinEatsError::NotFound),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<i64, AspirinEatsError> {
+    id.parse().map_err(|_| AspirinEatsError::InvalidRequest)
+}
+
+fn parse_uuid(uuid: &str) -> Result<Uuid, AspirinEatsError> {
+    Uuid::parse_str(uuid).map_err(|_| AspirinEatsError::InvalidRequest)
+}
+
+/// Handle a request against a store, converting any error into an HTTP Response
+pub fn respond(store: &dyn OrderStore, request: &HttpRequest) -> HttpResponse {
+    handle_request(store, request).unwrap_or_else(HttpResponse::from)
+}
+
+/// Get the database behind a store, for the resources only a database keeps. They are not
+/// found on stores without one
+fn database(store: &dyn OrderStore) -> Result<&AspirinEatsDb, AspirinEatsError> {
+    store.database().ok_or(AspirinEatsError::NotFound)
+}
+
+/// Route a request to the matching handler. Orders are served from any store, and every
+/// other resource from the database behind it
+pub fn handle_request(
+    store: &dyn OrderStore,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let route = Route::from_str(request.route())?;
+    let method = request
+        .method
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+
+    match (method, route) {
+        ("GET", Route::Root) => Ok(HttpResponse::new(200, "OK", WELCOME_MESSAGE)),
+        ("GET", Route::Orders) => get_orders(store),
+        ("POST", Route::Orders) => add_order(store, request),
+        ("DELETE", Route::Orders) => reset_orders(store),
+        ("GET", Route::Order(uuid)) => get_order(store, order_id(store, uuid)?),
+        ("DELETE", Route::Order(uuid)) => remove_order(store, order_id(store, uuid)?),
+        ("PUT", Route::OrderStatus(uuid)) => update_status(store, order_id(store, uuid)?, request),
+        ("POST", Route::OrderRestore(uuid)) => {
+            restore_order(database(store)?, order_id(store, uuid)?)
+        }
+        ("POST", Route::Customers) => add_customer(database(store)?, request),
+        ("GET", Route::Customer(uuid)) => get_customer(database(store)?, uuid),
+        ("GET", Route::CustomerOrders(uuid)) => get_customer_orders(database(store)?, uuid),
+        ("GET", Route::Inventory) => Ok(HttpResponse::json(
+            200,
+            "OK",
+            &serde_json::to_string(&database(store)?.inventory()?)?,
+        )),
+        ("GET", Route::LowStock) => Ok(HttpResponse::json(
+            200,
+            "OK",
+            &serde_json::to_string(&database(store)?.low_stock()?)?,
+        )),
+        ("PUT", Route::InventoryItem(ingredient)) => {
+            set_stock(database(store)?, ingredient, request)
+        }
+        ("GET", Route::Couriers) => Ok(HttpResponse::json(
+            200,
+            "OK",
+            &serde_json::to_string(&database(store)?.couriers())?,
+        )),
+        ("GET", Route::Webhooks) => get_webhooks(database(store)?),
+        ("POST", Route::Webhooks) => add_webhook(database(store)?, request),
+        ("DELETE", Route::Webhook(id)) => remove_webhook(database(store)?, id),
+        ("GET", Route::DeadLetters) => Ok(HttpResponse::json(
+            200,
+            "OK",
+            &serde_json::to_string(&database(store)?.dead_letters()?)?,
+        )),
+        ("GET", Route::SalesReport | Route::ItemsReport | Route::HourlyReport) => {
+            get_report(database(store)?, route, request)
+        }
+        ("GET", Route::OpenApi) => Ok(HttpResponse::json(200, "OK", openapi::document())),
+        ("GET", Route::Metrics) => {
+            Ok(
+                HttpResponse::new(200, "OK", &database(store)?.metrics().render())
+                    .with_header("Content-Type", metrics::CONTENT_TYPE),
+            )
+        }
+        // Streams and sockets hold on to the connection, so they are served by the connection
+        // handler
+        ("GET", Route::OrdersStream | Route::OrderStream(_) | Route::OrdersSocket) => {
+            Err(AspirinEatsError::NotFound)
+        }
+        _ => Err(AspirinEatsError::MethodNotAllowed),
+    }
+}
+
+fn get_orders(store: &dyn OrderStore) -> Result<HttpResponse, AspirinEatsError> {
+    let orders = store.get_all_orders()?;
+    Ok(HttpResponse::json(
+        200,
+        "OK",
+        &serde_json::to_string(&orders)?,
+    ))
+}
+
+fn get_order(store: &dyn OrderStore, id: i64) -> Result<HttpResponse, AspirinEatsError> {
+    let order = store.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    Ok(HttpResponse::json(200, "OK", &order.to_string()))
+}
+
+fn add_order(
+    store: &dyn OrderStore,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let order_request = OrderRequest::from_str(body)?;
+    let request_customer = order_request.customer_uuid;
+    let mut order: Order = order_request.into();
+    if let Some(uuid) = request_customer {
+        // Only a database keeps customers to order for
+        let customer = match store.database() {
+            Some(db) => match db.get_customer_id(&uuid)? {
+                Some(id) => db.get_customer(id)?,
+                None => None,
+            },
+            None => None,
+        };
+        let customer = customer.ok_or(AspirinEatsError::InvalidRequest)?;
+        order.customer = customer.name;
+        order.customer_id = customer.id;
+    }
+    let id = store.add_order(order)?;
+    let order = store.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    Ok(HttpResponse::json(201, "Created", &order.to_string()))
+}
+
+fn add_customer(
+    db: &AspirinEatsDb,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let customer: Customer = CustomerRequest::from_str(body)?.into();
+    let id = db.add_customer(&customer)?;
+    let customer = db.get_customer(id)?.ok_or(AspirinEatsError::NotFound)?;
+    Ok(HttpResponse::json(201, "Created", &customer.to_string()))
+}
+
+fn set_stock(
+    db: &AspirinEatsDb,
+    ingredient: Ingredient,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let item = db.set_stock(ingredient, &StockUpdate::from_str(body)?)?;
+    Ok(HttpResponse::json(200, "OK", &item.to_string()))
+}
+
+fn get_webhooks(db: &AspirinEatsDb) -> Result<HttpResponse, AspirinEatsError> {
+    let webhooks: Vec<Webhook> = db
+        .get_webhooks()?
+        .into_iter()
+        .map(|webhook| Webhook {
+            orders: Vec::new(),
+            customers: Vec::new(),
+            secret: None,
+            ..webhook
+        })
+        .collect();
+    Ok(HttpResponse::json(
+        200,
+        "OK",
+        &serde_json::to_string(&webhooks)?,
+    ))
+}
+
+fn add_webhook(
+    db: &AspirinEatsDb,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let mut webhook = Webhook::try_from(WebhookRequest::from_str(body)?)?;
+    webhooks::check_target(&webhook.url, db.private_webhooks())?;
+    // Only orders and customers the caller can name are covered
+    for uuid in &webhook.orders {
+        db.get_order_id(uuid)?
+            .ok_or(AspirinEatsError::InvalidRequest)?;
+    }
+    for uuid in &webhook.customers {
+        db.get_customer_id(uuid)?
+            .ok_or(AspirinEatsError::InvalidRequest)?;
+    }
+    webhook.id = Some(db.add_webhook(&webhook)?);
+    Ok(HttpResponse::json(201, "Created", &webhook.to_string()))
+}
+
+fn remove_webhook(db: &AspirinEatsDb, id: i64) -> Result<HttpResponse, AspirinEatsError> {
+    if !db.remove_webhook(id)? {
+        return Err(AspirinEatsError::NotFound);
+    }
+    Ok(HttpResponse::new(200, "OK", "Webhook removed"))
+}
+
+fn get_customer(db: &AspirinEatsDb, uuid: Uuid) -> Result<HttpResponse, AspirinEatsError> {
+    let id = customer_id(db, uuid)?;
+    let customer = db.get_customer(id)?.ok_or(AspirinEatsError::NotFound)?;
+    Ok(HttpResponse::json(200, "OK", &customer.to_string()))
+}
+
+fn get_customer_orders(db: &AspirinEatsDb, uuid: Uuid) -> Result<HttpResponse, AspirinEatsError> {
+    let id = customer_id(db, uuid)?;
+    let orders = db.get_customer_orders(id)?;
+    Ok(HttpResponse::json(
+        200,
+        "OK",
+        &serde_json::to_string(&orders)?,
+    ))
+}
+
+fn remove_order(store: &dyn OrderStore, id: i64) -> Result<HttpResponse, AspirinEatsError> {
+    store.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    store.remove_order(id)?;
+    Ok(HttpResponse::new(200, "OK", "Order removed"))
+}
+
+fn restore_order(db: &AspirinEatsDb, id: i64) -> Result<HttpResponse, AspirinEatsError> {
+    let order = db.restore_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    Ok(HttpResponse::json(200, "OK", &order.to_string()))
+}
+
+fn reset_orders(store: &dyn OrderStore) -> Result<HttpResponse, AspirinEatsError> {
+    store.reset_orders()?;
+    Ok(HttpResponse::new(200, "OK", "All orders removed"))
+}
+
+/// Serve a report for the `from` and `to` query parameters, as CSV if `format=csv` is given
+/// and as JSON otherwise
+fn get_report(
+    db: &AspirinEatsDb,
+    route: Route,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let range = Range::parse(
+        request.query("from").as_deref(),
+        request.query("to").as_deref(),
+    )?;
+    let report = match route {
+        Route::SalesReport => Report::Sales(db.sales_report(range)?),
+        Route::ItemsReport => Report::Items(db.items_report(range)?),
+        _ => Report::Hourly(db.hourly_report(range)?),
+    };
+
+    match request.query("format").as_deref() {
+        None | Some("json") => {
+            let body = match &report {
+                Report::Sales(report) => serde_json::to_string(report)?,
+                Report::Items(report) => serde_json::to_string(report)?,
+                Report::Hourly(report) => serde_json::to_string(report)?,
+            };
+            Ok(HttpResponse::json(200, "OK", &body))
+        }
+        Some("csv") => {
+            let mut body = Vec::new();
+            report.write_csv(&mut body)?;
+            let body = String::from_utf8(body).map_err(|_| AspirinEatsError::InvalidRequest)?;
+            Ok(HttpResponse::new(200, "OK", &body).with_header("Content-Type", "text/csv"))
+        }
+        Some(_) => Err(AspirinEatsError::InvalidRequest),
+    }
+}
+
+fn update_status(
+    store: &dyn OrderStore,
+    id: i64,
+    request: &HttpRequest,
+) -> Result<HttpResponse, AspirinEatsError> {
+    let body = request
+        .body
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let status = OrderStatus::from_str(body)?;
+    let order = store
+        .update_order_status(id, status)?
+        .ok_or(AspirinEatsError::NotFound)?;
+    Ok(HttpResponse::json(200, "OK", &order.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::InventoryItem;
+    use crate::reports::{HourlyReport, SalesReport};
+    use crate::store::MemoryStore;
+
+    const ORDER_REQUEST: &str = r#"{"customer":"Amit","food":["Fries","Drink"]}"#;
+
+    fn request(method: &str, path: &str, body: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            method: Some(method.to_string()),
+            path: Some(path.to_string()),
+            headers: Vec::new(),
+            body: body.map(str::to_string),
+        }
+    }
+
+    /// Place an order and get its public ID
+    fn order_uuid(store: &dyn OrderStore) -> Uuid {
+        let id = store
+            .add_order(Order::from(OrderRequest::from_str(ORDER_REQUEST).unwrap()))
+            .unwrap();
+        store.get_order(id).unwrap().unwrap().uuid.unwrap()
+    }
+
+    #[test]
+    fn test_route_from_str() {
+        assert_eq!(Route::from_str("/").unwrap(), Route::Root);
+        assert_eq!(Route::from_str("/orders/").unwrap(), Route::Orders);
+        assert_eq!(
+            Route::from_str("/orders/stream").unwrap(),
+            Route::OrdersStream
+        );
+        assert_eq!(
+            Route::from_str("/orders/socket").unwrap(),
+            Route::OrdersSocket
+        );
+        let uuid = Uuid::new_v4();
+        assert_eq!(
+            Route::from_str(&format!("/orders/{uuid}/stream")).unwrap(),
+            Route::OrderStream(uuid)
+        );
+        // Orders are only named by their public ID, so sequential IDs cannot be walked
+        for path in ["/orders/abc", "/orders/4", "/orders/4/stream"] {
+            assert!(matches!(
+                Route::from_str(path),
+                Err(AspirinEatsError::InvalidRequest)
+            ));
+        }
+        assert!(matches!(
+            Route::from_str("/menu"),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_endpoints_match_routes() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let uuid = order_uuid(&db);
+
+        for endpoint in ENDPOINTS {
+            let path = endpoint
+                .path
+                .replace("/orders/{id}", &format!("/orders/{uuid}"))
+                .replace("/customers/{id}", &format!("/customers/{}", Uuid::nil()))
+                .replace("{id}", "1")
+                .replace("{ingredient}", "fries");
+            let route = Route::from_str(&path).unwrap();
+            assert_eq!(route.template(), endpoint.path);
+
+            if endpoint.response.1 != Payload::EventStream {
+                let response = respond(&db, &request(endpoint.method, &path, None));
+                assert_ne!(response.status_code(), 405, "{:?}", endpoint);
+            }
+        }
+    }
+
+    /// A path for every route. The match has no wildcard, so adding a route fails to compile
+    /// until it is listed here
+    fn every_route_path(order: Uuid) -> Vec<String> {
+        let order = format!("/orders/{order}");
+        let paths = vec![
+            "/".to_string(),
+            "/orders".to_string(),
+            "/orders/stream".to_string(),
+            "/orders/socket".to_string(),
+            order.clone(),
+            format!("{order}/status"),
+            format!("{order}/stream"),
+            format!("{order}/restore"),
+            "/customers".to_string(),
+            format!("/customers/{}", Uuid::nil()),
+            format!("/customers/{}/orders", Uuid::nil()),
+            "/inventory".to_string(),
+            "/inventory/low".to_string(),
+            "/inventory/fries".to_string(),
+            "/couriers".to_string(),
+            "/webhooks".to_string(),
+            "/webhooks/1".to_string(),
+            "/webhooks/dead-letters".to_string(),
+            "/reports/sales".to_string(),
+            "/reports/items".to_string(),
+            "/reports/hourly".to_string(),
+            "/metrics".to_string(),
+            "/openapi.json".to_string(),
+        ];
+        let mut covered = Vec::new();
+        for path in &paths {
+            let route = Route::from_str(path).unwrap();
+            match route {
+                Route::Root
+                | Route::Orders
+                | Route::OrdersStream
+                | Route::OrdersSocket
+                | Route::Order(_)
+                | Route::OrderStatus(_)
+                | Route::OrderStream(_)
+                | Route::OrderRestore(_)
+                | Route::Customers
+                | Route::Customer(_)
+                | Route::CustomerOrders(_)
+                | Route::Inventory
+                | Route::LowStock
+                | Route::InventoryItem(_)
+                | Route::Couriers
+                | Route::Webhooks
+                | Route::Webhook(_)
+                | Route::DeadLetters
+                | Route::SalesReport
+                | Route::ItemsReport
+                | Route::HourlyReport
+                | Route::Metrics
+                | Route::OpenApi => covered.push(route.template()),
+            }
+        }
+        covered.sort_unstable();
+        covered.dedup();
+        assert_eq!(covered.len(), paths.len(), "a route is listed twice");
+        paths
+    }
+
+    #[test]
+    fn test_routes_match_endpoints() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let uuid = order_uuid(&db);
+
+        for path in every_route_path(uuid) {
+            let template = Route::from_str(&path).unwrap().template();
+            assert!(
+                ENDPOINTS.iter().any(|endpoint| endpoint.path == template),
+                "{} has no endpoint",
+                template
+            );
+
+            for method in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+                let response = respond(&db, &request(method, &path, None));
+                let listed = ENDPOINTS
+                    .iter()
+                    .any(|endpoint| endpoint.path == template && endpoint.method == method);
+                assert_eq!(
+                    response.status_code() != 405,
+                    listed,
+                    "{} {} is served but not listed, or listed but not served",
+                    method,
+                    template
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_get_remove_order() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+
+        let response = respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        assert_eq!(response.status_code(), 201);
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.total, 8.0);
+        // The internal ID is never handed out
+        assert_eq!(order.id, None);
+        assert!(!response.body().contains("\"id\""));
+        let path = format!("/orders/{}", order.uuid.unwrap());
+
+        let response = respond(&db, &request("GET", &path, None));
+        assert_eq!(Order::from_str(response.body()).unwrap(), order);
+        let response = respond(&db, &request("GET", "/orders/1", None));
+        assert_eq!(response.status_code(), 400);
+
+        let response = respond(&db, &request("DELETE", &path, None));
+        assert_eq!(response.status_code(), 200);
+        let response = respond(&db, &request("GET", &path, None));
+        assert_eq!(response.status_code(), 404);
+
+        let restore = format!("{path}/restore");
+        let response = respond(&db, &request("POST", &restore, None));
+        assert_eq!(Order::from_str(response.body()).unwrap(), order);
+        let response = respond(&db, &request("POST", &restore, None));
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[test]
+    fn test_orders_by_uuid() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let response = respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        let order = Order::from_str(response.body()).unwrap();
+        let path = format!("/orders/{}", order.uuid.unwrap());
+
+        let response = respond(&db, &request("GET", &path, None));
+        assert_eq!(Order::from_str(response.body()).unwrap(), order);
+        let status = Some("\"Preparing\"");
+        let response = respond(&db, &request("PUT", &format!("{path}/status"), status));
+        assert_eq!(response.status_code(), 200);
+
+        respond(&db, &request("DELETE", &path, None));
+        let response = respond(&db, &request("GET", &path, None));
+        assert_eq!(response.status_code(), 404);
+        let response = respond(&db, &request("POST", &format!("{path}/restore"), None));
+        assert_eq!(Order::from_str(response.body()).unwrap().uuid, order.uuid);
+
+        let path = format!("/orders/{}", Uuid::new_v4());
+        let response = respond(&db, &request("GET", &path, None));
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[test]
+    fn test_customers() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let body = r#"{"name":"Amit","contact":"555-0100"}"#;
+
+        let response = respond(&db, &request("POST", "/customers", Some(body)));
+        assert_eq!(response.status_code(), 201);
+        let customer = Customer::from_str(response.body()).unwrap();
+        let response = respond(&db, &request("POST", "/customers", Some(body)));
+        assert_eq!(Customer::from_str(response.body()).unwrap(), customer);
+
+        // Customers are only named by their public ID
+        assert!(!response.body().contains(r#""id""#));
+        let uuid = customer.uuid.unwrap();
+        let response = respond(&db, &request("GET", &format!("/customers/{uuid}"), None));
+        assert_eq!(Customer::from_str(response.body()).unwrap(), customer);
+        let response = respond(&db, &request("GET", "/customers/1", None));
+        assert_eq!(response.status_code(), 400);
+
+        let order_request =
+            format!(r#"{{"customer":"","customer_uuid":"{uuid}","food":["Fries"]}}"#);
+        let response = respond(&db, &request("POST", "/orders", Some(&order_request)));
+        assert!(!response.body().contains("customer_id"));
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.customer, "Amit");
+        respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+
+        let response = respond(
+            &db,
+            &request("GET", &format!("/customers/{uuid}/orders"), None),
+        );
+        let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(orders, vec![order]);
+
+        let unknown = Uuid::new_v4();
+        let path = format!("/customers/{unknown}/orders");
+        let response = respond(&db, &request("GET", &path, None));
+        assert_eq!(response.status_code(), 404);
+        let order_request =
+            format!(r#"{{"customer":"Amit","customer_uuid":"{unknown}","food":[]}}"#);
+        let response = respond(&db, &request("POST", "/orders", Some(&order_request)));
+        assert_eq!(response.status_code(), 400);
+
+        // The internal customer ID is not accepted in its place
+        let order_request = r#"{"customer":"Bob","customer_id":1,"food":["Fries"]}"#;
+        let response = respond(&db, &request("POST", "/orders", Some(order_request)));
+        let order = Order::from_str(response.body()).unwrap();
+        assert_eq!(order.customer, "Bob");
+        let id = db.get_order_id(&order.uuid.unwrap()).unwrap().unwrap();
+        assert_ne!(db.get_order(id).unwrap().unwrap().customer_id, Some(1));
+    }
+
+    #[test]
+    fn test_inventory() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let response = respond(
+            &db,
+            &request(
+                "PUT",
+                "/inventory/fries",
+                Some(r#"{"stock":1,"low_stock":2}"#),
+            ),
+        );
+        assert_eq!(response.status_code(), 200);
+        assert!(InventoryItem::from_str(response.body()).unwrap().low);
+
+        let response = respond(&db, &request("GET", "/inventory/low", None));
+        let low: Vec<InventoryItem> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(low.len(), 1);
+        assert_eq!(low[0].ingredient, "fries");
+
+        // ORDER_REQUEST takes one portion of fries
+        let response = respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        assert_eq!(response.status_code(), 201);
+        let uuid = Order::from_str(response.body()).unwrap().uuid.unwrap();
+        let response = respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        assert_eq!(response.status_code(), 409);
+        assert_eq!(response.body(), "Out of stock: fries");
+
+        let response = respond(
+            &db,
+            &request(
+                "PUT",
+                &format!("/orders/{uuid}/status"),
+                Some("\"Cancelled\""),
+            ),
+        );
+        assert_eq!(response.status_code(), 200);
+        let response = respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        assert_eq!(response.status_code(), 201);
+
+        let response = respond(&db, &request("PUT", "/inventory/ketchup", Some("{}")));
+        assert_eq!(response.status_code(), 404);
+        let response = respond(
+            &db,
+            &request("PUT", "/inventory/fries", Some(r#"{"stock":-1}"#)),
+        );
+        assert_eq!(response.status_code(), 400);
+    }
+
+    #[test]
+    fn test_reports() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        respond(&db, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        let uuid = order_uuid(&db);
+        respond(
+            &db,
+            &request(
+                "PUT",
+                &format!("/orders/{uuid}/status"),
+                Some("\"Cancelled\""),
+            ),
+        );
+
+        let response = respond(&db, &request("GET", "/reports/sales", None));
+        assert_eq!(response.status_code(), 200);
+        let report: SalesReport = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(report.orders, 1);
+        assert_eq!(report.revenue, 8.0);
+        assert_eq!(report.by_status.len(), 2);
+
+        let response = respond(&db, &request("GET", "/reports/items?format=csv", None));
+        assert_eq!(response.header("Content-Type"), Some("text/csv"));
+        assert_eq!(
+            response.body(),
+            "kind,name,count\nitem,drink,1\nitem,fries,1\n"
+        );
+
+        // Nothing was ordered before 2000
+        let response = respond(&db, &request("GET", "/reports/hourly?to=2000-01-01", None));
+        let report: HourlyReport = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(report.hours.len(), 24);
+        assert_eq!(report.busiest, None);
+
+        let response = respond(&db, &request("GET", "/reports/sales?from=soon", None));
+        assert_eq!(response.status_code(), 400);
+        let response = respond(&db, &request("GET", "/reports/sales?format=xml", None));
+        assert_eq!(response.status_code(), 400);
+    }
+
+    #[test]
+    fn test_order_handlers_without_sqlite() {
+        let store = MemoryStore::new();
+        let response = respond(&store, &request("POST", "/orders", Some(ORDER_REQUEST)));
+        assert_eq!(response.status_code(), 201);
+        let order = Order::from_str(response.body()).unwrap();
+        let path = format!("/orders/{}", order.uuid.unwrap());
+
+        let response = respond(&store, &request("GET", &path, None));
+        assert_eq!(Order::from_str(response.body()).unwrap(), order);
+        let status = Some("\"Ready\"");
+        let response = respond(&store, &request("PUT", &format!("{path}/status"), status));
+        assert_eq!(
+            Order::from_str(response.body()).unwrap().status,
+            OrderStatus::Ready
+        );
+
+        let response = respond(&store, &request("DELETE", &path, None));
+        assert_eq!(response.status_code(), 200);
+        let response = respond(&store, &request("GET", &path, None));
+        assert_eq!(response.status_code(), 404);
+        let response = respond(&store, &request("GET", "/orders", None));
+        assert_eq!(response.body(), "[]");
+
+        // Everything besides orders is kept by a database, which this store does not have
+        let customer = format!("/customers/{}", Uuid::new_v4());
+        for path in ["/inventory", &customer, "/reports/sales", "/metrics"] {
+            let response = respond(&store, &request("GET", path, None));
+            assert_eq!(response.status_code(), 404, "{path}");
+        }
+        let response = respond(&store, &request("POST", &format!("{path}/restore"), None));
+        assert_eq!(response.status_code(), 404);
+        let response = respond(&store, &request("PATCH", "/orders", None));
+        assert_eq!(response.status_code(), 405);
+    }
+
+    #[test]
+    fn test_update_status() {
+        let store = MemoryStore::new();
+        let path = format!("/orders/{}/status", order_uuid(&store));
+
+        let response = respond(&store, &request("PUT", &path, Some("\"Preparing\"")));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            Order::from_str(response.body()).unwrap().status,
+            OrderStatus::Preparing
+        );
+
+        let response = respond(&store, &request("PUT", &path, Some("\"Eaten\"")));
+        assert_eq!(response.status_code(), 400);
+    }
+
+    #[test]
+    fn test_errors() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        assert_eq!(
+            respond(&db, &request("GET", "/", None)).body(),
+            WELCOME_MESSAGE
+        );
+        assert_eq!(
+            respond(&db, &request("PATCH", "/orders", None)).status_code(),
+            405
+        );
+        assert_eq!(
+            respond(&db, &request("GET", "/metrics", None)).status_code(),
+            200
+        );
+        assert_eq!(
+            respond(&db, &request("GET", "/menu", None)).status_code(),
+            404
+        );
+        assert_eq!(
+            respond(&db, &request("POST", "/orders", Some("{}"))).status_code(),
+            400
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::error::AspirinEatsError;
+use crate::http::{self, HttpRequest, HttpResponse, Limits};
+use crate::log;
+use crate::server::{self, OriginServer, Upgrade};
+use crate::sse;
+use crate::websocket::{self, MessageReader, Session};
+
+/// The origin server running on tokio. Connections are served by async tasks, while routing
+/// and every `AspirinEatsDb` call run on the blocking pool through the same handlers as
+/// `OriginServer`
+#[derive(Clone)]
+pub struct AsyncOriginServer {
+    server: OriginServer,
+}
+
+impl AsyncOriginServer {
+    pub fn new(server: OriginServer) -> Self {
+        AsyncOriginServer { server }
+    }
+
+    /// Accept connections forever, handling each one on its own task
+    pub async fn serve(&self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let server = self.server.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(&server, stream).await {
+                            server.logger.error(None, &e);
+                        }
+                    });
+                }
+                Err(e) => self.server.logger.error(None, &e.into()),
+            }
+        }
+    }
+
+    /// Start a multi-threaded runtime and serve on it, blocking forever
+    pub fn run(self, listener: std::net::TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .enable_time()
+            .build()?;
+        runtime.block_on(async {
+            let listener = TcpListener::from_std(listener)?;
+            self.serve(listener).await;
+            Ok(())
+        })
+    }
+
+    /// Bind to the given address and serve on a background runtime. Useful for testing with
+    /// an ephemeral port (`127.0.0.1:0`)
+    pub fn spawn<A: ToSocketAddrs>(self, addr: A) -> io::Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        thread::spawn(move || self.run(listener));
+        Ok(local_addr)
+    }
+}
+
+/// Read a single HTTP Request from an async reader, enforcing the limits. Mirrors
+/// `http::read_request_limited`
+async fn read_request<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Option<HttpRequest>, AspirinEatsError> {
+    match timeout(limits.idle_timeout, reader.fill_buf()).await {
+        Ok(Ok([])) | Err(_) => return Ok(None),
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(e.into()),
+    }
+
+    let head = timeout(limits.read_header_timeout, async {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            let read = (&mut *reader)
+                .take(limits.max_request_line as u64 + 2)
+                .read_line(&mut line)
+                .await?;
+            if read == 0 {
+                return Err(AspirinEatsError::InvalidRequest);
+            }
+            if http::push_head_line(&mut head, &line, limits)? {
+                return Ok(head);
+            }
+        }
+    })
+    .await
+    .map_err(|_| AspirinEatsError::RequestTimeout)??;
+
+    let mut body = vec![0; http::body_length(&head, limits)?];
+    timeout(limits.read_body_timeout, reader.read_exact(&mut body))
+        .await
+        .map_err(|_| AspirinEatsError::RequestTimeout)??;
+    let message = http::append_body(head, body)?;
+    HttpRequest::from_str(&message).map(Some)
+}
+
+/// Write a response, compressed as negotiated, giving up after `write_timeout`
+async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: HttpResponse,
+    close: bool,
+    accept_encoding: Option<&str>,
+    write_timeout: Duration,
+) -> Result<u64, AspirinEatsError> {
+    let (message, bytes) = server::frame_response(response, close, accept_encoding)?;
+    timeout(write_timeout, async {
+        writer.write_all(&message).await?;
+        writer.flush().await
+    })
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    Ok(bytes)
+}
+
+/// Serve requests on a single connection until the client closes it
+async fn handle_connection(
+    server: &OriginServer,
+    stream: TcpStream,
+) -> Result<(), AspirinEatsError> {
+    let _connection = server.metrics.track_connection();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let request = match read_request(&mut reader, &server.limits).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                server.logger.error(None, &e);
+                write_response(
+                    &mut writer,
+                    HttpResponse::from(e),
+                    true,
+                    None,
+                    server.limits.write_timeout,
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        let started = Instant::now();
+        let request_id = log::request_id(&request);
+
+        // Finding the order a stream is for locks the store, which blocks like a handler
+        let (mut request, upgrade) = {
+            let server = server.clone();
+            tokio::task::spawn_blocking(move || {
+                let upgrade = server.upgrade_route(&request);
+                (request, upgrade)
+            })
+            .await
+            .map_err(io::Error::other)?
+        };
+        let answered = match upgrade {
+            Some(Upgrade::Events(order_id)) => {
+                match server.upgrade_head(&mut request, &request_id, |_| sse::stream_response()) {
+                    Ok(head) => {
+                        let result =
+                            serve_stream(server, &mut writer, head, &request, order_id).await;
+                        server.log_access(&request_id, &request, 200, 0, started);
+                        return result;
+                    }
+                    Err(response) => Some(response),
+                }
+            }
+            Some(Upgrade::Socket) => {
+                match server.upgrade_head(&mut request, &request_id, |r| server.handshake(r)) {
+                    Ok(head) => {
+                        let result =
+                            serve_socket(server, reader, &mut writer, head, &request_id).await;
+                        server.log_access(&request_id, &request, 101, 0, started);
+                        return result;
+                    }
+                    Err(response) => Some(response),
+                }
+            }
+            None => None,
+        };
+
+        let (request, response) = match answered {
+            Some(response) => (request, response),
+            None => {
+                let server = server.clone();
+                let request_id = request_id.clone();
+                tokio::task::spawn_blocking(move || {
+                    let response = server.dispatch(&mut request, &request_id);
+                    (request, response)
+                })
+                .await
+                .map_err(io::Error::other)?
+            }
+        };
+
+        let status = response.status_code();
+        let close = request.wants_close();
+        let bytes = write_response(
+            &mut writer,
+            response,
+            close,
+            request.header("Accept-Encoding"),
+            server.limits.write_timeout,
+        )
+        .await?;
+        server.log_access(&request_id, &request, status, bytes, started);
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Serve an event stream, awaiting events from the bus without holding a thread, and
+/// sending a keep-alive comment after every quiet `KEEP_ALIVE_INTERVAL`
+async fn serve_stream<W: AsyncWrite + Unpin>(
+    server: &OriginServer,
+    writer: &mut W,
+    head: HttpResponse,
+    request: &HttpRequest,
+    order_id: Option<i64>,
+) -> Result<(), AspirinEatsError> {
+    let mut subscription = server.events.subscribe_async(sse::last_event_id(request));
+    writer.write_all(head.to_string().as_bytes()).await?;
+    for event in subscription
+        .backlog
+        .iter()
+        .filter(|event| sse::matches(event, order_id))
+    {
+        writer
+            .write_all(sse::format_event(event).as_bytes())
+            .await?;
+    }
+    writer.flush().await?;
+
+    loop {
+        match timeout(sse::KEEP_ALIVE_INTERVAL, subscription.receiver.recv()).await {
+            Ok(Some(event)) if sse::matches(&event, order_id) => {
+                writer
+                    .write_all(sse::format_event(&event).as_bytes())
+                    .await?
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => return Ok(()),
+            Err(_) => writer.write_all(b": keep-alive\n\n").await?,
+        }
+        writer.flush().await?;
+    }
+}
+
+/// Serve an order socket on tasks, by the same session as on the threaded server. Any
+/// bytes the client sent after its handshake are still buffered in `reader`
+async fn serve_socket<R, W>(
+    server: &OriginServer,
+    reader: R,
+    writer: &mut W,
+    head: HttpResponse,
+    request_id: &str,
+) -> Result<(), AspirinEatsError>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let write_timeout = server.limits.write_timeout;
+    timeout(write_timeout, async {
+        writer.write_all(head.to_string().as_bytes()).await?;
+        writer.flush().await
+    })
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let reader = MessageReader::new(reader, server.limits.max_body, true);
+    Session::new(
+        server.store.clone(),
+        server.logger.clone(),
+        request_id,
+        websocket::KEEP_ALIVE_INTERVAL,
+    )
+    .run_async(reader, writer, &server.events, write_timeout)
+    .await
+}
@@ -0,0 +1,294 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::db::SCHEMA_VERSION;
+use crate::error::AspirinEatsError;
+
+/// Pages copied per backup step. Between steps the source is unlocked, so writers are only
+/// ever held up for the time it takes to copy this many pages
+const PAGES_PER_STEP: i32 = 256;
+
+/// How long to wait before retrying a backup step while the source is busy
+const STEP_PAUSE: Duration = Duration::from_millis(10);
+
+/// Copy the database at `db_path` to `dest` using SQLite's online backup API, while other
+/// connections keep reading and writing it. The copy is written next to `dest` and only moved
+/// into place once complete, so `dest` never holds a partial backup
+pub fn backup<P: AsRef<Path>, Q: AsRef<Path>>(db_path: P, dest: Q) -> Result<(), AspirinEatsError> {
+    let dest = dest.as_ref();
+    let partial = with_suffix(dest, ".partial");
+    let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut target = Connection::open(&partial)?;
+    Backup::new(&source, &mut target)?.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)?;
+    // The source may be in WAL mode; backups are single, self-contained files
+    target.pragma_update_and_check(None, "journal_mode", "DELETE", |_| Ok(()))?;
+    drop(target);
+    fs::rename(partial, dest)?;
+    Ok(())
+}
+
+/// Check that a backup can be restored: it must be an intact SQLite database holding orders,
+/// with a schema no newer than this build knows about. Older schemas are migrated when the
+/// database is next opened. Returns the backup's schema version
+pub fn validate<P: AsRef<Path>>(path: P) -> Result<usize, AspirinEatsError> {
+    let invalid = |reason: String| AspirinEatsError::InvalidBackup(reason);
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let check: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| invalid(e.to_string()))?;
+    if check != "ok" {
+        return Err(invalid(format!("integrity check failed: {}", check)));
+    }
+
+    let has_orders: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'orders')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_orders {
+        return Err(invalid("no orders table".to_string()));
+    }
+
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(invalid(format!(
+            "schema version {} is newer than the supported version {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// Replace the database at `db_path` with a backup, after validating it. The current
+/// database is first backed up to `<db_path>.pre-restore`. Nothing may have the database
+/// open while it is restored, so stop the origin first. Returns the backup's schema version
+pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(
+    backup_path: P,
+    db_path: Q,
+) -> Result<usize, AspirinEatsError> {
+    let version = validate(&backup_path)?;
+    let db_path = db_path.as_ref();
+    if db_path.exists() {
+        backup(db_path, with_suffix(db_path, ".pre-restore"))?;
+    }
+
+    let staged = with_suffix(db_path, ".restoring");
+    fs::copy(backup_path, &staged)?;
+    fs::File::open(&staged)?.sync_all()?;
+    // Journal files belong to the database being replaced
+    for suffix in ["-wal", "-shm", "-journal"] {
+        match fs::remove_file(with_suffix(db_path, suffix)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    fs::rename(staged, db_path)?;
+    Ok(version)
+}
+
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Backups taken at regular times into a directory, named after the Unix time they were
+/// taken at. Only the newest `keep` are kept
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl Snapshots {
+    /// Keep up to `keep` snapshots (at least one) in `dir`
+    pub fn new<P: AsRef<Path>>(dir: P, keep: usize) -> Self {
+        Snapshots {
+            dir: dir.as_ref().to_path_buf(),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Back up the database into a snapshot for time `now`, then delete the snapshots past
+    /// the retention limit. Returns the new snapshot's path
+    pub fn take<P: AsRef<Path>>(&self, db_path: P, now: i64) -> Result<PathBuf, AspirinEatsError> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("snapshot-{}.db", now));
+        backup(db_path, &path)?;
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Every snapshot as `(time taken, path)`, oldest first
+    pub fn list(&self) -> Result<Vec<(i64, PathBuf)>, AspirinEatsError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let time = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("snapshot-")?.strip_suffix(".db"))
+                .and_then(|time| time.parse().ok());
+            if let Some(time) = time {
+                snapshots.push((time, path));
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    /// The newest snapshot taken at or before `time`, for restoring the database to how it
+    /// was at that point
+    pub fn at(&self, time: i64) -> Result<Option<PathBuf>, AspirinEatsError> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .rev()
+            .find(|(taken, _)| *taken <= time)
+            .map(|(_, path)| path))
+    }
+
+    /// Delete the oldest snapshots until at most `keep` are left
+    fn prune(&self) -> Result<(), AspirinEatsError> {
+        let snapshots = self.list()?;
+        let excess = snapshots.len().saturating_sub(self.keep);
+        for (_, path) in &snapshots[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::db::AspirinEatsDb;
+    use crate::food::{Ingredient, MenuItem, Order, OrderStatus};
+    use crate::inventory::StockUpdate;
+
+    const STOCK: i64 = 1_000_000;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("aspirin-{}-{}", name, uuid::Uuid::new_v4()))
+    }
+
+    fn get_test_order() -> Order {
+        Order {
+            id: None,
+            uuid: None,
+            customer: "Amit".to_string(),
+            customer_id: None,
+            created_at: None,
+            food: vec![MenuItem::Fries],
+            status: OrderStatus::Pending,
+            total: 4.0,
+            estimated_ready_at: None,
+        }
+    }
+
+    #[test]
+    fn test_backup_under_concurrent_writes_is_consistent() {
+        let path = temp_path("live.db");
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        db.set_stock(
+            Ingredient::Fries,
+            &StockUpdate {
+                stock: STOCK,
+                low_stock: None,
+            },
+        )
+        .unwrap();
+        drop(db);
+
+        let writer_path = path.clone();
+        let writer = thread::spawn(move || {
+            let db = AspirinEatsDb::from_path(writer_path).unwrap();
+            for _ in 0..300 {
+                db.add_order(get_test_order()).unwrap();
+            }
+        });
+
+        // Every order takes one portion of fries in the same transaction it is inserted in,
+        // so a consistent copy never has one without the other
+        let mut backups = Vec::new();
+        while !writer.is_finished() || backups.is_empty() {
+            let dest = temp_path("backup.db");
+            backup(&path, &dest).unwrap();
+            backups.push(dest);
+        }
+        writer.join().unwrap();
+
+        for dest in backups {
+            assert_eq!(validate(&dest).unwrap(), SCHEMA_VERSION);
+            let conn = Connection::open(&dest).unwrap();
+            let orders: i64 = conn
+                .query_row("SELECT COUNT(*) FROM orders", [], |row| row.get(0))
+                .unwrap();
+            let stock: i64 = conn
+                .query_row(
+                    "SELECT stock FROM inventory WHERE ingredient = 'fries'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(STOCK - stock, orders);
+        }
+    }
+
+    #[test]
+    fn test_restore_validates_and_swaps() {
+        let path = temp_path("live.db");
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        db.add_order(get_test_order()).unwrap();
+        let snapshots = Snapshots::new(temp_path("snapshots"), 2);
+        let first = snapshots.take(&path, 100).unwrap();
+        db.add_order(get_test_order()).unwrap();
+        snapshots.take(&path, 200).unwrap();
+        snapshots.take(&path, 300).unwrap();
+        drop(db);
+
+        assert!(!first.exists());
+        let times: Vec<i64> = snapshots.list().unwrap().iter().map(|(t, _)| *t).collect();
+        assert_eq!(times, vec![200, 300]);
+        assert_eq!(snapshots.at(150).unwrap(), None);
+
+        let newer = temp_path("newer.db");
+        fs::copy(snapshots.at(250).unwrap().unwrap(), &newer).unwrap();
+        Connection::open(&newer)
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(matches!(
+            restore(&newer, &path),
+            Err(AspirinEatsError::InvalidBackup(_))
+        ));
+        let garbage = temp_path("garbage.db");
+        fs::write(&garbage, "not a database").unwrap();
+        assert!(restore(&garbage, &path).is_err());
+
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        db.reset_orders().unwrap();
+        drop(db);
+        restore(snapshots.at(250).unwrap().unwrap(), &path).unwrap();
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        assert_eq!(db.get_all_orders().unwrap().len(), 2);
+
+        let before = AspirinEatsDb::from_path(with_suffix(&path, ".pre-restore")).unwrap();
+        assert!(before.get_all_orders().unwrap().is_empty());
+    }
+}
@@ -1,9 +1,218 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use aspirin_eats::backup::{self, Snapshots};
+use aspirin_eats::bulk::{Format, IdMode, ImportOptions};
+use aspirin_eats::cors::Cors;
 use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::log::{LogTarget, Logger};
+use aspirin_eats::reports;
+use aspirin_eats::server::OriginServer;
+use aspirin_eats::webhooks::RetryPolicy;
 
 /// Change this path to match where you want to store the database file
-const DB_PATH: &str =
-    "/home/amit/Documents/code/aspirin/dev-aspirin/assignments/05-networking/aspirin_eats.db";
+const DB_PATH: &str = "aspirin_eats.db";
+
+/// Address the origin server listens on
+const ORIGIN_ADDR: &str = "127.0.0.1:8080";
+
+/// How long removed orders can still be restored before they are purged for good
+const DELETED_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How often removed orders past their retention are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Directory scheduled snapshots of the database are written to
+const SNAPSHOT_DIR: &str = "snapshots";
+
+/// How often the database is snapshotted
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Number of snapshots kept. Older ones are deleted
+const SNAPSHOT_RETENTION: usize = 48;
+
+/// How often the kitchen queue and the couriers are moved along
+const SIMULATION_INTERVAL: Duration = Duration::from_secs(1);
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [serve]", program);
+    eprintln!("       {} export <jsonl|csv> [file]", program);
+    eprintln!(
+        "       {} import <jsonl|csv> <file> [--dry-run] [--remap-ids]",
+        program
+    );
+    eprintln!("       {} backup <file>", program);
+    eprintln!("       {} snapshots", program);
+    eprintln!("       {} restore <file | --at <time>>", program);
+    std::process::exit(2);
+}
 
 fn main() {
-    let db = AspirinEatsDb::from_path(DB_PATH).expect("Failed to open database");
+    let args = env::args().collect::<Vec<String>>();
+    // These work on the database file itself, so they must not hold it open
+    match args.get(1).map(String::as_str) {
+        Some("backup") => return backup(&args),
+        Some("snapshots") => return list_snapshots(),
+        Some("restore") => return restore(&args),
+        _ => {}
+    }
+    // Webhooks to loopback, private and link-local addresses are refused unless this is set
+    let private_webhooks = env::var("ASPIRIN_WEBHOOKS_ALLOW_PRIVATE").is_ok_and(|v| v == "true");
+    let db = AspirinEatsDb::from_path(DB_PATH)
+        .expect("Failed to open database")
+        .with_private_webhooks(private_webhooks);
+
+    match args.get(1).map(String::as_str) {
+        None | Some("serve") => serve(db),
+        Some("export") => export(&db, &args),
+        Some("import") => import(&db, &args),
+        Some(_) => usage(&args[0]),
+    }
+}
+
+fn serve(db: AspirinEatsDb) {
+    let logger = Logger::new("origin", LogTarget::from_env()).expect("Failed to open log file");
+    let listener = TcpListener::bind(ORIGIN_ADDR).expect("Failed to bind origin address");
+    println!("Origin listening on {}", ORIGIN_ADDR);
+    let mut server = OriginServer::new(db).with_logger(logger);
+    let cors = Cors::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(cors) = cors {
+        server = server.with_cors(cors);
+    }
+    server.start_purge_job(DELETED_RETENTION, PURGE_INTERVAL);
+    server.start_simulation(SIMULATION_INTERVAL);
+    server.start_webhook_worker(RetryPolicy::default());
+    server.start_snapshot_job(
+        PathBuf::from(DB_PATH),
+        Snapshots::new(SNAPSHOT_DIR, SNAPSHOT_RETENTION),
+        SNAPSHOT_INTERVAL,
+    );
+
+    #[cfg(feature = "async")]
+    aspirin_eats::async_server::AsyncOriginServer::new(server)
+        .run(listener)
+        .expect("Failed to start async runtime");
+    #[cfg(not(feature = "async"))]
+    server.serve(listener);
+}
+
+fn export(db: &AspirinEatsDb, args: &[String]) {
+    let format = args
+        .get(2)
+        .and_then(|format| Format::from_str(format).ok())
+        .unwrap_or_else(|| usage(&args[0]));
+
+    let result = match args.get(3) {
+        Some(path) => File::create(path)
+            .map_err(Into::into)
+            .and_then(|file| db.export(file, format)),
+        None => db.export(io::stdout().lock(), format),
+    };
+    match result {
+        Ok(count) => eprintln!("Exported {} orders", count),
+        Err(e) => {
+            eprintln!("Export failed: {} ({:?})", e, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn import(db: &AspirinEatsDb, args: &[String]) {
+    let (format, path) = match (args.get(2), args.get(3)) {
+        (Some(format), Some(path)) => (
+            Format::from_str(format).unwrap_or_else(|_| usage(&args[0])),
+            path,
+        ),
+        _ => usage(&args[0]),
+    };
+    let mut options = ImportOptions::default();
+    for flag in &args[4..] {
+        match flag.as_str() {
+            "--dry-run" => options.dry_run = true,
+            "--remap-ids" => options.ids = IdMode::Remap,
+            _ => usage(&args[0]),
+        }
+    }
+
+    let file = File::open(path).expect("Failed to open import file");
+    match db.import(file, format, &options) {
+        Ok(report) => {
+            println!("{}", report);
+            if !report.errors.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Import failed: {} ({:?})", e, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn backup(args: &[String]) {
+    let dest = args.get(2).unwrap_or_else(|| usage(&args[0]));
+    if let Err(e) = backup::backup(DB_PATH, dest) {
+        eprintln!("Backup failed: {} ({:?})", e, e);
+        std::process::exit(1);
+    }
+    eprintln!("Backed up {} to {}", DB_PATH, dest);
+}
+
+fn list_snapshots() {
+    match Snapshots::new(SNAPSHOT_DIR, SNAPSHOT_RETENTION).list() {
+        Ok(snapshots) => {
+            for (time, path) in snapshots {
+                println!("{}\t{}", time, path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to list snapshots: {} ({:?})", e, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Restore from a backup file, or with `--at <time>` from the newest snapshot taken at or
+/// before that time. The origin must not be running
+fn restore(args: &[String]) {
+    let source = match (args.get(2).map(String::as_str), args.get(3)) {
+        (Some("--at"), Some(time)) => {
+            let time = reports::parse_time(time).unwrap_or_else(|_| usage(&args[0]));
+            match Snapshots::new(SNAPSHOT_DIR, SNAPSHOT_RETENTION).at(time) {
+                Ok(Some(path)) => path,
+                Ok(None) => {
+                    eprintln!("No snapshot taken at or before {}", time);
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Failed to list snapshots: {} ({:?})", e, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        (Some(path), None) if path != "--at" => PathBuf::from(path),
+        _ => usage(&args[0]),
+    };
+
+    match backup::restore(&source, DB_PATH) {
+        Ok(version) => eprintln!(
+            "Restored {} from {} (schema version {}); the previous database was kept as {}.pre-restore",
+            DB_PATH,
+            source.display(),
+            version,
+            DB_PATH
+        ),
+        Err(e) => {
+            eprintln!("Restore failed: {} ({:?})", e, e);
+            std::process::exit(1);
+        }
+    }
 }
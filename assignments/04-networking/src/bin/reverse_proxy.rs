@@ -1,12 +1,59 @@
 use std::env;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use aspirin_eats::log::{LogTarget, Logger};
+use aspirin_eats::proxy::ReverseProxy;
+use aspirin_eats::routing::ProxyConfig;
+
+/// How often the routing configuration file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <proxy-from> <proxy-to>", program);
+    eprintln!("       {} <proxy-from> --config <file>", program);
+    std::process::exit(2);
+}
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
     if args.len() < 3 {
-        eprintln!("Usage: {} <proxy-from> <proxy-to>", args[0]);
-        std::process::exit(2);
+        usage(&args[0]);
     }
 
     let proxy_addr = &args[1];
-    let origin_addr = &args[2];
+    let logger = Logger::new("proxy", LogTarget::from_env()).expect("Failed to open log file");
+    let proxy = match args[2].as_str() {
+        "--config" => {
+            let Some(path) = args.get(3).map(PathBuf::from) else {
+                usage(&args[0]);
+            };
+            let config = ProxyConfig::load(&path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let proxy = ReverseProxy::from_config(config)
+                .expect("Loaded configuration is valid")
+                .with_logger(logger);
+            proxy.watch_config(path.clone(), CONFIG_POLL_INTERVAL);
+            #[cfg(unix)]
+            proxy
+                .reload_on_hangup(path.clone())
+                .expect("Failed to listen for SIGHUP");
+            println!(
+                "Proxying {} with routes from {}",
+                proxy_addr,
+                path.display()
+            );
+            proxy
+        }
+        origin_addr => {
+            println!("Proxying {} -> {}", proxy_addr, origin_addr);
+            ReverseProxy::new(origin_addr).with_logger(logger)
+        }
+    };
+
+    let listener = TcpListener::bind(proxy_addr).expect("Failed to bind proxy address");
+    proxy.serve(listener);
 }
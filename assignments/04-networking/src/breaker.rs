@@ -0,0 +1,221 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+
+/// When a circuit breaker opens, and how it recovers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the circuit
+    pub failure_threshold: u32,
+
+    /// How long the circuit stays open before a trial request is let through. The breaker's
+    /// clock counts whole seconds
+    pub open_for: Duration,
+
+    /// Consecutive successful trial requests that close the circuit again
+    pub success_threshold: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failure_threshold: 5,
+            open_for: Duration::from_secs(10),
+            success_threshold: 1,
+        }
+    }
+}
+
+/// State of a circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through; failures are counted
+    Closed,
+    /// Requests are refused without trying the upstream
+    Open,
+    /// One trial request at a time goes through, to find out whether the upstream is back
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    failures: u32,
+    successes: u32,
+    opened_at: i64,
+    trial_in_flight: bool,
+}
+
+/// Circuit breaker for a single upstream. After enough consecutive failures it opens and
+/// refuses requests straight away, instead of letting every client wait for an upstream
+/// that is down
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    clock: Arc<dyn Clock>,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    /// Create a closed breaker that tells time with the system clock
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Create a closed breaker that tells time with the given clock
+    pub fn with_clock(config: BreakerConfig, clock: Arc<dyn Clock>) -> Self {
+        CircuitBreaker {
+            config,
+            clock,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                failures: 0,
+                successes: 0,
+                opened_at: 0,
+                trial_in_flight: false,
+            }),
+        }
+    }
+
+    pub fn config(&self) -> BreakerConfig {
+        self.config
+    }
+
+    /// The current state. An open circuit whose time is up reports half-open
+    pub fn state(&self) -> CircuitState {
+        let mut inner = self.inner();
+        self.update(&mut inner);
+        inner.state
+    }
+
+    /// Ask to send a request upstream. Refused requests get how long to wait before
+    /// trying again. Every allowed request must be followed by `record`
+    pub fn acquire(&self) -> Result<(), Duration> {
+        let mut inner = self.inner();
+        self.update(&mut inner);
+        match inner.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::HalfOpen if !inner.trial_in_flight => {
+                inner.trial_in_flight = true;
+                Ok(())
+            }
+            CircuitState::HalfOpen => Err(Duration::from_secs(1)),
+            CircuitState::Open => {
+                let open_until = inner.opened_at + self.config.open_for.as_secs() as i64;
+                Err(Duration::from_secs(
+                    (open_until - self.clock.now()).max(1) as u64
+                ))
+            }
+        }
+    }
+
+    /// Record whether an allowed request reached the upstream
+    pub fn record(&self, success: bool) {
+        let mut inner = self.inner();
+        self.update(&mut inner);
+        match (inner.state, success) {
+            (CircuitState::Closed, true) => inner.failures = 0,
+            (CircuitState::Closed, false) => {
+                inner.failures += 1;
+                if inner.failures >= self.config.failure_threshold {
+                    self.open(&mut inner);
+                }
+            }
+            (CircuitState::HalfOpen, true) => {
+                inner.trial_in_flight = false;
+                inner.successes += 1;
+                if inner.successes >= self.config.success_threshold {
+                    inner.state = CircuitState::Closed;
+                    inner.failures = 0;
+                }
+            }
+            (CircuitState::HalfOpen, false) => self.open(&mut inner),
+            // Requests allowed before the circuit opened have nothing left to decide
+            (CircuitState::Open, _) => {}
+        }
+    }
+
+    fn open(&self, inner: &mut Inner) {
+        inner.state = CircuitState::Open;
+        inner.opened_at = self.clock.now();
+        inner.trial_in_flight = false;
+    }
+
+    /// Move an open circuit to half-open once it has been open long enough
+    fn update(&self, inner: &mut Inner) {
+        if inner.state == CircuitState::Open
+            && self.clock.now() - inner.opened_at >= self.config.open_for.as_secs() as i64
+        {
+            inner.state = CircuitState::HalfOpen;
+            inner.successes = 0;
+        }
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("breaker lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn breaker() -> (CircuitBreaker, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(1000));
+        let config = BreakerConfig {
+            failure_threshold: 3,
+            open_for: Duration::from_secs(10),
+            success_threshold: 2,
+        };
+        (CircuitBreaker::with_clock(config, clock.clone()), clock)
+    }
+
+    fn fail(breaker: &CircuitBreaker, times: u32) {
+        for _ in 0..times {
+            breaker.acquire().unwrap();
+            breaker.record(false);
+        }
+    }
+
+    #[test]
+    fn test_breaker_opens_after_consecutive_failures() {
+        let (breaker, clock) = breaker();
+        fail(&breaker, 2);
+        breaker.acquire().unwrap();
+        breaker.record(true);
+        fail(&breaker, 2);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        fail(&breaker, 1);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.acquire(), Err(Duration::from_secs(10)));
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(breaker.acquire(), Err(Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn test_breaker_half_open_trials() {
+        let (breaker, clock) = breaker();
+        fail(&breaker, 3);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // One trial at a time
+        breaker.acquire().unwrap();
+        assert_eq!(breaker.acquire(), Err(Duration::from_secs(1)));
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.acquire().unwrap();
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // A failed trial opens the circuit for another full period
+        fail(&breaker, 3);
+        clock.advance(Duration::from_secs(10));
+        breaker.acquire().unwrap();
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.acquire(), Err(Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,293 @@
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::AspirinEatsError;
+use crate::food::{MenuItem, Order, OrderStatus, WithId};
+
+/// File formats supported for bulk import and export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One JSON `Order` per line
+    JsonLines,
+
+    /// A header row followed by `id,customer,food,status,total,uuid,customer_id,created_at`
+    /// rows. `food` holds the JSON encoding of the menu items. The last three columns may be
+    /// left out
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            _ => Err(AspirinEatsError::InvalidRequest),
+        }
+    }
+}
+
+/// How order IDs in an import file are treated
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IdMode {
+    /// Insert orders with the IDs from the file. Clashes with existing orders are errors
+    #[default]
+    Keep,
+
+    /// Ignore the IDs from the file and let the database assign new ones
+    Remap,
+}
+
+/// Options for `AspirinEatsDb::import`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// Validate and insert everything, then roll the transaction back
+    pub dry_run: bool,
+
+    /// How IDs in the file are treated
+    pub ids: IdMode,
+}
+
+/// Error found on a single line of an import file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineError {
+    /// 1-based line number in the file
+    pub line: u64,
+    pub message: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// `(id in file, id in database)` for every order that was (or in a dry run, would be)
+    /// imported
+    pub imported: Vec<(Option<i64>, i64)>,
+
+    /// Every line that could not be imported. If not empty, nothing was committed
+    pub errors: Vec<LineError>,
+
+    /// Whether the import was written to the database
+    pub committed: bool,
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "line {}: {}", error.line, error.message)?;
+        }
+        write!(
+            f,
+            "{} orders {}, {} errors",
+            self.imported.len(),
+            if self.committed {
+                "imported"
+            } else {
+                "validated (nothing committed)"
+            },
+            self.errors.len()
+        )
+    }
+}
+
+/// Row layout used for CSV files
+#[derive(Serialize, Deserialize)]
+struct CsvOrder {
+    id: Option<i64>,
+    customer: String,
+    food: String,
+    status: OrderStatus,
+    total: f64,
+    #[serde(default)]
+    uuid: Option<Uuid>,
+    #[serde(default)]
+    customer_id: Option<i64>,
+    #[serde(default)]
+    created_at: Option<i64>,
+}
+
+impl From<&Order> for CsvOrder {
+    fn from(order: &Order) -> Self {
+        CsvOrder {
+            id: order.id,
+            customer: order.customer.clone(),
+            food: serde_json::to_string(&order.food).expect("Failed to serialize food"),
+            status: order.status.clone(),
+            total: order.total,
+            uuid: order.uuid,
+            customer_id: order.customer_id,
+            created_at: order.created_at,
+        }
+    }
+}
+
+impl TryFrom<CsvOrder> for Order {
+    type Error = serde_json::Error;
+
+    fn try_from(row: CsvOrder) -> Result<Self, Self::Error> {
+        Ok(Order {
+            id: row.id,
+            uuid: row.uuid,
+            customer: row.customer,
+            customer_id: row.customer_id,
+            created_at: row.created_at,
+            food: serde_json::from_str::<Vec<MenuItem>>(&row.food)?,
+            status: row.status,
+            total: row.total,
+            estimated_ready_at: None,
+        })
+    }
+}
+
+/// Write orders to the writer in the given format
+pub fn write_orders<W: Write>(
+    writer: W,
+    format: Format,
+    orders: &[Order],
+) -> Result<(), AspirinEatsError> {
+    match format {
+        Format::JsonLines => {
+            let mut writer = writer;
+            for order in orders {
+                serde_json::to_writer(&mut writer, &WithId::from(order))?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for order in orders {
+                writer.serialize(CsvOrder::from(order)).map_err(csv_error)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Read every order from the reader in the given format. Each entry is the 1-based line
+/// number of the record and either the parsed order or a description of what was wrong
+pub fn read_orders<R: Read>(reader: R, format: Format) -> Vec<(u64, Result<Order, String>)> {
+    match format {
+        Format::JsonLines => BufReader::new(reader)
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index as u64 + 1, line))
+            .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .map(|(number, line)| {
+                let order = line
+                    .map_err(|e| e.to_string())
+                    .and_then(|line| Order::from_str(&line).map_err(|e| e.to_string()));
+                (number, order)
+            })
+            .collect(),
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let mut orders = Vec::new();
+            let mut record = csv::StringRecord::new();
+            let headers = match reader.headers() {
+                Ok(headers) => headers.clone(),
+                Err(e) => return vec![(1, Err(e.to_string()))],
+            };
+            loop {
+                let line = reader.position().line();
+                match reader.read_record(&mut record) {
+                    Ok(false) => break,
+                    Ok(true) => {
+                        let order = record
+                            .deserialize::<CsvOrder>(Some(&headers))
+                            .map_err(|e| e.to_string())
+                            .and_then(|row| Order::try_from(row).map_err(|e| e.to_string()));
+                        orders.push((line, order));
+                    }
+                    Err(e) => {
+                        orders.push((line, Err(e.to_string())));
+                        if !matches!(e.kind(), csv::ErrorKind::UnequalLengths { .. }) {
+                            break;
+                        }
+                    }
+                }
+            }
+            orders
+        }
+    }
+}
+
+pub(crate) fn csv_error(error: csv::Error) -> AspirinEatsError {
+    match error.into_kind() {
+        csv::ErrorKind::Io(e) => AspirinEatsError::Io(e),
+        _ => AspirinEatsError::InvalidRequest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{Bun, Burger, Patty, Topping};
+
+    fn get_test_orders() -> Vec<Order> {
+        vec![
+            Order {
+                id: Some(3),
+                uuid: Some(Uuid::from_u128(0x6f1c_2d4e_8a3b_4c5d_9e7f_0a1b_2c3d_4e5f)),
+                customer: "Amit, \"the\" customer".to_string(),
+                customer_id: Some(4),
+                created_at: Some(1_709_285_400),
+                food: vec![MenuItem::Burger(Burger::new(
+                    Bun::Plain,
+                    Patty::Veggie,
+                    vec![Topping::Cheese],
+                ))],
+                status: OrderStatus::Completed,
+                total: 7.0,
+                estimated_ready_at: None,
+            },
+            Order {
+                id: Some(7),
+                uuid: None,
+                customer: "Alice".to_string(),
+                customer_id: None,
+                created_at: None,
+                food: vec![MenuItem::Drink],
+                status: OrderStatus::Pending,
+                total: 3.0,
+                estimated_ready_at: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::JsonLines, Format::Csv] {
+            let mut buffer = Vec::new();
+            write_orders(&mut buffer, format, &get_test_orders()).unwrap();
+
+            let orders: Vec<Order> = read_orders(buffer.as_slice(), format)
+                .into_iter()
+                .map(|(_, order)| order.unwrap())
+                .collect();
+            assert_eq!(orders, get_test_orders(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_read_orders_reports_line_numbers() {
+        let jsonl = format!("{}\n\nnot json\n", get_test_orders()[0]);
+        let lines: Vec<(u64, bool)> = read_orders(jsonl.as_bytes(), Format::JsonLines)
+            .into_iter()
+            .map(|(line, order)| (line, order.is_ok()))
+            .collect();
+        assert_eq!(lines, vec![(1, true), (3, false)]);
+
+        let csv = "id,customer,food,status,total\n1,Amit,[],Pending,0\n2,Amit,[],Eaten,0\n";
+        let lines: Vec<(u64, bool)> = read_orders(csv.as_bytes(), Format::Csv)
+            .into_iter()
+            .map(|(line, order)| (line, order.is_ok()))
+            .collect();
+        assert_eq!(lines, vec![(2, true), (3, false)]);
+    }
+}
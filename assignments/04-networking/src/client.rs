@@ -0,0 +1,290 @@
+use std::io::{self, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::dispatch::Courier;
+use crate::error::AspirinEatsError;
+use crate::food::{Customer, CustomerRequest, Order, OrderRequest, OrderStatus};
+use crate::http::{self, Deadline, HttpRequest, HttpResponse, Limits, TimedStream};
+
+/// Default timeout for connecting, reading and writing
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default limit on the size of a response body, in bytes
+const DEFAULT_MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Typed client for the Aspirin Eats API. Keeps one connection open between requests
+pub struct Client {
+    addr: String,
+    connect_timeout: Duration,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_body: usize,
+    connection: Option<BufReader<TimedStream>>,
+}
+
+impl Client {
+    /// Create a new Client for the server at `addr` (`host:port`). No connection is made
+    /// until the first request
+    pub fn new(addr: &str) -> Self {
+        Client {
+            addr: addr.to_string(),
+            connect_timeout: DEFAULT_TIMEOUT,
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            max_body: DEFAULT_MAX_BODY,
+            connection: None,
+        }
+    }
+
+    /// Set how long to wait for a connection to be established
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set how long to wait for each part of a response: its first byte, the rest of its
+    /// head, and its body. `None` waits forever
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Set how long to wait for a request to be sent. `None` waits forever
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Set the largest response body, in bytes, that will be read. Larger responses fail
+    /// with `PayloadTooLarge`
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Place a new order
+    pub fn create_order(&mut self, order: &OrderRequest) -> Result<Order, AspirinEatsError> {
+        let response = self.send("POST", "/orders", Some(&order.to_string()))?;
+        Ok(Order::from_str(response.body())?)
+    }
+
+    /// Get a single order by its public ID
+    pub fn get_order(&mut self, uuid: Uuid) -> Result<Order, AspirinEatsError> {
+        let response = self.send("GET", &format!("/orders/{}", uuid), None)?;
+        Ok(Order::from_str(response.body())?)
+    }
+
+    /// Get every order
+    pub fn list_orders(&mut self) -> Result<Vec<Order>, AspirinEatsError> {
+        let response = self.send("GET", "/orders", None)?;
+        Ok(serde_json::from_str(response.body())?)
+    }
+
+    /// Remove an order by its public ID
+    pub fn delete_order(&mut self, uuid: Uuid) -> Result<(), AspirinEatsError> {
+        self.send("DELETE", &format!("/orders/{}", uuid), None)?;
+        Ok(())
+    }
+
+    /// Change the status of an order, returning the updated order
+    pub fn update_status(
+        &mut self,
+        uuid: Uuid,
+        status: OrderStatus,
+    ) -> Result<Order, AspirinEatsError> {
+        let response = self.send(
+            "PUT",
+            &format!("/orders/{}/status", uuid),
+            Some(&status.to_string()),
+        )?;
+        Ok(Order::from_str(response.body())?)
+    }
+
+    /// Create a customer, or get the existing one with the same name and contact
+    pub fn create_customer(
+        &mut self,
+        customer: &CustomerRequest,
+    ) -> Result<Customer, AspirinEatsError> {
+        let response = self.send("POST", "/customers", Some(&customer.to_string()))?;
+        Ok(Customer::from_str(response.body())?)
+    }
+
+    /// Get every order placed by a customer
+    pub fn customer_orders(&mut self, uuid: Uuid) -> Result<Vec<Order>, AspirinEatsError> {
+        let response = self.send("GET", &format!("/customers/{}/orders", uuid), None)?;
+        Ok(serde_json::from_str(response.body())?)
+    }
+
+    /// Get every courier with the deliveries assigned to it
+    pub fn list_couriers(&mut self) -> Result<Vec<Courier>, AspirinEatsError> {
+        let response = self.send("GET", "/couriers", None)?;
+        Ok(serde_json::from_str(response.body())?)
+    }
+
+    /// Send a request and map any non-2xx response to an error. A kept-alive connection
+    /// that the server has since closed is replaced before non-idempotent requests are sent
+    /// on it. Idempotent requests are retried once on a new connection if the old one fails
+    /// before any of the response arrives; other requests are never sent twice, because the
+    /// server may have acted on them before the connection failed
+    pub fn send(
+        &mut self,
+        method: &str,
+        path: &str,
+        body: Option<&str>,
+    ) -> Result<HttpResponse, AspirinEatsError> {
+        let mut request = HttpRequest {
+            method: Some(method.to_string()),
+            path: Some(path.to_string()),
+            headers: Vec::new(),
+            body: body.map(str::to_string),
+        };
+        request.set_header("Host", &self.addr);
+        request.set_header("Content-Length", &body.map_or(0, str::len).to_string());
+
+        let idempotent = matches!(method, "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS");
+        if !idempotent && self.connection.as_ref().is_some_and(is_closed) {
+            self.connection = None;
+        }
+        let reused = self.connection.is_some();
+        let response = match self.round_trip(&request) {
+            Ok(None) if reused && idempotent => {
+                self.connection = None;
+                self.round_trip(&request)
+            }
+            response => response,
+        };
+        let response = response
+            .and_then(|response| response.ok_or(AspirinEatsError::BadGateway))
+            .inspect_err(|_| self.connection = None)?;
+
+        if response
+            .header("Connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+        {
+            self.connection = None;
+        }
+        check_status(response)
+    }
+
+    /// Send a request over the current connection, opening one if there is none. Returns
+    /// `None` if the request went unanswered on a kept-alive connection: writing it failed,
+    /// or the connection closed before any of the response arrived
+    fn round_trip(
+        &mut self,
+        request: &HttpRequest,
+    ) -> Result<Option<HttpResponse>, AspirinEatsError> {
+        let reused = self.connection.is_some();
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => {
+                let stream = TimedStream::new(self.connect()?);
+                self.connection.insert(BufReader::new(stream))
+            }
+        };
+
+        let stream = connection.get_mut();
+        let written = stream
+            .write_all(request.to_string().as_bytes())
+            .and_then(|()| stream.flush());
+        match written {
+            Ok(()) => {}
+            Err(_) if reused => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        // Deadlines, unlike the socket's read timeout, also stop a server that trickles
+        // its response in
+        let timeout = self.read_timeout.unwrap_or(Duration::MAX);
+        let limits = Limits {
+            read_header_timeout: timeout,
+            read_body_timeout: timeout,
+            max_body: self.max_body,
+            ..Limits::default()
+        };
+        connection.set_deadline(Instant::now().checked_add(timeout));
+        http::read_message_limited(connection, &limits)?
+            .map(|message| HttpResponse::from_str(&message))
+            .transpose()
+    }
+
+    fn connect(&self) -> Result<TcpStream, AspirinEatsError> {
+        let mut last_error = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(self.write_timeout)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error
+            .map(AspirinEatsError::from)
+            .unwrap_or(AspirinEatsError::InvalidRequest))
+    }
+}
+
+/// Whether the server has closed a kept-alive connection, or sent something on it that no
+/// request asked for. Checked without waiting
+fn is_closed(connection: &BufReader<TimedStream>) -> bool {
+    if !connection.buffer().is_empty() {
+        return true;
+    }
+    let stream = connection.get_ref().get_ref();
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let peeked = stream.peek(&mut [0]);
+    let restored = stream.set_nonblocking(false);
+    !matches!(peeked, Err(e) if e.kind() == io::ErrorKind::WouldBlock) || restored.is_err()
+}
+
+/// Turn an error response back into the AspirinEatsError that most likely caused it
+fn check_status(response: HttpResponse) -> Result<HttpResponse, AspirinEatsError> {
+    match response.status_code() {
+        200..=299 => Ok(response),
+        400 => Err(AspirinEatsError::InvalidRequest),
+        404 => Err(AspirinEatsError::NotFound),
+        405 => Err(AspirinEatsError::MethodNotAllowed),
+        408 => Err(AspirinEatsError::RequestTimeout),
+        413 => Err(AspirinEatsError::PayloadTooLarge),
+        431 => Err(AspirinEatsError::HeadersTooLarge),
+        409 => Err(AspirinEatsError::OutOfStock(
+            response
+                .body()
+                .trim_start_matches("Out of stock: ")
+                .split(", ")
+                .map(str::to_string)
+                .collect(),
+        )),
+        502 => Err(AspirinEatsError::BadGateway),
+        503 => Err(AspirinEatsError::ServiceUnavailable),
+        status => Err(AspirinEatsError::UnexpectedStatus(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_status() {
+        assert!(check_status(HttpResponse::new(201, "Created", "")).is_ok());
+        assert!(matches!(
+            check_status(HttpResponse::new(404, "Not Found", "")),
+            Err(AspirinEatsError::NotFound)
+        ));
+        assert!(matches!(
+            check_status(HttpResponse::new(500, "Internal Server Error", "")),
+            Err(AspirinEatsError::UnexpectedStatus(500))
+        ));
+        assert!(matches!(
+            check_status(HttpResponse::new(409, "Conflict", "Out of stock: fries, drink")),
+            Err(AspirinEatsError::OutOfStock(missing)) if missing == ["fries", "drink"]
+        ));
+    }
+}
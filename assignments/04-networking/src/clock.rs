@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch, by the system's wall clock
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Source of the current time for the kitchen and delivery simulation, in seconds since the
+/// Unix epoch
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// The system's wall clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        unix_now()
+    }
+}
+
+/// Clock that only moves when told to. Useful for testing
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicI64,
+}
+
+impl ManualClock {
+    /// Create a clock stopped at `now`
+    pub fn new(now: i64) -> Self {
+        ManualClock {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    /// Move the clock to `now`
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `duration` (rounded down to whole seconds)
+    pub fn advance(&self, duration: Duration) {
+        self.now
+            .fetch_add(duration.as_secs() as i64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::{self, Write};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use crate::http::HttpResponse;
+
+/// Bodies smaller than this are sent as they are; compressing them saves too little to be
+/// worth the time
+pub const MIN_SIZE: usize = 1024;
+
+/// A content coding the server can compress responses with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`
+    Deflate,
+}
+
+impl Encoding {
+    /// The name of the coding in `Accept-Encoding` and `Content-Encoding` headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Compress a whole body
+    pub fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Pick the coding to use for a client's `Accept-Encoding` header: the supported coding
+/// with the highest quality value, preferring gzip on a tie. Codings with `q=0` (directly or
+/// through `*`) are refused. Returns `None` if the body should be sent uncompressed
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut wildcard = None;
+    let mut gzip = None;
+    let mut deflate = None;
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match name.as_str() {
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "deflate" => deflate = Some(quality),
+            "*" => wildcard = Some(quality),
+            _ => {}
+        }
+    }
+
+    let gzip = gzip.or(wildcard).unwrap_or(0.0);
+    let deflate = deflate.or(wildcard).unwrap_or(0.0);
+    if gzip <= 0.0 && deflate <= 0.0 {
+        None
+    } else if gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else {
+        Some(Encoding::Deflate)
+    }
+}
+
+/// Whether a response could be sent compressed to a client that asks for it: it must be
+/// large enough, not already encoded, and of a textual type. Event streams are never
+/// compressed, since every event has to reach the client as soon as it is written
+pub fn is_compressible(response: &HttpResponse, body_length: usize) -> bool {
+    if body_length < MIN_SIZE || response.header("Content-Encoding").is_some() {
+        return false;
+    }
+    let content_type = response
+        .header("Content-Type")
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    (content_type.starts_with("text/") || content_type.contains("json"))
+        && !content_type.starts_with("text/event-stream")
+}
+
+/// Compress a response body for a client that sent `accept_encoding`, updating the headers
+/// to match. Responses that could be compressed always get `Vary: Accept-Encoding`, so
+/// caches keep the compressed and uncompressed versions apart. Returns the body to send
+pub fn encode(
+    response: &mut HttpResponse,
+    body: Vec<u8>,
+    accept_encoding: Option<&str>,
+) -> io::Result<Vec<u8>> {
+    if !is_compressible(response, body.len()) {
+        return Ok(body);
+    }
+    response.add_vary("Accept-Encoding");
+    match accept_encoding.and_then(negotiate) {
+        Some(encoding) => {
+            response.set_header("Content-Encoding", encoding.name());
+            encoding.compress(&body)
+        }
+        None => Ok(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    use super::*;
+
+    fn large_json() -> (HttpResponse, Vec<u8>) {
+        let body = format!("[{}]", vec!["{\"id\":1}"; 200].join(","));
+        let response = HttpResponse::json(200, "OK", &body);
+        (response, body.into_bytes())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("GZIP ; q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("br, identity"), None);
+        assert_eq!(negotiate("gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let (mut response, body) = large_json();
+        let gzipped = encode(&mut response, body.clone(), Some("gzip")).unwrap();
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert!(gzipped.len() < body.len());
+        let mut decoded = Vec::new();
+        GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let (mut response, body) = large_json();
+        let deflated = encode(&mut response, body.clone(), Some("deflate")).unwrap();
+        assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(&deflated[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn test_encode_skips_ineligible_responses() {
+        // Not asked for: sent as it is, but still marked as negotiable
+        let (mut response, body) = large_json();
+        response.set_header("Vary", "Origin");
+        assert_eq!(encode(&mut response, body.clone(), None).unwrap(), body);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Origin, Accept-Encoding"));
+
+        let mut small = HttpResponse::json(200, "OK", "[]");
+        assert_eq!(
+            encode(&mut small, b"[]".to_vec(), Some("gzip")).unwrap(),
+            b"[]"
+        );
+        assert_eq!(small.header("Vary"), None);
+
+        let (response, body) = large_json();
+        let mut encoded = response.with_header("Content-Encoding", "br");
+        assert_eq!(
+            encode(&mut encoded, body.clone(), Some("gzip")).unwrap(),
+            body
+        );
+        assert_eq!(encoded.header("Content-Encoding"), Some("br"));
+
+        let mut stream =
+            HttpResponse::new(200, "OK", "").with_header("Content-Type", "text/event-stream");
+        assert!(!is_compressible(&stream, MIN_SIZE));
+        stream.set_header("Content-Type", "image/png");
+        assert!(!is_compressible(&stream, MIN_SIZE));
+    }
+}
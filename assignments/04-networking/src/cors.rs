@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use crate::error::AspirinEatsError;
+use crate::http::{HttpRequest, HttpResponse};
+use crate::log::REQUEST_ID_HEADER;
+use crate::middleware::Middleware;
+
+/// Methods allowed by default: everything the API serves
+const DEFAULT_METHODS: [&str; 4] = ["GET", "POST", "PUT", "DELETE"];
+
+/// Request headers allowed by default
+const DEFAULT_HEADERS: [&str; 3] = ["Content-Type", "Last-Event-ID", REQUEST_ID_HEADER];
+
+/// Cross-origin resource sharing policy: which web pages, served from other origins, may
+/// call the API from a browser, and how. Preflight requests are answered from the policy
+/// without reaching the API, and allowed origins get `Access-Control-*` headers on every
+/// response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cors {
+    /// Allowed origins, such as `https://order.aspirin-eats.com`. `None` allows any origin
+    origins: Option<Vec<String>>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// A policy that allows no origins yet, with the default methods and headers
+    pub fn new() -> Self {
+        Cors {
+            origins: Some(Vec::new()),
+            methods: DEFAULT_METHODS.iter().map(|m| m.to_string()).collect(),
+            headers: DEFAULT_HEADERS.iter().map(|h| h.to_string()).collect(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allow requests from the given origin
+    pub fn with_origin(mut self, origin: &str) -> Self {
+        if let Some(origins) = &mut self.origins {
+            origins.push(origin.trim_end_matches('/').to_string());
+        }
+        self
+    }
+
+    /// Allow requests from any origin
+    pub fn with_any_origin(mut self) -> Self {
+        self.origins = None;
+        self
+    }
+
+    /// Allow only the given methods, instead of the defaults
+    pub fn with_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods.iter().map(|m| m.to_ascii_uppercase()).collect();
+        self
+    }
+
+    /// Allow only the given request headers, instead of the defaults
+    pub fn with_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|h| h.to_string()).collect();
+        self
+    }
+
+    /// Let browsers send cookies and other credentials with requests. Only named origins
+    /// get credentials: a policy that allows any origin never does
+    pub fn with_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Let browsers cache preflight results for the given time
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Read the policy from the environment. `ASPIRIN_CORS_ORIGINS` is a comma-separated
+    /// list of origins, or `*` for any; without it CORS is disabled. `ASPIRIN_CORS_METHODS`
+    /// and `ASPIRIN_CORS_HEADERS` replace the default lists, `ASPIRIN_CORS_CREDENTIALS=true`
+    /// allows credentials and `ASPIRIN_CORS_MAX_AGE` is in seconds. Credentials with `*` are
+    /// refused, since any web page could then act as a signed-in user
+    pub fn from_env() -> Result<Option<Self>, AspirinEatsError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, AspirinEatsError> {
+        let Some(origins) = var("ASPIRIN_CORS_ORIGINS") else {
+            return Ok(None);
+        };
+        let mut cors = Cors::new();
+        for origin in list(&origins) {
+            cors = match origin {
+                "*" => cors.with_any_origin(),
+                origin => cors.with_origin(origin),
+            };
+        }
+        if let Some(methods) = var("ASPIRIN_CORS_METHODS") {
+            cors = cors.with_methods(&list(&methods));
+        }
+        if let Some(headers) = var("ASPIRIN_CORS_HEADERS") {
+            cors = cors.with_headers(&list(&headers));
+        }
+        if let Some(credentials) = var("ASPIRIN_CORS_CREDENTIALS") {
+            cors = cors.with_credentials(credentials.trim() == "true");
+        }
+        if let Some(seconds) =
+            var("ASPIRIN_CORS_MAX_AGE").and_then(|seconds| seconds.trim().parse().ok())
+        {
+            cors = cors.with_max_age(Duration::from_secs(seconds));
+        }
+        if cors.origins.is_none() && cors.credentials {
+            return Err(AspirinEatsError::InvalidConfig(
+                "ASPIRIN_CORS_CREDENTIALS needs named origins in ASPIRIN_CORS_ORIGINS, not *"
+                    .to_string(),
+            ));
+        }
+        Ok(Some(cors))
+    }
+
+    /// Whether the policy allows requests from the given origin
+    pub(crate) fn allows_origin(&self, origin: &str) -> bool {
+        match &self.origins {
+            Some(origins) => origins.iter().any(|allowed| allowed == origin),
+            None => true,
+        }
+    }
+
+    /// Answer a preflight request (`OPTIONS` with `Origin` and
+    /// `Access-Control-Request-Method`). Preflights for an origin, method or header the
+    /// policy does not allow are refused with 403. Returns `None` for any other request
+    pub fn preflight(&self, request: &HttpRequest) -> Option<HttpResponse> {
+        if !is_preflight(request) {
+            return None;
+        }
+        let origin = request.header("Origin")?;
+        let method = request.header("Access-Control-Request-Method")?;
+
+        let requested_headers = request
+            .header("Access-Control-Request-Headers")
+            .map(list)
+            .unwrap_or_default();
+        let allowed = self.allows_origin(origin)
+            && self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+            && requested_headers
+                .iter()
+                .all(|h| self.headers.iter().any(|a| a.eq_ignore_ascii_case(h)));
+        if !allowed {
+            return Some(HttpResponse::new(
+                403,
+                "Forbidden",
+                "Cross-origin request not allowed",
+            ));
+        }
+
+        let mut response = HttpResponse::new(204, "No Content", "")
+            .with_header("Access-Control-Allow-Methods", &self.methods.join(", "))
+            .with_header("Access-Control-Allow-Headers", &self.headers.join(", "));
+        if let Some(max_age) = self.max_age {
+            response.set_header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+        self.allow_origin(&mut response, origin);
+        response.add_vary("Access-Control-Request-Method");
+        response.add_vary("Access-Control-Request-Headers");
+        Some(response)
+    }
+
+    /// Add the CORS headers to the response of an actual (not preflight) request, if it came
+    /// from an allowed origin
+    pub fn apply(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        if let Some(origin) = request.header("Origin") {
+            if self.allows_origin(origin) {
+                self.allow_origin(response, origin);
+                response.set_header("Access-Control-Expose-Headers", REQUEST_ID_HEADER);
+            }
+        }
+        if self.origins.is_some() {
+            // The answer depends on the origin, even when it is refused
+            response.add_vary("Origin");
+        }
+    }
+
+    fn allow_origin(&self, response: &mut HttpResponse, origin: &str) {
+        // Echoing any origin with credentials would let every web page act as the user, so
+        // the wildcard is answered without them
+        if self.origins.is_none() {
+            response.set_header("Access-Control-Allow-Origin", "*");
+            return;
+        }
+        response.set_header("Access-Control-Allow-Origin", origin);
+        response.add_vary("Origin");
+        if self.credentials {
+            response.set_header("Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+/// Preflights are answered before the handler runs; everything else gets the CORS headers
+/// afterwards
+impl Middleware for Cors {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<HttpResponse>, AspirinEatsError> {
+        Ok(self.preflight(request))
+    }
+
+    fn after(
+        &self,
+        request: &HttpRequest,
+        response: &mut HttpResponse,
+    ) -> Result<(), AspirinEatsError> {
+        if !is_preflight(request) {
+            self.apply(request, response);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+fn is_preflight(request: &HttpRequest) -> bool {
+    request.method.as_deref() == Some("OPTIONS")
+        && request.header("Origin").is_some()
+        && request.header("Access-Control-Request-Method").is_some()
+}
+
+/// Split a comma-separated header or setting into its trimmed, non-empty items
+fn list(value: &str) -> Vec<&str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: &str = "https://order.aspirin-eats.com";
+
+    fn request(s: &str) -> HttpRequest {
+        s.parse().unwrap()
+    }
+
+    fn preflight(origin: &str, method: &str, headers: &str) -> HttpRequest {
+        request(&format!(
+            "OPTIONS /orders HTTP/1.1\r\nOrigin: {}\r\nAccess-Control-Request-Method: {}\r\n\
+             Access-Control-Request-Headers: {}\r\n\r\n",
+            origin, method, headers
+        ))
+    }
+
+    fn policy() -> Cors {
+        Cors::new()
+            .with_origin(ORIGIN)
+            .with_max_age(Duration::from_secs(600))
+    }
+
+    #[test]
+    fn test_preflight() {
+        let response = policy()
+            .preflight(&preflight(ORIGIN, "POST", "content-type"))
+            .unwrap();
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some(ORIGIN));
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, POST, PUT, DELETE")
+        );
+        assert!(response
+            .header("Access-Control-Allow-Headers")
+            .unwrap()
+            .contains("Content-Type"));
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+        assert_eq!(
+            response.header("Vary"),
+            Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers")
+        );
+
+        for refused in [
+            preflight("https://evil.example", "POST", ""),
+            preflight(ORIGIN, "PATCH", ""),
+            preflight(ORIGIN, "POST", "Content-Type, X-Secret"),
+        ] {
+            let response = policy().preflight(&refused).unwrap();
+            assert_eq!(response.status_code(), 403);
+            assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        }
+
+        // Plain OPTIONS requests are left to the API
+        assert!(policy()
+            .preflight(&request("OPTIONS /orders HTTP/1.1\r\n\r\n"))
+            .is_none());
+        assert!(policy()
+            .preflight(&request(&format!(
+                "GET /orders HTTP/1.1\r\nOrigin: {}\r\n\r\n",
+                ORIGIN
+            )))
+            .is_none());
+    }
+
+    #[test]
+    fn test_simple_request() {
+        let cors = policy().with_credentials(true);
+        let mut response = HttpResponse::json(200, "OK", "[]");
+        cors.apply(
+            &request(&format!(
+                "GET /orders HTTP/1.1\r\nOrigin: {}\r\n\r\n",
+                ORIGIN
+            )),
+            &mut response,
+        );
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some(ORIGIN));
+        assert_eq!(
+            response.header("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            response.header("Access-Control-Expose-Headers"),
+            Some(REQUEST_ID_HEADER)
+        );
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        let mut response = HttpResponse::json(200, "OK", "[]");
+        cors.apply(
+            &request("GET /orders HTTP/1.1\r\nOrigin: https://evil.example\r\n\r\n"),
+            &mut response,
+        );
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn test_any_origin() {
+        let cors = Cors::new().with_any_origin();
+        let mut response = HttpResponse::json(200, "OK", "[]");
+        cors.apply(
+            &request("GET / HTTP/1.1\r\nOrigin: https://anywhere.example\r\n\r\n"),
+            &mut response,
+        );
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Vary"), None);
+
+        // Credentials are never offered to any origin
+        let cors = cors.with_credentials(true);
+        let response = cors
+            .preflight(&preflight("https://anywhere.example", "GET", ""))
+            .unwrap();
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Access-Control-Allow-Credentials"), None);
+    }
+
+    #[test]
+    fn test_from_env() {
+        let from = |vars: &[(&str, &str)]| {
+            Cors::from_vars(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+
+        assert_eq!(from(&[]).unwrap(), None);
+        assert_eq!(
+            from(&[
+                ("ASPIRIN_CORS_ORIGINS", "https://order.aspirin-eats.com/, "),
+                ("ASPIRIN_CORS_CREDENTIALS", "true"),
+                ("ASPIRIN_CORS_MAX_AGE", "600"),
+            ])
+            .unwrap(),
+            Some(policy().with_credentials(true))
+        );
+        assert_eq!(
+            from(&[("ASPIRIN_CORS_ORIGINS", "*")]).unwrap(),
+            Some(Cors::new().with_any_origin())
+        );
+
+        assert!(matches!(
+            from(&[
+                ("ASPIRIN_CORS_ORIGINS", "*"),
+                ("ASPIRIN_CORS_CREDENTIALS", "true")
+            ]),
+            Err(AspirinEatsError::InvalidConfig(_))
+        ));
+    }
+}
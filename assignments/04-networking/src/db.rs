@@ -1,12 +1,162 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
+use uuid::Uuid;
 
+use crate::bulk::{self, Format, IdMode, ImportOptions, ImportReport, LineError};
+use crate::clock::{Clock, SystemClock};
+use crate::dispatch::{Courier, DispatchProgress, Dispatcher};
+use crate::error::AspirinEatsError;
+use crate::events::{EventBus, OrderEventKind};
 use crate::food::*;
+use crate::inventory::{self, InventoryItem, StockUpdate, DEFAULT_LOW_STOCK, DEFAULT_STOCK};
+use crate::kitchen::{Kitchen, Progress};
+use crate::metrics::Metrics;
+use crate::reports::{
+    DailySales, HourlyReport, HourlySales, ItemsReport, Range, SalesReport, StatusCount,
+};
+use crate::store::OrderStore;
+use crate::webhooks::{DeadLetter, Delivery, Webhook};
+
+/// Schema changes applied on top of the original `orders` table, in order. The number of
+/// migrations applied to a database is stored in its `user_version`
+const MIGRATIONS: &[&str] = &[
+    // Soft deletes: rows with a deleted_at timestamp are hidden from every normal query
+    "ALTER TABLE orders ADD COLUMN deleted_at INTEGER",
+    // Customer accounts. Orders from before this migration only carry a name, so every
+    // distinct name becomes a customer record without contact details
+    "CREATE TABLE customers (
+        id          INTEGER NOT NULL,
+        name        TEXT NOT NULL,
+        contact     TEXT,
+        PRIMARY KEY(id AUTOINCREMENT)
+    );
+    CREATE UNIQUE INDEX customers_identity ON customers (name, COALESCE(contact, ''));
+    ALTER TABLE orders ADD COLUMN customer_id INTEGER REFERENCES customers(id);
+    INSERT OR IGNORE INTO customers (name) SELECT customer FROM orders ORDER BY id;
+    UPDATE orders SET customer_id =
+        (SELECT id FROM customers WHERE name = orders.customer AND contact IS NULL);
+    CREATE INDEX orders_customer ON orders (customer_id);",
+    // Kitchen estimates, kept up to date while an order is queued or being prepared
+    "ALTER TABLE orders ADD COLUMN estimated_ready_at INTEGER",
+    // Ingredient stock. Rows are added for every ingredient on the menu when the database is
+    // opened
+    "CREATE TABLE inventory (
+        ingredient  TEXT NOT NULL PRIMARY KEY,
+        stock       INTEGER NOT NULL CHECK (stock >= 0),
+        low_stock   INTEGER NOT NULL
+    )",
+    // When each order was placed, for reports. Orders from before this migration have no
+    // creation time and are only counted in reports without a time range
+    "ALTER TABLE orders ADD COLUMN created_at INTEGER;
+    CREATE INDEX orders_created_at ON orders (created_at);",
+    // Public order IDs. Existing orders get a random (version 4) UUID built from SQLite's
+    // randomblob, in the same lowercase hyphenated form the uuid crate writes
+    "ALTER TABLE orders ADD COLUMN uuid TEXT;
+    UPDATE orders SET uuid = lower(
+        hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+        substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random() % 4), 1) ||
+        substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+    );
+    CREATE UNIQUE INDEX orders_uuid ON orders (uuid);",
+    // Webhook subscriptions, and the deliveries to them that were given up on. `events` is a
+    // JSON array of event names
+    "CREATE TABLE webhooks (
+        id          INTEGER NOT NULL,
+        url         TEXT NOT NULL,
+        events      TEXT NOT NULL,
+        secret      TEXT NOT NULL,
+        PRIMARY KEY(id AUTOINCREMENT)
+    );
+    CREATE TABLE webhook_dead_letters (
+        id          INTEGER NOT NULL,
+        webhook_id  INTEGER NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+        delivery_id TEXT NOT NULL,
+        event       TEXT NOT NULL,
+        payload     TEXT NOT NULL,
+        attempts    INTEGER NOT NULL,
+        error       TEXT NOT NULL,
+        failed_at   INTEGER NOT NULL,
+        PRIMARY KEY(id AUTOINCREMENT)
+    );
+    CREATE INDEX webhook_dead_letters_webhook ON webhook_dead_letters (webhook_id);",
+    // Webhook deliveries that have not succeeded yet, so they are still made after a restart.
+    // `due_at` is when the next attempt is due
+    "CREATE TABLE webhook_deliveries (
+        id          INTEGER NOT NULL,
+        webhook_id  INTEGER NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+        delivery_id TEXT NOT NULL,
+        event       TEXT NOT NULL,
+        payload     TEXT NOT NULL,
+        attempts    INTEGER NOT NULL,
+        due_at      INTEGER NOT NULL,
+        PRIMARY KEY(id AUTOINCREMENT)
+    );
+    CREATE INDEX webhook_deliveries_webhook ON webhook_deliveries (webhook_id);",
+    // Public customer IDs, made for existing customers the same way as public order IDs
+    "ALTER TABLE customers ADD COLUMN uuid TEXT;
+    UPDATE customers SET uuid = lower(
+        hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+        substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random() % 4), 1) ||
+        substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))
+    );
+    CREATE UNIQUE INDEX customers_uuid ON customers (uuid);",
+    // The orders and customers each webhook covers, as JSON arrays of public IDs. Webhooks
+    // from before this migration cover nothing until they are added again
+    "ALTER TABLE webhooks ADD COLUMN orders TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE webhooks ADD COLUMN customers TEXT NOT NULL DEFAULT '[]';",
+];
+
+/// Schema version of a fully migrated database, as stored in its `user_version`
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// Condition matching orders that are not removed and were placed within the range given by
+/// parameters `?1` (from, inclusive) and `?2` (to, exclusive)
+const IN_RANGE: &str = "deleted_at IS NULL
+    AND (?1 IS NULL OR created_at >= ?1)
+    AND (?2 IS NULL OR created_at < ?2)";
+
+/// Columns selected for every order query, in the order `order_from_row` expects them
+const ORDER_COLUMNS: &str =
+    "id, customer, customer_id, food, status, total, estimated_ready_at, uuid, created_at";
+
+/// Build an Order from a row selected with `ORDER_COLUMNS`
+fn order_from_row(row: &rusqlite::Row) -> Result<Order> {
+    Ok(Order {
+        id: row.get(0)?,
+        uuid: {
+            let uuid: Option<String> = row.get(7)?;
+            uuid.map(|uuid| Uuid::parse_str(&uuid).expect("db should contain valid uuids"))
+        },
+        customer: row.get(1)?,
+        customer_id: row.get(2)?,
+        created_at: row.get(8)?,
+        food: {
+            let food_str: String = row.get(3)?;
+            serde_json::from_str(&food_str).expect("db should contain valid json")
+        },
+        status: {
+            let status: String = row.get(4)?;
+            OrderStatus::from_str(&status).expect("db should contain valid status")
+        },
+        total: row.get(5)?,
+        estimated_ready_at: row.get(6)?,
+    })
+}
 
 pub struct AspirinEatsDb {
     conn: Connection,
+    events: Arc<EventBus>,
+    metrics: Arc<Metrics>,
+    kitchen: Mutex<Kitchen>,
+    dispatcher: Mutex<Dispatcher>,
+    clock: Arc<dyn Clock>,
+    private_webhooks: bool,
 }
 
 impl AspirinEatsDb {
@@ -18,7 +168,16 @@ impl AspirinEatsDb {
     {
         let db = Self {
             conn: Connection::open(db_path)?,
+            events: Arc::new(EventBus::default()),
+            metrics: Arc::new(Metrics::new("origin")),
+            kitchen: Mutex::new(Kitchen::default()),
+            dispatcher: Mutex::new(Dispatcher::default()),
+            clock: Arc::new(SystemClock),
+            private_webhooks: false,
         };
+        // Write-ahead logging lets backups read the file while orders keep being written
+        db.conn
+            .pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
         db.create_table()?;
         Ok(db)
     }
@@ -27,12 +186,75 @@ impl AspirinEatsDb {
     pub fn in_memory() -> Result<Self> {
         let db = Self {
             conn: Connection::open_in_memory()?,
+            events: Arc::new(EventBus::default()),
+            metrics: Arc::new(Metrics::new("origin")),
+            kitchen: Mutex::new(Kitchen::default()),
+            dispatcher: Mutex::new(Dispatcher::default()),
+            clock: Arc::new(SystemClock),
+            private_webhooks: false,
         };
         db.create_table()?;
         Ok(db)
     }
 
+    /// Let webhooks deliver to loopback, private and link-local addresses, which are refused
+    /// by default. For receivers on the same host or network as the server
+    pub fn with_private_webhooks(mut self, allowed: bool) -> Self {
+        self.private_webhooks = allowed;
+        self
+    }
+
+    /// Whether webhooks may deliver to loopback, private and link-local addresses
+    pub fn private_webhooks(&self) -> bool {
+        self.private_webhooks
+    }
+
+    /// Get a handle to the bus that every write to this database is published on
+    pub fn events(&self) -> Arc<EventBus> {
+        Arc::clone(&self.events)
+    }
+
+    /// Get a handle to the metrics registry that query durations are recorded in
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Prepare orders on the given number of kitchen stations instead of the default
+    pub fn with_kitchen_stations(self, stations: usize) -> Result<Self> {
+        *self.kitchen() = Kitchen::new(stations);
+        self.load_kitchen()?;
+        Ok(self)
+    }
+
+    /// Time orders, removals and kitchen estimates with the given clock instead of the
+    /// system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Result<Self> {
+        self.set_clock(clock)?;
+        Ok(self)
+    }
+
+    /// Switch to the given clock, bringing the kitchen estimates in line with it
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) -> Result<()> {
+        self.clock = clock;
+        self.write_estimates(self.clock.now())
+    }
+
+    /// Deliver orders with the given couriers instead of the default ones
+    pub fn with_couriers(self, couriers: Vec<Courier>) -> Self {
+        *self.dispatcher() = Dispatcher::new(couriers);
+        self
+    }
+
+    fn kitchen(&self) -> std::sync::MutexGuard<'_, Kitchen> {
+        self.kitchen.lock().expect("kitchen lock poisoned")
+    }
+
+    fn dispatcher(&self) -> std::sync::MutexGuard<'_, Dispatcher> {
+        self.dispatcher.lock().expect("dispatcher lock poisoned")
+    }
+
     fn create_table(&self) -> Result<()> {
+        self.conn.pragma_update(None, "foreign_keys", true)?;
         self.conn.execute(
             "CREATE TABLE IF NOT EXISTS orders (
             id	        INTEGER NOT NULL,
@@ -44,105 +266,966 @@ impl AspirinEatsDb {
         )",
             [], // no params for this query
         )?;
+        self.migrate()?;
+        self.seed_inventory()?;
+        self.load_kitchen()
+    }
+
+    /// Add every ingredient on the menu that the inventory does not know about yet
+    fn seed_inventory(&self) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "INSERT OR IGNORE INTO inventory (ingredient, stock, low_stock) VALUES (?1, ?2, ?3)",
+        )?;
+        for ingredient in Ingredient::ALL {
+            stmt.execute((ingredient.name(), DEFAULT_STOCK, DEFAULT_LOW_STOCK))?;
+        }
+        Ok(())
+    }
+
+    /// Apply every migration the database has not seen yet
+    fn migrate(&self) -> Result<()> {
+        let version: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.unchecked_transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+            tx.commit()?;
+        }
         Ok(())
     }
 }
 
 impl AspirinEatsDb {
-    /// Insert a new Order into the database
-    pub fn add_order(&self, order: Order) -> Result<i64> {
+    /// Insert a new Order into the database, taking its ingredients out of the inventory.
+    /// Fails with `OutOfStock` (and changes nothing) if any ingredient is not in stock
+    pub fn add_order(&self, mut order: Order) -> Result<i64, AspirinEatsError> {
+        let _timer = self.metrics.time_db("add_order");
+        order.uuid = Some(Uuid::new_v4());
+        order.created_at = Some(self.clock.now());
+        let tx = self.conn.unchecked_transaction()?;
+        self.reserve_stock(&order.food)?;
+        order.customer_id = Some(self.resolve_customer(&order)?);
+        let id = self.insert_order(&order, None)?;
+        tx.commit()?;
+        order.id = Some(id);
+        self.schedule(&order)?;
+        order.estimated_ready_at = self.estimated_ready_at(id)?;
+        self.events.publish(OrderEventKind::Created, order);
+        Ok(id)
+    }
+
+    /// Get the customer an order belongs to: the customer it names by ID if that customer
+    /// exists under the same name, or is created under that ID because no customer has it
+    /// yet (as for imported orders). Otherwise the customer with that name and no contact
+    /// details, which is created if needed
+    fn resolve_customer(&self, order: &Order) -> Result<i64> {
+        if let Some(id) = order.customer_id {
+            let found = self
+                .conn
+                .prepare("SELECT 1 FROM customers WHERE id = ?1 AND name = ?2")?
+                .exists((id, &order.customer))?;
+            let created = !found
+                && self.conn.execute(
+                    "INSERT OR IGNORE INTO customers (id, name, uuid) VALUES (?1, ?2, ?3)",
+                    (id, &order.customer, Uuid::new_v4().to_string()),
+                )? == 1;
+            if found || created {
+                return Ok(id);
+            }
+        }
+        self.add_customer(&Customer {
+            id: None,
+            uuid: None,
+            name: order.customer.clone(),
+            contact: None,
+        })
+    }
+
+    /// Insert an order row, with an explicit ID or (if `None`) one assigned by the database.
+    /// `order.customer_id` and `order.uuid` must already be filled in. Orders without a
+    /// creation time are taken to be placed now
+    fn insert_order(&self, order: &Order, id: Option<i64>) -> Result<i64> {
         self.conn.execute(
-            "INSERT INTO orders (customer, food, status, total) VALUES (?1, ?2, ?3, ?4)",
-            [
-                order.customer,
+            "INSERT INTO orders
+                (id, customer, customer_id, food, status, total, estimated_ready_at, created_at,
+                uuid)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            (
+                id,
+                &order.customer,
+                order.customer_id,
                 serde_json::to_string(&order.food).expect("Failed to serialize food"),
                 serde_json::to_string(&order.status).expect("Failed to serialize status"),
-                order.total.to_string(),
-            ],
+                order.total,
+                order.estimated_ready_at,
+                order.created_at.unwrap_or_else(|| self.clock.now()),
+                order.uuid.map(|uuid| uuid.to_string()),
+            ),
         )?;
         Ok(self.conn.last_insert_rowid())
     }
 
     /// Get an order by ID from the database
     pub fn get_order(&self, id: i64) -> Result<Option<Order>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT customer, food, status, total FROM orders WHERE id = ?1")?;
+        let _timer = self.metrics.time_db("get_order");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ORDER_COLUMNS} FROM orders WHERE id = ?1 AND deleted_at IS NULL"
+        ))?;
         let mut rows = stmt.query([&id])?;
 
-        if let Some(row) = rows.next()? {
-            Ok(Some(Order {
-                id: Some(id),
-                customer: row.get(0)?,
-                food: {
-                    let food_str: String = row.get(1)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
-                },
-                status: {
-                    let status: String = row.get(2)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
-                },
-                total: row.get(3)?,
-            }))
-        } else {
-            Ok(None)
-        }
+        rows.next()?.map(order_from_row).transpose()
     }
 
-    /// Remove an order by ID from the database
+    /// Remove an order by ID from the database. The row is only marked as deleted, so it can
+    /// be brought back with `restore_order` until it is purged. Removing an order the kitchen
+    /// has not finished puts its ingredients back in the inventory
     pub fn remove_order(&self, id: i64) -> Result<()> {
-        self.conn
-            .execute("DELETE FROM orders WHERE id = ?1", [&id])?;
+        let _timer = self.metrics.time_db("remove_order");
+        let order = self.get_order(id)?;
+        let tx = self.conn.unchecked_transaction()?;
+        self.conn.execute(
+            "UPDATE orders SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            [self.clock.now(), id],
+        )?;
+        if let Some(order) = order.as_ref().filter(|order| in_kitchen(order)) {
+            self.release_stock(&order.food)?;
+        }
+        tx.commit()?;
+        if let Some(order) = order {
+            self.unschedule(id)?;
+            self.events.publish(OrderEventKind::Deleted, order);
+        }
         Ok(())
     }
 
-    /// Remove all orders from the database
+    /// Bring back an order that was removed. Returns the restored order, or `None` if there
+    /// is no removed order with that ID. Restoring an order the kitchen has not finished takes
+    /// its ingredients out of the inventory again, failing with `OutOfStock` (and changing
+    /// nothing) if it cannot
+    pub fn restore_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        let _timer = self.metrics.time_db("restore_order");
+        let tx = self.conn.unchecked_transaction()?;
+        let restored = self.conn.execute(
+            "UPDATE orders SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+            [id],
+        )?;
+        if restored == 0 {
+            return Ok(None);
+        }
+        let order = self.get_order(id)?;
+        if let Some(order) = order.as_ref().filter(|order| in_kitchen(order)) {
+            self.reserve_stock(&order.food)?;
+        }
+        tx.commit()?;
+
+        let order = order.map(|order| self.schedule(&order)).transpose()?;
+        if let Some(order) = &order {
+            self.events.publish(OrderEventKind::Restored, order.clone());
+        }
+        Ok(order)
+    }
+
+    /// Permanently delete every removed order that was removed at least `retention` ago.
+    /// Returns the number of rows deleted
+    pub fn purge_deleted(&self, retention: Duration) -> Result<usize> {
+        let _timer = self.metrics.time_db("purge_deleted");
+        self.conn.execute(
+            "DELETE FROM orders WHERE deleted_at IS NOT NULL AND deleted_at <= ?1",
+            [self.clock.now() - retention.as_secs() as i64],
+        )
+    }
+
+    /// Update the status of an order by ID. Returns the updated order, if it exists.
+    /// Cancelling an order the kitchen has not finished puts its ingredients back in the
+    /// inventory, and sending a cancelled order back to the kitchen takes them out again
+    /// (failing with `OutOfStock` if it cannot)
+    pub fn update_order_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+    ) -> Result<Option<Order>, AspirinEatsError> {
+        let _timer = self.metrics.time_db("update_order_status");
+        let Some(current) = self.get_order(id)? else {
+            return Ok(None);
+        };
+
+        let tx = self.conn.unchecked_transaction()?;
+        self.conn.execute(
+            "UPDATE orders SET status = ?1 WHERE id = ?2",
+            (
+                serde_json::to_string(&status).expect("Failed to serialize status"),
+                id,
+            ),
+        )?;
+        if in_kitchen(&current) && status == OrderStatus::Cancelled {
+            self.release_stock(&current.food)?;
+        } else if current.status == OrderStatus::Cancelled && needs_kitchen(&status) {
+            self.reserve_stock(&current.food)?;
+        }
+        tx.commit()?;
+
+        let order = self
+            .get_order(id)?
+            .map(|order| self.schedule(&order))
+            .transpose()?;
+        if let Some(order) = &order {
+            self.events
+                .publish(OrderEventKind::StatusChanged, order.clone());
+        }
+        Ok(order)
+    }
+
+    /// Remove all orders from the database. Like `remove_order`, every row is only marked as
+    /// deleted, IDs are never reused, and the ingredients of unfinished orders are put back
     pub fn reset_orders(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM orders", [])?;
+        let _timer = self.metrics.time_db("reset_orders");
+        let orders = self.get_all_orders()?;
+        let tx = self.conn.unchecked_transaction()?;
         self.conn.execute(
-            "UPDATE SQLITE_SEQUENCE SET SEQ='0' WHERE NAME='orders';",
-            [],
+            "UPDATE orders SET deleted_at = ?1 WHERE deleted_at IS NULL",
+            [self.clock.now()],
         )?;
+        for order in orders.iter().filter(|order| in_kitchen(order)) {
+            self.release_stock(&order.food)?;
+        }
+        tx.commit()?;
+        let mut kitchen = self.kitchen();
+        *kitchen = Kitchen::new(kitchen.stations());
+        drop(kitchen);
+        for order in orders {
+            self.events.publish(OrderEventKind::Deleted, order);
+        }
         Ok(())
     }
 
+    /// Get the ID of the order with the given public ID, including removed orders
+    pub fn get_order_id(&self, uuid: &Uuid) -> Result<Option<i64>> {
+        let _timer = self.metrics.time_db("get_order_id");
+        self.conn
+            .query_row(
+                "SELECT id FROM orders WHERE uuid = ?1",
+                [uuid.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
     /// Get all orders from the database
     pub fn get_all_orders(&self) -> Result<Vec<Order>> {
+        let _timer = self.metrics.time_db("get_all_orders");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ORDER_COLUMNS} FROM orders WHERE deleted_at IS NULL"
+        ))?;
+
+        let order_iter = stmt.query_map([], order_from_row)?;
+
+        Ok(order_iter.map(Result::unwrap).collect())
+    }
+}
+
+impl OrderStore for AspirinEatsDb {
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        AspirinEatsDb::add_order(self, order)
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_order(self, id)?)
+    }
+
+    fn get_order_id(&self, uuid: &Uuid) -> Result<Option<i64>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_order_id(self, uuid)?)
+    }
+
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(AspirinEatsDb::get_all_orders(self)?)
+    }
+
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        Ok(AspirinEatsDb::remove_order(self, id)?)
+    }
+
+    fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        Ok(AspirinEatsDb::reset_orders(self)?)
+    }
+
+    fn update_order_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+    ) -> Result<Option<Order>, AspirinEatsError> {
+        AspirinEatsDb::update_order_status(self, id, status)
+    }
+
+    fn database(&self) -> Option<&AspirinEatsDb> {
+        Some(self)
+    }
+}
+
+impl AspirinEatsDb {
+    /// Insert a new Customer into the database, giving it a new public ID. Customers are
+    /// deduplicated: if one with the same name and contact already exists, its ID is returned
+    /// instead
+    pub fn add_customer(&self, customer: &Customer) -> Result<i64> {
+        let _timer = self.metrics.time_db("add_customer");
+        self.conn.execute(
+            "INSERT OR IGNORE INTO customers (name, contact, uuid) VALUES (?1, ?2, ?3)",
+            (
+                &customer.name,
+                &customer.contact,
+                Uuid::new_v4().to_string(),
+            ),
+        )?;
+        self.conn.query_row(
+            "SELECT id FROM customers WHERE name = ?1 AND COALESCE(contact, '') = COALESCE(?2, '')",
+            (&customer.name, &customer.contact),
+            |row| row.get(0),
+        )
+    }
+
+    /// Get a customer by ID from the database
+    pub fn get_customer(&self, id: i64) -> Result<Option<Customer>> {
+        let _timer = self.metrics.time_db("get_customer");
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, contact, uuid FROM customers WHERE id = ?1")?;
+        let mut rows = stmt.query([&id])?;
+
+        rows.next()?
+            .map(|row| {
+                Ok(Customer {
+                    id: row.get(0)?,
+                    uuid: {
+                        let uuid: String = row.get(3)?;
+                        Some(Uuid::parse_str(&uuid).expect("db should contain valid uuids"))
+                    },
+                    name: row.get(1)?,
+                    contact: row.get(2)?,
+                })
+            })
+            .transpose()
+    }
+
+    /// Get the ID of the customer with the given public ID
+    pub fn get_customer_id(&self, uuid: &Uuid) -> Result<Option<i64>> {
+        let _timer = self.metrics.time_db("get_customer_id");
+        self.conn
+            .query_row(
+                "SELECT id FROM customers WHERE uuid = ?1",
+                [uuid.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Get every order placed by a customer
+    pub fn get_customer_orders(&self, customer_id: i64) -> Result<Vec<Order>> {
+        let _timer = self.metrics.time_db("get_customer_orders");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ORDER_COLUMNS} FROM orders WHERE customer_id = ?1 AND deleted_at IS NULL"
+        ))?;
+
+        let order_iter = stmt.query_map([customer_id], order_from_row)?;
+
+        Ok(order_iter.map(Result::unwrap).collect())
+    }
+}
+
+impl AspirinEatsDb {
+    /// Move the kitchen queue along to `now` (seconds since the Unix epoch): orders put on a
+    /// station start `Preparing`, finished orders become `Ready`, and every estimate is
+    /// brought up to date. Returns the orders that started and finished
+    pub fn advance_kitchen(&self, now: i64) -> Result<Progress, AspirinEatsError> {
+        let _timer = self.metrics.time_db("advance_kitchen");
+        let progress = self.kitchen().advance(now);
+        self.write_estimates(now)?;
+        for &id in &progress.finished {
+            self.update_order_status(id, OrderStatus::Ready)?;
+        }
+        for &id in &progress.started {
+            self.update_order_status(id, OrderStatus::Preparing)?;
+        }
+        Ok(progress)
+    }
+
+    /// Queue every order that is still waiting on the kitchen, oldest first
+    fn load_kitchen(&self) -> Result<()> {
+        let orders = self.get_all_orders()?;
+        {
+            let mut kitchen = self.kitchen();
+            *kitchen = Kitchen::new(kitchen.stations());
+            for order in orders.iter().filter(|order| in_kitchen(order)) {
+                kitchen.enqueue(order);
+            }
+        }
+        self.write_estimates(self.clock.now())
+    }
+
+    /// Put an order in the kitchen or take it out, depending on its status, and refresh the
+    /// estimates. Returns the order with its new estimate
+    fn schedule(&self, order: &Order) -> Result<Order> {
+        let id = order.id.expect("scheduled orders should have an ID");
+        if in_kitchen(order) {
+            self.kitchen().enqueue(order);
+            self.write_estimates(self.clock.now())?;
+        } else {
+            self.unschedule(id)?;
+        }
+        Ok(Order {
+            estimated_ready_at: self.estimated_ready_at(id)?,
+            ..order.clone()
+        })
+    }
+
+    /// Take an order out of the kitchen and refresh the estimates of the orders behind it
+    fn unschedule(&self, id: i64) -> Result<()> {
+        if self.kitchen().remove(id) {
+            self.write_estimates(self.clock.now())?;
+        }
+        Ok(())
+    }
+
+    fn write_estimates(&self, now: i64) -> Result<()> {
+        let estimates = self.kitchen().estimates(now);
+        let mut stmt = self
+            .conn
+            .prepare("UPDATE orders SET estimated_ready_at = ?1 WHERE id = ?2")?;
+        for (id, ready_at) in estimates {
+            stmt.execute([ready_at, id])?;
+        }
+        Ok(())
+    }
+
+    fn estimated_ready_at(&self, id: i64) -> Result<Option<i64>> {
+        self.conn.query_row(
+            "SELECT estimated_ready_at FROM orders WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        )
+    }
+}
+
+impl AspirinEatsDb {
+    /// Take the ingredients for the food out of the inventory. If any of them are not in
+    /// stock, fails with `OutOfStock` naming them; the caller must then roll back
+    fn reserve_stock(&self, food: &[MenuItem]) -> Result<(), AspirinEatsError> {
+        let mut stmt = self.conn.prepare(
+            "UPDATE inventory SET stock = stock - ?1 WHERE ingredient = ?2 AND stock >= ?1",
+        )?;
+        let mut missing = Vec::new();
+        for (ingredient, count) in inventory::requirements(food) {
+            if stmt.execute((count, ingredient.name()))? == 0 {
+                missing.push(ingredient.name().to_string());
+            }
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(AspirinEatsError::OutOfStock(missing))
+        }
+    }
+
+    /// Put the ingredients for the food back in the inventory
+    fn release_stock(&self, food: &[MenuItem]) -> Result<()> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, customer, food, status, total FROM orders")?;
+            .prepare("UPDATE inventory SET stock = stock + ?1 WHERE ingredient = ?2")?;
+        for (ingredient, count) in inventory::requirements(food) {
+            stmt.execute((count, ingredient.name()))?;
+        }
+        Ok(())
+    }
+
+    /// Get the stock of every ingredient
+    pub fn inventory(&self) -> Result<Vec<InventoryItem>> {
+        let _timer = self.metrics.time_db("inventory");
+        self.query_inventory(
+            "SELECT ingredient, stock, low_stock FROM inventory ORDER BY rowid",
+            [],
+        )
+    }
 
-        let order_iter = stmt.query_map([], |row| {
-            Ok(Order {
+    /// Get every ingredient whose stock is at or below its low stock threshold
+    pub fn low_stock(&self) -> Result<Vec<InventoryItem>> {
+        let _timer = self.metrics.time_db("low_stock");
+        self.query_inventory(
+            "SELECT ingredient, stock, low_stock FROM inventory WHERE stock <= low_stock
+            ORDER BY rowid",
+            [],
+        )
+    }
+
+    /// Set the stock (and optionally the low stock threshold) of an ingredient
+    pub fn set_stock(
+        &self,
+        ingredient: Ingredient,
+        update: &StockUpdate,
+    ) -> Result<InventoryItem, AspirinEatsError> {
+        let _timer = self.metrics.time_db("set_stock");
+        if update.stock < 0 || update.low_stock.is_some_and(|low_stock| low_stock < 0) {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        self.conn.execute(
+            "UPDATE inventory SET stock = ?1, low_stock = COALESCE(?2, low_stock)
+            WHERE ingredient = ?3",
+            (update.stock, update.low_stock, ingredient.name()),
+        )?;
+        let mut items = self.query_inventory(
+            "SELECT ingredient, stock, low_stock FROM inventory WHERE ingredient = ?1",
+            [ingredient.name()],
+        )?;
+        Ok(items.remove(0))
+    }
+
+    fn query_inventory<P: rusqlite::Params>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<Vec<InventoryItem>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let items = stmt.query_map(params, |row| {
+            let stock = row.get(1)?;
+            let low_stock = row.get(2)?;
+            Ok(InventoryItem {
+                ingredient: row.get(0)?,
+                stock,
+                low_stock,
+                low: stock <= low_stock,
+            })
+        })?;
+        items.collect()
+    }
+}
+
+impl AspirinEatsDb {
+    /// Insert a new webhook subscription, which must have a secret. Returns its ID
+    pub fn add_webhook(&self, webhook: &Webhook) -> Result<i64> {
+        let _timer = self.metrics.time_db("add_webhook");
+        self.conn.execute(
+            "INSERT INTO webhooks (url, events, secret, orders, customers)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                &webhook.url,
+                serde_json::to_string(&webhook.events).expect("Failed to serialize events"),
+                webhook.secret.as_deref().expect("webhooks need a secret"),
+                serde_json::to_string(&webhook.orders).expect("Failed to serialize orders"),
+                serde_json::to_string(&webhook.customers).expect("Failed to serialize customers"),
+            ),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Get every webhook subscription, including its secret
+    pub fn get_webhooks(&self) -> Result<Vec<Webhook>> {
+        let _timer = self.metrics.time_db("get_webhooks");
+        let mut stmt = self.conn.prepare(
+            "SELECT id, url, events, secret, orders, customers FROM webhooks ORDER BY id",
+        )?;
+        let webhooks = stmt.query_map([], |row| {
+            Ok(Webhook {
                 id: row.get(0)?,
-                customer: row.get(1)?,
-                food: {
-                    let food_str: String = row.get(2)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
+                url: row.get(1)?,
+                events: {
+                    let events: String = row.get(2)?;
+                    serde_json::from_str(&events).expect("db should contain valid json")
+                },
+                orders: {
+                    let orders: String = row.get(4)?;
+                    serde_json::from_str(&orders).expect("db should contain valid json")
                 },
-                status: {
-                    let status: String = row.get(3)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
+                customers: {
+                    let customers: String = row.get(5)?;
+                    serde_json::from_str(&customers).expect("db should contain valid json")
                 },
-                total: row.get(4)?,
+                secret: row.get(3)?,
             })
         })?;
+        webhooks.collect()
+    }
 
-        Ok(order_iter.map(Result::unwrap).collect())
+    /// Remove a webhook subscription along with its pending deliveries and dead letters.
+    /// Returns whether it existed
+    pub fn remove_webhook(&self, id: i64) -> Result<bool> {
+        let _timer = self.metrics.time_db("remove_webhook");
+        Ok(self
+            .conn
+            .execute("DELETE FROM webhooks WHERE id = ?1", [id])?
+            > 0)
+    }
+
+    /// Record a delivery that has not been made yet. Returns its ID
+    pub fn add_delivery(&self, delivery: &Delivery) -> Result<i64> {
+        let _timer = self.metrics.time_db("add_delivery");
+        self.conn.execute(
+            "INSERT INTO webhook_deliveries
+                (webhook_id, delivery_id, event, payload, attempts, due_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                delivery.webhook_id,
+                &delivery.delivery_id,
+                &delivery.event,
+                &delivery.payload,
+                delivery.attempts,
+                delivery.due_at,
+            ),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Save the attempts made so far on a pending delivery, and when the next one is due
+    pub fn update_delivery(&self, id: i64, attempts: u32, due_at: i64) -> Result<()> {
+        let _timer = self.metrics.time_db("update_delivery");
+        self.conn.execute(
+            "UPDATE webhook_deliveries SET attempts = ?1, due_at = ?2 WHERE id = ?3",
+            (attempts, due_at, id),
+        )?;
+        Ok(())
+    }
+
+    /// Forget a pending delivery once it has been made. With a dead letter, the delivery is
+    /// moved to the dead letters instead, in one transaction
+    pub fn remove_delivery(&self, id: i64, letter: Option<&DeadLetter>) -> Result<()> {
+        let _timer = self.metrics.time_db("remove_delivery");
+        let tx = self.conn.unchecked_transaction()?;
+        self.conn
+            .execute("DELETE FROM webhook_deliveries WHERE id = ?1", [id])?;
+        if let Some(letter) = letter {
+            self.add_dead_letter(letter)?;
+        }
+        tx.commit()
+    }
+
+    /// Get every pending delivery, in the order they fall due
+    pub fn deliveries(&self) -> Result<Vec<Delivery>> {
+        let _timer = self.metrics.time_db("deliveries");
+        let mut stmt = self.conn.prepare(
+            "SELECT id, webhook_id, delivery_id, event, payload, attempts, due_at
+            FROM webhook_deliveries ORDER BY due_at, id",
+        )?;
+        let deliveries = stmt.query_map([], |row| {
+            Ok(Delivery {
+                id: row.get(0)?,
+                webhook_id: row.get(1)?,
+                delivery_id: row.get(2)?,
+                event: row.get(3)?,
+                payload: row.get(4)?,
+                attempts: row.get(5)?,
+                due_at: row.get(6)?,
+            })
+        })?;
+        deliveries.collect()
+    }
+
+    /// Record a delivery that was given up on. Returns its ID
+    pub fn add_dead_letter(&self, letter: &DeadLetter) -> Result<i64> {
+        let _timer = self.metrics.time_db("add_dead_letter");
+        self.conn.execute(
+            "INSERT INTO webhook_dead_letters
+                (webhook_id, delivery_id, event, payload, attempts, error, failed_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                letter.webhook_id,
+                &letter.delivery_id,
+                &letter.event,
+                &letter.payload,
+                letter.attempts,
+                &letter.error,
+                letter.failed_at,
+            ),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Get every delivery that was given up on, oldest first
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        let _timer = self.metrics.time_db("dead_letters");
+        let mut stmt = self.conn.prepare(
+            "SELECT id, webhook_id, delivery_id, event, payload, attempts, error, failed_at
+            FROM webhook_dead_letters ORDER BY id",
+        )?;
+        let letters = stmt.query_map([], |row| {
+            Ok(DeadLetter {
+                id: row.get(0)?,
+                webhook_id: row.get(1)?,
+                delivery_id: row.get(2)?,
+                event: row.get(3)?,
+                payload: row.get(4)?,
+                attempts: row.get(5)?,
+                error: row.get(6)?,
+                failed_at: row.get(7)?,
+            })
+        })?;
+        letters.collect()
+    }
+}
+
+impl AspirinEatsDb {
+    /// Every courier with its current assignments
+    pub fn couriers(&self) -> Vec<Courier> {
+        self.dispatcher().couriers().to_vec()
+    }
+
+    /// Move deliveries along to `now` (seconds since the Unix epoch): delivered orders become
+    /// `Completed`, and `Ready` orders are handed to couriers and start `Transporting`.
+    /// Orders that were transporting without a courier (e.g. after a restart) are handed out
+    /// again, and orders that no longer need delivering are taken away from their couriers
+    pub fn dispatch_couriers(&self, now: i64) -> Result<DispatchProgress, AspirinEatsError> {
+        let _timer = self.metrics.time_db("dispatch_couriers");
+        let mut progress = DispatchProgress::default();
+
+        let delivered = self.dispatcher().deliver(now);
+        for id in delivered {
+            let order = self.get_order(id)?;
+            if order.is_some_and(|order| order.status == OrderStatus::Transporting) {
+                self.update_order_status(id, OrderStatus::Completed)?;
+                progress.delivered.push(id);
+            }
+        }
+
+        let orders = self.get_all_orders()?;
+        let transporting: HashSet<i64> = orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Transporting)
+            .filter_map(|order| order.id)
+            .collect();
+        self.dispatcher()
+            .retain(|order_id| transporting.contains(&order_id));
+
+        for order in orders {
+            let id = order.id.expect("db orders should have an ID");
+            let needs_courier = match order.status {
+                OrderStatus::Ready => true,
+                OrderStatus::Transporting => !self.dispatcher().is_assigned(id),
+                _ => false,
+            };
+            if !needs_courier {
+                continue;
+            }
+            let Some(courier) = self.dispatcher().assign(id, now) else {
+                break;
+            };
+            progress.assigned.push((id, courier));
+            if order.status == OrderStatus::Ready {
+                self.update_order_status(id, OrderStatus::Transporting)?;
+            }
+        }
+        Ok(progress)
+    }
+}
+
+/// Whether an order still needs the kitchen
+fn in_kitchen(order: &Order) -> bool {
+    needs_kitchen(&order.status)
+}
+
+/// Whether an order with this status still needs the kitchen
+fn needs_kitchen(status: &OrderStatus) -> bool {
+    matches!(status, OrderStatus::Pending | OrderStatus::Preparing)
+}
+
+impl AspirinEatsDb {
+    /// Revenue, average order value, orders per status and daily totals for the orders
+    /// placed in the range
+    pub fn sales_report(&self, range: Range) -> Result<SalesReport> {
+        let _timer = self.metrics.time_db("sales_report");
+        let cancelled =
+            serde_json::to_string(&OrderStatus::Cancelled).expect("Failed to serialize status");
+        let params = (range.from, range.to, &cancelled);
+
+        let (orders, revenue): (i64, f64) = self.conn.query_row(
+            &format!(
+                "SELECT COUNT(*), COALESCE(SUM(total), 0) FROM orders
+                WHERE {IN_RANGE} AND status != ?3"
+            ),
+            params,
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT status, COUNT(*) FROM orders WHERE {IN_RANGE}
+            GROUP BY status ORDER BY COUNT(*) DESC, status"
+        ))?;
+        let by_status = stmt
+            .query_map((range.from, range.to), |row| {
+                let status: String = row.get(0)?;
+                Ok(StatusCount {
+                    status: OrderStatus::from_str(&status).expect("db should contain valid status"),
+                    orders: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT date(created_at, 'unixepoch'), COUNT(*), SUM(total) FROM orders
+            WHERE {IN_RANGE} AND status != ?3 AND created_at IS NOT NULL
+            GROUP BY 1 ORDER BY 1"
+        ))?;
+        let daily = stmt
+            .query_map(params, |row| {
+                Ok(DailySales {
+                    date: row.get(0)?,
+                    orders: row.get(1)?,
+                    revenue: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+
+        Ok(SalesReport {
+            range,
+            orders,
+            revenue,
+            average_order_value: if orders == 0 {
+                0.0
+            } else {
+                revenue / orders as f64
+            },
+            by_status,
+            daily,
+        })
+    }
+
+    /// How often each menu item and topping was ordered in the range
+    pub fn items_report(&self, range: Range) -> Result<ItemsReport> {
+        let _timer = self.metrics.time_db("items_report");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {ORDER_COLUMNS} FROM orders WHERE {IN_RANGE}"
+        ))?;
+        let orders = stmt
+            .query_map((range.from, range.to), order_from_row)?
+            .collect::<Result<Vec<Order>>>()?;
+        Ok(ItemsReport::from_orders(range, &orders))
+    }
+
+    /// Orders and revenue per hour of the day (UTC) in the range
+    pub fn hourly_report(&self, range: Range) -> Result<HourlyReport> {
+        let _timer = self.metrics.time_db("hourly_report");
+        let cancelled =
+            serde_json::to_string(&OrderStatus::Cancelled).expect("Failed to serialize status");
+        let mut hours: Vec<HourlySales> = (0..24)
+            .map(|hour| HourlySales {
+                hour,
+                orders: 0,
+                revenue: 0.0,
+            })
+            .collect();
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT CAST(strftime('%H', created_at, 'unixepoch') AS INTEGER), COUNT(*), SUM(total)
+            FROM orders WHERE {IN_RANGE} AND status != ?3 AND created_at IS NOT NULL
+            GROUP BY 1"
+        ))?;
+        let mut rows = stmt.query((range.from, range.to, &cancelled))?;
+        while let Some(row) = rows.next()? {
+            let hour: usize = row.get(0)?;
+            hours[hour].orders = row.get(1)?;
+            hours[hour].revenue = row.get(2)?;
+        }
+
+        let busiest = hours
+            .iter()
+            .filter(|hour| hour.orders > 0)
+            .max_by(|a, b| a.orders.cmp(&b.orders).then(b.hour.cmp(&a.hour)))
+            .map(|hour| hour.hour);
+        Ok(HourlyReport {
+            range,
+            hours,
+            busiest,
+        })
+    }
+}
+
+impl AspirinEatsDb {
+    /// Write every order to the writer in the given format. Returns the number of orders
+    pub fn export<W: Write>(&self, writer: W, format: Format) -> Result<usize, AspirinEatsError> {
+        let _timer = self.metrics.time_db("export");
+        let orders = self.get_all_orders()?;
+        bulk::write_orders(writer, format, &orders)?;
+        Ok(orders.len())
+    }
+
+    /// Import orders from the reader in a single transaction. If any line fails to parse or
+    /// insert, or `options.dry_run` is set, the transaction is rolled back and nothing changes.
+    /// Orders the kitchen has not finished take their ingredients out of the inventory, so a
+    /// line fails with `OutOfStock` if they are not there. Orders keep their creation time and
+    /// their customer's ID, and customers missing here are created under that ID from their
+    /// name alone, as files carry no contact details
+    pub fn import<R: Read>(
+        &self,
+        reader: R,
+        format: Format,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, AspirinEatsError> {
+        let _timer = self.metrics.time_db("import");
+        let tx = self.conn.unchecked_transaction()?;
+        let mut report = ImportReport::default();
+        let mut inserted = Vec::new();
+
+        for (line, order) in bulk::read_orders(reader, format) {
+            let result = order.and_then(|mut order| {
+                let id = match options.ids {
+                    IdMode::Keep => order.id,
+                    IdMode::Remap => None,
+                };
+                if id.is_none() || order.uuid.is_none() {
+                    order.uuid = Some(Uuid::new_v4());
+                }
+                let mut imported = || -> Result<i64, AspirinEatsError> {
+                    order.customer_id = Some(self.resolve_customer(&order)?);
+                    let new_id = self.insert_order(&order, id)?;
+                    // Orders still in the kitchen hold their ingredients, like new ones
+                    if in_kitchen(&order) {
+                        self.reserve_stock(&order.food)?;
+                    }
+                    Ok(new_id)
+                };
+                imported()
+                    .map(|new_id| (order, new_id))
+                    .map_err(|e| e.to_string())
+            });
+            match result {
+                Ok((order, new_id)) => {
+                    report.imported.push((order.id, new_id));
+                    inserted.push(Order {
+                        id: Some(new_id),
+                        ..order
+                    });
+                }
+                Err(message) => report.errors.push(LineError { line, message }),
+            }
+        }
+
+        if options.dry_run || !report.errors.is_empty() {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+            report.committed = true;
+            self.load_kitchen()?;
+            for order in inserted {
+                self.events.publish(OrderEventKind::Created, order);
+            }
+        }
+        Ok(report)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::{self, ManualClock};
+    use crate::dispatch::Location;
 
     fn get_test_order() -> Order {
         Order {
             id: None,
+            uuid: None,
             customer: "Amit".to_string(),
+            customer_id: None,
+            created_at: None,
             food: vec![MenuItem::Fries, MenuItem::Drink],
             status: OrderStatus::Pending,
             total: 8.0,
+            estimated_ready_at: None,
         }
     }
 
@@ -152,8 +1235,15 @@ mod tests {
         let mut order = get_test_order();
 
         order.id = Some(db.add_order(order.clone()).unwrap());
+        order.customer_id = Some(1);
 
         let got = db.get_order(order.id.unwrap()).unwrap().unwrap();
+        assert!(got.estimated_ready_at.is_some());
+        assert!(got.uuid.is_some());
+        assert!(got.created_at.is_some());
+        order.estimated_ready_at = got.estimated_ready_at;
+        order.uuid = got.uuid;
+        order.created_at = got.created_at;
         assert_eq!(got, order);
     }
 
@@ -165,8 +1255,16 @@ mod tests {
 
         order1.id = Some(db.add_order(order1.clone()).unwrap());
         order2.id = Some(db.add_order(order2.clone()).unwrap());
+        order1.customer_id = Some(1);
+        order2.customer_id = Some(1);
 
         let got = db.get_all_orders().unwrap();
+        order1.estimated_ready_at = got[0].estimated_ready_at;
+        order2.estimated_ready_at = got[1].estimated_ready_at;
+        order1.uuid = got[0].uuid;
+        order2.uuid = got[1].uuid;
+        order1.created_at = got[0].created_at;
+        order2.created_at = got[1].created_at;
         assert_eq!(got, vec![order1, order2]);
     }
 
@@ -193,4 +1291,666 @@ mod tests {
         let orders = db.get_all_orders().unwrap();
         assert_eq!(orders.len(), 0);
     }
+
+    #[test]
+    fn test_restore_order() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let mut order = get_test_order();
+        order.id = Some(db.add_order(order.clone()).unwrap());
+        order.customer_id = Some(1);
+
+        assert_eq!(db.restore_order(1).unwrap(), None);
+        db.reset_orders().unwrap();
+        assert_eq!(db.get_order(1).unwrap(), None);
+
+        let restored = db.restore_order(1).unwrap().unwrap();
+        assert!(restored.estimated_ready_at.is_some());
+        order.estimated_ready_at = restored.estimated_ready_at;
+        order.uuid = restored.uuid;
+        order.created_at = restored.created_at;
+        assert_eq!(restored, order);
+        assert_eq!(db.get_all_orders().unwrap(), vec![order]);
+        // IDs of removed orders are never handed out again
+        assert_eq!(db.add_order(get_test_order()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_purge_deleted() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let id = db.add_order(get_test_order()).unwrap();
+        db.remove_order(id).unwrap();
+
+        assert_eq!(db.purge_deleted(Duration::from_secs(3600)).unwrap(), 0);
+        assert_eq!(db.purge_deleted(Duration::ZERO).unwrap(), 1);
+        assert_eq!(db.restore_order(id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_update_order_status() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let id = db.add_order(get_test_order()).unwrap();
+
+        let got = db
+            .update_order_status(id, OrderStatus::Preparing)
+            .unwrap()
+            .unwrap();
+        assert_eq!(got.status, OrderStatus::Preparing);
+        assert_eq!(
+            db.update_order_status(id + 1, OrderStatus::Preparing)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_export_import() {
+        let source = AspirinEatsDb::in_memory().unwrap();
+        for _ in 0..3 {
+            source.add_order(get_test_order()).unwrap();
+        }
+        source.remove_order(2).unwrap();
+        let mut exported = Vec::new();
+        source.export(&mut exported, Format::Csv).unwrap();
+
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let options = ImportOptions {
+            dry_run: true,
+            ids: IdMode::Keep,
+        };
+        let report = db
+            .import(exported.as_slice(), Format::Csv, &options)
+            .unwrap();
+        assert_eq!(report.imported, vec![(Some(1), 1), (Some(3), 3)]);
+        assert!(!report.committed);
+        assert!(db.get_all_orders().unwrap().is_empty());
+
+        let options = ImportOptions {
+            dry_run: false,
+            ids: IdMode::Keep,
+        };
+        let report = db
+            .import(exported.as_slice(), Format::Csv, &options)
+            .unwrap();
+        assert!(report.committed);
+        assert_eq!(
+            db.get_all_orders().unwrap(),
+            source.get_all_orders().unwrap()
+        );
+
+        // Importing the same IDs again clashes, so nothing is committed
+        let report = db
+            .import(exported.as_slice(), Format::Csv, &options)
+            .unwrap();
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.errors[0].line, 2);
+        assert!(!report.committed);
+
+        let options = ImportOptions {
+            dry_run: false,
+            ids: IdMode::Remap,
+        };
+        let report = db
+            .import(exported.as_slice(), Format::Csv, &options)
+            .unwrap();
+        assert_eq!(report.imported, vec![(Some(1), 4), (Some(3), 5)]);
+        assert_eq!(db.get_all_orders().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_customers() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let customer = Customer {
+            id: None,
+            uuid: None,
+            name: "Amit".to_string(),
+            contact: Some("amit@example.com".to_string()),
+        };
+        let id = db.add_customer(&customer).unwrap();
+        assert_eq!(db.add_customer(&customer).unwrap(), id);
+        let got = db.get_customer(id).unwrap().unwrap();
+        let uuid = got.uuid.unwrap();
+        assert_eq!(
+            got,
+            Customer {
+                id: Some(id),
+                uuid: Some(uuid),
+                ..customer
+            }
+        );
+        assert_eq!(db.get_customer_id(&uuid).unwrap(), Some(id));
+        assert_eq!(db.get_customer_id(&Uuid::new_v4()).unwrap(), None);
+
+        // A second Amit without contact details is a different customer
+        let order_id = db.add_order(get_test_order()).unwrap();
+        let other = db
+            .get_order(order_id)
+            .unwrap()
+            .unwrap()
+            .customer_id
+            .unwrap();
+        assert_ne!(other, id);
+
+        let mut order = get_test_order();
+        order.customer_id = Some(id);
+        let mine = db.add_order(order).unwrap();
+        let orders = db.get_customer_orders(id).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].id, Some(mine));
+        assert_eq!(db.get_customer_orders(other).unwrap().len(), 1);
+        assert_eq!(db.get_customer(id + other).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_name_only_orders() {
+        let path = std::env::temp_dir().join(format!("aspirin-{}.db", uuid::Uuid::new_v4()));
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE orders (
+                    id INTEGER NOT NULL, customer TEXT NOT NULL, food TEXT NOT NULL,
+                    status TEXT NOT NULL, total REAL NOT NULL, PRIMARY KEY(id AUTOINCREMENT)
+                );
+                ALTER TABLE orders ADD COLUMN deleted_at INTEGER;
+                PRAGMA user_version = 1;
+                INSERT INTO orders (customer, food, status, total) VALUES
+                    ('Amit', '[]', '\"Pending\"', 0),
+                    ('Alice', '[]', '\"Pending\"', 0),
+                    ('Amit', '[]', '\"Pending\"', 0);",
+            )
+            .unwrap();
+        }
+
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        let ids: Vec<Option<i64>> = db
+            .get_all_orders()
+            .unwrap()
+            .iter()
+            .map(|order| order.customer_id)
+            .collect();
+        assert_eq!(ids, vec![Some(1), Some(2), Some(1)]);
+        assert_eq!(db.get_customer(2).unwrap().unwrap().name, "Alice");
+        assert_eq!(db.get_customer_orders(1).unwrap().len(), 2);
+
+        // Every order is given its own random public ID
+        let uuids: HashSet<Uuid> = db
+            .get_all_orders()
+            .unwrap()
+            .iter()
+            .map(|order| order.uuid.unwrap())
+            .collect();
+        assert_eq!(uuids.len(), 3);
+        for uuid in &uuids {
+            assert_eq!(uuid.get_version_num(), 4);
+            assert_eq!(uuid.get_variant(), uuid::Variant::RFC4122);
+            assert!(db.get_order_id(uuid).unwrap().is_some());
+        }
+
+        // And so is every customer
+        let alice = db.get_customer(2).unwrap().unwrap().uuid.unwrap();
+        assert_eq!(alice.get_version_num(), 4);
+        assert_eq!(db.get_customer_id(&alice).unwrap(), Some(2));
+        assert_ne!(db.get_customer(1).unwrap().unwrap().uuid, Some(alice));
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_kitchen_queue() {
+        let now = 1_000_000;
+        let db = AspirinEatsDb::in_memory()
+            .unwrap()
+            .with_clock(Arc::new(ManualClock::new(now)))
+            .unwrap()
+            .with_kitchen_stations(1)
+            .unwrap();
+        let first = db.add_order(get_test_order()).unwrap();
+        let second = db.add_order(get_test_order()).unwrap();
+        // Estimates are made on the database's clock as soon as the order is queued
+        assert_eq!(
+            db.get_order(second).unwrap().unwrap().estimated_ready_at,
+            Some(now + 420)
+        );
+
+        let progress = db.advance_kitchen(now).unwrap();
+        assert_eq!(progress.started, vec![first]);
+        let order = db.get_order(first).unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Preparing);
+        assert_eq!(order.estimated_ready_at, Some(now + 210));
+        let order = db.get_order(second).unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert_eq!(order.estimated_ready_at, Some(now + 420));
+
+        // Cancelling the first order moves the second one up
+        db.update_order_status(first, OrderStatus::Cancelled)
+            .unwrap();
+        db.advance_kitchen(now).unwrap();
+        let order = db.get_order(second).unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Preparing);
+        assert_eq!(order.estimated_ready_at, Some(now + 210));
+
+        let progress = db.advance_kitchen(now + 210).unwrap();
+        assert_eq!(progress.finished, vec![second]);
+        let order = db.get_order(second).unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Ready);
+        assert_eq!(order.estimated_ready_at, Some(now + 210));
+    }
+
+    #[test]
+    fn test_dispatch_couriers() {
+        let courier = Courier::new(1, "Ada", Location::RESTAURANT, 1);
+        let db = AspirinEatsDb::in_memory()
+            .unwrap()
+            .with_couriers(vec![courier]);
+        let first = db.add_order(get_test_order()).unwrap();
+        let second = db.add_order(get_test_order()).unwrap();
+        let cancelled = db.add_order(get_test_order()).unwrap();
+        for id in [first, second, cancelled] {
+            db.update_order_status(id, OrderStatus::Ready).unwrap();
+        }
+        let now = clock::unix_now();
+
+        let progress = db.dispatch_couriers(now).unwrap();
+        assert_eq!(progress.assigned, vec![(first, 1)]);
+        assert_eq!(
+            db.get_order(first).unwrap().unwrap().status,
+            OrderStatus::Transporting
+        );
+        assert_eq!(
+            db.get_order(second).unwrap().unwrap().status,
+            OrderStatus::Ready
+        );
+
+        let delivered_at = db.couriers()[0].assignments[0].delivered_at;
+        assert_eq!(
+            db.dispatch_couriers(delivered_at - 1).unwrap(),
+            DispatchProgress::default()
+        );
+        let progress = db.dispatch_couriers(delivered_at).unwrap();
+        assert_eq!(progress.delivered, vec![first]);
+        assert_eq!(progress.assigned, vec![(second, 1)]);
+        assert_eq!(
+            db.get_order(first).unwrap().unwrap().status,
+            OrderStatus::Completed
+        );
+
+        // Cancelled orders are taken away from their courier
+        db.update_order_status(second, OrderStatus::Cancelled)
+            .unwrap();
+        let progress = db.dispatch_couriers(delivered_at).unwrap();
+        assert_eq!(progress.assigned, vec![(cancelled, 1)]);
+        let assignments = &db.couriers()[0].assignments;
+        assert_eq!(assignments.len(), 1);
+        assert_eq!(assignments[0].order_id, cancelled);
+    }
+
+    #[test]
+    fn test_export_import_keeps_reports() {
+        let clock = Arc::new(ManualClock::new(1_709_285_400));
+        let source = AspirinEatsDb::in_memory()
+            .unwrap()
+            .with_clock(clock.clone())
+            .unwrap();
+        let customer = source
+            .add_customer(&Customer {
+                id: None,
+                uuid: None,
+                name: "Amit".to_string(),
+                contact: Some("amit@example.com".to_string()),
+            })
+            .unwrap();
+        for hours in [0, 9, 30] {
+            clock.set(1_709_285_400 + hours * 3600);
+            let mut order = get_test_order();
+            order.customer_id = Some(customer);
+            source.add_order(order).unwrap();
+            let mut order = get_test_order();
+            order.customer = "Alice".to_string();
+            source.add_order(order).unwrap();
+        }
+        source
+            .update_order_status(2, OrderStatus::Cancelled)
+            .unwrap();
+        let reports = |db: &AspirinEatsDb| {
+            let range = Range::parse(Some("2024-03-01"), Some("2024-03-02")).unwrap();
+            (
+                db.sales_report(Range::default()).unwrap(),
+                db.sales_report(range).unwrap(),
+                db.hourly_report(Range::default()).unwrap(),
+                db.items_report(range).unwrap(),
+            )
+        };
+
+        for format in [Format::JsonLines, Format::Csv] {
+            let mut exported = Vec::new();
+            source.export(&mut exported, format).unwrap();
+            // Imported long after the orders were placed
+            let db = AspirinEatsDb::in_memory()
+                .unwrap()
+                .with_clock(Arc::new(ManualClock::new(1_800_000_000)))
+                .unwrap();
+            let report = db
+                .import(exported.as_slice(), format, &ImportOptions::default())
+                .unwrap();
+            assert!(report.committed, "{report}");
+
+            assert_eq!(reports(&db), reports(&source));
+            let orders = db.get_customer_orders(customer).unwrap();
+            assert_eq!(orders.len(), 3);
+            assert_eq!(db.get_customer(customer).unwrap().unwrap().name, "Amit");
+        }
+    }
+
+    #[test]
+    fn test_reports_filter_by_time() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        for _ in 0..4 {
+            db.add_order(get_test_order()).unwrap();
+        }
+        db.update_order_status(4, OrderStatus::Cancelled).unwrap();
+        // 2024-03-01 09:30, 2024-03-01 18:00, 2024-03-02 18:15 and (cancelled) 2024-03-02 18:20
+        for (id, created_at) in [
+            (1, 1_709_285_400),
+            (2, 1_709_316_000),
+            (3, 1_709_403_300),
+            (4, 1_709_403_600),
+        ] {
+            db.conn
+                .execute(
+                    "UPDATE orders SET created_at = ?2 WHERE id = ?1",
+                    (id, created_at),
+                )
+                .unwrap();
+        }
+
+        let report = db.sales_report(Range::default()).unwrap();
+        assert_eq!((report.orders, report.revenue), (3, 24.0));
+        assert_eq!(report.average_order_value, 8.0);
+        assert_eq!(
+            report.daily,
+            vec![
+                DailySales {
+                    date: "2024-03-01".to_string(),
+                    orders: 2,
+                    revenue: 16.0
+                },
+                DailySales {
+                    date: "2024-03-02".to_string(),
+                    orders: 1,
+                    revenue: 8.0
+                },
+            ]
+        );
+
+        let range = Range::parse(Some("2024-03-02"), None).unwrap();
+        let report = db.sales_report(range).unwrap();
+        assert_eq!(report.orders, 1);
+        assert_eq!(report.by_status.len(), 2);
+
+        let report = db.hourly_report(Range::default()).unwrap();
+        assert_eq!(report.busiest, Some(18));
+        assert_eq!((report.hours[18].orders, report.hours[9].orders), (2, 1));
+
+        let range = Range::parse(None, Some("2024-03-02")).unwrap();
+        let report = db.items_report(range).unwrap();
+        assert_eq!(report.items[0].count, 2);
+    }
+
+    #[test]
+    fn test_inventory() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let veggie = Ingredient::Patty(Patty::Veggie);
+        let stock = |ingredient: Ingredient| {
+            db.inventory()
+                .unwrap()
+                .into_iter()
+                .find(|item| item.ingredient == ingredient.name())
+                .unwrap()
+                .stock
+        };
+        db.set_stock(
+            veggie,
+            &StockUpdate {
+                stock: 0,
+                low_stock: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(db.low_stock().unwrap()[0].ingredient, "veggie-patty");
+
+        let mut order = get_test_order();
+        order.food.push(MenuItem::Burger(Burger::new(
+            Bun::Plain,
+            Patty::Veggie,
+            vec![],
+        )));
+        let error = db.add_order(order).unwrap_err();
+        assert!(matches!(
+            &error,
+            AspirinEatsError::OutOfStock(missing) if missing == &vec!["veggie-patty".to_string()]
+        ));
+        // Nothing was taken for the rejected order
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK);
+        assert!(db.get_all_orders().unwrap().is_empty());
+
+        let id = db.add_order(get_test_order()).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK - 1);
+        assert_eq!(stock(Ingredient::Drink), DEFAULT_STOCK - 1);
+        db.update_order_status(id, OrderStatus::Cancelled).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK);
+        db.update_order_status(id, OrderStatus::Cancelled).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK);
+        db.update_order_status(id, OrderStatus::Pending).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK - 1);
+
+        // Removing an unfinished order puts its ingredients back, and restoring it takes them
+        // out again
+        db.remove_order(id).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK);
+        db.remove_order(id).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK);
+        db.restore_order(id).unwrap().unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK - 1);
+
+        // An order cannot be restored once its ingredients have run out
+        db.remove_order(id).unwrap();
+        db.set_stock(
+            Ingredient::Fries,
+            &StockUpdate {
+                stock: 0,
+                low_stock: None,
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            db.restore_order(id),
+            Err(AspirinEatsError::OutOfStock(missing)) if missing == vec!["fries".to_string()]
+        ));
+        assert!(db.get_order(id).unwrap().is_none());
+        assert_eq!(stock(Ingredient::Drink), DEFAULT_STOCK);
+        db.set_stock(
+            Ingredient::Fries,
+            &StockUpdate {
+                stock: DEFAULT_STOCK,
+                low_stock: None,
+            },
+        )
+        .unwrap();
+
+        // Finished orders have used their ingredients, so removing them gives nothing back
+        db.restore_order(id).unwrap().unwrap();
+        db.update_order_status(id, OrderStatus::Ready).unwrap();
+        db.remove_order(id).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK - 1);
+
+        // Resetting puts back the ingredients of every unfinished order
+        db.add_order(get_test_order()).unwrap();
+        db.add_order(get_test_order()).unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK - 3);
+        db.reset_orders().unwrap();
+        assert_eq!(stock(Ingredient::Fries), DEFAULT_STOCK - 1);
+    }
+
+    #[test]
+    fn test_only_unfinished_orders_hold_stock() {
+        let fries = |db: &AspirinEatsDb| {
+            db.inventory()
+                .unwrap()
+                .into_iter()
+                .find(|item| item.ingredient == Ingredient::Fries.name())
+                .unwrap()
+                .stock
+        };
+
+        // A cooked order has used its ingredients, so cancelling it gives nothing back, and
+        // bringing it back does not take them again
+        let source = AspirinEatsDb::in_memory().unwrap();
+        let ready = source.add_order(get_test_order()).unwrap();
+        source
+            .update_order_status(ready, OrderStatus::Ready)
+            .unwrap();
+        source
+            .update_order_status(ready, OrderStatus::Cancelled)
+            .unwrap();
+        assert_eq!(fries(&source), DEFAULT_STOCK - 1);
+        source
+            .update_order_status(ready, OrderStatus::Ready)
+            .unwrap();
+        assert_eq!(fries(&source), DEFAULT_STOCK - 1);
+
+        // Imported orders still in the kitchen take their ingredients, so cancelling them
+        // later only gives back what they took
+        source.add_order(get_test_order()).unwrap();
+        let mut exported = Vec::new();
+        source.export(&mut exported, Format::JsonLines).unwrap();
+        let options = ImportOptions {
+            dry_run: false,
+            ids: IdMode::Keep,
+        };
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let report = db
+            .import(exported.as_slice(), Format::JsonLines, &options)
+            .unwrap();
+        assert!(report.committed);
+        assert_eq!(fries(&db), DEFAULT_STOCK - 1);
+        db.update_order_status(2, OrderStatus::Cancelled).unwrap();
+        assert_eq!(fries(&db), DEFAULT_STOCK);
+
+        // Nor can they be imported without the ingredients
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.set_stock(
+            Ingredient::Fries,
+            &StockUpdate {
+                stock: 0,
+                low_stock: None,
+            },
+        )
+        .unwrap();
+        let report = db
+            .import(exported.as_slice(), Format::JsonLines, &options)
+            .unwrap();
+        assert!(!report.committed);
+        assert_eq!(report.errors[0].line, 2);
+        assert!(report.errors[0].message.contains("fries"));
+    }
+
+    #[test]
+    fn test_webhooks() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let webhook = Webhook {
+            id: None,
+            url: "http://partner.example/hooks".to_string(),
+            events: vec!["created".to_string()],
+            orders: vec![Uuid::from_u128(1)],
+            customers: vec![Uuid::from_u128(2), Uuid::from_u128(3)],
+            secret: Some("s3cret".to_string()),
+        };
+        let id = db.add_webhook(&webhook).unwrap();
+        assert_eq!(
+            db.get_webhooks().unwrap(),
+            vec![Webhook {
+                id: Some(id),
+                ..webhook
+            }]
+        );
+
+        let letter = DeadLetter {
+            id: None,
+            webhook_id: id,
+            delivery_id: "d1".to_string(),
+            event: "created".to_string(),
+            payload: "{}".to_string(),
+            attempts: 8,
+            error: "Unexpected response status 500".to_string(),
+            failed_at: 1_700_000_000,
+        };
+        let letter_id = db.add_dead_letter(&letter).unwrap();
+        assert_eq!(
+            db.dead_letters().unwrap(),
+            vec![DeadLetter {
+                id: Some(letter_id),
+                ..letter.clone()
+            }]
+        );
+
+        let delivery = Delivery {
+            id: None,
+            webhook_id: id,
+            delivery_id: "d2".to_string(),
+            event: "created".to_string(),
+            payload: "{}".to_string(),
+            attempts: 0,
+            due_at: 1_700_000_000,
+        };
+        let first = db.add_delivery(&delivery).unwrap();
+        let second = db.add_delivery(&delivery).unwrap();
+        db.update_delivery(first, 2, 1_700_000_060).unwrap();
+        assert_eq!(
+            db.deliveries().unwrap(),
+            vec![
+                Delivery {
+                    id: Some(second),
+                    ..delivery.clone()
+                },
+                Delivery {
+                    id: Some(first),
+                    attempts: 2,
+                    due_at: 1_700_000_060,
+                    ..delivery.clone()
+                },
+            ]
+        );
+        db.remove_delivery(second, None).unwrap();
+        db.remove_delivery(first, Some(&letter)).unwrap();
+        assert_eq!(db.dead_letters().unwrap().len(), 2);
+        db.add_delivery(&delivery).unwrap();
+
+        // Deliveries and dead letters go with their webhook, and need one to belong to
+        assert!(db.remove_webhook(id).unwrap());
+        assert!(!db.remove_webhook(id).unwrap());
+        assert!(db.get_webhooks().unwrap().is_empty());
+        assert!(db.deliveries().unwrap().is_empty());
+        assert!(db.dead_letters().unwrap().is_empty());
+        assert!(db.add_dead_letter(&letter).is_err());
+        assert!(db.add_delivery(&delivery).is_err());
+    }
+
+    #[test]
+    fn test_writes_publish_events() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let subscription = db.events().subscribe(None);
+
+        let id = db.add_order(get_test_order()).unwrap();
+        db.update_order_status(id, OrderStatus::Completed).unwrap();
+        db.remove_order(id).unwrap();
+
+        let kinds: Vec<OrderEventKind> = subscription.receiver.try_iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                OrderEventKind::Created,
+                OrderEventKind::StatusChanged,
+                OrderEventKind::Deleted
+            ]
+        );
+    }
 }
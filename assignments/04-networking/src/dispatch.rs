@@ -0,0 +1,243 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How fast couriers travel, in kilometres per hour
+const SPEED_KMH: f64 = 20.0;
+
+/// Furthest a simulated delivery address can be from the restaurant, in kilometres
+const MAX_DELIVERY_KM: f64 = 5.0;
+
+/// A point on the delivery map, in kilometres east and north of the restaurant
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Copy, Default)]
+pub struct Location {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Location {
+    /// Where the restaurant is. Every order is picked up here
+    pub const RESTAURANT: Location = Location { x: 0.0, y: 0.0 };
+
+    /// Straight line distance to another location, in kilometres
+    pub fn distance(&self, other: &Location) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+
+    /// Seconds it takes a courier to travel to another location, rounded up
+    fn travel_time(&self, other: &Location) -> i64 {
+        (self.distance(other) / SPEED_KMH * 3600.0).ceil() as i64
+    }
+
+    /// Simulated delivery address of an order. Deterministic, so that the same order always
+    /// goes to the same place
+    pub fn of_order(order_id: i64) -> Location {
+        let mut state = order_id as u64;
+        let mut next = || {
+            // SplitMix64, mapped to [-1, 1]
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            (z >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+        };
+        Location {
+            x: next() * MAX_DELIVERY_KM,
+            y: next() * MAX_DELIVERY_KM,
+        }
+    }
+}
+
+/// An order a courier has been given
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Assignment {
+    pub order_id: i64,
+
+    /// Where the order is going
+    pub destination: Location,
+
+    /// When the order will be handed over, in seconds since the Unix epoch
+    pub delivered_at: i64,
+}
+
+/// Struct that represents a courier and the deliveries it is working through
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Courier {
+    pub id: u32,
+    pub name: String,
+
+    /// Where the courier is, or will be once its current assignments are delivered
+    pub location: Location,
+
+    /// Most assignments the courier takes on at once
+    pub capacity: usize,
+
+    /// Deliveries in the order they will be made
+    pub assignments: Vec<Assignment>,
+}
+
+impl Courier {
+    /// Create an idle courier
+    pub fn new(id: u32, name: &str, location: Location, capacity: usize) -> Self {
+        Courier {
+            id,
+            name: name.to_string(),
+            location,
+            capacity,
+            assignments: Vec::new(),
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        self.assignments.len() < self.capacity
+    }
+
+    /// When the courier is done with everything it has been assigned
+    fn available_at(&self, now: i64) -> i64 {
+        self.assignments
+            .last()
+            .map_or(now, |assignment| assignment.delivered_at.max(now))
+    }
+}
+
+/// Orders that moved through delivery in a call to `AspirinEatsDb::dispatch_couriers`
+#[derive(Debug, Default, PartialEq)]
+pub struct DispatchProgress {
+    /// `(order id, courier id)` for every order handed to a courier
+    pub assigned: Vec<(i64, u32)>,
+
+    /// Orders that were delivered
+    pub delivered: Vec<i64>,
+}
+
+/// Couriers the origin starts with
+pub fn default_couriers() -> Vec<Courier> {
+    vec![
+        Courier::new(1, "Ada", Location { x: 0.5, y: 0.5 }, 2),
+        Courier::new(2, "Brian", Location { x: -2.0, y: 1.0 }, 2),
+        Courier::new(3, "Chen", Location { x: 1.5, y: -3.0 }, 1),
+    ]
+}
+
+/// Assigns ready orders to couriers and tracks their deliveries. Every method takes the
+/// current time, so a run is fully determined by the times it is driven with
+#[derive(Debug)]
+pub struct Dispatcher {
+    couriers: Vec<Courier>,
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Dispatcher::new(default_couriers())
+    }
+}
+
+impl Dispatcher {
+    pub fn new(couriers: Vec<Courier>) -> Self {
+        Dispatcher { couriers }
+    }
+
+    /// Every courier with its current assignments
+    pub fn couriers(&self) -> &[Courier] {
+        &self.couriers
+    }
+
+    /// Whether the order has been given to a courier and not yet delivered
+    pub fn is_assigned(&self, order_id: i64) -> bool {
+        self.couriers
+            .iter()
+            .flat_map(|courier| &courier.assignments)
+            .any(|assignment| assignment.order_id == order_id)
+    }
+
+    /// Give an order that is ready at the restaurant to the free courier that can get there
+    /// soonest. The courier picks it up once it is done with its earlier assignments.
+    /// Returns the courier's ID, or `None` if every courier is at capacity
+    pub fn assign(&mut self, order_id: i64, now: i64) -> Option<u32> {
+        let courier = self
+            .couriers
+            .iter_mut()
+            .filter(|courier| courier.is_free())
+            .min_by_key(|courier| {
+                courier.available_at(now) + courier.location.travel_time(&Location::RESTAURANT)
+            })?;
+
+        let destination = Location::of_order(order_id);
+        let picked_up_at =
+            courier.available_at(now) + courier.location.travel_time(&Location::RESTAURANT);
+        courier.assignments.push(Assignment {
+            order_id,
+            destination,
+            delivered_at: picked_up_at + Location::RESTAURANT.travel_time(&destination),
+        });
+        courier.location = destination;
+        Some(courier.id)
+    }
+
+    /// Drop every assignment delivered by `now`, returning their order IDs
+    pub fn deliver(&mut self, now: i64) -> Vec<i64> {
+        let mut delivered = Vec::new();
+        for courier in &mut self.couriers {
+            courier.assignments.retain(|assignment| {
+                let done = assignment.delivered_at <= now;
+                if done {
+                    delivered.push(assignment.order_id);
+                }
+                !done
+            });
+        }
+        delivered.sort_unstable();
+        delivered
+    }
+
+    /// Take away every assignment whose order no longer needs delivering, e.g. because it
+    /// was cancelled. Couriers still end up where those deliveries would have taken them
+    pub fn retain(&mut self, keep: impl Fn(i64) -> bool) {
+        for courier in &mut self.couriers {
+            courier
+                .assignments
+                .retain(|assignment| keep(assignment.order_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_locations_are_deterministic() {
+        let location = Location::of_order(7);
+        assert_eq!(location, Location::of_order(7));
+        assert_ne!(location, Location::of_order(8));
+        for id in 0..100 {
+            let location = Location::of_order(id);
+            assert!(location.x.abs() <= MAX_DELIVERY_KM && location.y.abs() <= MAX_DELIVERY_KM);
+        }
+    }
+
+    #[test]
+    fn test_nearest_free_courier_is_assigned() {
+        let mut dispatcher = Dispatcher::new(vec![
+            Courier::new(1, "Far", Location { x: 10.0, y: 0.0 }, 1),
+            Courier::new(2, "Near", Location { x: 1.0, y: 0.0 }, 1),
+        ]);
+
+        assert_eq!(dispatcher.assign(1, 0), Some(2));
+        let assignment = dispatcher.couriers()[1].assignments[0].clone();
+        // 1km to the restaurant takes 3 minutes at 20km/h
+        let expected = 180 + Location::RESTAURANT.travel_time(&assignment.destination);
+        assert_eq!(assignment.delivered_at, expected);
+
+        assert_eq!(dispatcher.assign(2, 0), Some(1));
+        assert_eq!(dispatcher.assign(3, 0), None);
+        assert!(dispatcher.is_assigned(1));
+
+        assert_eq!(dispatcher.deliver(expected - 1), Vec::<i64>::new());
+        assert_eq!(dispatcher.deliver(expected), vec![1]);
+        assert!(!dispatcher.is_assigned(1));
+        dispatcher.retain(|order_id| order_id != 2);
+        assert!(!dispatcher.is_assigned(2));
+        assert!(dispatcher.assign(3, expected).is_some());
+    }
+}
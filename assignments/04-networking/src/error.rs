@@ -25,4 +25,49 @@ pub enum AspirinEatsError {
     /// Error when request is for an HTTP method not supported on that path
     #[error("Method not allowed")]
     MethodNotAllowed,
+
+    /// Error when a client takes too long to send its request
+    #[error("Request timeout")]
+    RequestTimeout,
+
+    /// Error when a request body is larger than the server accepts
+    #[error("Payload too large")]
+    PayloadTooLarge,
+
+    /// Error when a request line or its headers are larger than the server accepts
+    #[error("Request header fields too large")]
+    HeadersTooLarge,
+
+    /// Error when an order needs ingredients that are not in stock. Holds their names
+    #[error("Out of stock: {}", .0.join(", "))]
+    OutOfStock(Vec<String>),
+
+    /// Error when the proxy cannot get a valid response from the origin
+    #[error("Bad gateway")]
+    BadGateway,
+
+    /// Error when the proxy turns a request away because its route is over its rate limit
+    #[error("Too many requests")]
+    TooManyRequests,
+
+    /// Error when the proxy will not try the origin because its circuit breaker is open
+    #[error("Service unavailable")]
+    ServiceUnavailable,
+
+    /// Error when a database backup cannot be restored. Holds the reason
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+
+    /// Error when a proxy or server configuration cannot be used. Holds the reason
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// Error when a server answers with a status code the client does not expect
+    #[error("Unexpected response status {0}")]
+    UnexpectedStatus(u16),
+
+    /// Error when a WebSocket peer breaks the protocol. Holds the code to close the
+    /// connection with
+    #[error("WebSocket protocol error {0}")]
+    WebSocket(u16),
 }
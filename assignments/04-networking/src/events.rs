@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+use crate::food::Order;
+
+/// Number of past events kept around so that reconnecting clients can resume
+const DEFAULT_HISTORY: usize = 1024;
+
+/// Kind of change that happened to an order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderEventKind {
+    Created,
+    StatusChanged,
+    Deleted,
+    Restored,
+}
+
+impl OrderEventKind {
+    /// Every kind of change, in the order they are listed in documentation
+    pub const ALL: [OrderEventKind; 4] = [
+        OrderEventKind::Created,
+        OrderEventKind::StatusChanged,
+        OrderEventKind::Deleted,
+        OrderEventKind::Restored,
+    ];
+
+    /// Look up a kind by the name it is sent under
+    pub fn from_name(name: &str) -> Option<Self> {
+        OrderEventKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+
+    /// Name used for the event on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            OrderEventKind::Created => "created",
+            OrderEventKind::StatusChanged => "status_changed",
+            OrderEventKind::Deleted => "deleted",
+            OrderEventKind::Restored => "restored",
+        }
+    }
+}
+
+/// Struct that represents a single change to an order
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderEvent {
+    /// Sequence number of the event. Strictly increasing, starting at 1
+    pub id: u64,
+
+    /// What happened to the order
+    pub kind: OrderEventKind,
+
+    /// The order after the change (or right before it was deleted)
+    pub order: Order,
+}
+
+/// Handle returned by `EventBus::subscribe`
+pub struct Subscription {
+    /// Events that were already published after the requested resume point
+    pub backlog: Vec<OrderEvent>,
+
+    /// Receiver for all events published from now on
+    pub receiver: Receiver<OrderEvent>,
+}
+
+/// Handle returned by `EventBus::subscribe_async`, for subscribers running on tokio
+#[cfg(feature = "async")]
+pub struct AsyncSubscription {
+    /// Events that were already published after the requested resume point
+    pub backlog: Vec<OrderEvent>,
+
+    /// Receiver for all events published from now on
+    pub receiver: tokio::sync::mpsc::UnboundedReceiver<OrderEvent>,
+}
+
+/// Where a subscriber's events are sent
+enum Subscriber {
+    Blocking(Sender<OrderEvent>),
+    #[cfg(feature = "async")]
+    Async(tokio::sync::mpsc::UnboundedSender<OrderEvent>),
+    Forward(Box<dyn Fn(&OrderEvent) -> bool + Send>),
+}
+
+impl Subscriber {
+    /// Send an event, returning whether the subscriber is still there
+    fn send(&self, event: &OrderEvent) -> bool {
+        match self {
+            Subscriber::Blocking(sender) => sender.send(event.clone()).is_ok(),
+            #[cfg(feature = "async")]
+            Subscriber::Async(sender) => sender.send(event.clone()).is_ok(),
+            Subscriber::Forward(forward) => forward(event),
+        }
+    }
+}
+
+struct Inner {
+    next_id: u64,
+    history: VecDeque<OrderEvent>,
+    subscribers: Vec<Subscriber>,
+}
+
+/// In-process publish/subscribe bus for order changes
+pub struct EventBus {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(DEFAULT_HISTORY)
+    }
+}
+
+impl EventBus {
+    /// Create a new EventBus that remembers the last `capacity` events
+    pub fn new(capacity: usize) -> Self {
+        EventBus {
+            capacity,
+            inner: Mutex::new(Inner {
+                next_id: 1,
+                history: VecDeque::with_capacity(capacity),
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    /// Publish an event to every subscriber, dropping subscribers that have gone away
+    pub fn publish(&self, kind: OrderEventKind, order: Order) -> OrderEvent {
+        let mut inner = self.inner.lock().expect("event bus lock poisoned");
+        let event = OrderEvent {
+            id: inner.next_id,
+            kind,
+            order,
+        };
+        inner.next_id += 1;
+
+        if self.capacity > 0 {
+            if inner.history.len() == self.capacity {
+                inner.history.pop_front();
+            }
+            inner.history.push_back(event.clone());
+        }
+        inner
+            .subscribers
+            .retain(|subscriber| subscriber.send(&event));
+
+        event
+    }
+
+    /// Subscribe to future events. If `last_event_id` is given, every remembered event
+    /// published after it is returned in the backlog
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        let backlog = self.register(Subscriber::Blocking(sender), last_event_id);
+        Subscription { backlog, receiver }
+    }
+
+    /// Subscribe to future events from async code, which can await them without holding a
+    /// thread. Otherwise the same as `subscribe`
+    #[cfg(feature = "async")]
+    pub fn subscribe_async(&self, last_event_id: Option<u64>) -> AsyncSubscription {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let backlog = self.register(Subscriber::Async(sender), last_event_id);
+        AsyncSubscription { backlog, receiver }
+    }
+
+    /// Subscribe with a function that is handed every future event, and returns whether it
+    /// still wants them. It runs while the bus is locked, so it should only pass the event
+    /// on, such as into a channel the subscriber already reads from. Otherwise the same as
+    /// `subscribe`
+    pub fn subscribe_with<F>(&self, last_event_id: Option<u64>, forward: F) -> Vec<OrderEvent>
+    where
+        F: Fn(&OrderEvent) -> bool + Send + 'static,
+    {
+        self.register(Subscriber::Forward(Box::new(forward)), last_event_id)
+    }
+
+    /// Add a subscriber and get the backlog it asked for, under the same lock so that no
+    /// event falls between the two
+    fn register(&self, subscriber: Subscriber, last_event_id: Option<u64>) -> Vec<OrderEvent> {
+        let mut inner = self.inner.lock().expect("event bus lock poisoned");
+        inner.subscribers.push(subscriber);
+
+        match last_event_id {
+            Some(last_id) => inner
+                .history
+                .iter()
+                .filter(|event| event.id > last_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::*;
+
+    fn get_test_order() -> Order {
+        Order {
+            id: Some(1),
+            uuid: None,
+            customer: "Amit".to_string(),
+            customer_id: None,
+            created_at: None,
+            food: vec![MenuItem::Fries],
+            status: OrderStatus::Pending,
+            total: 5.0,
+            estimated_ready_at: None,
+        }
+    }
+
+    #[test]
+    fn test_publish_reaches_subscribers() {
+        let bus = EventBus::default();
+        let subscription = bus.subscribe(None);
+
+        bus.publish(OrderEventKind::Created, get_test_order());
+        let event = subscription.receiver.try_recv().unwrap();
+        assert_eq!(event.id, 1);
+        assert_eq!(event.kind, OrderEventKind::Created);
+        assert!(subscription.backlog.is_empty());
+    }
+
+    #[test]
+    fn test_subscribe_resumes_after_last_event_id() {
+        let bus = EventBus::new(2);
+        for _ in 0..3 {
+            bus.publish(OrderEventKind::StatusChanged, get_test_order());
+        }
+
+        let ids: Vec<u64> = bus
+            .subscribe(Some(1))
+            .backlog
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+
+        // Event 1 has fallen out of the history, so only what is remembered comes back
+        let ids: Vec<u64> = bus
+            .subscribe(Some(0))
+            .backlog
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_forwarding_subscribers() {
+        let bus = EventBus::default();
+        let (sender, receiver) = mpsc::channel();
+        bus.subscribe_with(None, move |event| sender.send(event.id).is_ok());
+
+        bus.publish(OrderEventKind::Created, get_test_order());
+        assert_eq!(receiver.try_recv(), Ok(1));
+
+        // Subscribers that no longer want events are dropped at the next publish
+        drop(receiver);
+        bus.publish(OrderEventKind::Deleted, get_test_order());
+        assert!(bus.inner.lock().unwrap().subscribers.is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_subscribers() {
+        let bus = EventBus::default();
+        bus.publish(OrderEventKind::Created, get_test_order());
+        let mut subscription = bus.subscribe_async(Some(0));
+        assert_eq!(subscription.backlog.len(), 1);
+
+        bus.publish(OrderEventKind::StatusChanged, get_test_order());
+        let event = subscription.receiver.try_recv().unwrap();
+        assert_eq!(event.id, 2);
+
+        // Subscribers that have gone away are dropped at the next publish
+        drop(subscription);
+        bus.publish(OrderEventKind::Deleted, get_test_order());
+        assert!(bus.inner.lock().unwrap().subscribers.is_empty());
+    }
+}
@@ -1,15 +1,37 @@
 use display_json::{DisplayAsJson, FromStrAsJson};
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+use std::time::Duration;
+use uuid::Uuid;
 
 /// Struct that represents an order
-#[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
 pub struct Order {
-    /// Order ID (unique). Should be generated by the SQL database
+    /// Order ID (unique). Should be generated by the SQL database. Internal only: it is never
+    /// serialized, so sequential IDs cannot be used to guess other orders
+    #[serde(skip_serializing)]
     pub id: Option<i64>,
 
+    /// Public order ID. Random, so unlike `id` it can be handed out without letting anyone
+    /// guess other orders. Generated by the database
+    #[serde(default)]
+    pub uuid: Option<Uuid>,
+
     /// Customer Name
     pub customer: String,
 
+    /// Internal ID of the customer that placed the order. Filled in by the database and, like
+    /// `id`, never serialized
+    #[serde(default, skip_serializing)]
+    pub customer_id: Option<i64>,
+
+    /// When the order was placed, in seconds since the Unix epoch. Filled in by the database.
+    /// Internal only: reports use it, the API does not show it
+    #[serde(default, skip_serializing)]
+    pub created_at: Option<i64>,
+
     /// Vec of all of the food items in the order
     pub food: Vec<MenuItem>,
 
@@ -18,15 +40,64 @@ pub struct Order {
 
     /// Total price of the order
     pub total: f64,
+
+    /// When the kitchen expects the order to be ready, in seconds since the Unix epoch.
+    /// Filled in by the database while the order is queued or being prepared
+    #[serde(default)]
+    pub estimated_ready_at: Option<i64>,
+}
+
+impl Order {
+    /// How long the kitchen takes to prepare every item in the order, one after another
+    pub fn prep_time(&self) -> Duration {
+        self.food.iter().map(MenuItem::prep_time).sum()
+    }
+}
+
+/// An order together with its internal IDs, which `Order` leaves out when serialized. For
+/// files the server keeps for itself, such as store logs and bulk exports
+#[derive(Serialize)]
+pub(crate) struct WithId<'a> {
+    id: Option<i64>,
+    customer_id: Option<i64>,
+    created_at: Option<i64>,
+    #[serde(flatten)]
+    order: &'a Order,
+}
+
+impl<'a> From<&'a Order> for WithId<'a> {
+    fn from(order: &'a Order) -> Self {
+        WithId {
+            id: order.id,
+            customer_id: order.customer_id,
+            created_at: order.created_at,
+            order,
+        }
+    }
+}
+
+/// Serialize an order together with its internal IDs, for `#[serde(serialize_with)]`
+pub(crate) fn serialize_with_id<S: Serializer>(
+    order: &Order,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    WithId::from(order).serialize(serializer)
 }
 
 /// Struct that represents an incoming order request to be added to the database. Separate from the
 /// Order struct because many of the fields will be generated for new orders
-#[derive(Deserialize, FromStrAsJson)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
 pub struct OrderRequest {
     /// Customer Name
     pub customer: String,
 
+    /// Public ID of an existing customer placing the order. If not given, the order is linked to
+    /// a customer record with the given name and no contact details
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_uuid: Option<Uuid>,
+
     /// Vec of all the food items in the order
     pub food: Vec<MenuItem>,
 }
@@ -36,26 +107,79 @@ impl From<OrderRequest> for Order {
     fn from(order_request: OrderRequest) -> Self {
         Order {
             id: None,
+            uuid: None,
             customer: order_request.customer,
+            customer_id: None,
+            created_at: None,
             status: OrderStatus::Pending,
             total: order_request.food.iter().map(|item| item.price()).sum(),
             food: order_request.food,
+            estimated_ready_at: None,
+        }
+    }
+}
+
+/// Struct that represents a customer account
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct Customer {
+    /// Customer ID (unique). Should be generated by the SQL database. Internal only, like
+    /// `Order::id`
+    #[serde(skip_serializing)]
+    pub id: Option<i64>,
+
+    /// Public customer ID. Random, so it can be handed out without letting anyone guess other
+    /// customers. Generated by the database
+    #[serde(default)]
+    pub uuid: Option<Uuid>,
+
+    /// Customer Name
+    pub name: String,
+
+    /// How to reach the customer, e.g. a phone number or email address
+    pub contact: Option<String>,
+}
+
+/// Struct that represents an incoming request to create a customer
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct CustomerRequest {
+    /// Customer Name
+    pub name: String,
+
+    /// How to reach the customer, e.g. a phone number or email address
+    #[serde(default)]
+    pub contact: Option<String>,
+}
+
+impl From<CustomerRequest> for Customer {
+    fn from(request: CustomerRequest) -> Self {
+        Customer {
+            id: None,
+            uuid: None,
+            name: request.name,
+            contact: request.contact,
         }
     }
 }
 
 /// Enum that represents the status of an order
-#[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
 pub enum OrderStatus {
     Pending,
     Preparing,
+    Ready,
     Transporting,
     Completed,
     Cancelled,
 }
 
 /// Enum that represents a particular menu item
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum MenuItem {
     Burger(Burger),
     Fries,
@@ -70,10 +194,28 @@ impl MenuItem {
             MenuItem::Drink => 3.0,
         }
     }
+
+    /// Every ingredient taken from the inventory to make the item
+    pub fn ingredients(&self) -> Vec<Ingredient> {
+        match self {
+            MenuItem::Burger(burger) => burger.ingredients(),
+            MenuItem::Fries => vec![Ingredient::Fries],
+            MenuItem::Drink => vec![Ingredient::Drink],
+        }
+    }
+
+    /// How long the kitchen takes to prepare the item
+    pub fn prep_time(&self) -> Duration {
+        match self {
+            MenuItem::Burger(burger) => burger.prep_time(),
+            MenuItem::Fries => Duration::from_secs(180),
+            MenuItem::Drink => Duration::from_secs(30),
+        }
+    }
 }
 
 /// Struct that represents a burger
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Clone)]
 pub struct Burger {
     bun: Bun,
     patty: Patty,
@@ -98,10 +240,23 @@ impl Burger {
                 .map(|topping| topping.price())
                 .sum::<f64>()
     }
+
+    fn ingredients(&self) -> Vec<Ingredient> {
+        let mut ingredients = vec![Ingredient::Bun(self.bun), Ingredient::Patty(self.patty)];
+        ingredients.extend(self.toppings.iter().copied().map(Ingredient::Topping));
+        ingredients
+    }
+
+    /// Four minutes on the grill, plus half a minute for every topping
+    fn prep_time(&self) -> Duration {
+        Duration::from_secs(240 + 30 * self.toppings.len() as u64)
+    }
 }
 
 /// Enum that represents a type of bun
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Eq, Hash, Clone, Copy,
+)]
 pub enum Bun {
     Sesame,
     Plain,
@@ -119,7 +274,9 @@ impl Bun {
 }
 
 /// Enum that represents a type of patty
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Eq, Hash, Clone, Copy,
+)]
 pub enum Patty {
     Beef,
     Chicken,
@@ -137,7 +294,9 @@ impl Patty {
 }
 
 /// Enum that represents a type of topping
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, Debug, PartialEq, Eq, Hash, Clone, Copy,
+)]
 pub enum Topping {
     Lettuce,
     Tomato,
@@ -160,6 +319,64 @@ impl Topping {
     }
 }
 
+/// Enum that represents a single ingredient whose stock is tracked in the inventory
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Ingredient {
+    Bun(Bun),
+    Patty(Patty),
+    Topping(Topping),
+    Fries,
+    Drink,
+}
+
+impl Ingredient {
+    /// Every ingredient on the menu
+    pub const ALL: &'static [Ingredient] = &[
+        Ingredient::Bun(Bun::Sesame),
+        Ingredient::Bun(Bun::Plain),
+        Ingredient::Bun(Bun::GlutenFree),
+        Ingredient::Patty(Patty::Beef),
+        Ingredient::Patty(Patty::Chicken),
+        Ingredient::Patty(Patty::Veggie),
+        Ingredient::Topping(Topping::Lettuce),
+        Ingredient::Topping(Topping::Tomato),
+        Ingredient::Topping(Topping::Onion),
+        Ingredient::Topping(Topping::Pickle),
+        Ingredient::Topping(Topping::Cheese),
+        Ingredient::Topping(Topping::Bacon),
+        Ingredient::Fries,
+        Ingredient::Drink,
+    ];
+
+    /// Name of the ingredient, used as its key in the inventory and in URLs
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ingredient::Bun(Bun::Sesame) => "sesame-bun",
+            Ingredient::Bun(Bun::Plain) => "plain-bun",
+            Ingredient::Bun(Bun::GlutenFree) => "gluten-free-bun",
+            Ingredient::Patty(Patty::Beef) => "beef-patty",
+            Ingredient::Patty(Patty::Chicken) => "chicken-patty",
+            Ingredient::Patty(Patty::Veggie) => "veggie-patty",
+            Ingredient::Topping(Topping::Lettuce) => "lettuce",
+            Ingredient::Topping(Topping::Tomato) => "tomato",
+            Ingredient::Topping(Topping::Onion) => "onion",
+            Ingredient::Topping(Topping::Pickle) => "pickle",
+            Ingredient::Topping(Topping::Cheese) => "cheese",
+            Ingredient::Topping(Topping::Bacon) => "bacon",
+            Ingredient::Fries => "fries",
+            Ingredient::Drink => "drink",
+        }
+    }
+
+    /// Look up an ingredient by its name
+    pub fn from_name(name: &str) -> Option<Ingredient> {
+        Ingredient::ALL
+            .iter()
+            .copied()
+            .find(|ingredient| ingredient.name() == name)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,6 +395,7 @@ mod tests {
 
         let order_request = OrderRequest {
             customer: "Alice".to_string(),
+            customer_uuid: None,
             food: vec![
                 MenuItem::Burger(Burger::new(
                     Bun::Sesame,
@@ -193,11 +411,35 @@ mod tests {
             order,
             Order {
                 id: None,
+                uuid: None,
                 customer: "Alice".to_string(),
+                customer_id: None,
+                created_at: None,
                 status: OrderStatus::Pending,
                 total: 20.0,
                 food,
+                estimated_ready_at: None,
             }
         );
+        assert_eq!(order.prep_time(), Duration::from_secs(240 + 60 + 180 + 30));
+    }
+
+    #[test]
+    fn test_ingredients() {
+        let burger = MenuItem::Burger(Burger::new(
+            Bun::GlutenFree,
+            Patty::Veggie,
+            vec![Topping::Cheese, Topping::Cheese],
+        ));
+        let names: Vec<&str> = burger.ingredients().iter().map(Ingredient::name).collect();
+        assert_eq!(
+            names,
+            vec!["gluten-free-bun", "veggie-patty", "cheese", "cheese"]
+        );
+
+        for ingredient in Ingredient::ALL {
+            assert_eq!(Ingredient::from_name(ingredient.name()), Some(*ingredient));
+        }
+        assert_eq!(Ingredient::from_name("ketchup"), None);
     }
 }
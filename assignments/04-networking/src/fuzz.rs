@@ -0,0 +1,178 @@
+use std::io::{self, Cursor, Read, Write};
+use std::str::{self, FromStr};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use crate::breaker::{BreakerConfig, CircuitBreaker};
+use crate::http::{self, HttpRequest, HttpResponse, Limits};
+use crate::log::Logger;
+use crate::pool::{Pool, PoolConfig};
+use crate::proxy::{self, ReverseProxy};
+use crate::routing::{Routing, Server};
+use crate::server;
+
+/// Read arbitrary bytes as a request, both off a connection (with the origin's limits) and
+/// as text. Whatever parses must serialize back to text that parses to the same request.
+/// Run by the `request_parsing` fuzz target
+pub fn request_parsing(data: &[u8]) {
+    let mut reader = data;
+    if let Ok(Some(request)) = http::read_request_limited(&mut reader, &Limits::default()) {
+        assert_request_round_trip(&request);
+    }
+    if let Ok(Ok(request)) = str::from_utf8(data).map(HttpRequest::from_str) {
+        assert_request_round_trip(&request);
+    }
+}
+
+fn assert_request_round_trip(request: &HttpRequest) {
+    let serialized = request.to_string();
+    let reparsed = HttpRequest::from_str(&serialized).expect("serialized requests parse");
+    assert_eq!(&reparsed, request, "{:?}", serialized);
+}
+
+/// Parse arbitrary bytes as a response. Whatever parses must serialize back to text that
+/// parses to the same response, and framing it for the wire (compressed or not) must give
+/// a head that parses and a body of the advertised length. Run by the
+/// `response_serialization` fuzz target
+pub fn response_serialization(data: &[u8]) {
+    let Ok(Ok(response)) = str::from_utf8(data).map(HttpResponse::from_str) else {
+        return;
+    };
+    let serialized = response.to_string();
+    let reparsed = HttpResponse::from_str(&serialized).expect("serialized responses parse");
+    assert_eq!(reparsed, response, "{:?}", serialized);
+
+    for accept_encoding in [None, Some("gzip"), Some("deflate")] {
+        let (message, length) = server::frame_response(response.clone(), false, accept_encoding)
+            .expect("in-memory compression does not fail");
+        let split = message
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("framed responses have a head")
+            + 4;
+        let head = str::from_utf8(&message[..split]).expect("heads are text");
+        let head = HttpResponse::from_str(head).expect("framed heads parse");
+        let body = &message[split..];
+        assert_eq!(body.len() as u64, length);
+        assert_eq!(
+            head.header("Content-Length"),
+            Some(length.to_string().as_str())
+        );
+
+        let mut decoded = Vec::new();
+        match (
+            response.header("Content-Encoding"),
+            head.header("Content-Encoding"),
+        ) {
+            (None, Some("gzip")) => GzDecoder::new(body).read_to_end(&mut decoded),
+            (None, Some("deflate")) => ZlibDecoder::new(body).read_to_end(&mut decoded),
+            _ => {
+                decoded.extend_from_slice(body);
+                Ok(decoded.len())
+            }
+        }
+        .expect("compressed bodies decode");
+        assert_eq!(decoded, response.body().as_bytes());
+    }
+}
+
+/// An origin connection that answers with fixed bytes
+struct Origin {
+    response: Cursor<Vec<u8>>,
+}
+
+impl Origin {
+    fn new(response: &[u8]) -> Self {
+        Origin {
+            response: Cursor::new(response.to_vec()),
+        }
+    }
+}
+
+impl Read for Origin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.response.read(buf)
+    }
+}
+
+impl Write for Origin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Relay arbitrary bytes from the origin to a client through the proxy. Whatever the origin
+/// sends, a relayed response must start with a head that parses, and the proxy must report
+/// the status and size it sent. Run by the `proxy_forward` fuzz target
+pub fn proxy_forward(data: &[u8]) {
+    for request in [
+        "GET /orders HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n",
+        "HEAD /orders HTTP/1.1\r\n\r\n",
+    ] {
+        let mut request = HttpRequest::from_str(request).expect("fixed requests parse");
+        let mut origin = Origin::new(data);
+        let mut client = Vec::new();
+        let Ok(forwarded) = proxy::forward(&mut request, &mut client, &mut origin, |_, _| {})
+        else {
+            continue;
+        };
+
+        assert_eq!(forwarded.bytes, client.len() as u64);
+        let split = client
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("relayed responses have a head")
+            + 4;
+        let head = str::from_utf8(&client[..split]).expect("relayed heads are text");
+        let head = HttpResponse::from_str(head).expect("relayed heads parse");
+        assert_eq!(head.status_code(), forwarded.status);
+    }
+}
+
+/// Split a fuzz input into a client request and an upstream response at the first NUL byte.
+/// Without one, the upstream sends nothing
+fn split_exchange(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.iter().position(|&byte| byte == 0) {
+        Some(split) => (&data[..split], &data[split + 1..]),
+        None => (data, &[]),
+    }
+}
+
+/// Run arbitrary bytes through the whole proxy: a client request, then a NUL byte, then what
+/// the upstream answers with. Whatever either side sends, the proxy must not panic, and
+/// anything it writes to the client must start with a head that parses. Run by the
+/// `proxy_request` fuzz target
+pub fn proxy_request(data: &[u8]) {
+    let (request, response) = split_exchange(data);
+    let response = response.to_vec();
+    let pool = Pool::new(PoolConfig::default(), move || Ok(Origin::new(&response)));
+    let upstream = Server::new(
+        "upstream",
+        pool,
+        CircuitBreaker::new(BreakerConfig::default()),
+    );
+    let routing = Routing::single(upstream);
+    let proxy = ReverseProxy::new("upstream")
+        .with_logger(Logger::from_writer("proxy", Box::new(io::sink())));
+
+    let mut client = Vec::new();
+    let mut reader = request;
+    if proxy
+        .proxy_request(&mut reader, &mut client, &routing)
+        .is_err()
+        || client.is_empty()
+    {
+        return;
+    }
+    let split = client
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("proxied responses have a head")
+        + 4;
+    let head = str::from_utf8(&client[..split]).expect("proxied heads are text");
+    HttpResponse::from_str(head).expect("proxied heads parse");
+}
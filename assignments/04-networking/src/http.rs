@@ -1,9 +1,12 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
 use std::{fmt::Display, str::FromStr};
 
 use crate::error::AspirinEatsError;
 
 /// Simple wrapper for an HTTP Request
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct HttpRequest {
     /// The HTTP method used in the request (GET, POST, etc)
     pub method: Option<String>,
@@ -11,22 +14,128 @@ pub struct HttpRequest {
     /// The path requested by the client
     pub path: Option<String>,
 
+    /// The headers sent with the request, in the order they were received
+    pub headers: Vec<(String, String)>,
+
     /// The body of the request
     pub body: Option<String>,
 }
 
+impl HttpRequest {
+    /// Get the value of a header by name. Header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Set a header, replacing any existing value with the same name
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        set_header(&mut self.headers, name, value);
+    }
+
+    /// Remove every header with the given name
+    pub fn remove_header(&mut self, name: &str) {
+        remove_header(&mut self.headers, name);
+    }
+
+    /// The path of the request without any query string
+    pub fn route(&self) -> &str {
+        let path = self.path.as_deref().unwrap_or("/");
+        path.split('?').next().unwrap_or(path)
+    }
+
+    /// Get the value of a query string parameter by name, percent-decoded
+    pub fn query(&self, name: &str) -> Option<String> {
+        let (_, query) = self.path.as_deref()?.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(key, _)| percent_decode(key) == name)
+            .map(|(_, value)| percent_decode(value))
+    }
+
+    /// Whether the client asked for the connection to be closed after this request
+    pub fn wants_close(&self) -> bool {
+        self.header("Connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// Decode `%XX` escapes and `+` (as a space) in a query string component. Malformed escapes
+/// are kept as they are
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match value
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 impl FromStr for HttpRequest {
     type Err = AspirinEatsError;
 
     // Parse a string into an HTTP Request
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        todo!()
+        let (head, body) = s.split_once("\r\n\r\n").unwrap_or((s, ""));
+        let mut lines = head.split("\r\n");
+
+        let mut request_line = lines
+            .next()
+            .ok_or(AspirinEatsError::InvalidRequest)?
+            .split_whitespace();
+        let method = request_line.next().map(str::to_string);
+        let path = request_line.next().map(str::to_string);
+        if method.is_none() || path.is_none() {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+
+        let headers = parse_headers(lines)?;
+
+        Ok(HttpRequest {
+            method,
+            path,
+            headers,
+            body: (!body.is_empty()).then(|| body.to_string()),
+        })
     }
 }
 
+impl Display for HttpRequest {
+    /// Convert an HttpRequest struct back into a valid HTTP Request
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} HTTP/1.1\r\n",
+            self.method.as_deref().unwrap_or("GET"),
+            self.path.as_deref().unwrap_or("/")
+        )?;
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        write!(f, "\r\n{}", self.body.as_deref().unwrap_or(""))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct HttpResponse {
     status_code: u16,
     status_text: String,
+    headers: Vec<(String, String)>,
     body: String,
 }
 
@@ -35,23 +144,480 @@ impl HttpResponse {
         HttpResponse {
             status_code,
             status_text: status_text.to_string(),
+            headers: Vec::new(),
             body: body.to_string(),
         }
     }
+
+    /// Create a response with a JSON body and the matching Content-Type
+    pub fn json(status_code: u16, status_text: &str, body: &str) -> Self {
+        HttpResponse::new(status_code, status_text, body)
+            .with_header("Content-Type", "application/json")
+    }
+
+    /// Add a header to the response, replacing any existing value with the same name
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        set_header(&mut self.headers, name, value);
+        self
+    }
+
+    /// Set a header, replacing any existing value with the same name
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        set_header(&mut self.headers, name, value);
+    }
+
+    /// Remove every header with the given name
+    pub fn remove_header(&mut self, name: &str) {
+        remove_header(&mut self.headers, name);
+    }
+
+    /// Add a field to the `Vary` header, keeping any that are already there
+    pub fn add_vary(&mut self, field: &str) {
+        let vary = match self.header("Vary") {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .any(|existing| existing.trim().eq_ignore_ascii_case(field)) =>
+            {
+                return
+            }
+            Some(vary) => format!("{}, {}", vary, field),
+            None => field.to_string(),
+        };
+        self.set_header("Vary", &vary);
+    }
+
+    /// Get the value of a header by name. Header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    pub fn status_text(&self) -> &str {
+        &self.status_text
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    /// The status line and headers, up to and including the blank line that ends them
+    pub fn head(&self) -> String {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status_code, self.status_text);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// Take the body out of the response, leaving it empty
+    pub fn take_body(&mut self) -> String {
+        std::mem::take(&mut self.body)
+    }
+}
+
+impl FromStr for HttpResponse {
+    type Err = AspirinEatsError;
+
+    // Parse a string into an HTTP Response
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (head, body) = s.split_once("\r\n\r\n").unwrap_or((s, ""));
+        let mut lines = head.split("\r\n");
+
+        let mut status_line = lines
+            .next()
+            .ok_or(AspirinEatsError::InvalidRequest)?
+            .splitn(3, ' ');
+        let _version = status_line.next();
+        let status_code = status_line
+            .next()
+            .and_then(|code| code.parse().ok())
+            .ok_or(AspirinEatsError::InvalidRequest)?;
+        let status_text = status_line.next().unwrap_or("").to_string();
+
+        Ok(HttpResponse {
+            status_code,
+            status_text,
+            headers: parse_headers(lines)?,
+            body: body.to_string(),
+        })
+    }
 }
 
 impl Display for HttpResponse {
     /// Convert an HttpResponse struct to a valid HTTP Response
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        write!(f, "{}{}", self.head(), self.body)
     }
 }
 
 impl From<AspirinEatsError> for HttpResponse {
     /// Given an error type, convert it to an appropriate HTTP Response
     fn from(value: AspirinEatsError) -> Self {
-        todo!()
+        match value {
+            AspirinEatsError::ParseError(_)
+            | AspirinEatsError::InvalidRequest
+            | AspirinEatsError::WebSocket(_) => {
+                HttpResponse::new(400, "Bad Request", &value.to_string())
+            }
+            AspirinEatsError::NotFound => HttpResponse::new(404, "Not Found", &value.to_string()),
+            AspirinEatsError::MethodNotAllowed => {
+                HttpResponse::new(405, "Method Not Allowed", &value.to_string())
+            }
+            AspirinEatsError::RequestTimeout => {
+                HttpResponse::new(408, "Request Timeout", &value.to_string())
+            }
+            AspirinEatsError::PayloadTooLarge => {
+                HttpResponse::new(413, "Payload Too Large", &value.to_string())
+            }
+            AspirinEatsError::HeadersTooLarge => {
+                HttpResponse::new(431, "Request Header Fields Too Large", &value.to_string())
+            }
+            AspirinEatsError::OutOfStock(_) => {
+                HttpResponse::new(409, "Conflict", &value.to_string())
+            }
+            AspirinEatsError::BadGateway | AspirinEatsError::UnexpectedStatus(_) => {
+                HttpResponse::new(502, "Bad Gateway", &value.to_string())
+            }
+            AspirinEatsError::TooManyRequests => {
+                HttpResponse::new(429, "Too Many Requests", &value.to_string())
+            }
+            AspirinEatsError::ServiceUnavailable => {
+                HttpResponse::new(503, "Service Unavailable", &value.to_string())
+            }
+            AspirinEatsError::Database(_)
+            | AspirinEatsError::Io(_)
+            | AspirinEatsError::InvalidBackup(_)
+            | AspirinEatsError::InvalidConfig(_) => {
+                HttpResponse::new(500, "Internal Server Error", "Internal Server Error")
+            }
+        }
+    }
+}
+
+/// Read a single HTTP message (head plus a body sized by `Content-Length`) from a reader.
+/// Bodies larger than the default `Limits::max_body` are refused. Returns `None` if the
+/// reader is closed before any bytes of a new message arrive
+pub fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<String>, AspirinEatsError> {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            if head.is_empty() {
+                return Ok(None);
+            }
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        // Tolerate stray blank lines between pipelined messages
+        if head.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        head.push_str(&line);
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
     }
+
+    let length = body_length(&head, &Limits::default())?;
+    let body = read_body(reader, length)?;
+    append_body(head, body).map(Some)
+}
+
+/// Read a body of `length` bytes. The buffer grows as bytes arrive rather than being sized
+/// up front, so a peer cannot make us allocate a length it never sends
+pub(crate) fn read_body<R: Read>(reader: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    if reader.take(length as u64).read_to_end(&mut body)? < length {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(body)
+}
+
+/// Get the `Content-Length` declared in a message head, defaulting to 0
+pub(crate) fn content_length(head: &str) -> Result<usize, AspirinEatsError> {
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()
+        .map_err(|_| AspirinEatsError::InvalidRequest)
+        .map(|length| length.unwrap_or(0))
+}
+
+/// Join a message head with the body bytes read after it
+pub(crate) fn append_body(mut head: String, body: Vec<u8>) -> Result<String, AspirinEatsError> {
+    head.push_str(&String::from_utf8(body).map_err(|_| AspirinEatsError::InvalidRequest)?);
+    Ok(head)
+}
+
+/// How long a client may take to send a request, and how much it may send
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Time allowed for a request head to arrive, from its first byte
+    pub read_header_timeout: Duration,
+
+    /// Time allowed for a request body to arrive, once its head has been read
+    pub read_body_timeout: Duration,
+
+    /// Time allowed for each write of a response
+    pub write_timeout: Duration,
+
+    /// Time a connection may wait for its next request before it is closed
+    pub idle_timeout: Duration,
+
+    /// Longest request line, and longest header line, in bytes (excluding the line ending)
+    pub max_request_line: usize,
+
+    /// Most headers a request may have
+    pub max_headers: usize,
+
+    /// Largest request body, in bytes
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            read_header_timeout: Duration::from_secs(10),
+            read_body_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(60),
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+/// Reader whose reads can be made to fail once a point in time has passed
+pub trait Deadline {
+    /// Fail reads with `ErrorKind::TimedOut` from `deadline` on. `None` removes the deadline
+    fn set_deadline(&mut self, deadline: Option<Instant>);
+}
+
+impl<R: Deadline> Deadline for BufReader<R> {
+    fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.get_mut().set_deadline(deadline);
+    }
+}
+
+/// In-memory readers never wait, so deadlines have nothing to do
+impl Deadline for &[u8] {
+    fn set_deadline(&mut self, _deadline: Option<Instant>) {}
+}
+
+/// A `TcpStream` with a deadline for its reads. Unlike a read timeout, which restarts with
+/// every read, a deadline also stops clients that trickle bytes in slowly
+#[derive(Debug)]
+pub struct TimedStream {
+    stream: TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl TimedStream {
+    pub fn new(stream: TcpStream) -> Self {
+        TimedStream {
+            stream,
+            deadline: None,
+        }
+    }
+
+    /// Get the underlying stream
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl Deadline for TimedStream {
+    fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(ErrorKind::TimedOut.into()),
+            },
+            None => None,
+        };
+        self.stream.set_read_timeout(timeout)?;
+        // Sockets report an expired read timeout as `WouldBlock` on some platforms
+        self.stream.read(buf).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock if timeout.is_some() => ErrorKind::TimedOut.into(),
+            _ => e,
+        })
+    }
+}
+
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Whether an I/O error means a read ran out of time
+pub(crate) fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+fn read_error(error: io::Error) -> AspirinEatsError {
+    if is_timeout(&error) {
+        AspirinEatsError::RequestTimeout
+    } else {
+        error.into()
+    }
+}
+
+/// Add a line read with at most `max_request_line + 2` bytes to a request head, checking it
+/// against the limits. Blank lines before the head starts are skipped. Returns whether the
+/// line ended the head
+pub(crate) fn push_head_line(
+    head: &mut String,
+    line: &str,
+    limits: &Limits,
+) -> Result<bool, AspirinEatsError> {
+    if !line.ends_with('\n') {
+        // Either the line did not fit, or the client hung up in the middle of it
+        return if line.len() > limits.max_request_line {
+            Err(AspirinEatsError::HeadersTooLarge)
+        } else {
+            Err(AspirinEatsError::InvalidRequest)
+        };
+    }
+    if head.is_empty() && line.trim().is_empty() {
+        return Ok(false);
+    }
+    head.push_str(line);
+    if line == "\r\n" || line == "\n" {
+        return Ok(true);
+    }
+    // The request line plus one line per header
+    if head.lines().count() > limits.max_headers + 1 {
+        return Err(AspirinEatsError::HeadersTooLarge);
+    }
+    Ok(false)
+}
+
+/// Get the `Content-Length` of a request head, if it is within the limits
+pub(crate) fn body_length(head: &str, limits: &Limits) -> Result<usize, AspirinEatsError> {
+    let length = content_length(head)?;
+    if length > limits.max_body {
+        return Err(AspirinEatsError::PayloadTooLarge);
+    }
+    Ok(length)
+}
+
+/// Read and parse a single HTTP Request from a reader, enforcing the limits. Returns `None`
+/// if the reader is closed, or stays idle for `idle_timeout`, before a new request starts
+pub fn read_request_limited<R: BufRead + Deadline>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Option<HttpRequest>, AspirinEatsError> {
+    reader.set_deadline(deadline(limits.idle_timeout));
+    match reader.fill_buf() {
+        Ok([]) => return Ok(None),
+        Ok(_) => {}
+        Err(e) if is_timeout(&e) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let message = read_message_limited(reader, limits).map_err(|e| match e {
+        AspirinEatsError::Io(e) => read_error(e),
+        e => e,
+    })?;
+    message
+        .map(|message| HttpRequest::from_str(&message))
+        .transpose()
+}
+
+/// Read a single HTTP message (request or response) from a reader, enforcing the limits:
+/// its head must arrive within `read_header_timeout` of its first byte and its body within
+/// `read_body_timeout` after that. Waiting for the first byte is bounded only by the
+/// reader's current deadline. Reads that run out of time fail with `ErrorKind::TimedOut`.
+/// Returns `None` if the reader is closed before a new message starts
+pub fn read_message_limited<R: BufRead + Deadline>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Option<String>, AspirinEatsError> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    reader.set_deadline(deadline(limits.read_header_timeout));
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        let read = reader
+            .take(limits.max_request_line as u64 + 2)
+            .read_line(&mut line)?;
+        if read == 0 {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        if push_head_line(&mut head, &line, limits)? {
+            break;
+        }
+    }
+
+    reader.set_deadline(deadline(limits.read_body_timeout));
+    let body = read_body(reader, body_length(&head, limits)?)?;
+    reader.set_deadline(None);
+    append_body(head, body).map(Some)
+}
+
+/// The point `timeout` from now, or `None` if that is too far away to represent
+fn deadline(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+/// Read and parse a single HTTP Request from a reader
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Option<HttpRequest>, AspirinEatsError> {
+    read_message(reader)?
+        .map(|message| HttpRequest::from_str(&message))
+        .transpose()
+}
+
+fn parse_headers<'a>(
+    lines: impl Iterator<Item = &'a str>,
+) -> Result<Vec<(String, String)>, AspirinEatsError> {
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.split_once(':')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .ok_or(AspirinEatsError::InvalidRequest)
+        })
+        .collect()
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn set_header(headers: &mut Vec<(String, String)>, name: &str, value: &str) {
+    remove_header(headers, name);
+    headers.push((name.to_string(), value.to_string()));
+}
+
+fn remove_header(headers: &mut Vec<(String, String)>, name: &str) {
+    headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
 }
 
 #[cfg(test)]
@@ -67,6 +633,19 @@ mod tests {
         assert_eq!(http_request.body, Some("this is the body.".to_string()));
     }
 
+    #[test]
+    fn test_http_request_query() {
+        let request = HttpRequest::from_str(
+            "GET /reports/sales?from=2024-01-01&to=&q=a%20b+c HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.route(), "/reports/sales");
+        assert_eq!(request.query("from"), Some("2024-01-01".to_string()));
+        assert_eq!(request.query("to"), Some(String::new()));
+        assert_eq!(request.query("q"), Some("a b c".to_string()));
+        assert_eq!(request.query("format"), None);
+    }
+
     #[test]
     fn test_http_response_to_string() {
         let response = HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!");
@@ -96,10 +675,90 @@ mod tests {
         assert_eq!(response.status_text, "Method Not Allowed");
         assert_eq!(response.body, "Method not allowed");
 
-        let error = AspirinEatsError::Io(std::io::Error::new(std::io::ErrorKind::Other, "test"));
+        let error = AspirinEatsError::Io(std::io::Error::other("test"));
         let response: HttpResponse = error.into();
         assert_eq!(response.status_code, 500);
         assert_eq!(response.status_text, "Internal Server Error");
         assert_eq!(response.body, "Internal Server Error");
     }
+
+    #[test]
+    fn test_read_request_limited() {
+        let limits = Limits {
+            max_request_line: 24,
+            max_headers: 2,
+            max_body: 4,
+            ..Limits::default()
+        };
+        let read = |raw: &str| read_request_limited(&mut BufReader::new(raw.as_bytes()), &limits);
+
+        let request = read("\r\nPOST /orders HTTP/1.1\r\nA: 1\r\nContent-Length: 4\r\n\r\nbody")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.body, Some("body".to_string()));
+        assert!(read("").unwrap().is_none());
+
+        for (raw, expected) in [
+            ("GET /a-very-long-path HTTP/1.1\r\n\r\n", 431),
+            ("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n", 431),
+            ("GET / HTTP/1.1\r\nX: a-much-much-longer-value\r\n\r\n", 431),
+            ("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello", 413),
+            ("GET / HTTP/1.1\r\nA: 1", 400),
+        ] {
+            let response = HttpResponse::from(read(raw).unwrap_err());
+            assert_eq!(response.status_code, expected, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn test_read_request_uses_content_length() {
+        let raw = "POST /orders HTTP/1.1\r\nContent-Length: 4\r\n\r\nbodyGET / HTTP/1.1\r\n\r\n";
+        let mut reader = std::io::BufReader::new(raw.as_bytes());
+
+        let first = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(first.body, Some("body".to_string()));
+        assert_eq!(first.header("content-length"), Some("4"));
+
+        let second = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(second.path, Some("/".to_string()));
+        assert!(read_request(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_request_refuses_oversized_bodies() {
+        let raw = "POST /orders HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\n{}";
+        let error = read_request(&mut raw.as_bytes()).unwrap_err();
+        assert!(matches!(error, AspirinEatsError::PayloadTooLarge));
+
+        // A body shorter than its Content-Length is an error, not a short read
+        let raw = "POST /orders HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        assert!(read_request(&mut raw.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_message_limited_times_out_trickled_messages() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let limits = Limits {
+            read_header_timeout: Duration::from_millis(100),
+            ..Limits::default()
+        };
+
+        // Each byte arrives well within any read timeout, but the head never ends
+        let trickle = std::thread::spawn(move || {
+            for byte in b"HTTP/1.1 200 OK\r\nX: ".iter().cycle().take(100) {
+                if peer.write_all(&[*byte]).is_err() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let started = Instant::now();
+        let error = read_message_limited(&mut BufReader::new(TimedStream::new(stream)), &limits)
+            .unwrap_err();
+        assert!(matches!(error, AspirinEatsError::Io(e) if is_timeout(&e)));
+        assert!(started.elapsed() < Duration::from_millis(900));
+        trickle.join().unwrap();
+    }
 }
@@ -0,0 +1,83 @@
+use display_json::{DisplayAsJson, FromStrAsJson};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::food::{Ingredient, MenuItem};
+
+/// Stock every ingredient starts with when it is first added to the inventory
+pub const DEFAULT_STOCK: i64 = 100;
+
+/// Stock level at or below which an ingredient is reported as running low, unless
+/// configured otherwise
+pub const DEFAULT_LOW_STOCK: i64 = 10;
+
+/// Struct that represents the stock of a single ingredient
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct InventoryItem {
+    /// Name of the ingredient, e.g. `veggie-patty`
+    pub ingredient: String,
+
+    /// Units in stock
+    pub stock: i64,
+
+    /// Stock level at or below which the ingredient is running low
+    pub low_stock: i64,
+
+    /// Whether the ingredient is running low
+    pub low: bool,
+}
+
+/// Struct that represents an incoming request to set the stock of an ingredient
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct StockUpdate {
+    /// Units in stock
+    pub stock: i64,
+
+    /// New low stock threshold. Left unchanged if not given
+    #[serde(default)]
+    pub low_stock: Option<i64>,
+}
+
+/// Units of each ingredient needed for the food, in menu order
+pub fn requirements(food: &[MenuItem]) -> Vec<(Ingredient, i64)> {
+    let mut needed: Vec<(Ingredient, i64)> = Vec::new();
+    for ingredient in food.iter().flat_map(MenuItem::ingredients) {
+        match needed.iter_mut().find(|(other, _)| *other == ingredient) {
+            Some((_, count)) => *count += 1,
+            None => needed.push((ingredient, 1)),
+        }
+    }
+    needed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{Bun, Burger, Patty, Topping};
+
+    #[test]
+    fn test_requirements() {
+        let food = vec![
+            MenuItem::Fries,
+            MenuItem::Burger(Burger::new(
+                Bun::Plain,
+                Patty::Beef,
+                vec![Topping::Cheese, Topping::Cheese],
+            )),
+            MenuItem::Fries,
+        ];
+        assert_eq!(
+            requirements(&food),
+            vec![
+                (Ingredient::Fries, 2),
+                (Ingredient::Bun(Bun::Plain), 1),
+                (Ingredient::Patty(Patty::Beef), 1),
+                (Ingredient::Topping(Topping::Cheese), 2),
+            ]
+        );
+    }
+}
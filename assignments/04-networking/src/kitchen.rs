@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::food::Order;
+
+/// Number of stations orders are prepared on, unless configured otherwise
+pub const DEFAULT_STATIONS: usize = 2;
+
+/// An order waiting for a free station
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ticket {
+    order_id: i64,
+    prep_time: Duration,
+}
+
+/// An order being prepared on a station
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cooking {
+    order_id: i64,
+    ready_at: i64,
+}
+
+/// Orders that moved through the kitchen in a call to `Kitchen::advance`
+#[derive(Debug, Default, PartialEq)]
+pub struct Progress {
+    /// Orders that were taken from the queue and put on a station
+    pub started: Vec<i64>,
+
+    /// Orders whose preparation is done. They leave the kitchen
+    pub finished: Vec<i64>,
+}
+
+/// FIFO queue of pending orders, prepared one at a time on each of a fixed number of
+/// stations. Times are seconds since the Unix epoch
+#[derive(Debug)]
+pub struct Kitchen {
+    queue: VecDeque<Ticket>,
+    stations: Vec<Option<Cooking>>,
+}
+
+impl Default for Kitchen {
+    fn default() -> Self {
+        Kitchen::new(DEFAULT_STATIONS)
+    }
+}
+
+impl Kitchen {
+    /// Create an empty kitchen with the given number of stations (at least one)
+    pub fn new(stations: usize) -> Self {
+        Kitchen {
+            queue: VecDeque::new(),
+            stations: vec![None; stations.max(1)],
+        }
+    }
+
+    /// Number of stations orders are prepared on
+    pub fn stations(&self) -> usize {
+        self.stations.len()
+    }
+
+    /// Whether the order is queued or being prepared
+    pub fn contains(&self, order_id: i64) -> bool {
+        self.queue.iter().any(|ticket| ticket.order_id == order_id)
+            || self.cooking().any(|cooking| cooking.order_id == order_id)
+    }
+
+    /// Add an order to the back of the queue. Orders without an ID, or already in the
+    /// kitchen, are ignored
+    pub fn enqueue(&mut self, order: &Order) {
+        match order.id {
+            Some(order_id) if !self.contains(order_id) => self.queue.push_back(Ticket {
+                order_id,
+                prep_time: order.prep_time(),
+            }),
+            _ => {}
+        }
+    }
+
+    /// Take an order out of the kitchen, freeing its station if it was being prepared.
+    /// Returns whether the order was in the kitchen
+    pub fn remove(&mut self, order_id: i64) -> bool {
+        let queued = self.queue.len();
+        self.queue.retain(|ticket| ticket.order_id != order_id);
+        let mut removed = self.queue.len() != queued;
+        for station in &mut self.stations {
+            if station.is_some_and(|cooking| cooking.order_id == order_id) {
+                *station = None;
+                removed = true;
+            }
+        }
+        removed
+    }
+
+    /// Finish every order that is ready by `now`, then start queued orders on the free
+    /// stations
+    pub fn advance(&mut self, now: i64) -> Progress {
+        let mut progress = Progress::default();
+        for station in &mut self.stations {
+            if let Some(cooking) = station.filter(|cooking| cooking.ready_at <= now) {
+                progress.finished.push(cooking.order_id);
+                *station = None;
+            }
+            if station.is_none() {
+                if let Some(ticket) = self.queue.pop_front() {
+                    progress.started.push(ticket.order_id);
+                    *station = Some(Cooking {
+                        order_id: ticket.order_id,
+                        ready_at: now + seconds(ticket.prep_time),
+                    });
+                }
+            }
+        }
+        progress
+    }
+
+    /// Estimate when every order in the kitchen will be ready, as `(order id, ready at)`.
+    /// Queued orders are assigned, in order, to whichever station frees up first
+    pub fn estimates(&self, now: i64) -> Vec<(i64, i64)> {
+        let mut free_at: Vec<i64> = self
+            .stations
+            .iter()
+            .map(|station| station.map_or(now, |cooking| cooking.ready_at.max(now)))
+            .collect();
+        let mut estimates: Vec<(i64, i64)> = self
+            .cooking()
+            .map(|cooking| (cooking.order_id, cooking.ready_at))
+            .collect();
+
+        for ticket in &self.queue {
+            let station = free_at
+                .iter_mut()
+                .min()
+                .expect("kitchen should have at least one station");
+            *station += seconds(ticket.prep_time);
+            estimates.push((ticket.order_id, *station));
+        }
+        estimates
+    }
+
+    fn cooking(&self) -> impl Iterator<Item = &Cooking> {
+        self.stations.iter().flatten()
+    }
+}
+
+/// Whole seconds, rounded up
+fn seconds(duration: Duration) -> i64 {
+    duration.as_secs() as i64 + i64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::*;
+
+    fn get_test_order(id: i64, food: Vec<MenuItem>) -> Order {
+        Order {
+            id: Some(id),
+            uuid: None,
+            customer: "Amit".to_string(),
+            customer_id: None,
+            created_at: None,
+            food,
+            status: OrderStatus::Pending,
+            total: 0.0,
+            estimated_ready_at: None,
+        }
+    }
+
+    #[test]
+    fn test_estimates_follow_the_queue() {
+        let mut kitchen = Kitchen::new(2);
+        kitchen.enqueue(&get_test_order(1, vec![MenuItem::Fries]));
+        kitchen.enqueue(&get_test_order(2, vec![MenuItem::Drink]));
+        kitchen.enqueue(&get_test_order(3, vec![MenuItem::Drink]));
+        kitchen.enqueue(&get_test_order(3, vec![MenuItem::Drink]));
+
+        // Order 3 waits for order 2's station, which frees up first
+        assert_eq!(kitchen.estimates(0), vec![(1, 180), (2, 30), (3, 60)]);
+
+        let progress = kitchen.advance(0);
+        assert_eq!(progress.started, vec![1, 2]);
+        assert_eq!(kitchen.estimates(10), vec![(1, 180), (2, 30), (3, 60)]);
+
+        let progress = kitchen.advance(30);
+        assert_eq!(progress.finished, vec![2]);
+        assert_eq!(progress.started, vec![3]);
+        assert_eq!(kitchen.estimates(30), vec![(1, 180), (3, 60)]);
+    }
+
+    #[test]
+    fn test_remove_frees_the_station() {
+        let mut kitchen = Kitchen::new(1);
+        let burger = MenuItem::Burger(Burger::new(
+            Bun::Plain,
+            Patty::Beef,
+            vec![Topping::Cheese, Topping::Bacon],
+        ));
+        kitchen.enqueue(&get_test_order(1, vec![burger]));
+        kitchen.enqueue(&get_test_order(2, vec![MenuItem::Drink]));
+        kitchen.advance(0);
+        assert_eq!(kitchen.estimates(0), vec![(1, 300), (2, 330)]);
+
+        assert!(kitchen.remove(1));
+        assert!(!kitchen.remove(1));
+        assert_eq!(kitchen.estimates(0), vec![(2, 30)]);
+        assert_eq!(kitchen.advance(0).started, vec![2]);
+        assert!(kitchen.contains(2));
+    }
+}
@@ -1,4 +1,34 @@
+pub mod api;
+#[cfg(feature = "async")]
+pub mod async_server;
+pub mod backup;
+pub mod breaker;
+pub mod bulk;
+pub mod client;
+pub mod clock;
+pub mod compression;
+pub mod cors;
 pub mod db;
+pub mod dispatch;
 pub mod error;
+pub mod events;
 pub mod food;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 pub mod http;
+pub mod inventory;
+pub mod kitchen;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
+pub mod openapi;
+pub mod pool;
+pub mod proxy;
+pub mod ratelimit;
+pub mod reports;
+pub mod routing;
+pub mod server;
+pub mod sse;
+pub mod store;
+pub mod webhooks;
+pub mod websocket;
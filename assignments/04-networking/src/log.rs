@@ -0,0 +1,356 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::AspirinEatsError;
+use crate::http::HttpRequest;
+
+/// Header used to carry the request ID from the proxy to the origin (and back to the client)
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Default size a log file may grow to before it is rotated
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default number of rotated log files kept next to the live one
+const DEFAULT_MAX_FILES: usize = 5;
+
+/// Generate a new random request ID
+pub fn new_request_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Get the request ID the request was tagged with upstream, or generate a new one
+pub fn request_id(request: &HttpRequest) -> String {
+    request
+        .header(REQUEST_ID_HEADER)
+        .map(str::to_string)
+        .unwrap_or_else(new_request_id)
+}
+
+/// Milliseconds since the Unix epoch
+fn timestamp_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// One line of the access log
+#[derive(Serialize)]
+struct AccessLine<'a> {
+    service: &'a str,
+    request_id: &'a str,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    bytes: u64,
+    latency_ms: f64,
+}
+
+#[derive(Serialize)]
+struct Line<'a, T: Serialize> {
+    timestamp_ms: u128,
+    level: &'a str,
+    #[serde(flatten)]
+    fields: T,
+}
+
+#[derive(Serialize)]
+struct ErrorLine<'a> {
+    service: &'a str,
+    request_id: Option<&'a str>,
+    error: String,
+    detail: String,
+}
+
+#[derive(Serialize)]
+struct InfoLine<'a> {
+    service: &'a str,
+    message: &'a str,
+}
+
+/// Where log lines end up
+pub enum LogTarget {
+    /// Standard error
+    Stderr,
+
+    /// A file that is rotated to `<path>.1`, `<path>.2`, ... once it reaches `max_bytes`
+    RotatingFile {
+        path: PathBuf,
+        max_bytes: u64,
+        max_files: usize,
+    },
+}
+
+impl LogTarget {
+    /// Read the log target from the environment. `ASPIRIN_LOG` is either `stderr` (default) or
+    /// a file path; `ASPIRIN_LOG_MAX_BYTES` and `ASPIRIN_LOG_FILES` control rotation
+    pub fn from_env() -> Self {
+        match std::env::var("ASPIRIN_LOG") {
+            Ok(path) if !path.is_empty() && path != "stderr" => LogTarget::RotatingFile {
+                path: PathBuf::from(path),
+                max_bytes: env_or("ASPIRIN_LOG_MAX_BYTES", DEFAULT_MAX_BYTES),
+                max_files: env_or("ASPIRIN_LOG_FILES", DEFAULT_MAX_FILES),
+            },
+            _ => LogTarget::Stderr,
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+    Writer(Box<dyn Write + Send>),
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let _ = fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Structured JSON logger shared by the origin and the proxy
+pub struct Logger {
+    service: String,
+    sink: Mutex<Sink>,
+}
+
+impl Logger {
+    /// Create a logger for the named service writing to the given target
+    pub fn new(service: &str, target: LogTarget) -> io::Result<Self> {
+        let sink = match target {
+            LogTarget::Stderr => Sink::Stderr,
+            LogTarget::RotatingFile {
+                path,
+                max_bytes,
+                max_files,
+            } => Sink::File(RotatingFile::open(path, max_bytes, max_files)?),
+        };
+        Ok(Logger {
+            service: service.to_string(),
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Create a logger writing to standard error
+    pub fn stderr(service: &str) -> Self {
+        Logger {
+            service: service.to_string(),
+            sink: Mutex::new(Sink::Stderr),
+        }
+    }
+
+    /// Create a logger writing to an arbitrary writer. Useful for testing
+    pub fn from_writer(service: &str, writer: Box<dyn Write + Send>) -> Self {
+        Logger {
+            service: service.to_string(),
+            sink: Mutex::new(Sink::Writer(writer)),
+        }
+    }
+
+    /// Name of the service this logger writes lines for
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Log a completed request
+    pub fn access(
+        &self,
+        request_id: &str,
+        method: &str,
+        path: &str,
+        status: u16,
+        bytes: u64,
+        latency: Duration,
+    ) {
+        self.write(
+            "info",
+            AccessLine {
+                service: &self.service,
+                request_id,
+                method,
+                path,
+                status,
+                bytes,
+                latency_ms: latency.as_micros() as f64 / 1000.0,
+            },
+        );
+    }
+
+    /// Log something that happened outside of any request
+    pub fn info(&self, message: &str) {
+        self.write(
+            "info",
+            InfoLine {
+                service: &self.service,
+                message,
+            },
+        );
+    }
+
+    /// Log an error, tagged with the request it happened in (if known)
+    pub fn error(&self, request_id: Option<&str>, error: &AspirinEatsError) {
+        self.write(
+            "error",
+            ErrorLine {
+                service: &self.service,
+                request_id,
+                error: error.to_string(),
+                detail: format!("{:?}", error),
+            },
+        );
+    }
+
+    fn write<T: Serialize>(&self, level: &str, fields: T) {
+        let line = Line {
+            timestamp_ms: timestamp_ms(),
+            level,
+            fields,
+        };
+        let Ok(line) = serde_json::to_string(&line) else {
+            return;
+        };
+
+        let mut sink = self.sink.lock().expect("logger lock poisoned");
+        let result = match &mut *sink {
+            Sink::Stderr => {
+                eprintln!("{}", line);
+                Ok(())
+            }
+            Sink::File(file) => file.write_line(&line),
+            Sink::Writer(writer) => writeln!(writer, "{}", line),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write log line: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Writer that can be inspected after it has been handed to a Logger
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_access_and_error_lines_are_json() {
+        let buffer = SharedBuffer::default();
+        let logger = Logger::from_writer("origin", Box::new(buffer.clone()));
+
+        logger.access("abc", "GET", "/orders", 200, 2, Duration::from_millis(3));
+        logger.error(Some("abc"), &AspirinEatsError::NotFound);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["service"], "origin");
+        assert_eq!(lines[0]["status"], 200);
+        assert_eq!(lines[0]["latency_ms"], 3.0);
+        assert_eq!(lines[1]["level"], "error");
+        assert_eq!(lines[1]["request_id"], "abc");
+        assert_eq!(lines[1]["error"], "Resource not found");
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("aspirin-log-{}", new_request_id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let logger = Logger::new(
+            "proxy",
+            LogTarget::RotatingFile {
+                path: path.clone(),
+                max_bytes: 1,
+                max_files: 2,
+            },
+        )
+        .unwrap();
+
+        for _ in 0..4 {
+            logger.error(None, &AspirinEatsError::InvalidRequest);
+        }
+
+        assert!(path.exists());
+        assert!(dir.join("access.log.1").exists());
+        assert!(dir.join("access.log.2").exists());
+        assert!(!dir.join("access.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
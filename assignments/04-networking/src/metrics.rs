@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bucket bounds (in seconds) used for request latencies
+const REQUEST_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Bucket bounds (in seconds) used for database queries
+const DB_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// Content-Type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Cumulative histogram in the Prometheus style
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    requests: BTreeMap<(String, u16), u64>,
+    latencies: BTreeMap<String, Histogram>,
+    db_queries: BTreeMap<&'static str, Histogram>,
+    upstreams: BTreeMap<String, (bool, u64)>,
+}
+
+/// Registry of every metric exposed by one service on `/metrics`
+pub struct Metrics {
+    service: String,
+    in_flight: AtomicI64,
+    inner: Mutex<Inner>,
+}
+
+impl Metrics {
+    /// Create an empty registry for the named service
+    pub fn new(service: &str) -> Self {
+        Metrics {
+            service: service.to_string(),
+            in_flight: AtomicI64::new(0),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("metrics lock poisoned")
+    }
+
+    /// Record a finished request for a route (a path template, not the raw path)
+    pub fn observe_request(&self, route: &str, status: u16, latency: Duration) {
+        let mut inner = self.inner();
+        *inner
+            .requests
+            .entry((route.to_string(), status))
+            .or_default() += 1;
+        inner
+            .latencies
+            .entry(route.to_string())
+            .or_insert_with(|| Histogram::new(REQUEST_BUCKETS))
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Record how long a database method took
+    pub fn observe_db(&self, method: &'static str, duration: Duration) {
+        self.inner()
+            .db_queries
+            .entry(method)
+            .or_insert_with(|| Histogram::new(DB_BUCKETS))
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Start timing a database method. The duration is recorded when the timer is dropped
+    pub fn time_db(self: &Arc<Self>, method: &'static str) -> DbTimer {
+        DbTimer {
+            metrics: Arc::clone(self),
+            method,
+            started: Instant::now(),
+        }
+    }
+
+    /// Record whether the last attempt to reach an upstream succeeded
+    pub fn set_upstream_health(&self, upstream: &str, up: bool) {
+        let mut inner = self.inner();
+        let entry = inner
+            .upstreams
+            .entry(upstream.to_string())
+            .or_insert((up, 0));
+        entry.0 = up;
+        if !up {
+            entry.1 += 1;
+        }
+    }
+
+    /// Mark a connection as open until the returned guard is dropped
+    pub fn track_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            metrics: Arc::clone(self),
+        }
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let inner = self.inner();
+        let service = &self.service;
+        let mut out = String::new();
+
+        out.push_str("# HELP aspirin_http_requests_total Requests handled, by route and status\n");
+        out.push_str("# TYPE aspirin_http_requests_total counter\n");
+        for ((route, status), count) in &inner.requests {
+            let _ = writeln!(
+                out,
+                "aspirin_http_requests_total{{service=\"{service}\",route=\"{route}\",status=\"{status}\"}} {count}"
+            );
+        }
+
+        out.push_str("# HELP aspirin_http_request_duration_seconds Request latency, by route\n");
+        out.push_str("# TYPE aspirin_http_request_duration_seconds histogram\n");
+        for (route, histogram) in &inner.latencies {
+            histogram.render(
+                &mut out,
+                "aspirin_http_request_duration_seconds",
+                &format!("service=\"{service}\",route=\"{route}\""),
+            );
+        }
+
+        out.push_str("# HELP aspirin_connections_in_flight Client connections currently open\n");
+        out.push_str("# TYPE aspirin_connections_in_flight gauge\n");
+        let _ = writeln!(
+            out,
+            "aspirin_connections_in_flight{{service=\"{service}\"}} {}",
+            self.in_flight.load(Ordering::SeqCst)
+        );
+
+        if !inner.db_queries.is_empty() {
+            out.push_str(
+                "# HELP aspirin_db_query_duration_seconds Database call latency, by method\n",
+            );
+            out.push_str("# TYPE aspirin_db_query_duration_seconds histogram\n");
+            for (method, histogram) in &inner.db_queries {
+                histogram.render(
+                    &mut out,
+                    "aspirin_db_query_duration_seconds",
+                    &format!("method=\"{method}\""),
+                );
+            }
+        }
+
+        if !inner.upstreams.is_empty() {
+            out.push_str(
+                "# HELP aspirin_upstream_up Whether the last request to the upstream succeeded\n",
+            );
+            out.push_str("# TYPE aspirin_upstream_up gauge\n");
+            for (upstream, (up, _)) in &inner.upstreams {
+                let _ = writeln!(
+                    out,
+                    "aspirin_upstream_up{{upstream=\"{upstream}\"}} {}",
+                    u8::from(*up)
+                );
+            }
+            out.push_str(
+                "# HELP aspirin_upstream_failures_total Failed attempts to reach the upstream\n",
+            );
+            out.push_str("# TYPE aspirin_upstream_failures_total counter\n");
+            for (upstream, (_, failures)) in &inner.upstreams {
+                let _ = writeln!(
+                    out,
+                    "aspirin_upstream_failures_total{{upstream=\"{upstream}\"}} {failures}"
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// Guard returned by `Metrics::time_db`
+pub struct DbTimer {
+    metrics: Arc<Metrics>,
+    method: &'static str,
+    started: Instant,
+}
+
+impl Drop for DbTimer {
+    fn drop(&mut self) {
+        self.metrics.observe_db(self.method, self.started.elapsed());
+    }
+}
+
+/// Guard returned by `Metrics::track_connection`
+pub struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_requests_and_histograms() {
+        let metrics = Metrics::new("origin");
+        metrics.observe_request("/orders", 200, Duration::from_millis(20));
+        metrics.observe_request("/orders", 200, Duration::from_secs(20));
+        metrics.observe_request("/orders/{id}", 404, Duration::from_millis(1));
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "aspirin_http_requests_total{service=\"origin\",route=\"/orders\",status=\"200\"} 2"
+        ));
+        assert!(out.contains(
+            "aspirin_http_request_duration_seconds_bucket{service=\"origin\",route=\"/orders\",le=\"0.025\"} 1"
+        ));
+        assert!(out.contains(
+            "aspirin_http_request_duration_seconds_bucket{service=\"origin\",route=\"/orders\",le=\"+Inf\"} 2"
+        ));
+    }
+
+    #[test]
+    fn test_guards_track_connections_and_db_calls() {
+        let metrics = Arc::new(Metrics::new("origin"));
+        let guard = metrics.track_connection();
+        drop(metrics.time_db("get_order"));
+        assert!(metrics
+            .render()
+            .contains("aspirin_connections_in_flight{service=\"origin\"} 1"));
+        assert!(metrics
+            .render()
+            .contains("aspirin_db_query_duration_seconds_count{method=\"get_order\"} 1"));
+
+        drop(guard);
+        assert!(metrics
+            .render()
+            .contains("aspirin_connections_in_flight{service=\"origin\"} 0"));
+    }
+
+    #[test]
+    fn test_upstream_health() {
+        let metrics = Metrics::new("proxy");
+        metrics.set_upstream_health("127.0.0.1:8080", false);
+        metrics.set_upstream_health("127.0.0.1:8080", true);
+
+        let out = metrics.render();
+        assert!(out.contains("aspirin_upstream_up{upstream=\"127.0.0.1:8080\"} 1"));
+        assert!(out.contains("aspirin_upstream_failures_total{upstream=\"127.0.0.1:8080\"} 1"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::sync::Arc;
+
+use crate::error::AspirinEatsError;
+use crate::http::{HttpRequest, HttpResponse};
+
+/// Code that runs around the handler of every request, such as CORS or authentication.
+/// Both hooks do nothing by default
+pub trait Middleware: Send + Sync {
+    /// Runs before the handler, in the order the middleware was added, and may change the
+    /// request. Returning a response answers the request without running the handler or
+    /// any later middleware
+    fn before(&self, _request: &mut HttpRequest) -> Result<Option<HttpResponse>, AspirinEatsError> {
+        Ok(None)
+    }
+
+    /// Runs after the handler, in reverse order, and may change the response. Only runs if
+    /// `before` ran for this middleware
+    fn after(
+        &self,
+        _request: &HttpRequest,
+        _response: &mut HttpResponse,
+    ) -> Result<(), AspirinEatsError> {
+        Ok(())
+    }
+}
+
+/// Middleware composed in order around a handler. The first middleware added is the
+/// outermost: its `before` runs first and its `after` last. Errors from hooks and handlers
+/// are turned into responses by the chain with `From<AspirinEatsError> for HttpResponse`,
+/// and the response is still passed to the `after` hooks of the enclosing middleware.
+/// Cheap to clone; every clone shares the same middleware
+#[derive(Clone, Default)]
+pub struct Chain {
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Chain::default()
+    }
+
+    /// Add a middleware inside the ones already in the chain
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Run the `before` hooks in order, stopping at the first one that answers the request
+    /// itself or fails. Returns how many middleware were entered, for `after`, and the
+    /// response to send instead of running the handler, if any
+    pub fn before(&self, request: &mut HttpRequest) -> (usize, Option<HttpResponse>) {
+        for (i, layer) in self.layers.iter().enumerate() {
+            match layer.before(request) {
+                Ok(None) => {}
+                Ok(Some(response)) => return (i + 1, Some(response)),
+                Err(e) => return (i + 1, Some(HttpResponse::from(e))),
+            }
+        }
+        (self.layers.len(), None)
+    }
+
+    /// Run the `after` hooks of the first `entered` middleware, innermost first
+    pub fn after(&self, entered: usize, request: &HttpRequest, response: &mut HttpResponse) {
+        for layer in self.layers[..entered].iter().rev() {
+            if let Err(e) = layer.after(request, response) {
+                *response = HttpResponse::from(e);
+            }
+        }
+    }
+
+    /// Run a handler through the whole chain
+    pub fn run<F>(&self, request: &mut HttpRequest, handler: F) -> HttpResponse
+    where
+        F: FnOnce(&mut HttpRequest) -> Result<HttpResponse, AspirinEatsError>,
+    {
+        let (entered, response) = self.before(request);
+        let mut response =
+            response.unwrap_or_else(|| handler(request).unwrap_or_else(HttpResponse::from));
+        self.after(entered, request, &mut response);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Records when its hooks run, and answers requests for `stop` itself
+    struct Probe {
+        name: &'static str,
+        stop: Option<&'static str>,
+        trace: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Probe {
+        fn before(
+            &self,
+            request: &mut HttpRequest,
+        ) -> Result<Option<HttpResponse>, AspirinEatsError> {
+            self.trace
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.name));
+            request.set_header("X-Seen-By", self.name);
+            match self.stop {
+                Some("error") if request.route() == "/error" => Err(AspirinEatsError::NotFound),
+                Some(route) if request.route() == route => {
+                    Ok(Some(HttpResponse::new(401, "Unauthorized", "")))
+                }
+                _ => Ok(None),
+            }
+        }
+
+        fn after(
+            &self,
+            _request: &HttpRequest,
+            response: &mut HttpResponse,
+        ) -> Result<(), AspirinEatsError> {
+            self.trace
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.name));
+            response.add_vary(self.name);
+            Ok(())
+        }
+    }
+
+    fn chain(trace: &Arc<Mutex<Vec<String>>>) -> Chain {
+        let probe = |name, stop| Probe {
+            name,
+            stop,
+            trace: trace.clone(),
+        };
+        Chain::new()
+            .with(probe("outer", None))
+            .with(probe("auth", Some("/private")))
+            .with(probe("inner", Some("error")))
+    }
+
+    fn run(chain: &Chain, path: &str) -> (HttpResponse, Option<String>) {
+        let mut request: HttpRequest = format!("GET {} HTTP/1.1\r\n\r\n", path).parse().unwrap();
+        let mut seen = None;
+        let response = chain.run(&mut request, |request| {
+            seen = request.header("X-Seen-By").map(str::to_string);
+            match request.route() {
+                "/missing" => Err(AspirinEatsError::NotFound),
+                _ => Ok(HttpResponse::new(200, "OK", "")),
+            }
+        });
+        (response, seen)
+    }
+
+    #[test]
+    fn test_chain_runs_in_order() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let (response, seen) = run(&chain(&trace), "/");
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(seen.as_deref(), Some("inner"));
+        assert_eq!(response.header("Vary"), Some("inner, auth, outer"));
+        assert_eq!(
+            *trace.lock().unwrap(),
+            vec![
+                "before outer",
+                "before auth",
+                "before inner",
+                "after inner",
+                "after auth",
+                "after outer"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chain_short_circuits() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let (response, seen) = run(&chain(&trace), "/private");
+
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(seen, None);
+        assert_eq!(
+            *trace.lock().unwrap(),
+            vec!["before outer", "before auth", "after auth", "after outer"]
+        );
+    }
+
+    #[test]
+    fn test_chain_turns_errors_into_responses() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let chain = chain(&trace);
+
+        let (response, _) = run(&chain, "/missing");
+        assert_eq!(response, {
+            let mut expected = HttpResponse::from(AspirinEatsError::NotFound);
+            expected.add_vary("inner");
+            expected.add_vary("auth");
+            expected.add_vary("outer");
+            expected
+        });
+
+        let (response, seen) = run(&chain, "/error");
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(seen, None);
+        assert_eq!(response.header("Vary"), Some("inner, auth, outer"));
+    }
+}
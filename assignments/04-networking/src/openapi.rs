@@ -0,0 +1,214 @@
+use std::sync::OnceLock;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::api::{Payload, ENDPOINTS};
+use crate::dispatch::Courier;
+use crate::food::{Customer, CustomerRequest, Ingredient, Order, OrderRequest, OrderStatus};
+use crate::inventory::{InventoryItem, StockUpdate};
+use crate::reports::{HourlyReport, ItemsReport, SalesReport};
+use crate::webhooks::{DeadLetter, Webhook, WebhookRequest};
+use crate::websocket::{ClientMessage, ServerMessage};
+
+/// Get the OpenAPI document for the origin, generated once from the route table and the
+/// food types
+pub fn document() -> &'static str {
+    static DOCUMENT: OnceLock<String> = OnceLock::new();
+    DOCUMENT.get_or_init(generate)
+}
+
+/// Generate the OpenAPI 3 document as pretty-printed JSON
+pub fn generate() -> String {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+
+    for endpoint in ENDPOINTS {
+        let mut operation = Map::new();
+        operation.insert("summary".to_string(), json!(endpoint.summary));
+        let public = [("/orders/{id}", "order"), ("/customers/{id}", "customer")]
+            .into_iter()
+            .find(|(prefix, _)| endpoint.path.starts_with(prefix));
+        if let Some((_, resource)) = public {
+            operation.insert(
+                "parameters".to_string(),
+                json!([{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "description": format!("The {resource}'s public `uuid`"),
+                    "schema": { "type": "string", "format": "uuid" }
+                }]),
+            );
+        } else if endpoint.path.contains("{id}") {
+            operation.insert(
+                "parameters".to_string(),
+                json!([{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "integer", "format": "int64" }
+                }]),
+            );
+        }
+        if endpoint.path.contains("{ingredient}") {
+            let names: Vec<&str> = Ingredient::ALL.iter().map(Ingredient::name).collect();
+            operation.insert(
+                "parameters".to_string(),
+                json!([{
+                    "name": "ingredient",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string", "enum": names }
+                }]),
+            );
+        }
+        if endpoint.path.starts_with("/reports/") {
+            let time = "Date (YYYY-MM-DD, UTC) or seconds since the Unix epoch";
+            operation.insert(
+                "parameters".to_string(),
+                json!([
+                    {
+                        "name": "from",
+                        "in": "query",
+                        "description": format!("{time}. Orders placed at or after it are included"),
+                        "schema": { "type": "string" }
+                    },
+                    {
+                        "name": "to",
+                        "in": "query",
+                        "description": format!("{time}. Orders placed before it are included"),
+                        "schema": { "type": "string" }
+                    },
+                    {
+                        "name": "format",
+                        "in": "query",
+                        "schema": { "type": "string", "enum": ["json", "csv"], "default": "json" }
+                    }
+                ]),
+            );
+        }
+        if let Some(payload) = endpoint.request {
+            operation.insert(
+                "requestBody".to_string(),
+                json!({ "required": true, "content": content(&mut generator, payload) }),
+            );
+        }
+
+        let (status, payload) = endpoint.response;
+        let mut responses = Map::new();
+        responses.insert(
+            status.to_string(),
+            json!({ "description": "Success", "content": content(&mut generator, payload) }),
+        );
+        for (status, description) in [
+            (400, "Malformed request"),
+            (404, "Resource not found"),
+            (409, "Ingredients out of stock"),
+            (405, "Method not allowed"),
+            (408, "Request not received in time"),
+            (413, "Request body too large"),
+            (431, "Request line or headers too large"),
+            (500, "Internal server error"),
+        ] {
+            responses.insert(
+                status.to_string(),
+                json!({ "description": description, "content": content(&mut generator, Payload::Text) }),
+            );
+        }
+        operation.insert("responses".to_string(), Value::Object(responses));
+
+        paths
+            .entry(endpoint.path)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .expect("path item should be an object")
+            .insert(endpoint.method.to_lowercase(), Value::Object(operation));
+    }
+
+    let document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Aspirin Eats",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": generator.take_definitions() },
+    });
+    serde_json::to_string_pretty(&document).expect("OpenAPI document should serialize") + "\n"
+}
+
+fn content(generator: &mut SchemaGenerator, payload: Payload) -> Value {
+    let (media_type, schema) = match payload {
+        Payload::Order => ("application/json", schema::<Order>(generator)),
+        Payload::Orders => ("application/json", schema::<Vec<Order>>(generator)),
+        Payload::OrderRequest => ("application/json", schema::<OrderRequest>(generator)),
+        Payload::OrderStatus => ("application/json", schema::<OrderStatus>(generator)),
+        Payload::Customer => ("application/json", schema::<Customer>(generator)),
+        Payload::CustomerRequest => ("application/json", schema::<CustomerRequest>(generator)),
+        Payload::Inventory => ("application/json", schema::<Vec<InventoryItem>>(generator)),
+        Payload::InventoryItem => ("application/json", schema::<InventoryItem>(generator)),
+        Payload::StockUpdate => ("application/json", schema::<StockUpdate>(generator)),
+        Payload::Couriers => ("application/json", schema::<Vec<Courier>>(generator)),
+        Payload::Webhook => ("application/json", schema::<Webhook>(generator)),
+        Payload::Webhooks => ("application/json", schema::<Vec<Webhook>>(generator)),
+        Payload::WebhookRequest => ("application/json", schema::<WebhookRequest>(generator)),
+        Payload::DeadLetters => ("application/json", schema::<Vec<DeadLetter>>(generator)),
+        Payload::SalesReport => return report_content(schema::<SalesReport>(generator)),
+        Payload::ItemsReport => return report_content(schema::<ItemsReport>(generator)),
+        Payload::HourlyReport => return report_content(schema::<HourlyReport>(generator)),
+        Payload::Text => ("text/plain", json!({ "type": "string" })),
+        Payload::EventStream => ("text/event-stream", json!({ "type": "string" })),
+        // A socket has no body; its messages are only listed among the schemas
+        Payload::WebSocket => {
+            schema::<ClientMessage>(generator);
+            schema::<ServerMessage>(generator);
+            return json!({});
+        }
+        Payload::Metrics => ("text/plain", json!({ "type": "string" })),
+        Payload::Json => ("application/json", json!({ "type": "object" })),
+    };
+    json!({ media_type: { "schema": schema } })
+}
+
+/// Reports are JSON by default and CSV with `format=csv`
+fn report_content(schema: Value) -> Value {
+    json!({
+        "application/json": { "schema": schema },
+        "text/csv": { "schema": { "type": "string" } },
+    })
+}
+
+fn schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(generator.subschema_for::<T>()).expect("schema should serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    /// Fails when `openapi.json` is out of date. Run with `UPDATE_OPENAPI=1` to rewrite it
+    #[test]
+    fn test_committed_spec_is_up_to_date() {
+        let generated = generate();
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(SPEC_PATH, &generated).unwrap();
+        }
+        let committed = std::fs::read_to_string(SPEC_PATH).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "openapi.json is out of date; run `UPDATE_OPENAPI=1 cargo test` and commit it"
+        );
+    }
+
+    #[test]
+    fn test_menu_items_are_externally_tagged() {
+        let document: Value = serde_json::from_str(document()).unwrap();
+        let menu_item = document["components"]["schemas"]["MenuItem"].to_string();
+        assert!(menu_item.contains("\"Burger\""));
+        assert!(menu_item.contains("\"Fries\""));
+    }
+}
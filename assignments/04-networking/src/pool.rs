@@ -0,0 +1,235 @@
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many upstream connections are kept open, and for how long
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolConfig {
+    /// Most idle connections kept open. Connections returned to a full pool are closed
+    pub max_idle: usize,
+
+    /// How long a connection may sit idle before it is closed instead of reused. Keep it
+    /// below the upstream's own idle timeout, so connections are rarely reused just as the
+    /// upstream closes them
+    pub idle_timeout: Duration,
+
+    /// How long to wait for a new connection to be accepted
+    pub connect_timeout: Duration,
+
+    /// How long a read or write on a connection may wait for the upstream. Keep it above the
+    /// keep-alive interval of event streams, or quiet streams are cut off
+    pub io_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_idle: 16,
+            idle_timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(3),
+            io_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Keep-alive connections to a single upstream, reused across requests
+pub struct Pool<C> {
+    connect: Box<dyn Fn() -> io::Result<C> + Send + Sync>,
+    idle: Mutex<Vec<(C, Instant)>>,
+    open: Box<dyn Fn(&C) -> bool + Send + Sync>,
+    config: PoolConfig,
+}
+
+impl<C> Pool<C> {
+    /// Create a pool that opens new connections with `connect`. The connect timeout in
+    /// `config` is up to `connect` to honor
+    pub fn new<F>(config: PoolConfig, connect: F) -> Self
+    where
+        F: Fn() -> io::Result<C> + Send + Sync + 'static,
+    {
+        Pool {
+            connect: Box::new(connect),
+            idle: Mutex::new(Vec::new()),
+            open: Box::new(|_| true),
+            config,
+        }
+    }
+
+    /// Check idle connections with `open` before `get_open` hands them out. Without a check
+    /// every idle connection is taken to still be open
+    pub fn with_check<F>(mut self, open: F) -> Self
+    where
+        F: Fn(&C) -> bool + Send + Sync + 'static,
+    {
+        self.open = Box::new(open);
+        self
+    }
+
+    /// Take the most recently used idle connection, or open a new one if there is none.
+    /// Returns the connection and whether it was reused; reused connections may have been
+    /// closed by the upstream in the meantime
+    pub fn get(&self) -> io::Result<(C, bool)> {
+        {
+            let mut idle = self.idle.lock().expect("pool lock poisoned");
+            idle.retain(|(_, since)| since.elapsed() < self.config.idle_timeout);
+            if let Some((connection, _)) = idle.pop() {
+                return Ok((connection, true));
+            }
+        }
+        (self.connect)().map(|connection| (connection, false))
+    }
+
+    /// Like `get`, but idle connections that fail the pool's check are closed instead of
+    /// reused. For requests that must not be sent again if the connection turns out closed
+    pub fn get_open(&self) -> io::Result<(C, bool)> {
+        loop {
+            let (connection, reused) = self.get()?;
+            if !reused || (self.open)(&connection) {
+                return Ok((connection, reused));
+            }
+        }
+    }
+
+    /// Return a connection that is ready for another request
+    pub fn put(&self, connection: C) {
+        let mut idle = self.idle.lock().expect("pool lock poisoned");
+        if idle.len() < self.config.max_idle {
+            idle.push((connection, Instant::now()));
+        }
+    }
+
+    /// Number of idle connections in the pool
+    pub fn idle(&self) -> usize {
+        self.idle.lock().expect("pool lock poisoned").len()
+    }
+}
+
+impl Pool<TcpStream> {
+    /// Create a pool of TCP connections to `addr`, whose reads and writes give up after the
+    /// configured `io_timeout`
+    pub fn tcp(addr: &str, config: PoolConfig) -> Self {
+        let addr = addr.to_string();
+        Pool::new(config, move || {
+            let mut last_error =
+                io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+            for addr in addr.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&addr, config.connect_timeout) {
+                    Ok(stream) => {
+                        stream.set_read_timeout(Some(config.io_timeout))?;
+                        stream.set_write_timeout(Some(config.io_timeout))?;
+                        return Ok(stream);
+                    }
+                    Err(e) => last_error = e,
+                }
+            }
+            Err(last_error)
+        })
+        .with_check(tcp_is_open)
+    }
+}
+
+/// Whether the server is still holding a TCP connection open: it has neither closed it nor
+/// sent anything on it that no request asked for. Checked without waiting
+pub fn tcp_is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let peeked = stream.peek(&mut [0]);
+    let restored = stream.set_nonblocking(false);
+    matches!(peeked, Err(e) if e.kind() == io::ErrorKind::WouldBlock) && restored.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn counting_pool(config: PoolConfig) -> (Pool<usize>, Arc<AtomicUsize>) {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let pool = Pool::new(config, move || Ok(counter.fetch_add(1, Ordering::SeqCst)));
+        (pool, opened)
+    }
+
+    #[test]
+    fn test_pool_reuses_connections() {
+        let (pool, opened) = counting_pool(PoolConfig {
+            max_idle: 1,
+            ..PoolConfig::default()
+        });
+
+        let (first, reused) = pool.get().unwrap();
+        assert!(!reused);
+        let (second, _) = pool.get().unwrap();
+        pool.put(first);
+        pool.put(second);
+        assert_eq!(pool.idle(), 1);
+
+        assert_eq!(pool.get().unwrap(), (first, true));
+        assert_eq!(pool.get().unwrap(), (2, false));
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_pool_drops_expired_connections() {
+        let (pool, _) = counting_pool(PoolConfig {
+            idle_timeout: Duration::from_millis(20),
+            ..PoolConfig::default()
+        });
+
+        let (connection, _) = pool.get().unwrap();
+        pool.put(connection);
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(pool.get().unwrap(), (1, false));
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn test_pool_checks_connections_on_request() {
+        let (pool, _) = counting_pool(PoolConfig::default());
+        let pool = pool.with_check(|&connection| connection != 0);
+
+        let (connection, _) = pool.get().unwrap();
+        pool.put(connection);
+        assert_eq!(pool.get_open().unwrap(), (1, false));
+        assert_eq!(pool.idle(), 0);
+
+        pool.put(1);
+        assert_eq!(pool.get_open().unwrap(), (1, true));
+    }
+
+    #[test]
+    fn test_tcp_is_open() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        assert!(tcp_is_open(&stream));
+
+        drop(accepted);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!tcp_is_open(&stream));
+    }
+
+    #[test]
+    fn test_tcp_pool_sets_timeouts() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = PoolConfig {
+            io_timeout: Duration::from_secs(1),
+            ..PoolConfig::default()
+        };
+        let pool = Pool::tcp(&listener.local_addr().unwrap().to_string(), config);
+
+        let (stream, _) = pool.get().unwrap();
+        assert_eq!(stream.read_timeout().unwrap(), Some(config.io_timeout));
+        assert_eq!(stream.write_timeout().unwrap(), Some(config.io_timeout));
+    }
+
+    #[test]
+    fn test_tcp_pool_connect_error() {
+        let pool = Pool::tcp("127.0.0.1:1", PoolConfig::default());
+        assert!(pool.get().is_err());
+    }
+}
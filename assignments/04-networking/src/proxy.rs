@@ -0,0 +1,1358 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+use crate::api;
+use crate::breaker::BreakerConfig;
+use crate::clock::SystemClock;
+use crate::compression;
+use crate::error::AspirinEatsError;
+use crate::http::{self, Deadline, HttpRequest, HttpResponse, Limits, TimedStream};
+use crate::log::{self, Logger, REQUEST_ID_HEADER};
+use crate::metrics::{self, Metrics};
+use crate::middleware::{Chain, Middleware};
+use crate::pool::PoolConfig;
+use crate::routing::{HeaderRewrite, ProxyConfig, Routing, Server};
+use crate::server::{self, write_response};
+
+/// What happened to a request forwarded to the origin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forwarded {
+    /// Status code of the response sent to the client
+    pub status: u16,
+
+    /// Bytes sent to the client
+    pub bytes: u64,
+
+    /// Whether the origin connection is ready for another request
+    pub reusable: bool,
+}
+
+/// Forward one request to the origin over a keep-alive connection and copy its response
+/// back to the client. `after` may change the response head before it is sent; the body is
+/// streamed, so it cannot be changed. If `after` replaces the response instead, with another
+/// status or a body of its own, the origin's body is skipped and the replacement is sent.
+/// Bodies are passed through untouched, unless the client accepts a compressed body that the
+/// origin did not compress; then it is compressed here
+pub fn forward<W, O, F>(
+    request: &mut HttpRequest,
+    client: &mut W,
+    origin: &mut O,
+    after: F,
+) -> Result<Forwarded, AspirinEatsError>
+where
+    W: Write,
+    O: Read + Write,
+    F: Fn(&HttpRequest, &mut HttpResponse),
+{
+    let mut origin = BufReader::new(origin);
+    let head = send_request(request, &mut origin)?.ok_or(AspirinEatsError::BadGateway)?;
+    relay_response(head, request, client, &mut origin, after)
+}
+
+/// Send a request upstream and read the head of the response. Returns `None` if the origin
+/// closed or reset the connection without sending anything
+fn send_request<O: Read + Write>(
+    request: &mut HttpRequest,
+    origin: &mut BufReader<O>,
+) -> Result<Option<String>, AspirinEatsError> {
+    request.set_header("Connection", "keep-alive");
+    let sent = origin
+        .get_mut()
+        .write_all(request.to_string().as_bytes())
+        .and_then(|()| origin.get_mut().flush());
+    match sent {
+        Err(e) if is_closed(&e) => return Ok(None),
+        sent => sent?,
+    }
+
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        match origin.read_line(&mut line) {
+            Ok(0) if head.is_empty() => return Ok(None),
+            Ok(0) => return Err(AspirinEatsError::BadGateway),
+            Err(e) if head.is_empty() && is_closed(&e) => return Ok(None),
+            read => read?,
+        };
+        head.push_str(&line);
+        if line == "\r\n" || line == "\n" {
+            return Ok(Some(head));
+        }
+    }
+}
+
+/// Whether sending a request twice has the same effect as sending it once, so it may be sent
+/// again when the connection it went out on turns out to have been closed
+fn is_idempotent(method: Option<&str>) -> bool {
+    matches!(method, Some("GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS"))
+}
+
+/// Whether an error means the peer closed the connection
+fn is_closed(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// An upstream connection that remembers whether a read or write on it timed out, so a
+/// failed response can be blamed on the upstream rather than the client
+struct Watched<O> {
+    inner: O,
+    timed_out: bool,
+}
+
+impl<O> Watched<O> {
+    fn new(inner: O) -> Self {
+        Watched {
+            inner,
+            timed_out: false,
+        }
+    }
+
+    fn watch<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result {
+            self.timed_out |= http::is_timeout(e);
+        }
+        result
+    }
+}
+
+impl<O: Read> Read for Watched<O> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf);
+        self.watch(read)
+    }
+}
+
+impl<O: Write> Write for Watched<O> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf);
+        self.watch(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let flushed = self.inner.flush();
+        self.watch(flushed)
+    }
+}
+
+/// Copy a response whose head has been read from the origin to the client. Responses
+/// framed by `Content-Length` leave the origin connection reusable; others, like event
+/// streams, are copied until the origin closes the connection. The client's connection is
+/// closed after the response
+fn relay_response<W, O, F>(
+    head: String,
+    request: &HttpRequest,
+    client: &mut W,
+    origin: &mut BufReader<O>,
+    after: F,
+) -> Result<Forwarded, AspirinEatsError>
+where
+    W: Write,
+    O: Read,
+    F: Fn(&HttpRequest, &mut HttpResponse),
+{
+    let mut response: HttpResponse = head.parse().map_err(|_| AspirinEatsError::BadGateway)?;
+    let bodiless = request.method.as_deref() == Some("HEAD")
+        || matches!(response.status_code(), 100..=199 | 204 | 304);
+    let length: Option<u64> = if bodiless {
+        Some(0)
+    } else {
+        response
+            .header("Content-Length")
+            .and_then(|length| length.trim().parse().ok())
+    };
+    let reusable = length.is_some()
+        && !response
+            .header("Connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+
+    let origin_status = response.status_code();
+    after(request, &mut response);
+    let status = response.status_code();
+    let accept_encoding = request.header("Accept-Encoding");
+
+    // The parsed head has no body, so one set by `after` belongs to a replacement response.
+    // The origin's body is left unread, and the connection with it
+    if status != origin_status || !response.body().is_empty() {
+        let (message, _) = server::frame_response(response, true, accept_encoding)?;
+        client.write_all(&message)?;
+        client.flush()?;
+        return Ok(Forwarded {
+            status,
+            bytes: message.len() as u64,
+            reusable: false,
+        });
+    }
+
+    response.set_header("Connection", "close");
+    if let Some(length) = length.filter(|&length| {
+        accept_encoding.and_then(compression::negotiate).is_some()
+            && compression::is_compressible(&response, length as usize)
+    }) {
+        // Grown as the body arrives rather than sized up front, so a Content-Length the
+        // origin does not live up to cannot make the proxy allocate it
+        let mut body = Vec::new();
+        if origin.take(length).read_to_end(&mut body)? < length as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let body = compression::encode(&mut response, body, accept_encoding)?;
+        response.set_header("Content-Length", &body.len().to_string());
+        let head = response.head();
+        client.write_all(head.as_bytes())?;
+        client.write_all(&body)?;
+        client.flush()?;
+        return Ok(Forwarded {
+            status,
+            bytes: (head.len() + body.len()) as u64,
+            reusable,
+        });
+    }
+
+    let head = response.head();
+    client.write_all(head.as_bytes())?;
+    let copied = match length {
+        Some(length) => {
+            let copied = io::copy(&mut origin.take(length), client)?;
+            if copied < length {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            copied
+        }
+        None => io::copy(origin, client)?,
+    };
+    client.flush()?;
+    Ok(Forwarded {
+        status,
+        bytes: head.len() as u64 + copied,
+        reusable,
+    })
+}
+
+/// Reverse proxy that hands client requests to upstream servers picked by path prefix, over
+/// pools of keep-alive connections guarded by circuit breakers. Its routing can be replaced
+/// while it runs. Cheap to clone; every clone shares the same logger, metrics and routing
+#[derive(Clone)]
+pub struct ReverseProxy {
+    logger: Arc<Logger>,
+    metrics: Arc<Metrics>,
+    middleware: Chain,
+    limits: Limits,
+    pool_config: PoolConfig,
+    breaker_config: BreakerConfig,
+    routing: Arc<RwLock<Arc<Routing<TcpStream>>>>,
+}
+
+impl ReverseProxy {
+    /// Create a new ReverseProxy that forwards everything to the given origin, logging to
+    /// stderr
+    pub fn new(origin_addr: &str) -> Self {
+        Self::with_config(ProxyConfig::single(origin_addr))
+    }
+
+    /// Create a new ReverseProxy that routes requests as `config` says, logging to stderr
+    pub fn from_config(config: ProxyConfig) -> Result<Self, AspirinEatsError> {
+        config.validate()?;
+        Ok(Self::with_config(config))
+    }
+
+    fn with_config(config: ProxyConfig) -> Self {
+        let (pool_config, breaker_config) = (PoolConfig::default(), BreakerConfig::default());
+        let routing = Routing::build(config, Arc::new(SystemClock), None, |addr| {
+            Server::tcp(addr, pool_config, breaker_config)
+        });
+        ReverseProxy {
+            logger: Arc::new(Logger::stderr("proxy")),
+            metrics: Arc::new(Metrics::new("proxy")),
+            middleware: Chain::new(),
+            limits: Limits::default(),
+            pool_config,
+            breaker_config,
+            routing: Arc::new(RwLock::new(Arc::new(routing))),
+        }
+    }
+
+    /// Use the given timeouts and size limits for client requests instead of the defaults
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Use the given limits for the pools of upstream connections
+    pub fn with_pool(mut self, config: PoolConfig) -> Self {
+        self.pool_config = config;
+        self.rebuild(self.routing().config().clone());
+        self
+    }
+
+    /// Guard every upstream server with a circuit breaker configured like this
+    pub fn with_breaker(mut self, config: BreakerConfig) -> Self {
+        self.breaker_config = config;
+        self.rebuild(self.routing().config().clone());
+        self
+    }
+
+    /// Use the given logger for access and error logs
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = Arc::new(logger);
+        self
+    }
+
+    /// Run every request through the given middleware, inside any added before it.
+    /// Requests answered by a middleware never reach the origin
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware = self.middleware.with(middleware);
+        self
+    }
+
+    /// Accept connections forever, handling each one on its own thread
+    pub fn serve(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let proxy = self.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = proxy.handle_client(stream) {
+                            proxy.logger.error(None, &e);
+                        }
+                    });
+                }
+                Err(e) => self.logger.error(None, &e.into()),
+            }
+        }
+    }
+
+    /// The routing requests are currently forwarded with
+    pub fn routing(&self) -> Arc<Routing<TcpStream>> {
+        self.routing.read().expect("routing lock poisoned").clone()
+    }
+
+    /// Replace the routing with one for `config`, leaving it as it was if the configuration
+    /// is invalid. Requests already being forwarded finish with the routing they started
+    /// with. Upstream servers and rate limits that are unchanged carry over, along with
+    /// their idle connections and what is left of the limit
+    pub fn reconfigure(&self, config: ProxyConfig) -> Result<(), AspirinEatsError> {
+        let mut routing = self.routing.write().expect("routing lock poisoned");
+        let reloaded = routing.reload(config, |addr| self.server(addr))?;
+        *routing = Arc::new(reloaded);
+        Ok(())
+    }
+
+    /// Reconfigure from the configuration file at `path`, logging the outcome
+    pub fn reload(&self, path: &Path) -> Result<(), AspirinEatsError> {
+        let result = ProxyConfig::load(path).and_then(|config| self.reconfigure(config));
+        match &result {
+            Ok(()) => self
+                .logger
+                .info(&format!("Reloaded routing from {}", path.display())),
+            Err(e) => self.logger.error(None, e),
+        }
+        result
+    }
+
+    /// Start a background job that reloads the configuration file at `path` whenever its
+    /// modification time or size changes, checking every `interval`
+    pub fn watch_config(&self, path: PathBuf, interval: Duration) -> JoinHandle<()> {
+        let proxy = self.clone();
+        let stamp = |path: &Path| {
+            fs::metadata(path)
+                .ok()
+                .map(|metadata| (metadata.modified().ok(), metadata.len()))
+        };
+        let mut last = stamp(&path);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let current = stamp(&path);
+            if current.is_some() && current != last {
+                last = current;
+                let _ = proxy.reload(&path);
+            }
+        })
+    }
+
+    /// Start a background job that reloads the configuration file at `path` whenever the
+    /// process receives SIGHUP
+    #[cfg(unix)]
+    pub fn reload_on_hangup(&self, path: PathBuf) -> io::Result<JoinHandle<()>> {
+        let mut signals = Signals::new([SIGHUP])?;
+        let proxy = self.clone();
+        Ok(thread::spawn(move || {
+            for _ in signals.forever() {
+                let _ = proxy.reload(&path);
+            }
+        }))
+    }
+
+    /// Replace the routing with one for `config` built from scratch, for when the pool or
+    /// breaker settings change
+    fn rebuild(&mut self, config: ProxyConfig) {
+        let routing = Routing::build(config, Arc::new(SystemClock), None, |addr| {
+            self.server(addr)
+        });
+        self.routing = Arc::new(RwLock::new(Arc::new(routing)));
+    }
+
+    fn server(&self, addr: &str) -> Server<TcpStream> {
+        Server::tcp(addr, self.pool_config, self.breaker_config)
+    }
+
+    fn handle_client(&self, stream: TcpStream) -> Result<(), AspirinEatsError> {
+        let _connection = self.metrics.track_connection();
+        stream.set_write_timeout(Some(self.limits.write_timeout))?;
+        let mut reader = BufReader::new(TimedStream::new(stream.try_clone()?));
+        let mut writer = stream;
+        self.proxy_request(&mut reader, &mut writer, &self.routing())
+    }
+
+    /// Read a request from the client, within the proxy's limits, and forward it to an
+    /// upstream server picked by `routing`. Every request is tagged with a request ID
+    /// (generated here unless the client sent one) that is passed on upstream and written to
+    /// the access log. `GET /metrics` is answered by the proxy itself. Requests no route
+    /// covers get 404, requests over their route's rate limit get 429, and while the circuit
+    /// breakers of every server in the upstream group are open clients get 503 without any
+    /// of them being tried
+    pub fn proxy_request<R, W, O>(
+        &self,
+        client_reader: &mut R,
+        client_writer: &mut W,
+        routing: &Routing<O>,
+    ) -> Result<(), AspirinEatsError>
+    where
+        R: BufRead + Deadline,
+        W: Write,
+        O: Read + Write,
+    {
+        let mut request = match http::read_request_limited(client_reader, &self.limits) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                self.logger.error(None, &e);
+                write_response(client_writer, HttpResponse::from(e), true, None)?;
+                return Ok(());
+            }
+        };
+
+        let started = Instant::now();
+        let request_id = log::request_id(&request);
+        request.set_header(REQUEST_ID_HEADER, &request_id);
+
+        let (entered, answered) = self.middleware.before(&mut request);
+        let is_metrics = request.method.as_deref() == Some("GET") && request.route() == "/metrics";
+        let (status, bytes) = match answered {
+            None if !is_metrics => {
+                self.forward_routed(&mut request, client_writer, routing, entered, &request_id)?
+            }
+            answered => {
+                let response = answered.unwrap_or_else(|| {
+                    HttpResponse::new(200, "OK", &self.metrics.render())
+                        .with_header("Content-Type", metrics::CONTENT_TYPE)
+                });
+                self.respond(client_writer, entered, &request, &request_id, response)?
+            }
+        };
+
+        let latency = started.elapsed();
+        self.metrics
+            .observe_request(api::route_template(request.route()), status, latency);
+        self.logger.access(
+            &request_id,
+            request.method.as_deref().unwrap_or("-"),
+            request.path.as_deref().unwrap_or("-"),
+            status,
+            bytes,
+            latency,
+        );
+        Ok(())
+    }
+
+    /// Forward a request along its route, or answer it here if the route will not take it.
+    /// Returns the status code and number of bytes sent to the client
+    fn forward_routed<W, O>(
+        &self,
+        request: &mut HttpRequest,
+        client_writer: &mut W,
+        routing: &Routing<O>,
+        entered: usize,
+        request_id: &str,
+    ) -> Result<(u16, u64), AspirinEatsError>
+    where
+        W: Write,
+        O: Read + Write,
+    {
+        let Some(route) = routing.route(request.route()) else {
+            let response = HttpResponse::from(AspirinEatsError::NotFound);
+            return self.respond(client_writer, entered, request, request_id, response);
+        };
+        if let Err(retry_after) = route.admit() {
+            let response = HttpResponse::from(AspirinEatsError::TooManyRequests)
+                .with_header("Retry-After", &retry_after.as_secs().to_string());
+            return self.respond(client_writer, entered, request, request_id, response);
+        }
+        let server = match route.upstream().acquire() {
+            Ok(server) => server,
+            Err(retry_after) => {
+                let response = HttpResponse::from(AspirinEatsError::ServiceUnavailable)
+                    .with_header("Retry-After", &retry_after.as_secs().to_string());
+                return self.respond(client_writer, entered, request, request_id, response);
+            }
+        };
+
+        route.request_headers.rewrite_request(request);
+        let forwarded = self.forward_pooled(
+            request,
+            client_writer,
+            server,
+            entered,
+            &route.response_headers,
+        );
+        match forwarded {
+            Ok(forwarded) => Ok((forwarded.status, forwarded.bytes)),
+            Err(e) => {
+                self.logger.error(Some(request_id), &e);
+                let response = HttpResponse::from(AspirinEatsError::BadGateway);
+                self.respond(client_writer, entered, request, request_id, response)
+            }
+        }
+    }
+
+    /// Forward a request over a connection pooled for `server`, returning the connection to
+    /// the pool if it can be reused. If a pooled connection turns out to have been closed by
+    /// the server, idempotent requests are sent again on another; other requests only go out
+    /// on pooled connections that pass the pool's check, and get 502 if one closes anyway.
+    /// Any other failure is not retried. Whether the server answered, and whether it kept up
+    /// while the response was relayed, is recorded with its circuit breaker. The response
+    /// head is rewritten by `rewrite` before the middleware sees it
+    fn forward_pooled<W, O>(
+        &self,
+        request: &mut HttpRequest,
+        client_writer: &mut W,
+        server: &Server<O>,
+        entered: usize,
+        rewrite: &HeaderRewrite,
+    ) -> Result<Forwarded, AspirinEatsError>
+    where
+        W: Write,
+        O: Read + Write,
+    {
+        let idempotent = is_idempotent(request.method.as_deref());
+        loop {
+            let pooled = if idempotent {
+                server.pool.get()
+            } else {
+                server.pool.get_open()
+            };
+            let (mut connection, reused) = match pooled {
+                Ok(pooled) => pooled,
+                Err(e) => {
+                    self.record_upstream(server, false);
+                    return Err(e.into());
+                }
+            };
+            let mut origin = BufReader::new(Watched::new(&mut connection));
+            let head = match send_request(request, &mut origin) {
+                Ok(Some(head)) => head,
+                Ok(None) if reused && idempotent => continue,
+                // Closed as it was checked. The server may have seen the request, so it is
+                // not sent again
+                Ok(None) if reused => return Err(AspirinEatsError::BadGateway),
+                Ok(None) => {
+                    self.record_upstream(server, false);
+                    return Err(AspirinEatsError::BadGateway);
+                }
+                Err(e) => {
+                    self.record_upstream(server, false);
+                    return Err(e);
+                }
+            };
+            self.record_upstream(server, true);
+
+            let relayed = relay_response(
+                head,
+                request,
+                client_writer,
+                &mut origin,
+                |request, response| {
+                    rewrite.rewrite_response(response);
+                    self.middleware.after(entered, request, response)
+                },
+            );
+            let forwarded = match relayed {
+                Ok(forwarded) => forwarded,
+                Err(e) => {
+                    if origin.get_ref().timed_out {
+                        self.record_upstream(server, false);
+                    }
+                    return Err(e);
+                }
+            };
+            let reusable = forwarded.reusable && origin.buffer().is_empty();
+            drop(origin);
+            if reusable {
+                server.pool.put(connection);
+            }
+            return Ok(forwarded);
+        }
+    }
+
+    fn record_upstream<O>(&self, server: &Server<O>, up: bool) {
+        server.breaker.record(up);
+        self.metrics.set_upstream_health(server.addr(), up);
+    }
+
+    /// Finish a response the proxy answers itself: run the `after` hooks of the middleware
+    /// that were entered, then write it to the client. Returns its status code and the
+    /// number of body bytes written
+    fn respond<W: Write>(
+        &self,
+        client_writer: &mut W,
+        entered: usize,
+        request: &HttpRequest,
+        request_id: &str,
+        mut response: HttpResponse,
+    ) -> Result<(u16, u64), AspirinEatsError> {
+        self.middleware.after(entered, request, &mut response);
+        response.set_header(REQUEST_ID_HEADER, request_id);
+        let status = response.status_code();
+        let accept_encoding = request.header("Accept-Encoding");
+        let bytes = write_response(client_writer, response, true, accept_encoding)?;
+        Ok((status, bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breaker::CircuitBreaker;
+    use crate::clock::ManualClock;
+    use crate::db::AspirinEatsDb;
+    use crate::http::Limits;
+    use crate::pool::{self, Pool};
+    use crate::server::OriginServer;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// A fake origin connection with a canned response
+    struct MockOrigin {
+        response: Cursor<Vec<u8>>,
+        received: Arc<Mutex<Vec<u8>>>,
+        stalls: bool,
+    }
+
+    impl MockOrigin {
+        fn new(response: &[u8]) -> Self {
+            MockOrigin {
+                response: Cursor::new(response.to_vec()),
+                received: Arc::new(Mutex::new(Vec::new())),
+                stalls: false,
+            }
+        }
+
+        /// An origin that times out once it has sent `response`, instead of closing
+        fn stalling(response: &[u8]) -> Self {
+            MockOrigin {
+                stalls: true,
+                ..MockOrigin::new(response)
+            }
+        }
+
+        fn received(&self) -> String {
+            String::from_utf8(self.received.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Read for MockOrigin {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.response.read(buf)? {
+                0 if self.stalls && !buf.is_empty() => Err(io::ErrorKind::WouldBlock.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    impl Write for MockOrigin {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.received.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A pool whose connections all answer with `response`, and share what they receive
+    fn mock_pool(response: &'static [u8]) -> (Pool<MockOrigin>, Arc<Mutex<Vec<u8>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let shared = received.clone();
+        let pool = Pool::new(PoolConfig::default(), move || {
+            let mut origin = MockOrigin::new(response);
+            origin.received = shared.clone();
+            Ok(origin)
+        });
+        (pool, received)
+    }
+
+    /// A pool for an origin that refuses every connection, counting the attempts
+    fn refused_pool() -> (Pool<MockOrigin>, Arc<AtomicUsize>) {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let pool = Pool::new(PoolConfig::default(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+        });
+        (pool, attempts)
+    }
+
+    /// A pool for an origin that refuses connections until it is switched up, counting the
+    /// refused attempts. Once up, its connections answer with `response`
+    fn flaky_pool(
+        response: &'static [u8],
+    ) -> (Pool<MockOrigin>, Arc<AtomicBool>, Arc<AtomicUsize>) {
+        let up = Arc::new(AtomicBool::new(false));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (switch, counter) = (up.clone(), attempts.clone());
+        let pool = Pool::new(PoolConfig::default(), move || {
+            if switch.load(Ordering::SeqCst) {
+                return Ok(MockOrigin::new(response));
+            }
+            counter.fetch_add(1, Ordering::SeqCst);
+            Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+        });
+        (pool, up, attempts)
+    }
+
+    /// A pool of TCP connections to `addr`, counting the connections opened
+    fn counting_tcp_pool(addr: String) -> (Pool<TcpStream>, Arc<AtomicUsize>) {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let pool = Pool::new(PoolConfig::default(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            TcpStream::connect(&addr)
+        })
+        .with_check(pool::tcp_is_open);
+        (pool, opened)
+    }
+
+    fn test_proxy() -> ReverseProxy {
+        ReverseProxy::new("origin").with_logger(Logger::from_writer("proxy", Box::new(io::sink())))
+    }
+
+    /// Routing that sends everything to a single server, "origin", reached through `pool`
+    fn origin<O>(pool: Pool<O>) -> Routing<O> {
+        Routing::single(Server::new(
+            "origin",
+            pool,
+            CircuitBreaker::new(BreakerConfig::default()),
+        ))
+    }
+
+    fn proxy<O: Read + Write>(
+        proxy: &ReverseProxy,
+        request: &str,
+        routing: &Routing<O>,
+    ) -> HttpResponse {
+        let mut client_writer = Vec::new();
+        proxy
+            .proxy_request(&mut request.as_bytes(), &mut client_writer, routing)
+            .unwrap();
+        String::from_utf8(client_writer).unwrap().parse().unwrap()
+    }
+
+    /// Split a response that may not be text into its head and body
+    fn split_response(response: &[u8]) -> (HttpResponse, &[u8]) {
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = std::str::from_utf8(&response[..split]).unwrap();
+        (head.parse().unwrap(), &response[split..])
+    }
+
+    #[test]
+    fn test_forward() {
+        let mut origin = MockOrigin::new(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]");
+        let mut request: HttpRequest = "GET /orders HTTP/1.1\r\nHost: proxy\r\n\r\n"
+            .parse()
+            .unwrap();
+        let mut client = Vec::new();
+
+        let forwarded = forward(&mut request, &mut client, &mut origin, |_, _| {}).unwrap();
+
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]";
+        assert_eq!(
+            forwarded,
+            Forwarded {
+                status: 200,
+                bytes: response.len() as u64,
+                reusable: true
+            }
+        );
+        assert_eq!(String::from_utf8(client).unwrap(), response);
+        assert_eq!(
+            origin.received(),
+            "GET /orders HTTP/1.1\r\nHost: proxy\r\nConnection: keep-alive\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_forward_replaced_responses_skip_the_origin_body() {
+        let mut origin = MockOrigin::new(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n[1,2,3,4]");
+        let mut request: HttpRequest = "GET /orders HTTP/1.1\r\n\r\n".parse().unwrap();
+        let mut client = Vec::new();
+
+        let forwarded = forward(&mut request, &mut client, &mut origin, |_, response| {
+            *response = HttpResponse::from(AspirinEatsError::TooManyRequests);
+        })
+        .unwrap();
+
+        let (head, body) = split_response(&client);
+        assert_eq!(head.status_code(), 429);
+        assert_eq!(
+            body,
+            AspirinEatsError::TooManyRequests.to_string().as_bytes()
+        );
+        assert_eq!(
+            head.header("Content-Length"),
+            Some(body.len().to_string().as_str())
+        );
+        assert_eq!(
+            forwarded,
+            Forwarded {
+                status: 429,
+                bytes: client.len() as u64,
+                reusable: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_forward_unframed_responses_are_not_reusable() {
+        let mut origin =
+            MockOrigin::new(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\nid: 1\n\n");
+        let mut request: HttpRequest = "GET /orders/stream HTTP/1.1\r\n\r\n".parse().unwrap();
+        let mut client = Vec::new();
+
+        let forwarded = forward(&mut request, &mut client, &mut origin, |_, _| {}).unwrap();
+
+        assert!(!forwarded.reusable);
+        assert!(String::from_utf8(client)
+            .unwrap()
+            .ends_with("\r\n\r\nid: 1\n\n"));
+    }
+
+    #[test]
+    fn test_forward_compresses_when_origin_did_not() {
+        let body = format!("[{}]", vec!["{\"id\":1}"; 200].join(","));
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let mut origin = MockOrigin::new(response.as_bytes());
+        let mut request: HttpRequest = "GET /orders HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n"
+            .parse()
+            .unwrap();
+        let mut client = Vec::new();
+
+        let forwarded = forward(&mut request, &mut client, &mut origin, |_, _| {}).unwrap();
+
+        assert_eq!(forwarded.bytes, client.len() as u64);
+        let (head, compressed) = split_response(&client);
+        assert_eq!(head.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(head.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            head.header("Content-Length"),
+            Some(compressed.len().to_string().as_str())
+        );
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(compressed)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn test_forward_passes_encoded_bodies_through() {
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+            Content-Encoding: gzip\r\nContent-Length: 2000\r\n\r\n"
+            .to_vec();
+        let body: Vec<u8> = (0..2000).map(|i| (i % 251) as u8).collect();
+        response.extend(&body);
+        let mut origin = MockOrigin::new(&response);
+        let mut request: HttpRequest = "GET /orders HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n"
+            .parse()
+            .unwrap();
+        let mut client = Vec::new();
+
+        forward(&mut request, &mut client, &mut origin, |_, _| {}).unwrap();
+
+        let (head, passed) = split_response(&client);
+        assert_eq!(head.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(passed, body);
+    }
+
+    /// Refuses requests without an API key, and labels every response
+    struct ApiKey;
+
+    impl Middleware for ApiKey {
+        fn before(
+            &self,
+            request: &mut HttpRequest,
+        ) -> Result<Option<HttpResponse>, AspirinEatsError> {
+            Ok(request
+                .header("X-Api-Key")
+                .is_none()
+                .then(|| HttpResponse::new(401, "Unauthorized", "")))
+        }
+
+        fn after(
+            &self,
+            _request: &HttpRequest,
+            response: &mut HttpResponse,
+        ) -> Result<(), AspirinEatsError> {
+            response.set_header("X-Served-By", "proxy");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_proxy_request_runs_middleware() {
+        let proxy_with_key = test_proxy().with_middleware(ApiKey);
+
+        let (refused, attempts) = refused_pool();
+        let response = proxy(
+            &proxy_with_key,
+            "GET /orders HTTP/1.1\r\n\r\n",
+            &origin(refused),
+        );
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(response.header("X-Served-By"), Some("proxy"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+
+        let (pool, _) = mock_pool(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]");
+        let response = proxy(
+            &proxy_with_key,
+            "GET /orders HTTP/1.1\r\nX-Api-Key: k\r\n\r\n",
+            &origin(pool),
+        );
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("X-Served-By"), Some("proxy"));
+        assert_eq!(response.body(), "[]");
+    }
+
+    #[test]
+    fn test_proxy_request_propagates_request_id() {
+        let (pool, received) = mock_pool(b"HTTP/1.1 200 OK\r\n\r\n");
+
+        proxy(
+            &test_proxy(),
+            "GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n",
+            &origin(pool),
+        );
+
+        let forwarded: HttpRequest = String::from_utf8(received.lock().unwrap().clone())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(forwarded.header(REQUEST_ID_HEADER), Some("abc"));
+    }
+
+    #[test]
+    fn test_proxy_request_bad_gateway() {
+        let proxy_under_test = test_proxy();
+        let (pool, _) = refused_pool();
+
+        let response = proxy(&proxy_under_test, "GET / HTTP/1.1\r\n\r\n", &origin(pool));
+
+        assert_eq!(response.status_code(), 502);
+        assert!(response.header(REQUEST_ID_HEADER).is_some());
+        assert!(proxy_under_test
+            .metrics
+            .render()
+            .contains("aspirin_upstream_up{upstream=\"origin\"} 0"));
+    }
+
+    #[test]
+    fn test_proxy_request_enforces_limits() {
+        let proxy_under_test = test_proxy().with_limits(Limits {
+            max_request_line: 32,
+            max_body: 4,
+            ..Limits::default()
+        });
+        let (pool, attempts) = refused_pool();
+        let routing = origin(pool);
+
+        for (request, expected) in [
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n",
+                413,
+            ),
+            (
+                "GET /a-path-longer-than-the-limit-allows HTTP/1.1\r\n\r\n",
+                431,
+            ),
+        ] {
+            let response = proxy(&proxy_under_test, request, &routing);
+            assert_eq!(response.status_code(), expected, "{:?}", request);
+        }
+        // Neither request was worth bothering the origin with
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_proxy_request_circuit_breaker() {
+        let clock = Arc::new(ManualClock::new(1000));
+        let config = BreakerConfig {
+            failure_threshold: 2,
+            open_for: Duration::from_secs(10),
+            success_threshold: 1,
+        };
+        let (pool, up, attempts) = flaky_pool(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]");
+        let breaker = CircuitBreaker::with_clock(config, clock.clone());
+        let routing = Routing::single(Server::new("origin", pool, breaker));
+        let proxy_under_test = test_proxy();
+        let request = "GET /orders HTTP/1.1\r\n\r\n";
+
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            502
+        );
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            502
+        );
+        let response = proxy(&proxy_under_test, request, &routing);
+        assert_eq!(response.status_code(), 503);
+        assert_eq!(response.header("Retry-After"), Some("10"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // After a while one trial request is let through, and closes the circuit
+        clock.advance(Duration::from_secs(10));
+        up.store(true, Ordering::SeqCst);
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            200
+        );
+        up.store(false, Ordering::SeqCst);
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            502
+        );
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_proxy_request_upstream_timeouts() {
+        let clock = Arc::new(ManualClock::new(1000));
+        let config = BreakerConfig {
+            failure_threshold: 1,
+            open_for: Duration::from_secs(10),
+            success_threshold: 1,
+        };
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let pool = Pool::new(PoolConfig::default(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(MockOrigin::stalling(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n[1,2",
+            ))
+        });
+        pool.put(MockOrigin::stalling(b""));
+        let breaker = CircuitBreaker::with_clock(config, clock.clone());
+        let routing = Routing::single(Server::new("origin", pool, breaker));
+        let proxy_under_test = test_proxy();
+        let request = "GET /orders HTTP/1.1\r\n\r\n";
+
+        // A pooled connection that times out is not retried, and opens the circuit
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            502
+        );
+        assert_eq!(opened.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            503
+        );
+
+        // So does a body that stops arriving, even after the head came through
+        clock.advance(Duration::from_secs(10));
+        let mut client_writer = Vec::new();
+        proxy_under_test
+            .proxy_request(&mut request.as_bytes(), &mut client_writer, &routing)
+            .unwrap();
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            503
+        );
+    }
+
+    #[test]
+    fn test_proxy_request_reuses_origin_connections() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let addr = OriginServer::new(db)
+            .with_logger(Logger::from_writer("origin", Box::new(io::sink())))
+            .spawn("127.0.0.1:0")
+            .unwrap();
+        let (pool, opened) = counting_tcp_pool(addr.to_string());
+        let routing = origin(pool);
+        let proxy_under_test = test_proxy();
+
+        for _ in 0..3 {
+            let response = proxy(&proxy_under_test, "GET /orders HTTP/1.1\r\n\r\n", &routing);
+            assert_eq!(response.body(), "[]");
+            assert_eq!(response.header("Connection"), Some("close"));
+        }
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        let server = &routing.route("/").unwrap().upstream.servers[0];
+        assert_eq!(server.pool.idle(), 1);
+    }
+
+    #[test]
+    fn test_proxy_request_retries_closed_connections() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let addr = OriginServer::new(db)
+            .with_logger(Logger::from_writer("origin", Box::new(io::sink())))
+            .with_limits(Limits {
+                idle_timeout: Duration::from_millis(50),
+                ..Limits::default()
+            })
+            .spawn("127.0.0.1:0")
+            .unwrap();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let addr = addr.to_string();
+        // No check, so the closed connection is only noticed once the request is sent on it
+        let pool = Pool::new(PoolConfig::default(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            TcpStream::connect(&addr)
+        });
+        let routing = origin(pool);
+        let proxy_under_test = test_proxy();
+
+        let request = "GET /orders HTTP/1.1\r\n\r\n";
+        assert_eq!(proxy(&proxy_under_test, request, &routing).body(), "[]");
+        // The origin closes the pooled connection once it has been idle long enough
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(proxy(&proxy_under_test, request, &routing).body(), "[]");
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_proxy_request_checks_connections_before_posting() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let addr = OriginServer::new(db)
+            .with_logger(Logger::from_writer("origin", Box::new(io::sink())))
+            .with_limits(Limits {
+                idle_timeout: Duration::from_millis(50),
+                ..Limits::default()
+            })
+            .spawn("127.0.0.1:0")
+            .unwrap();
+        let (pool, opened) = counting_tcp_pool(addr.to_string());
+        let routing = origin(pool);
+        let proxy_under_test = test_proxy();
+        let request = "POST /orders HTTP/1.1\r\nContent-Length: 36\r\n\r\n\
+                       {\"customer\":\"Amit\",\"food\":[\"Fries\"]}";
+
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            201
+        );
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            201
+        );
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+
+        let response = proxy(&proxy_under_test, "GET /orders HTTP/1.1\r\n\r\n", &routing);
+        let orders: Vec<serde_json::Value> = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(orders.len(), 2);
+    }
+
+    #[test]
+    fn test_proxy_request_does_not_replay_posts() {
+        // An origin that closes every connection without answering, on a pooled connection
+        // that passes the pool's check
+        let (pool, received) = mock_pool(b"");
+        let (connection, _) = pool.get().unwrap();
+        pool.put(connection);
+        let routing = origin(pool);
+        let request = "POST /orders HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+
+        let response = proxy(&test_proxy(), request, &routing);
+        assert_eq!(response.status_code(), 502);
+        let received = String::from_utf8(received.lock().unwrap().clone()).unwrap();
+        assert_eq!(received.matches("POST /orders").count(), 1);
+    }
+
+    /// Routing for `config` over mock servers, each answering with its own address in the
+    /// `X-Served-By` header. Returns what each server received
+    #[allow(clippy::type_complexity)]
+    fn mock_routing(
+        config: serde_json::Value,
+        clock: Arc<ManualClock>,
+    ) -> (
+        Routing<MockOrigin>,
+        Arc<Mutex<Vec<(String, Arc<Mutex<Vec<u8>>>)>>>,
+    ) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let servers = received.clone();
+        let config = serde_json::from_value(config).unwrap();
+        let routing = Routing::new(config, clock, |addr| {
+            let response: &'static [u8] = match addr {
+                "reports:80" => b"HTTP/1.1 200 OK\r\nX-Served-By: reports\r\nX-Internal: 1\r\n\r\n",
+                _ => b"HTTP/1.1 200 OK\r\nX-Served-By: orders\r\nX-Internal: 1\r\n\r\n",
+            };
+            let (pool, received) = mock_pool(response);
+            servers.lock().unwrap().push((addr.to_string(), received));
+            let breaker = CircuitBreaker::new(BreakerConfig::default());
+            Server::new(addr, pool, breaker)
+        })
+        .unwrap();
+        (routing, received)
+    }
+
+    #[test]
+    fn test_proxy_request_routes_by_prefix() {
+        let config = serde_json::json!({
+            "upstreams": {
+                "orders": { "servers": ["orders:80"] },
+                "reports": { "servers": ["reports:80"] }
+            },
+            "routes": [
+                { "prefix": "/orders", "upstream": "orders" },
+                {
+                    "prefix": "/reports",
+                    "upstream": "reports",
+                    "request_headers": { "set": { "X-Team": "reports" }, "remove": ["Cookie"] },
+                    "response_headers": { "remove": ["X-Internal"] }
+                }
+            ]
+        });
+        let (routing, received) = mock_routing(config, Arc::new(ManualClock::new(1000)));
+        let proxy_under_test = test_proxy();
+
+        let response = proxy(
+            &proxy_under_test,
+            "GET /orders/1 HTTP/1.1\r\n\r\n",
+            &routing,
+        );
+        assert_eq!(response.header("X-Served-By"), Some("orders"));
+        assert_eq!(response.header("X-Internal"), Some("1"));
+
+        let response = proxy(
+            &proxy_under_test,
+            "GET /reports/daily HTTP/1.1\r\nCookie: session=1\r\n\r\n",
+            &routing,
+        );
+        assert_eq!(response.header("X-Served-By"), Some("reports"));
+        assert_eq!(response.header("X-Internal"), None);
+        let received = received.lock().unwrap();
+        let (_, reports) = received
+            .iter()
+            .find(|(addr, _)| addr == "reports:80")
+            .unwrap();
+        let forwarded: HttpRequest = String::from_utf8(reports.lock().unwrap().clone())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(forwarded.header("Cookie"), None);
+        assert_eq!(forwarded.header("X-Team"), Some("reports"));
+
+        // Nothing covers the root
+        let response = proxy(&proxy_under_test, "GET / HTTP/1.1\r\n\r\n", &routing);
+        assert_eq!(response.status_code(), 404);
+    }
+
+    #[test]
+    fn test_proxy_request_rate_limit() {
+        let config = serde_json::json!({
+            "upstreams": { "orders": { "servers": ["orders:80"] } },
+            "routes": [{
+                "prefix": "/",
+                "upstream": "orders",
+                "rate_limit": { "requests_per_second": 1, "burst": 2 }
+            }]
+        });
+        let clock = Arc::new(ManualClock::new(1000));
+        let (routing, received) = mock_routing(config, clock.clone());
+        let proxy_under_test = test_proxy();
+        let request = "GET /orders HTTP/1.1\r\n\r\n";
+
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            200
+        );
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            200
+        );
+        let response = proxy(&proxy_under_test, request, &routing);
+        assert_eq!(response.status_code(), 429);
+        assert_eq!(response.header("Retry-After"), Some("1"));
+        let (_, orders) = &received.lock().unwrap()[0];
+        let forwarded = String::from_utf8(orders.lock().unwrap().clone()).unwrap();
+        assert_eq!(forwarded.matches("GET /orders").count(), 2);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            proxy(&proxy_under_test, request, &routing).status_code(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let proxy_under_test = ReverseProxy::new("127.0.0.1:8080");
+        let in_flight = proxy_under_test.routing();
+
+        let mut unknown_upstream = ProxyConfig::single("127.0.0.1:8080");
+        unknown_upstream.routes[0].upstream = "missing".to_string();
+        assert!(matches!(
+            proxy_under_test.reconfigure(unknown_upstream),
+            Err(AspirinEatsError::InvalidConfig(_))
+        ));
+        assert!(Arc::ptr_eq(&proxy_under_test.routing(), &in_flight));
+
+        let mut config = ProxyConfig::single("127.0.0.1:8080");
+        config.routes[0].prefix = "/orders".to_string();
+        proxy_under_test.reconfigure(config.clone()).unwrap();
+
+        let routing = proxy_under_test.routing();
+        assert_eq!(routing.config(), &config);
+        assert!(routing.route("/").is_none());
+        // Requests that started before keep routing the old way
+        assert!(in_flight.route("/").is_some());
+        // The unchanged server carried over
+        assert!(Arc::ptr_eq(
+            &in_flight.route("/").unwrap().upstream.servers[0],
+            &routing.route("/orders").unwrap().upstream.servers[0]
+        ));
+    }
+
+    #[test]
+    fn test_proxy_serves_own_metrics() {
+        let (pool, attempts) = refused_pool();
+
+        let response = proxy(
+            &test_proxy(),
+            "GET /metrics HTTP/1.1\r\n\r\n",
+            &origin(pool),
+        );
+
+        assert_eq!(response.status_code(), 200);
+        assert!(response.body().contains("aspirin_connections_in_flight"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+    }
+}
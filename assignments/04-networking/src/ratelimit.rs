@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SystemClock};
+
+/// How many requests a rate limiter lets through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Requests let through per second, once any burst has been used up
+    pub requests_per_second: u32,
+
+    /// Requests that may be let through at once after a quiet period
+    pub burst: u32,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: u64,
+    refilled_at: i64,
+}
+
+/// Token bucket rate limiter. The bucket starts full with `burst` tokens, every request
+/// takes one, and `requests_per_second` are put back every second, up to `burst`
+pub struct RateLimiter {
+    limit: RateLimit,
+    clock: Arc<dyn Clock>,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter on the system clock
+    pub fn new(limit: RateLimit) -> Self {
+        Self::with_clock(limit, Arc::new(SystemClock))
+    }
+
+    /// Create a rate limiter on the given clock. The clock counts whole seconds, so tokens
+    /// are put back a second's worth at a time
+    pub fn with_clock(limit: RateLimit, clock: Arc<dyn Clock>) -> Self {
+        let bucket = Bucket {
+            tokens: u64::from(limit.burst),
+            refilled_at: clock.now(),
+        };
+        RateLimiter {
+            limit,
+            clock,
+            bucket: Mutex::new(bucket),
+        }
+    }
+
+    /// The limit this rate limiter enforces
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Take a token for a request. Returns how long to wait before trying again if there
+    /// are none left
+    pub fn acquire(&self) -> Result<(), Duration> {
+        let now = self.clock.now();
+        let mut bucket = self.bucket.lock().expect("rate limiter lock poisoned");
+        let elapsed = (now - bucket.refilled_at).max(0) as u64;
+        if elapsed > 0 {
+            let refill = elapsed.saturating_mul(u64::from(self.limit.requests_per_second));
+            bucket.tokens = bucket
+                .tokens
+                .saturating_add(refill)
+                .min(u64::from(self.limit.burst));
+            bucket.refilled_at = now;
+        }
+        if bucket.tokens == 0 {
+            return Err(Duration::from_secs(1));
+        }
+        bucket.tokens -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn limiter(requests_per_second: u32, burst: u32) -> (RateLimiter, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(1000));
+        let limit = RateLimit {
+            requests_per_second,
+            burst,
+        };
+        (RateLimiter::with_clock(limit, clock.clone()), clock)
+    }
+
+    #[test]
+    fn test_burst_then_limited() {
+        let (limiter, _) = limiter(1, 3);
+
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(), Ok(()));
+        }
+        assert_eq!(limiter.acquire(), Err(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_refills_every_second_up_to_burst() {
+        let (limiter, clock) = limiter(2, 3);
+        for _ in 0..3 {
+            limiter.acquire().unwrap();
+        }
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.acquire(), Ok(()));
+        assert_eq!(limiter.acquire(), Ok(()));
+        assert!(limiter.acquire().is_err());
+
+        // A long quiet period only fills the bucket back up to the burst
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(), Ok(()));
+        }
+        assert!(limiter.acquire().is_err());
+    }
+
+    #[test]
+    fn test_clock_going_backwards_does_not_refill() {
+        let (limiter, clock) = limiter(5, 1);
+        limiter.acquire().unwrap();
+
+        clock.set(900);
+
+        assert!(limiter.acquire().is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::Write;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::bulk::csv_error;
+use crate::error::AspirinEatsError;
+use crate::food::{Ingredient, MenuItem, Order, OrderStatus};
+
+/// Seconds in a day
+const DAY: i64 = 24 * 60 * 60;
+
+/// Time range a report covers, in seconds since the Unix epoch. `from` is inclusive and `to`
+/// exclusive; a missing bound leaves that side open
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Copy, Default)]
+pub struct Range {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl Range {
+    /// Parse a range from `from` and `to` query parameters. Each is either a date
+    /// (`YYYY-MM-DD`, midnight UTC) or seconds since the Unix epoch; empty values are ignored
+    pub fn parse(from: Option<&str>, to: Option<&str>) -> Result<Self, AspirinEatsError> {
+        let parse = |value: Option<&str>| {
+            value
+                .filter(|value| !value.is_empty())
+                .map(parse_time)
+                .transpose()
+        };
+        Ok(Range {
+            from: parse(from)?,
+            to: parse(to)?,
+        })
+    }
+}
+
+/// Parse a date (`YYYY-MM-DD`, midnight UTC) or a number of seconds since the Unix epoch
+pub fn parse_time(value: &str) -> Result<i64, AspirinEatsError> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
+    }
+    let parts: Vec<&str> = value.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(AspirinEatsError::InvalidRequest);
+    };
+    let parse = |part: &str| {
+        part.parse::<i64>()
+            .map_err(|_| AspirinEatsError::InvalidRequest)
+    };
+    let (year, month, day) = (parse(year)?, parse(month)?, parse(day)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(AspirinEatsError::InvalidRequest);
+    }
+    Ok(days_from_civil(year, month, day) * DAY)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Orders and revenue for a single day (UTC)
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct DailySales {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub orders: i64,
+    pub revenue: f64,
+}
+
+/// Number of orders in a single status
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct StatusCount {
+    pub status: OrderStatus,
+    pub orders: i64,
+}
+
+/// Revenue report. Cancelled orders count towards `by_status` only
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct SalesReport {
+    pub range: Range,
+
+    /// Orders that were not cancelled
+    pub orders: i64,
+    pub revenue: f64,
+    pub average_order_value: f64,
+    pub by_status: Vec<StatusCount>,
+    pub daily: Vec<DailySales>,
+}
+
+/// How many times something was ordered
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct ItemCount {
+    pub name: String,
+    pub count: i64,
+}
+
+/// Popularity report for menu items and toppings, most ordered first. Cancelled orders are
+/// left out
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct ItemsReport {
+    pub range: Range,
+    pub items: Vec<ItemCount>,
+    pub toppings: Vec<ItemCount>,
+}
+
+impl ItemsReport {
+    /// Tally the items and toppings of the given orders
+    pub fn from_orders(range: Range, orders: &[Order]) -> Self {
+        let mut items = Vec::new();
+        let mut toppings = Vec::new();
+        for order in orders
+            .iter()
+            .filter(|order| order.status != OrderStatus::Cancelled)
+        {
+            for item in &order.food {
+                let name = match item {
+                    MenuItem::Burger(_) => "burger",
+                    MenuItem::Fries => "fries",
+                    MenuItem::Drink => "drink",
+                };
+                count(&mut items, name);
+                for ingredient in item.ingredients() {
+                    if let Ingredient::Topping(_) = ingredient {
+                        count(&mut toppings, ingredient.name());
+                    }
+                }
+            }
+        }
+        ItemsReport {
+            range,
+            items: ranked(items),
+            toppings: ranked(toppings),
+        }
+    }
+}
+
+fn count(counts: &mut Vec<ItemCount>, name: &str) {
+    match counts.iter_mut().find(|item| item.name == name) {
+        Some(item) => item.count += 1,
+        None => counts.push(ItemCount {
+            name: name.to_string(),
+            count: 1,
+        }),
+    }
+}
+
+fn ranked(mut counts: Vec<ItemCount>) -> Vec<ItemCount> {
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    counts
+}
+
+/// Orders and revenue for one hour of the day (UTC), summed over every day in the range
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct HourlySales {
+    /// 0 to 23
+    pub hour: u8,
+    pub orders: i64,
+    pub revenue: f64,
+}
+
+/// Report of when orders come in. Every hour is listed, even without orders. Cancelled
+/// orders are left out
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct HourlyReport {
+    pub range: Range,
+    pub hours: Vec<HourlySales>,
+
+    /// Hour with the most orders, if there were any
+    pub busiest: Option<u8>,
+}
+
+/// Row layout used for the CSV version of `ItemsReport`
+#[derive(Serialize)]
+struct ItemRow<'a> {
+    kind: &'a str,
+    name: &'a str,
+    count: i64,
+}
+
+/// Which report to write as CSV
+pub enum Report {
+    Sales(SalesReport),
+    Items(ItemsReport),
+    Hourly(HourlyReport),
+}
+
+impl Report {
+    /// Write the table at the heart of the report as CSV: one row per day for sales, per
+    /// item or topping for items, and per hour for hourly
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), AspirinEatsError> {
+        let mut writer = csv::Writer::from_writer(writer);
+        match self {
+            Report::Sales(report) => {
+                for day in &report.daily {
+                    writer.serialize(day).map_err(csv_error)?;
+                }
+            }
+            Report::Items(report) => {
+                let rows = report
+                    .items
+                    .iter()
+                    .map(|item| ("item", item))
+                    .chain(report.toppings.iter().map(|item| ("topping", item)));
+                for (kind, item) in rows {
+                    writer
+                        .serialize(ItemRow {
+                            kind,
+                            name: &item.name,
+                            count: item.count,
+                        })
+                        .map_err(csv_error)?;
+                }
+            }
+            Report::Hourly(report) => {
+                for hour in &report.hours {
+                    writer.serialize(hour).map_err(csv_error)?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{Bun, Burger, Patty, Topping};
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1700000000").unwrap(), 1_700_000_000);
+        assert_eq!(parse_time("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_time("2024-03-01").unwrap(), 1_709_251_200);
+        assert!(parse_time("2024-13-01").is_err());
+        assert!(parse_time("yesterday").is_err());
+        assert_eq!(
+            Range::parse(Some("1970-01-02"), Some("")).unwrap(),
+            Range {
+                from: Some(DAY),
+                to: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_items_report() {
+        let order = |food: Vec<MenuItem>, status: OrderStatus| Order {
+            id: None,
+            uuid: None,
+            customer: "Amit".to_string(),
+            customer_id: None,
+            created_at: None,
+            food,
+            status,
+            total: 0.0,
+            estimated_ready_at: None,
+        };
+        let burger = || {
+            MenuItem::Burger(Burger::new(
+                Bun::Plain,
+                Patty::Beef,
+                vec![Topping::Bacon, Topping::Cheese],
+            ))
+        };
+        let orders = vec![
+            order(vec![burger(), MenuItem::Fries], OrderStatus::Completed),
+            order(vec![burger()], OrderStatus::Pending),
+            order(vec![MenuItem::Drink; 5], OrderStatus::Cancelled),
+        ];
+
+        let report = ItemsReport::from_orders(Range::default(), &orders);
+        let names: Vec<(&str, i64)> = report
+            .items
+            .iter()
+            .map(|item| (item.name.as_str(), item.count))
+            .collect();
+        assert_eq!(names, vec![("burger", 2), ("fries", 1)]);
+        assert_eq!(report.toppings[0].name, "bacon");
+
+        let mut csv = Vec::new();
+        Report::Items(report).write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "kind,name,count\nitem,burger,2\nitem,fries,1\ntopping,bacon,2\ntopping,cheese,2\n"
+        );
+    }
+}
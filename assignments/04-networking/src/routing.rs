@@ -0,0 +1,687 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::breaker::{BreakerConfig, CircuitBreaker};
+use crate::clock::{Clock, SystemClock};
+use crate::error::AspirinEatsError;
+use crate::http::{HttpRequest, HttpResponse};
+use crate::pool::{Pool, PoolConfig};
+use crate::ratelimit::{RateLimit, RateLimiter};
+
+/// Headers a rewrite may not touch, because the proxy relies on them to frame messages
+const FRAMING_HEADERS: [&str; 3] = ["Content-Length", "Transfer-Encoding", "Connection"];
+
+/// Routing configuration for the reverse proxy, read from a JSON file:
+///
+/// ```json
+/// {
+///   "upstreams": {
+///     "origin": { "servers": ["127.0.0.1:8080", "127.0.0.1:8081"] },
+///     "reports": { "servers": ["127.0.0.1:9090"] }
+///   },
+///   "routes": [
+///     {
+///       "prefix": "/reports",
+///       "upstream": "reports",
+///       "request_headers": { "remove": ["Cookie"] },
+///       "response_headers": { "set": { "Cache-Control": "max-age=60" } },
+///       "rate_limit": { "requests_per_second": 5, "burst": 10 }
+///     },
+///     { "prefix": "/", "upstream": "origin" }
+///   ]
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Groups of interchangeable servers, by name
+    pub upstreams: BTreeMap<String, UpstreamConfig>,
+
+    /// Where requests go, by path prefix. The longest matching prefix wins
+    pub routes: Vec<RouteConfig>,
+}
+
+/// A group of interchangeable servers requests are spread over
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// Addresses of the servers, as `host:port`
+    pub servers: Vec<String>,
+}
+
+/// Requests under a path prefix, and how they are handled
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// Path the route covers: itself and everything below it. `/orders` covers `/orders`
+    /// and `/orders/1`, but not `/ordersx`
+    pub prefix: String,
+
+    /// Name of the upstream group requests are forwarded to
+    pub upstream: String,
+
+    /// Changes made to requests before they are forwarded
+    #[serde(default)]
+    pub request_headers: HeaderRewrite,
+
+    /// Changes made to responses before they are relayed to the client
+    #[serde(default)]
+    pub response_headers: HeaderRewrite,
+
+    /// Most requests the route lets through, shared by every client. Unlimited if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Headers to remove from a message, then headers to set on it
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderRewrite {
+    /// Headers set, replacing any existing value with the same name
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+
+    /// Names of headers removed
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// A configuration that forwards everything to a single server
+    pub fn single(addr: &str) -> Self {
+        ProxyConfig {
+            upstreams: BTreeMap::from([(
+                "origin".to_string(),
+                UpstreamConfig {
+                    servers: vec![addr.to_string()],
+                },
+            )]),
+            routes: vec![RouteConfig {
+                prefix: "/".to_string(),
+                upstream: "origin".to_string(),
+                request_headers: HeaderRewrite::default(),
+                response_headers: HeaderRewrite::default(),
+                rate_limit: None,
+            }],
+        }
+    }
+
+    /// Read and validate the configuration file at `path`
+    pub fn load(path: &Path) -> Result<Self, AspirinEatsError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            AspirinEatsError::InvalidConfig(format!("{}: {}", path.display(), e))
+        };
+        let text = fs::read_to_string(path).map_err(|e| invalid(&e))?;
+        let config: ProxyConfig = serde_json::from_str(&text).map_err(|e| invalid(&e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that every route can be served: prefixes are paths and unique, upstreams exist
+    /// and have servers, rate limits let something through, and rewrites produce valid
+    /// headers without touching message framing
+    pub fn validate(&self) -> Result<(), AspirinEatsError> {
+        let invalid = |reason: String| Err(AspirinEatsError::InvalidConfig(reason));
+
+        for (name, upstream) in &self.upstreams {
+            if upstream.servers.is_empty() {
+                return invalid(format!("upstream {} has no servers", name));
+            }
+            for server in &upstream.servers {
+                let port = server.rsplit_once(':').map(|(_, port)| port.parse::<u16>());
+                if !matches!(port, Some(Ok(_))) || server.contains(char::is_whitespace) {
+                    return invalid(format!("upstream {} has invalid server {:?}", name, server));
+                }
+            }
+        }
+
+        if self.routes.is_empty() {
+            return invalid("no routes".to_string());
+        }
+        let mut prefixes = HashSet::new();
+        for route in &self.routes {
+            let prefix = &route.prefix;
+            if !prefix.starts_with('/')
+                || prefix.contains(|c: char| c == '?' || !c.is_ascii_graphic())
+            {
+                return invalid(format!("route prefix {:?} is not a path", prefix));
+            }
+            if !prefixes.insert(prefix) {
+                return invalid(format!("route prefix {} is used twice", prefix));
+            }
+            if !self.upstreams.contains_key(&route.upstream) {
+                return invalid(format!(
+                    "route {} uses unknown upstream {}",
+                    prefix, route.upstream
+                ));
+            }
+            if let Some(limit) = route.rate_limit {
+                if limit.requests_per_second == 0 || limit.burst == 0 {
+                    return invalid(format!("route {} has a rate limit of zero", prefix));
+                }
+            }
+            for rewrite in [&route.request_headers, &route.response_headers] {
+                rewrite.validate().map_err(|reason| {
+                    AspirinEatsError::InvalidConfig(format!("route {} {}", prefix, reason))
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HeaderRewrite {
+    fn validate(&self) -> Result<(), String> {
+        let names = self.set.keys().chain(&self.remove);
+        for name in names {
+            let is_token = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+            if !is_token {
+                return Err(format!("rewrites invalid header name {:?}", name));
+            }
+            if FRAMING_HEADERS
+                .iter()
+                .any(|framing| framing.eq_ignore_ascii_case(name))
+            {
+                return Err(format!("rewrites framing header {}", name));
+            }
+        }
+        for (name, value) in &self.set {
+            if value.chars().any(|c| c.is_ascii_control() && c != '\t') || value.trim() != value {
+                return Err(format!("sets header {} to invalid value {:?}", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the rewrite to a request
+    pub fn rewrite_request(&self, request: &mut HttpRequest) {
+        for name in &self.remove {
+            request.remove_header(name);
+        }
+        for (name, value) in &self.set {
+            request.set_header(name, value);
+        }
+    }
+
+    /// Apply the rewrite to a response
+    pub fn rewrite_response(&self, response: &mut HttpResponse) {
+        for name in &self.remove {
+            response.remove_header(name);
+        }
+        for (name, value) in &self.set {
+            response.set_header(name, value);
+        }
+    }
+}
+
+/// One upstream server, with its own pool of connections and circuit breaker
+pub struct Server<C> {
+    pub(crate) addr: String,
+    pub(crate) pool: Pool<C>,
+    pub(crate) breaker: CircuitBreaker,
+}
+
+impl<C> Server<C> {
+    /// A server reached through `pool`, guarded by `breaker`
+    pub fn new(addr: &str, pool: Pool<C>, breaker: CircuitBreaker) -> Self {
+        Server {
+            addr: addr.to_string(),
+            pool,
+            breaker,
+        }
+    }
+
+    /// Address of the server
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+}
+
+impl Server<TcpStream> {
+    /// A server reached over TCP
+    pub fn tcp(addr: &str, pool: PoolConfig, breaker: BreakerConfig) -> Self {
+        Server::new(addr, Pool::tcp(addr, pool), CircuitBreaker::new(breaker))
+    }
+}
+
+/// A group of servers that requests are spread over in turn
+pub struct Upstream<C> {
+    name: String,
+    pub(crate) servers: Vec<Arc<Server<C>>>,
+    next: AtomicUsize,
+}
+
+impl<C> Upstream<C> {
+    /// Name of the group in the configuration
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Pick the next server whose circuit breaker lets a request through. Returns how long
+    /// until one will if none does
+    pub fn acquire(&self) -> Result<&Server<C>, Duration> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut retry_after = Duration::MAX;
+        for i in 0..self.servers.len() {
+            let server = &self.servers[(start + i) % self.servers.len()];
+            match server.breaker.acquire() {
+                Ok(()) => return Ok(server),
+                Err(wait) => retry_after = retry_after.min(wait),
+            }
+        }
+        Err(retry_after)
+    }
+}
+
+/// Where requests under a path prefix go
+pub struct Route<C> {
+    pub(crate) prefix: String,
+    pub(crate) upstream: Arc<Upstream<C>>,
+    pub(crate) request_headers: HeaderRewrite,
+    pub(crate) response_headers: HeaderRewrite,
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<C> Route<C> {
+    /// The path prefix the route covers
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The upstream group requests are forwarded to
+    pub fn upstream(&self) -> &Upstream<C> {
+        &self.upstream
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str()).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/')
+        })
+    }
+
+    /// Take a request from the route's rate limit. Returns how long to wait before trying
+    /// again if it is used up
+    pub fn admit(&self) -> Result<(), Duration> {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.acquire(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A proxy configuration ready to route requests. Built once per configuration and shared
+/// by the requests routed with it, so a reload never changes where a request in flight goes
+pub struct Routing<C> {
+    config: ProxyConfig,
+    routes: Vec<Route<C>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<C> Routing<C> {
+    /// Validate a configuration and build its routes, opening servers with `server`. Rate
+    /// limits are kept on `clock`
+    pub fn new<F>(
+        config: ProxyConfig,
+        clock: Arc<dyn Clock>,
+        server: F,
+    ) -> Result<Self, AspirinEatsError>
+    where
+        F: FnMut(&str) -> Server<C>,
+    {
+        config.validate()?;
+        Ok(Self::build(config, clock, None, server))
+    }
+
+    /// Route everything to a single server
+    pub fn single(server: Server<C>) -> Self {
+        let addr = server.addr.clone();
+        let mut server = Some(server);
+        Self::build(
+            ProxyConfig::single(&addr),
+            Arc::new(SystemClock),
+            None,
+            |_| server.take().expect("a single server is opened once"),
+        )
+    }
+
+    /// Validate a new configuration and build routes for it to replace these. Servers and
+    /// rate limiters that are unchanged are carried over rather than opened again: servers
+    /// with the same address keep their idle connections and circuit breaker, and routes
+    /// with the same prefix and rate limit keep what is left of it
+    pub fn reload<F>(&self, config: ProxyConfig, server: F) -> Result<Self, AspirinEatsError>
+    where
+        F: FnMut(&str) -> Server<C>,
+    {
+        config.validate()?;
+        Ok(Self::build(config, self.clock.clone(), Some(self), server))
+    }
+
+    /// Build routes without validating the configuration
+    pub(crate) fn build<F>(
+        config: ProxyConfig,
+        clock: Arc<dyn Clock>,
+        previous: Option<&Routing<C>>,
+        mut server: F,
+    ) -> Self
+    where
+        F: FnMut(&str) -> Server<C>,
+    {
+        let mut upstreams = BTreeMap::new();
+        for (name, upstream) in &config.upstreams {
+            let servers = upstream
+                .servers
+                .iter()
+                .map(|addr| {
+                    previous
+                        .and_then(|previous| previous.server(addr))
+                        .unwrap_or_else(|| Arc::new(server(addr)))
+                })
+                .collect();
+            let upstream = Upstream {
+                name: name.clone(),
+                servers,
+                next: AtomicUsize::new(0),
+            };
+            upstreams.insert(name.clone(), Arc::new(upstream));
+        }
+
+        let mut routes: Vec<Route<C>> = config
+            .routes
+            .iter()
+            .filter_map(|route| {
+                let rate_limiter = route.rate_limit.map(|limit| {
+                    previous
+                        .and_then(|previous| previous.rate_limiter(&route.prefix, limit))
+                        .unwrap_or_else(|| Arc::new(RateLimiter::with_clock(limit, clock.clone())))
+                });
+                Some(Route {
+                    prefix: route.prefix.clone(),
+                    upstream: upstreams.get(&route.upstream)?.clone(),
+                    request_headers: route.request_headers.clone(),
+                    response_headers: route.response_headers.clone(),
+                    rate_limiter,
+                })
+            })
+            .collect();
+        routes.sort_by_key(|route| Reverse(route.prefix.len()));
+
+        Routing {
+            config,
+            routes,
+            clock,
+        }
+    }
+
+    /// The configuration the routes were built from
+    pub fn config(&self) -> &ProxyConfig {
+        &self.config
+    }
+
+    /// Find the route for a request path: the one with the longest prefix covering it
+    pub fn route(&self, path: &str) -> Option<&Route<C>> {
+        self.routes.iter().find(|route| route.covers(path))
+    }
+
+    fn server(&self, addr: &str) -> Option<Arc<Server<C>>> {
+        self.routes
+            .iter()
+            .flat_map(|route| &route.upstream.servers)
+            .find(|server| server.addr == addr)
+            .cloned()
+    }
+
+    fn rate_limiter(&self, prefix: &str, limit: RateLimit) -> Option<Arc<RateLimiter>> {
+        self.routes
+            .iter()
+            .find(|route| route.prefix == prefix)
+            .and_then(|route| route.rate_limiter.clone())
+            .filter(|limiter| limiter.limit() == limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use serde_json::json;
+
+    fn config(value: serde_json::Value) -> ProxyConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn example() -> ProxyConfig {
+        config(json!({
+            "upstreams": {
+                "origin": { "servers": ["127.0.0.1:8080", "127.0.0.1:8081"] },
+                "reports": { "servers": ["127.0.0.1:9090"] }
+            },
+            "routes": [
+                { "prefix": "/", "upstream": "origin" },
+                {
+                    "prefix": "/reports",
+                    "upstream": "reports",
+                    "request_headers": { "set": { "X-Team": "reports" }, "remove": ["Cookie"] },
+                    "rate_limit": { "requests_per_second": 1, "burst": 1 }
+                }
+            ]
+        }))
+    }
+
+    fn server(addr: &str) -> Server<()> {
+        Server::new(
+            addr,
+            Pool::new(PoolConfig::default(), || Ok(())),
+            CircuitBreaker::new(BreakerConfig::default()),
+        )
+    }
+
+    fn build_routing(config: ProxyConfig) -> Routing<()> {
+        Routing::new(config, Arc::new(ManualClock::new(1000)), server).unwrap()
+    }
+
+    fn invalid_reason(config: ProxyConfig) -> String {
+        match config.validate() {
+            Err(AspirinEatsError::InvalidConfig(reason)) => reason,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_example_is_valid() {
+        assert!(example().validate().is_ok());
+        assert!(ProxyConfig::single("127.0.0.1:8080").validate().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let result = serde_json::from_value::<ProxyConfig>(json!({
+            "upstreams": {},
+            "routes": [{ "prefix": "/", "upstream": "origin", "rate_limt": {} }]
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut no_servers = example();
+        no_servers
+            .upstreams
+            .get_mut("reports")
+            .unwrap()
+            .servers
+            .clear();
+        assert_eq!(
+            invalid_reason(no_servers),
+            "upstream reports has no servers"
+        );
+
+        let mut no_port = example();
+        no_port.upstreams.get_mut("reports").unwrap().servers = vec!["localhost".to_string()];
+        assert!(invalid_reason(no_port).contains("invalid server"));
+
+        let mut relative = example();
+        relative.routes[1].prefix = "reports".to_string();
+        assert!(invalid_reason(relative).contains("is not a path"));
+
+        let mut twice = example();
+        twice.routes[1].prefix = "/".to_string();
+        assert_eq!(invalid_reason(twice), "route prefix / is used twice");
+
+        let mut unknown = example();
+        unknown.routes[1].upstream = "billing".to_string();
+        assert_eq!(
+            invalid_reason(unknown),
+            "route /reports uses unknown upstream billing"
+        );
+
+        let mut zero = example();
+        zero.routes[1].rate_limit = Some(RateLimit {
+            requests_per_second: 0,
+            burst: 5,
+        });
+        assert!(invalid_reason(zero).contains("rate limit of zero"));
+
+        let mut framing = example();
+        framing.routes[0].response_headers.remove = vec!["content-length".to_string()];
+        assert!(invalid_reason(framing).contains("framing header"));
+
+        let mut bad_value = example();
+        bad_value.routes[0]
+            .request_headers
+            .set
+            .insert("X-Injected".to_string(), "a\r\nHost: evil".to_string());
+        assert!(invalid_reason(bad_value).contains("invalid value"));
+
+        let mut no_routes = example();
+        no_routes.routes.clear();
+        assert_eq!(invalid_reason(no_routes), "no routes");
+    }
+
+    #[test]
+    fn test_load_reports_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("aspirin-proxy-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, "{\"upstreams\": {}").unwrap();
+
+        let error = ProxyConfig::load(&path).unwrap_err();
+
+        assert!(error.to_string().contains(&path.display().to_string()));
+        fs::write(&path, serde_json::to_string(&example()).unwrap()).unwrap();
+        assert_eq!(ProxyConfig::load(&path).unwrap(), example());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let routing = build_routing(example());
+
+        let upstream = |path| {
+            routing
+                .route(path)
+                .map(|route| route.upstream().name().to_string())
+        };
+        assert_eq!(upstream("/reports").as_deref(), Some("reports"));
+        assert_eq!(upstream("/reports/daily").as_deref(), Some("reports"));
+        assert_eq!(upstream("/reportsx").as_deref(), Some("origin"));
+        assert_eq!(upstream("/orders/1").as_deref(), Some("origin"));
+
+        let mut no_root = example();
+        no_root.routes.remove(0);
+        assert!(build_routing(no_root).route("/orders").is_none());
+    }
+
+    #[test]
+    fn test_servers_are_taken_in_turn() {
+        let routing = build_routing(example());
+        let upstream = routing.route("/").unwrap().upstream();
+
+        let picked: Vec<_> = (0..4)
+            .map(|_| upstream.acquire().unwrap().addr().to_string())
+            .collect();
+
+        assert_eq!(
+            picked,
+            [
+                "127.0.0.1:8080",
+                "127.0.0.1:8081",
+                "127.0.0.1:8080",
+                "127.0.0.1:8081"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_servers_with_open_circuits_are_skipped() {
+        let routing = build_routing(example());
+        let upstream = routing.route("/").unwrap().upstream();
+        let failing = upstream.acquire().unwrap();
+        for _ in 0..BreakerConfig::default().failure_threshold {
+            failing.breaker.record(false);
+        }
+
+        for _ in 0..3 {
+            assert_eq!(upstream.acquire().unwrap().addr(), "127.0.0.1:8081");
+        }
+
+        let reports = routing.route("/reports").unwrap().upstream();
+        let only = reports.acquire().unwrap();
+        for _ in 0..BreakerConfig::default().failure_threshold {
+            only.breaker.record(false);
+        }
+        assert_eq!(
+            reports.acquire().err(),
+            Some(BreakerConfig::default().open_for)
+        );
+    }
+
+    #[test]
+    fn test_unchanged_servers_and_limits_carry_over() {
+        let old = build_routing(example());
+        old.route("/reports").unwrap().admit().unwrap();
+
+        let mut changed = example();
+        changed.upstreams.get_mut("origin").unwrap().servers[1] = "127.0.0.1:8082".to_string();
+        let new = old.reload(changed, server).unwrap();
+
+        let servers = |routing: &Routing<()>| -> Vec<Arc<Server<()>>> {
+            routing.route("/").unwrap().upstream.servers.clone()
+        };
+        assert!(Arc::ptr_eq(&servers(&old)[0], &servers(&new)[0]));
+        assert!(!Arc::ptr_eq(&servers(&old)[1], &servers(&new)[1]));
+        // The carried over limiter is still used up
+        assert!(new.route("/reports").unwrap().admit().is_err());
+
+        let mut relimited = example();
+        relimited.routes[1].rate_limit = Some(RateLimit {
+            requests_per_second: 1,
+            burst: 2,
+        });
+        let relimited = old.reload(relimited, server).unwrap();
+        assert!(relimited.route("/reports").unwrap().admit().is_ok());
+    }
+
+    #[test]
+    fn test_header_rewrite() {
+        let rewrite = &example().routes[1].request_headers;
+        let mut request: HttpRequest =
+            "GET /reports HTTP/1.1\r\nCookie: a=b\r\nX-Team: web\r\n\r\n"
+                .parse()
+                .unwrap();
+
+        rewrite.rewrite_request(&mut request);
+
+        assert_eq!(request.header("Cookie"), None);
+        assert_eq!(request.header("X-Team"), Some("reports"));
+    }
+}
@@ -0,0 +1,440 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::api::{self, Route};
+use crate::backup::Snapshots;
+use crate::clock::{self, Clock, SystemClock};
+use crate::compression;
+use crate::cors::Cors;
+use crate::db::AspirinEatsDb;
+use crate::error::AspirinEatsError;
+use crate::events::EventBus;
+use crate::http::{self, HttpRequest, HttpResponse, Limits, TimedStream};
+use crate::log::{self, Logger, REQUEST_ID_HEADER};
+use crate::metrics::Metrics;
+use crate::middleware::{Chain, Middleware};
+use crate::sse;
+use crate::store::OrderStore;
+use crate::webhooks::{RetryPolicy, Worker};
+use crate::websocket::{self, MessageReader, Session};
+
+/// What a request that takes its connection over, rather than getting a single response,
+/// wants it for
+pub(crate) enum Upgrade {
+    /// An event stream, optionally limited to a single order
+    Events(Option<i64>),
+    /// A WebSocket
+    Socket,
+}
+
+/// The origin server. Cheap to clone; every clone shares the same store
+#[derive(Clone)]
+pub struct OriginServer {
+    pub(crate) store: Arc<Mutex<dyn OrderStore + Send>>,
+    /// The store again, if it is a database, for the jobs that need one
+    db: Option<Arc<Mutex<AspirinEatsDb>>>,
+    pub(crate) events: Arc<EventBus>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) logger: Arc<Logger>,
+    pub(crate) limits: Limits,
+    middleware: Chain,
+    /// The CORS policy again, for the WebSocket handshakes it also covers
+    cors: Option<Cors>,
+    clock: Arc<dyn Clock>,
+}
+
+impl OriginServer {
+    /// Create a new OriginServer serving the given database, logging to stderr
+    pub fn new(db: AspirinEatsDb) -> Self {
+        let events = db.events();
+        let metrics = db.metrics();
+        let db = Arc::new(Mutex::new(db));
+        OriginServer {
+            store: db.clone(),
+            db: Some(db),
+            events,
+            metrics,
+            logger: Arc::new(Logger::stderr("origin")),
+            limits: Limits::default(),
+            middleware: Chain::new(),
+            cors: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Create a new OriginServer serving orders from any store, logging to stderr. Only a
+    /// database keeps the other resources, publishes order events, and has work for the
+    /// background jobs, so over other stores those are not found, streams stay quiet, and
+    /// the jobs do nothing
+    pub fn from_store<S: OrderStore + Send + 'static>(store: S) -> Self {
+        OriginServer {
+            store: Arc::new(Mutex::new(store)),
+            db: None,
+            events: Arc::new(EventBus::default()),
+            metrics: Arc::new(Metrics::new("origin")),
+            logger: Arc::new(Logger::stderr("origin")),
+            limits: Limits::default(),
+            middleware: Chain::new(),
+            cors: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use the given logger for access and error logs
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = Arc::new(logger);
+        self
+    }
+
+    /// Use the given timeouts and size limits for requests instead of the defaults
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run every request through the given middleware, inside any added before it
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware = self.middleware.with(middleware);
+        self
+    }
+
+    /// Let browsers call the API, and open sockets, from other origins as allowed by the
+    /// given policy
+    pub fn with_cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors.clone());
+        self.with_middleware(cors)
+    }
+
+    /// Answer a WebSocket opening handshake, refusing origins the CORS policy does not allow
+    pub(crate) fn handshake(&self, request: &HttpRequest) -> HttpResponse {
+        websocket::handshake(request, self.cors.as_ref())
+    }
+
+    /// Drive the kitchen and delivery simulation, and time every order, with the given clock
+    /// instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        if let Some(db) = &self.db {
+            let set = db
+                .lock()
+                .expect("database lock poisoned")
+                .set_clock(clock.clone());
+            if let Err(e) = set {
+                self.logger.error(None, &e.into());
+            }
+        }
+        self.clock = clock;
+        self
+    }
+
+    /// Accept connections forever, handling each one on its own thread
+    pub fn serve(&self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = self.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = server.handle_connection(stream) {
+                            server.logger.error(None, &e);
+                        }
+                    });
+                }
+                Err(e) => self.logger.error(None, &e.into()),
+            }
+        }
+    }
+
+    /// Start a background job that permanently deletes removed orders once they have been
+    /// removed for longer than `retention`, checking every `interval`
+    pub fn start_purge_job(&self, retention: Duration, interval: Duration) -> JoinHandle<()> {
+        let server = self.clone();
+        thread::spawn(move || {
+            let Some(db) = &server.db else {
+                return;
+            };
+            loop {
+                let purged = db
+                    .lock()
+                    .expect("database lock poisoned")
+                    .purge_deleted(retention);
+                if let Err(e) = purged {
+                    server.logger.error(None, &e.into());
+                }
+                thread::sleep(interval);
+            }
+        })
+    }
+
+    /// Start a background job that snapshots the database file at `db_path` every
+    /// `interval`. Snapshots are taken with a separate connection, so requests are served
+    /// while they run
+    pub fn start_snapshot_job(
+        &self,
+        db_path: PathBuf,
+        snapshots: Snapshots,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let server = self.clone();
+        thread::spawn(move || loop {
+            if let Err(e) = snapshots.take(&db_path, clock::unix_now()) {
+                server.logger.error(None, &e);
+            }
+            thread::sleep(interval);
+        })
+    }
+
+    /// Start a background job that delivers order events to the webhooks subscribed to them,
+    /// retrying failed deliveries as the policy allows and recording the ones it gives up on
+    /// as dead letters
+    pub fn start_webhook_worker(&self, policy: RetryPolicy) -> JoinHandle<()> {
+        let server = self.clone();
+        let subscription = self.events.subscribe(None);
+        thread::spawn(move || {
+            if let Some(db) = &server.db {
+                Worker::new(policy).run(db, subscription.receiver, &server.logger)
+            }
+        })
+    }
+
+    /// Move the kitchen queue and the couriers along to the clock's current time
+    pub fn step_simulation(&self) -> Result<(), AspirinEatsError> {
+        let Some(db) = &self.db else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        let db = db.lock().expect("database lock poisoned");
+        db.advance_kitchen(now)?;
+        db.dispatch_couriers(now)?;
+        Ok(())
+    }
+
+    /// Start a background job that steps the kitchen and delivery simulation every `interval`
+    pub fn start_simulation(&self, interval: Duration) -> JoinHandle<()> {
+        let server = self.clone();
+        thread::spawn(move || loop {
+            if let Err(e) = server.step_simulation() {
+                server.logger.error(None, &e);
+            }
+            thread::sleep(interval);
+        })
+    }
+
+    /// Bind to the given address and serve on a background thread. Useful for testing with
+    /// an ephemeral port (`127.0.0.1:0`)
+    pub fn spawn<A: ToSocketAddrs>(self, addr: A) -> std::io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        thread::spawn(move || self.serve(listener));
+        Ok(local_addr)
+    }
+
+    /// Serve requests on a single connection until the client closes it
+    pub fn handle_connection(&self, stream: TcpStream) -> Result<(), AspirinEatsError> {
+        let _connection = self.metrics.track_connection();
+        stream.set_write_timeout(Some(self.limits.write_timeout))?;
+        let mut reader = BufReader::new(TimedStream::new(stream.try_clone()?));
+        let mut writer = stream;
+
+        loop {
+            let mut request = match http::read_request_limited(&mut reader, &self.limits) {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    self.logger.error(None, &e);
+                    write_response(&mut writer, HttpResponse::from(e), true, None)?;
+                    return Ok(());
+                }
+            };
+
+            let started = Instant::now();
+            let request_id = log::request_id(&request);
+
+            let response = match self.upgrade_route(&request) {
+                Some(Upgrade::Events(order_id)) => {
+                    match self.upgrade_head(&mut request, &request_id, |_| sse::stream_response()) {
+                        Ok(head) => {
+                            let result = self.stream(&mut writer, head, &request, order_id);
+                            self.log_access(&request_id, &request, 200, 0, started);
+                            return result;
+                        }
+                        Err(response) => response,
+                    }
+                }
+                Some(Upgrade::Socket) => {
+                    match self.upgrade_head(&mut request, &request_id, |r| self.handshake(r)) {
+                        Ok(head) => {
+                            let result = self.socket(reader, writer, head, &request_id);
+                            self.log_access(&request_id, &request, 101, 0, started);
+                            return result;
+                        }
+                        Err(response) => response,
+                    }
+                }
+                None => self.dispatch(&mut request, &request_id),
+            };
+
+            let status = response.status_code();
+            let close = request.wants_close();
+            let accept_encoding = request.header("Accept-Encoding");
+            let bytes = write_response(&mut writer, response, close, accept_encoding)?;
+            self.log_access(&request_id, &request, status, bytes, started);
+            if close {
+                return Ok(());
+            }
+        }
+    }
+
+    /// If the request takes its connection over, get what for. Streams for orders that
+    /// cannot be found are left to `dispatch`, which answers 404
+    pub(crate) fn upgrade_route(&self, request: &HttpRequest) -> Option<Upgrade> {
+        if request.method.as_deref() != Some("GET") {
+            return None;
+        }
+        match Route::from_str(request.route()) {
+            Ok(Route::OrdersStream) => Some(Upgrade::Events(None)),
+            Ok(Route::OrderStream(uuid)) => {
+                let store = self.store.lock().expect("store lock poisoned");
+                api::order_id(&*store, uuid)
+                    .ok()
+                    .map(|id| Upgrade::Events(Some(id)))
+            }
+            Ok(Route::OrdersSocket) => Some(Upgrade::Socket),
+            _ => None,
+        }
+    }
+
+    /// Route a request through the middleware to its handler, logging any error the
+    /// handler fails with. Streams are not handled here
+    pub(crate) fn dispatch(&self, request: &mut HttpRequest, request_id: &str) -> HttpResponse {
+        self.middleware
+            .run(request, |request| {
+                let store = self.store.lock().expect("store lock poisoned");
+                api::handle_request(&*store, request).inspect_err(|e| {
+                    self.logger.error(Some(request_id), e);
+                })
+            })
+            .with_header(REQUEST_ID_HEADER, request_id)
+    }
+
+    /// Run the middleware around taking a connection over, with `open` answering the
+    /// request in place of a handler. Returns the head to take the connection over with, or
+    /// the response to send instead if a middleware answered the request or `open` refused it
+    pub(crate) fn upgrade_head<F>(
+        &self,
+        request: &mut HttpRequest,
+        request_id: &str,
+        open: F,
+    ) -> Result<HttpResponse, HttpResponse>
+    where
+        F: FnOnce(&HttpRequest) -> HttpResponse,
+    {
+        let mut opened = false;
+        let response = self
+            .middleware
+            .run(request, |request| {
+                opened = true;
+                Ok(open(request))
+            })
+            .with_header(REQUEST_ID_HEADER, request_id);
+        if opened && matches!(response.status_code(), 101 | 200) {
+            Ok(response)
+        } else {
+            Err(response)
+        }
+    }
+
+    fn stream<W: Write>(
+        &self,
+        writer: &mut W,
+        head: HttpResponse,
+        request: &HttpRequest,
+        order_id: Option<i64>,
+    ) -> Result<(), AspirinEatsError> {
+        let subscription = self.events.subscribe(sse::last_event_id(request));
+        writer.write_all(head.to_string().as_bytes())?;
+        sse::stream_events(writer, subscription, order_id, sse::KEEP_ALIVE_INTERVAL)
+    }
+
+    /// Serve a WebSocket on a connection whose opening handshake was accepted with `head`.
+    /// Anything the client sent after its handshake must be at the start of `reader`
+    pub(crate) fn socket<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+        mut stream: TcpStream,
+        head: HttpResponse,
+        request_id: &str,
+    ) -> Result<(), AspirinEatsError> {
+        stream.set_write_timeout(Some(self.limits.write_timeout))?;
+        stream.write_all(head.to_string().as_bytes())?;
+        let reader = MessageReader::new(reader, self.limits.max_body, true);
+        let result = Session::new(
+            self.store.clone(),
+            self.logger.clone(),
+            request_id,
+            websocket::KEEP_ALIVE_INTERVAL,
+        )
+        .run(reader, &mut stream, &self.events);
+        // Stops the session's reader, which holds a handle of its own to the connection
+        let _ = stream.shutdown(Shutdown::Both);
+        result
+    }
+
+    pub(crate) fn log_access(
+        &self,
+        request_id: &str,
+        request: &HttpRequest,
+        status: u16,
+        bytes: u64,
+        started: Instant,
+    ) {
+        self.metrics.observe_request(
+            api::route_template(request.route()),
+            status,
+            started.elapsed(),
+        );
+        self.logger.access(
+            request_id,
+            request.method.as_deref().unwrap_or("-"),
+            request.path.as_deref().unwrap_or("-"),
+            status,
+            bytes,
+            started.elapsed(),
+        );
+    }
+}
+
+/// Write a response with the framing headers the client needs to find its end, compressed
+/// if the client's `Accept-Encoding` allows it. Returns the number of body bytes written
+pub fn write_response<W: Write>(
+    writer: &mut W,
+    response: HttpResponse,
+    close: bool,
+    accept_encoding: Option<&str>,
+) -> Result<u64, AspirinEatsError> {
+    let (message, bytes) = frame_response(response, close, accept_encoding)?;
+    writer.write_all(&message)?;
+    writer.flush()?;
+    Ok(bytes)
+}
+
+/// Compress a response's body as negotiated, add the framing headers and serialize it.
+/// Returns the message and the number of body bytes in it
+pub(crate) fn frame_response(
+    mut response: HttpResponse,
+    close: bool,
+    accept_encoding: Option<&str>,
+) -> Result<(Vec<u8>, u64), AspirinEatsError> {
+    let body = response.take_body().into_bytes();
+    let body = compression::encode(&mut response, body, accept_encoding)?;
+    response.set_header("Content-Length", &body.len().to_string());
+    if close {
+        response.set_header("Connection", "close");
+    }
+    let mut message = response.head().into_bytes();
+    message.extend_from_slice(&body);
+    Ok((message, body.len() as u64))
+}
@@ -0,0 +1,122 @@
+use std::io::Write;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use crate::error::AspirinEatsError;
+use crate::events::{OrderEvent, Subscription};
+use crate::http::{HttpRequest, HttpResponse};
+
+/// How long a stream may stay quiet before a keep-alive comment is sent
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Build the response head that opens an event stream
+pub fn stream_response() -> HttpResponse {
+    HttpResponse::new(200, "OK", "")
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        .with_header("Connection", "keep-alive")
+}
+
+/// Get the event ID a reconnecting client wants to resume after, if any
+pub fn last_event_id(request: &HttpRequest) -> Option<u64> {
+    request
+        .header("Last-Event-ID")
+        .and_then(|id| id.trim().parse().ok())
+}
+
+/// Format a single order event as a Server-Sent Event
+pub fn format_event(event: &OrderEvent) -> String {
+    format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id,
+        event.kind.name(),
+        event.order
+    )
+}
+
+/// Whether an event belongs on a stream that is optionally limited to a single order
+pub fn matches(event: &OrderEvent, order_id: Option<i64>) -> bool {
+    order_id.is_none() || event.order.id == order_id
+}
+
+/// Write every event in the subscription to the writer, optionally only for a single order.
+/// Returns once the client goes away or the event bus is dropped
+pub fn stream_events<W: Write>(
+    writer: &mut W,
+    subscription: Subscription,
+    order_id: Option<i64>,
+    keep_alive: Duration,
+) -> Result<(), AspirinEatsError> {
+    for event in subscription
+        .backlog
+        .iter()
+        .filter(|event| matches(event, order_id))
+    {
+        writer.write_all(format_event(event).as_bytes())?;
+    }
+    writer.flush()?;
+
+    loop {
+        match subscription.receiver.recv_timeout(keep_alive) {
+            Ok(event) if matches(&event, order_id) => {
+                writer.write_all(format_event(&event).as_bytes())?
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => writer.write_all(b": keep-alive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        writer.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventBus, OrderEventKind};
+    use crate::food::*;
+
+    fn get_test_order(id: i64) -> Order {
+        Order {
+            id: Some(id),
+            uuid: None,
+            customer: "Amit".to_string(),
+            customer_id: None,
+            created_at: None,
+            food: vec![MenuItem::Drink],
+            status: OrderStatus::Pending,
+            total: 3.0,
+            estimated_ready_at: None,
+        }
+    }
+
+    #[test]
+    fn test_stream_events_filters_and_resumes() {
+        let bus = EventBus::default();
+        bus.publish(OrderEventKind::Created, get_test_order(1));
+        bus.publish(OrderEventKind::Created, get_test_order(2));
+        let subscription = bus.subscribe(Some(0));
+        bus.publish(OrderEventKind::Deleted, get_test_order(2));
+        drop(bus);
+
+        let mut output = Vec::new();
+        stream_events(&mut output, subscription, Some(2), KEEP_ALIVE_INTERVAL).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                "id: 2\nevent: created\ndata: {}\n\nid: 3\nevent: deleted\ndata: {}\n\n",
+                get_test_order(2),
+                get_test_order(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_last_event_id() {
+        let request: HttpRequest = "GET /orders/stream HTTP/1.1\r\nLast-Event-ID: 42\r\n\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(last_event_id(&request), Some(42));
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::db::AspirinEatsDb;
+use crate::error::AspirinEatsError;
+use crate::food::{self, Order, OrderStatus};
+
+/// Storage for orders. The order handlers only need this, so they can be run against any
+/// backend. IDs are assigned by the store and never reused, even after a reset
+pub trait OrderStore {
+    /// Store a new order, ignoring any IDs it already has. Returns the assigned ID
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError>;
+
+    /// Get an order by ID
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError>;
+
+    /// Get the ID of the order with the given public ID. Stores that keep removed orders
+    /// around may return their IDs too
+    fn get_order_id(&self, uuid: &Uuid) -> Result<Option<i64>, AspirinEatsError>;
+
+    /// Get every order, by ascending ID
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError>;
+
+    /// Remove an order by ID. Removing an order that does not exist is not an error
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError>;
+
+    /// Remove every order
+    fn reset_orders(&self) -> Result<(), AspirinEatsError>;
+
+    /// Set the status of an order. Returns the updated order, or `None` if there is no order
+    /// with that ID
+    fn update_order_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+    ) -> Result<Option<Order>, AspirinEatsError>;
+
+    /// The database behind the store, if there is one. Everything besides orders, such as
+    /// customers, inventory, webhooks and reports, is only kept by a database
+    fn database(&self) -> Option<&AspirinEatsDb> {
+        None
+    }
+}
+
+/// A single change to a store's orders, as recorded in a `FileStore` log
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+enum Entry {
+    Add(#[serde(serialize_with = "food::serialize_with_id")] Order),
+    Remove(i64),
+    Reset,
+    Status(i64, OrderStatus),
+}
+
+/// Orders held in memory, shared by `MemoryStore` and `FileStore`
+#[derive(Debug)]
+struct Orders {
+    orders: HashMap<i64, Order>,
+    next_id: i64,
+}
+
+impl Default for Orders {
+    fn default() -> Self {
+        Orders {
+            orders: HashMap::new(),
+            next_id: 1,
+        }
+    }
+}
+
+impl Orders {
+    /// The entry that adds the order under the next free ID and a new public ID
+    fn add_entry(&self, mut order: Order) -> Entry {
+        order.id = Some(self.next_id);
+        order.uuid = Some(Uuid::new_v4());
+        Entry::Add(order)
+    }
+
+    fn find(&self, uuid: &Uuid) -> Option<i64> {
+        self.orders
+            .values()
+            .find(|order| order.uuid.as_ref() == Some(uuid))
+            .and_then(|order| order.id)
+    }
+
+    /// Make the change described by the entry
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Add(order) => {
+                let id = order.id.expect("added orders should have an ID");
+                self.next_id = self.next_id.max(id + 1);
+                self.orders.insert(id, order);
+            }
+            Entry::Remove(id) => {
+                self.orders.remove(&id);
+            }
+            Entry::Reset => self.orders.clear(),
+            Entry::Status(id, status) => {
+                if let Some(order) = self.orders.get_mut(&id) {
+                    order.status = status;
+                }
+            }
+        }
+    }
+
+    fn all(&self) -> Vec<Order> {
+        let mut orders: Vec<Order> = self.orders.values().cloned().collect();
+        orders.sort_by_key(|order| order.id);
+        orders
+    }
+}
+
+/// Store that keeps orders in a `HashMap`. Nothing outlives the store
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    orders: Mutex<Orders>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn orders(&self) -> MutexGuard<'_, Orders> {
+        self.orders.lock().expect("store lock poisoned")
+    }
+}
+
+impl OrderStore for MemoryStore {
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        let mut orders = self.orders();
+        let id = orders.next_id;
+        let entry = orders.add_entry(order);
+        orders.apply(entry);
+        Ok(id)
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(self.orders().orders.get(&id).cloned())
+    }
+
+    fn get_order_id(&self, uuid: &Uuid) -> Result<Option<i64>, AspirinEatsError> {
+        Ok(self.orders().find(uuid))
+    }
+
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(self.orders().all())
+    }
+
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        self.orders().apply(Entry::Remove(id));
+        Ok(())
+    }
+
+    fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        self.orders().apply(Entry::Reset);
+        Ok(())
+    }
+
+    fn update_order_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+    ) -> Result<Option<Order>, AspirinEatsError> {
+        let mut orders = self.orders();
+        orders.apply(Entry::Status(id, status));
+        Ok(orders.orders.get(&id).cloned())
+    }
+}
+
+/// Store that keeps orders in memory, backed by an append-only log file with one JSON entry
+/// per change. Every change is written and synced to the log before it is applied, and the
+/// log is replayed when the store is opened
+#[derive(Debug)]
+pub struct FileStore {
+    state: Mutex<(File, Orders)>,
+}
+
+impl FileStore {
+    /// Open the log at `path`, creating it if needed, and replay it. A final entry that was
+    /// only partly written (e.g. because of a crash) is dropped from the log. Any complete
+    /// entry that does not parse fails, leaving the log as it is
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, AspirinEatsError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut orders = Orders::default();
+        let mut valid_len = 0;
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            // Only the last line can lack its newline, and then it is a torn write
+            if !line.ends_with('\n') {
+                break;
+            }
+            orders.apply(serde_json::from_str(&line)?);
+            valid_len += line.len() as u64;
+            line.clear();
+        }
+        file.set_len(valid_len)?;
+
+        Ok(FileStore {
+            state: Mutex::new((file, orders)),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, (File, Orders)> {
+        self.state.lock().expect("store lock poisoned")
+    }
+}
+
+/// Write the entry to the end of the log and wait for it to reach the disk, then apply it
+fn commit(file: &mut File, orders: &mut Orders, entry: Entry) -> Result<(), AspirinEatsError> {
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    orders.apply(entry);
+    Ok(())
+}
+
+impl OrderStore for FileStore {
+    fn add_order(&self, order: Order) -> Result<i64, AspirinEatsError> {
+        let (file, orders) = &mut *self.state();
+        let id = orders.next_id;
+        let entry = orders.add_entry(order);
+        commit(file, orders, entry)?;
+        Ok(id)
+    }
+
+    fn get_order(&self, id: i64) -> Result<Option<Order>, AspirinEatsError> {
+        Ok(self.state().1.orders.get(&id).cloned())
+    }
+
+    fn get_order_id(&self, uuid: &Uuid) -> Result<Option<i64>, AspirinEatsError> {
+        Ok(self.state().1.find(uuid))
+    }
+
+    fn get_all_orders(&self) -> Result<Vec<Order>, AspirinEatsError> {
+        Ok(self.state().1.all())
+    }
+
+    fn remove_order(&self, id: i64) -> Result<(), AspirinEatsError> {
+        let (file, orders) = &mut *self.state();
+        if orders.orders.contains_key(&id) {
+            commit(file, orders, Entry::Remove(id))?;
+        }
+        Ok(())
+    }
+
+    fn reset_orders(&self) -> Result<(), AspirinEatsError> {
+        let (file, orders) = &mut *self.state();
+        commit(file, orders, Entry::Reset)
+    }
+
+    fn update_order_status(
+        &self,
+        id: i64,
+        status: OrderStatus,
+    ) -> Result<Option<Order>, AspirinEatsError> {
+        let (file, orders) = &mut *self.state();
+        if !orders.orders.contains_key(&id) {
+            return Ok(None);
+        }
+        commit(file, orders, Entry::Status(id, status))?;
+        Ok(orders.orders.get(&id).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::db::AspirinEatsDb;
+    use crate::food::MenuItem;
+
+    fn get_test_order(customer: &str) -> Order {
+        Order {
+            id: Some(99),
+            uuid: None,
+            customer: customer.to_string(),
+            customer_id: None,
+            created_at: None,
+            food: vec![MenuItem::Fries, MenuItem::Drink],
+            status: OrderStatus::Pending,
+            total: 8.0,
+            estimated_ready_at: None,
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("aspirin-{}.log", uuid::Uuid::new_v4()))
+    }
+
+    /// Customers of every order in the store, by ascending ID
+    fn customers(store: &dyn OrderStore) -> Vec<String> {
+        let orders = store.get_all_orders().unwrap();
+        assert!(orders.windows(2).all(|pair| pair[0].id < pair[1].id));
+        orders.into_iter().map(|order| order.customer).collect()
+    }
+
+    /// Behaviour every `OrderStore` must have
+    fn conformance(store: &dyn OrderStore) {
+        assert!(store.get_all_orders().unwrap().is_empty());
+        assert_eq!(store.get_order(1).unwrap(), None);
+
+        let amit = store.add_order(get_test_order("Amit")).unwrap();
+        let alice = store.add_order(get_test_order("Alice")).unwrap();
+        assert_ne!(amit, alice);
+        let order = store.get_order(amit).unwrap().unwrap();
+        assert_eq!(order.id, Some(amit));
+        let uuid = order.uuid.expect("stores should assign public IDs");
+        assert_eq!(store.get_order_id(&uuid).unwrap(), Some(amit));
+        assert_eq!(store.get_order_id(&Uuid::new_v4()).unwrap(), None);
+        let other = store.get_order(alice).unwrap().unwrap().uuid;
+        assert_ne!(other, Some(uuid));
+        assert_eq!(order.customer, "Amit");
+        assert_eq!(order.food, vec![MenuItem::Fries, MenuItem::Drink]);
+        assert_eq!(order.status, OrderStatus::Pending);
+        assert_eq!(order.total, 8.0);
+        assert_eq!(customers(store), vec!["Amit", "Alice"]);
+
+        let order = store
+            .update_order_status(alice, OrderStatus::Preparing)
+            .unwrap()
+            .unwrap();
+        assert_eq!(order.status, OrderStatus::Preparing);
+        assert_eq!(
+            store.get_order(alice).unwrap().unwrap().status,
+            OrderStatus::Preparing
+        );
+        assert_eq!(
+            store
+                .update_order_status(alice + 100, OrderStatus::Ready)
+                .unwrap(),
+            None
+        );
+
+        store.remove_order(amit).unwrap();
+        store.remove_order(amit).unwrap();
+        assert_eq!(store.get_order(amit).unwrap(), None);
+        assert_eq!(customers(store), vec!["Alice"]);
+
+        store.reset_orders().unwrap();
+        assert!(store.get_all_orders().unwrap().is_empty());
+        let bob = store.add_order(get_test_order("Bob")).unwrap();
+        assert!(bob > alice, "IDs must not be reused");
+        assert_eq!(customers(store), vec!["Bob"]);
+    }
+
+    #[test]
+    fn test_sqlite_store_conformance() {
+        conformance(&AspirinEatsDb::in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_memory_store_conformance() {
+        conformance(&MemoryStore::new());
+    }
+
+    #[test]
+    fn test_file_store_conformance() {
+        conformance(&FileStore::open(temp_path()).unwrap());
+    }
+
+    #[test]
+    fn test_file_store_replays_log() {
+        let path = temp_path();
+        let store = FileStore::open(&path).unwrap();
+        let amit = store.add_order(get_test_order("Amit")).unwrap();
+        let alice = store.add_order(get_test_order("Alice")).unwrap();
+        store
+            .update_order_status(alice, OrderStatus::Ready)
+            .unwrap();
+        store.remove_order(amit).unwrap();
+        let expected = store.get_all_orders().unwrap();
+        drop(store);
+
+        // A crash in the middle of writing an entry leaves half a line behind
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"Add":{"id":3,"cus"#).unwrap();
+        drop(file);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get_all_orders().unwrap(), expected);
+        let bob = store.add_order(get_test_order("Bob")).unwrap();
+        assert_eq!(bob, alice + 1);
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(customers(&store), vec!["Alice", "Bob"]);
+        drop(store);
+
+        // A complete entry was committed, so one that does not parse is not cut off
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"Unknown\":1}\n").unwrap();
+        drop(file);
+        let length = std::fs::metadata(&path).unwrap().len();
+        assert!(matches!(
+            FileStore::open(&path),
+            Err(AspirinEatsError::ParseError(_))
+        ));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), length);
+    }
+}
@@ -0,0 +1,1070 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use display_json::{DisplayAsJson, FromStrAsJson};
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::clock;
+use crate::db::AspirinEatsDb;
+use crate::error::AspirinEatsError;
+use crate::events::{OrderEvent, OrderEventKind};
+use crate::http::{self, Deadline, HttpRequest, HttpResponse, Limits, TimedStream};
+use crate::log::Logger;
+
+/// Header carrying the name of the event a delivery is for
+pub const EVENT_HEADER: &str = "X-Aspirin-Event";
+
+/// Header carrying the ID of a delivery. Retries of a delivery keep its ID, so receivers can
+/// ignore the ones they have already seen
+pub const DELIVERY_HEADER: &str = "X-Aspirin-Delivery";
+
+/// Header carrying the time a delivery was signed, in seconds since the Unix epoch
+pub const TIMESTAMP_HEADER: &str = "X-Aspirin-Timestamp";
+
+/// Header carrying the signature of a delivery, as `sha256=<hex digest>`
+pub const SIGNATURE_HEADER: &str = "X-Aspirin-Signature";
+
+/// Largest response body read from a receiver, in bytes. Only the status is used
+const MAX_RESPONSE_BODY: usize = 64 * 1024;
+
+/// Struct that represents a subscription to order events
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct Webhook {
+    /// Webhook ID (unique). Should be generated by the SQL database
+    pub id: Option<i64>,
+
+    /// Where events are delivered, e.g. `http://partner.example:8000/aspirin`. Only plain
+    /// `http` URLs are supported
+    pub url: String,
+
+    /// Names of the events delivered, such as `created` or `status_changed`. Every event is
+    /// delivered if empty
+    pub events: Vec<String>,
+
+    /// Public IDs of the orders whose events are delivered. Only returned when the webhook is
+    /// created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub orders: Vec<Uuid>,
+
+    /// Public IDs of the customers whose orders' events are delivered. Only returned when the
+    /// webhook is created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub customers: Vec<Uuid>,
+
+    /// Key deliveries are signed with. Only returned when the webhook is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl Webhook {
+    /// Whether the webhook is subscribed to the given kind of event
+    pub fn wants(&self, kind: OrderEventKind) -> bool {
+        self.events.is_empty() || self.events.iter().any(|name| name == kind.name())
+    }
+
+    /// Whether the webhook covers an order, given its public ID and its customer's
+    pub fn covers(&self, order: Option<Uuid>, customer: Option<Uuid>) -> bool {
+        order.is_some_and(|order| self.orders.contains(&order))
+            || customer.is_some_and(|customer| self.customers.contains(&customer))
+    }
+}
+
+/// Struct that represents an incoming request to subscribe to order events
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct WebhookRequest {
+    /// Where events are delivered. Only plain `http` URLs are supported
+    pub url: String,
+
+    /// Names of the events to deliver: `created`, `status_changed`, `deleted` or `restored`.
+    /// Every event is delivered if not given
+    #[serde(default)]
+    pub events: Vec<String>,
+
+    /// Public IDs of the orders to deliver events for
+    #[serde(default)]
+    pub orders: Vec<Uuid>,
+
+    /// Public IDs of the customers to deliver the order events of. At least one order or
+    /// customer must be given
+    #[serde(default)]
+    pub customers: Vec<Uuid>,
+
+    /// Key to sign deliveries with. A random one is generated if not given
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl TryFrom<WebhookRequest> for Webhook {
+    type Error = AspirinEatsError;
+
+    /// Check the URL, event names and scope, and fill in a secret if none was given
+    fn try_from(request: WebhookRequest) -> Result<Self, Self::Error> {
+        parse_url(&request.url)?;
+        if request
+            .events
+            .iter()
+            .any(|name| OrderEventKind::from_name(name).is_none())
+            || request.orders.is_empty() && request.customers.is_empty()
+        {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+        let secret = match request.secret {
+            Some(secret) if secret.is_empty() => return Err(AspirinEatsError::InvalidRequest),
+            Some(secret) => secret,
+            None => Uuid::new_v4().simple().to_string(),
+        };
+        Ok(Webhook {
+            id: None,
+            url: request.url,
+            events: request.events,
+            orders: request.orders,
+            customers: request.customers,
+            secret: Some(secret),
+        })
+    }
+}
+
+/// Struct that represents a delivery that was given up on after running out of attempts
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+pub struct DeadLetter {
+    /// Dead letter ID (unique). Should be generated by the SQL database
+    pub id: Option<i64>,
+
+    /// The webhook the delivery was for
+    pub webhook_id: i64,
+
+    /// ID the delivery was sent with
+    pub delivery_id: String,
+
+    /// Name of the event
+    pub event: String,
+
+    /// The JSON body that could not be delivered
+    pub payload: String,
+
+    /// Number of attempts made
+    pub attempts: u32,
+
+    /// Why the last attempt failed
+    pub error: String,
+
+    /// When the delivery was given up on, in seconds since the Unix epoch
+    pub failed_at: i64,
+}
+
+/// How often and how patiently deliveries are attempted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made before a delivery is moved to the dead letters
+    pub max_attempts: u32,
+
+    /// Wait before the first retry. Every later retry waits twice as long as the one before
+    pub initial_backoff: Duration,
+
+    /// Longest wait between two attempts
+    pub max_backoff: Duration,
+
+    /// How long a receiver may take to accept the connection, and then to answer
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 8,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10 * 60),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the given (1-based) failed attempt before trying again
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Split an `http://host[:port][/path]` URL into the address to connect to and the path to
+/// request
+pub fn parse_url(url: &str) -> Result<(String, String), AspirinEatsError> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() || authority.contains('@') {
+        return Err(AspirinEatsError::InvalidRequest);
+    }
+    let addr = match authority.rsplit_once(':') {
+        // The colons inside an IPv6 literal such as `[::1]` do not start a port
+        Some((_, port)) if !port.ends_with(']') => {
+            port.parse::<u16>()
+                .map_err(|_| AspirinEatsError::InvalidRequest)?;
+            authority.to_string()
+        }
+        _ => format!("{}:80", authority),
+    };
+    Ok((addr, path.to_string()))
+}
+
+/// Whether deliveries may go to an address. Loopback, private, link-local and unspecified
+/// addresses are refused unless `private` allows them, so subscribers cannot use the server to
+/// reach into the network it runs in
+pub fn allowed_target(ip: IpAddr, private: bool) -> bool {
+    let internal = match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => return allowed_target(IpAddr::V4(ip), private),
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80
+            }
+        },
+    };
+    private || !internal
+}
+
+/// Check that a webhook URL only leads to addresses deliveries may go to, as
+/// `allowed_target` decides. Host names are resolved, and refused if they cannot be
+pub fn check_target(url: &str, private: bool) -> Result<(), AspirinEatsError> {
+    let (addr, _) = parse_url(url)?;
+    let addrs: Vec<SocketAddr> = addr
+        .to_socket_addrs()
+        .map_err(|_| AspirinEatsError::InvalidRequest)?
+        .collect();
+    if addrs.is_empty() || !addrs.iter().all(|addr| allowed_target(addr.ip(), private)) {
+        return Err(AspirinEatsError::InvalidRequest);
+    }
+    Ok(())
+}
+
+/// Sign a delivery body, sent at `timestamp`, with a webhook's secret. The signature covers
+/// `<timestamp>.<body>`, so a captured delivery cannot be replayed with a new timestamp
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let digest = mac(secret, timestamp, body).finalize().into_bytes();
+    format!("sha256={}", hex::encode(digest))
+}
+
+/// Check a signature made by `sign`, in constant time
+pub fn verify(secret: &str, timestamp: i64, body: &str, signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix("sha256=")
+        .and_then(|digest| hex::decode(digest).ok())
+    else {
+        return false;
+    };
+    mac(secret, timestamp, body).verify_slice(&digest).is_ok()
+}
+
+fn mac(secret: &str, timestamp: i64, body: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    mac
+}
+
+/// Build the JSON body delivered for an event
+pub fn payload(delivery_id: &str, event: &OrderEvent) -> String {
+    json!({
+        "id": delivery_id,
+        "event": event.kind.name(),
+        "order": event.order,
+    })
+    .to_string()
+}
+
+/// Make a single delivery attempt: POST the signed body to the webhook's URL. Succeeds if
+/// the receiver answers with a 2xx status. The URL is resolved again for every attempt, and
+/// only addresses `allowed_target` accepts (given `private`) are connected to
+pub fn deliver(
+    webhook: &Webhook,
+    delivery_id: &str,
+    event: &str,
+    body: &str,
+    timeout: Duration,
+    private: bool,
+) -> Result<(), AspirinEatsError> {
+    let (addr, path) = parse_url(&webhook.url)?;
+    let secret = webhook
+        .secret
+        .as_deref()
+        .ok_or(AspirinEatsError::InvalidRequest)?;
+    let timestamp = clock::unix_now();
+
+    let mut request = HttpRequest {
+        method: Some("POST".to_string()),
+        path: Some(path),
+        headers: Vec::new(),
+        body: Some(body.to_string()),
+    };
+    request.set_header("Host", &addr);
+    request.set_header("Content-Type", "application/json");
+    request.set_header("Content-Length", &body.len().to_string());
+    request.set_header("Connection", "close");
+    request.set_header(EVENT_HEADER, event);
+    request.set_header(DELIVERY_HEADER, delivery_id);
+    request.set_header(TIMESTAMP_HEADER, &timestamp.to_string());
+    request.set_header(SIGNATURE_HEADER, &sign(secret, timestamp, body));
+
+    let mut stream = connect(&addr, timeout, private)?;
+    stream.write_all(request.to_string().as_bytes())?;
+    stream.flush()?;
+    // Each part of the response must arrive within the timeout, even if it trickles in
+    let mut reader = BufReader::new(TimedStream::new(stream));
+    reader.set_deadline(Instant::now().checked_add(timeout));
+    let limits = Limits {
+        read_header_timeout: timeout,
+        read_body_timeout: timeout,
+        max_body: MAX_RESPONSE_BODY,
+        ..Limits::default()
+    };
+    let message =
+        http::read_message_limited(&mut reader, &limits)?.ok_or(AspirinEatsError::BadGateway)?;
+    match HttpResponse::from_str(&message)?.status_code() {
+        200..=299 => Ok(()),
+        status => Err(AspirinEatsError::UnexpectedStatus(status)),
+    }
+}
+
+fn connect(addr: &str, timeout: Duration, private: bool) -> Result<TcpStream, AspirinEatsError> {
+    let mut last_error = None;
+    let allowed = addr
+        .to_socket_addrs()?
+        .filter(|addr| allowed_target(addr.ip(), private));
+    for addr in allowed {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .map(AspirinEatsError::from)
+        .unwrap_or(AspirinEatsError::InvalidRequest))
+}
+
+/// A delivery that has not been made yet. Pending deliveries are kept in the database, so
+/// they survive a restart
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    /// Delivery row ID (unique). Should be generated by the SQL database
+    pub id: Option<i64>,
+
+    /// The webhook the delivery is for
+    pub webhook_id: i64,
+
+    /// ID the delivery is sent with, the same on every attempt
+    pub delivery_id: String,
+
+    /// Name of the event
+    pub event: String,
+
+    /// The JSON body to deliver
+    pub payload: String,
+
+    /// Number of attempts made so far
+    pub attempts: u32,
+
+    /// When the next attempt is due, in seconds since the Unix epoch
+    pub due_at: i64,
+}
+
+/// Record a delivery of the event, due at `now` (seconds since the Unix epoch), to every
+/// webhook subscribed to it and covering its order. Returns the recorded deliveries
+pub(crate) fn enqueue(
+    db: &AspirinEatsDb,
+    event: &OrderEvent,
+    now: i64,
+) -> Result<Vec<Delivery>, AspirinEatsError> {
+    let customer = match event.order.customer_id {
+        Some(id) => db.get_customer(id)?.and_then(|customer| customer.uuid),
+        None => None,
+    };
+    let mut deliveries = Vec::new();
+    for webhook in db.get_webhooks()? {
+        let wanted = webhook.wants(event.kind) && webhook.covers(event.order.uuid, customer);
+        let Some(webhook_id) = webhook.id.filter(|_| wanted) else {
+            continue;
+        };
+        let delivery_id = Uuid::new_v4().to_string();
+        let mut delivery = Delivery {
+            id: None,
+            webhook_id,
+            payload: payload(&delivery_id, event),
+            delivery_id,
+            event: event.kind.name().to_string(),
+            attempts: 0,
+            due_at: now,
+        };
+        delivery.id = Some(db.add_delivery(&delivery)?);
+        deliveries.push(delivery);
+    }
+    Ok(deliveries)
+}
+
+/// The deliveries to a single webhook, attempted one at a time in the order they fall due.
+/// Every lane runs on its own thread, so a receiver that is down only holds up its own
+/// deliveries
+struct Lane {
+    webhook_id: i64,
+    policy: RetryPolicy,
+    queue: Vec<(Delivery, Instant)>,
+}
+
+impl Lane {
+    fn new(webhook_id: i64, policy: RetryPolicy) -> Self {
+        Lane {
+            webhook_id,
+            policy,
+            queue: Vec::new(),
+        }
+    }
+
+    /// Queue a delivery, due when its `due_at` says
+    fn push(&mut self, delivery: Delivery) {
+        let wait = (delivery.due_at - clock::unix_now()).max(0) as u64;
+        let due = Instant::now() + Duration::from_secs(wait);
+        self.queue.push((delivery, due));
+    }
+
+    /// When the next queued delivery falls due, if any
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.iter().map(|(_, due)| *due).min()
+    }
+
+    /// Attempt every delivery that is due, saving the outcome of each to the database.
+    /// Deliveries that fail their last attempt are moved to the dead letters. Returns
+    /// `false`, dropping the queue, once the webhook has been removed
+    fn deliver_due(&mut self, db: &Mutex<AspirinEatsDb>, now: Instant, logger: &Logger) -> bool {
+        let lock = || db.lock().expect("database lock poisoned");
+        let private = lock().private_webhooks();
+        let webhook = match lock().get_webhooks() {
+            Ok(webhooks) => webhooks
+                .into_iter()
+                .find(|webhook| webhook.id == Some(self.webhook_id)),
+            Err(e) => {
+                logger.error(None, &e.into());
+                return true;
+            }
+        };
+        let Some(webhook) = webhook else {
+            self.queue.clear();
+            return false;
+        };
+
+        let (mut due, waiting): (Vec<_>, Vec<_>) =
+            self.queue.drain(..).partition(|(_, due)| *due <= now);
+        self.queue = waiting;
+        due.sort_by_key(|(delivery, due)| (*due, delivery.id));
+
+        for (mut delivery, _) in due {
+            let id = delivery.id.expect("queued deliveries are saved");
+            delivery.attempts += 1;
+            let saved = match deliver(
+                &webhook,
+                &delivery.delivery_id,
+                &delivery.event,
+                &delivery.payload,
+                self.policy.timeout,
+                private,
+            ) {
+                Ok(()) => lock().remove_delivery(id, None),
+                Err(_) if delivery.attempts < self.policy.max_attempts => {
+                    let backoff = self.policy.backoff(delivery.attempts);
+                    // Saved in whole seconds, rounded up so a restart never retries early
+                    delivery.due_at = clock::unix_now() + backoff.as_millis().div_ceil(1000) as i64;
+                    let saved = lock().update_delivery(id, delivery.attempts, delivery.due_at);
+                    self.queue.push((delivery, Instant::now() + backoff));
+                    saved
+                }
+                Err(error) => {
+                    logger.error(Some(&delivery.delivery_id), &error);
+                    let letter = DeadLetter {
+                        id: None,
+                        webhook_id: delivery.webhook_id,
+                        delivery_id: delivery.delivery_id,
+                        event: delivery.event,
+                        payload: delivery.payload,
+                        attempts: delivery.attempts,
+                        error: error.to_string(),
+                        failed_at: clock::unix_now(),
+                    };
+                    lock().remove_delivery(id, Some(&letter))
+                }
+            };
+            if let Err(e) = saved {
+                logger.error(None, &e.into());
+            }
+        }
+        true
+    }
+
+    /// Deliver everything received over `deliveries` until the sender is dropped or the
+    /// webhook is removed
+    fn run(mut self, db: &Mutex<AspirinEatsDb>, deliveries: Receiver<Delivery>, logger: &Logger) {
+        loop {
+            let received = match self.next_due() {
+                Some(due) => deliveries.recv_timeout(due.saturating_duration_since(Instant::now())),
+                None => deliveries
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(delivery) => self.push(delivery),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if self.next_due().is_some_and(|due| due <= Instant::now())
+                && !self.deliver_due(db, Instant::now(), logger)
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Delivers order events to the webhooks subscribed to them, retrying failed deliveries
+/// with exponential backoff. Each webhook gets its own lane, and deliveries are saved until
+/// they are made or given up on
+pub(crate) struct Worker {
+    policy: RetryPolicy,
+}
+
+impl Worker {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        Worker { policy }
+    }
+
+    /// Pick up the deliveries saved before a restart, then deliver every event received
+    /// until the event bus is dropped. Subscriptions are read from the database for every
+    /// event and before every round of attempts, so changes to them take effect straight
+    /// away
+    pub(crate) fn run(
+        self,
+        db: &Mutex<AspirinEatsDb>,
+        events: Receiver<OrderEvent>,
+        logger: &Logger,
+    ) {
+        let lock = || db.lock().expect("database lock poisoned");
+        thread::scope(|scope| {
+            let mut lanes: HashMap<i64, Sender<Delivery>> = HashMap::new();
+            let mut dispatch = |delivery: Delivery| {
+                let webhook_id = delivery.webhook_id;
+                // A lane whose webhook was removed has ended; the ID may have been reused
+                let delivery = match lanes.get(&webhook_id) {
+                    Some(lane) => match lane.send(delivery) {
+                        Ok(()) => return,
+                        Err(SendError(delivery)) => delivery,
+                    },
+                    None => delivery,
+                };
+                let (sender, receiver) = mpsc::channel();
+                let lane = Lane::new(webhook_id, self.policy);
+                scope.spawn(move || lane.run(db, receiver, logger));
+                sender.send(delivery).expect("the new lane is receiving");
+                lanes.insert(webhook_id, sender);
+            };
+
+            let saved = lock().deliveries();
+            match saved {
+                Ok(saved) => saved.into_iter().for_each(&mut dispatch),
+                Err(e) => logger.error(None, &e.into()),
+            }
+            for event in events {
+                let enqueued = enqueue(&lock(), &event, clock::unix_now());
+                match enqueued {
+                    Ok(deliveries) => deliveries.into_iter().for_each(&mut dispatch),
+                    Err(e) => logger.error(None, &e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use super::*;
+    use crate::food::{MenuItem, Order, OrderStatus};
+
+    fn event(kind: OrderEventKind) -> OrderEvent {
+        OrderEvent {
+            id: 1,
+            kind,
+            order: Order {
+                id: Some(1),
+                uuid: Some(ORDER),
+                customer: "Amit".to_string(),
+                customer_id: Some(1),
+                created_at: None,
+                food: vec![MenuItem::Fries],
+                status: OrderStatus::Pending,
+                total: 5.0,
+                estimated_ready_at: None,
+            },
+        }
+    }
+
+    /// Public ID of the order in every test event
+    const ORDER: Uuid = Uuid::from_u128(0x6f1c_2d4e_8a3b_4c5d_9e7f_0a1b_2c3d_4e5f);
+
+    /// A webhook covering the order in every test event
+    fn webhook(id: i64, url: &str, events: &[&str]) -> Webhook {
+        Webhook {
+            id: Some(id),
+            url: url.to_string(),
+            events: events.iter().map(|name| name.to_string()).collect(),
+            orders: vec![ORDER],
+            customers: Vec::new(),
+            secret: Some("s3cret".to_string()),
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Answer each request with the next status, sending every request received over the
+    /// channel. Returns the receiver's URL
+    fn receiver(statuses: &'static [u16]) -> (String, mpsc::Receiver<HttpRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/aspirin", listener.local_addr().unwrap());
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            for &status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let request = http::read_request(&mut reader).unwrap().unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                sender.send(request).unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signature = sign("s3cret", 1_700_000_000, "{}");
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert!(verify("s3cret", 1_700_000_000, "{}", &signature));
+
+        assert!(!verify("other", 1_700_000_000, "{}", &signature));
+        assert!(!verify("s3cret", 1_700_000_001, "{}", &signature));
+        assert!(!verify("s3cret", 1_700_000_000, "{ }", &signature));
+        assert!(!verify("s3cret", 1_700_000_000, "{}", "sha256=zz"));
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("http://partner.example:8000/hooks?x=1").unwrap(),
+            ("partner.example:8000".to_string(), "/hooks?x=1".to_string())
+        );
+        assert_eq!(
+            parse_url("http://partner.example").unwrap(),
+            ("partner.example:80".to_string(), "/".to_string())
+        );
+        assert_eq!(
+            parse_url("http://[::1]:9000/").unwrap(),
+            ("[::1]:9000".to_string(), "/".to_string())
+        );
+        for invalid in [
+            "https://partner.example/",
+            "partner.example",
+            "http:///path",
+            "http://partner.example:http/",
+            "http://user@partner.example/",
+        ] {
+            assert!(parse_url(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_limit() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            ..RetryPolicy::default()
+        };
+        let waits: Vec<u64> = (1..=6).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(waits, vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_webhook_request() {
+        let request = |events: &[&str], secret: Option<&str>| WebhookRequest {
+            url: "http://partner.example/hooks".to_string(),
+            events: events.iter().map(|name| name.to_string()).collect(),
+            orders: vec![ORDER],
+            customers: Vec::new(),
+            secret: secret.map(str::to_string),
+        };
+
+        let webhook = Webhook::try_from(request(&["created"], None)).unwrap();
+        assert_eq!(webhook.secret.as_ref().map(String::len), Some(32));
+        assert!(webhook.wants(OrderEventKind::Created));
+        assert!(!webhook.wants(OrderEventKind::Deleted));
+        assert!(Webhook::try_from(request(&[], Some("key")))
+            .unwrap()
+            .wants(OrderEventKind::Deleted));
+
+        assert!(Webhook::try_from(request(&["eaten"], None)).is_err());
+        assert!(Webhook::try_from(request(&[], Some(""))).is_err());
+        assert!(Webhook::try_from(WebhookRequest {
+            url: "ftp://partner.example/".to_string(),
+            ..request(&[], None)
+        })
+        .is_err());
+        // Subscriptions must be scoped to something
+        assert!(Webhook::try_from(WebhookRequest {
+            orders: Vec::new(),
+            ..request(&[], None)
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_allowed_target() {
+        for internal in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            let ip = internal.parse().unwrap();
+            assert!(!allowed_target(ip, false), "{internal}");
+            assert!(allowed_target(ip, true), "{internal}");
+        }
+        for public in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(allowed_target(public.parse().unwrap(), false), "{public}");
+        }
+
+        assert!(check_target("http://127.0.0.1:9000/", false).is_err());
+        assert!(check_target("http://[::1]:9000/", false).is_err());
+        assert!(check_target("http://localhost:9000/", false).is_err());
+        assert!(check_target("http://127.0.0.1:9000/", true).is_ok());
+        assert!(check_target("http://93.184.216.34/", false).is_ok());
+    }
+
+    /// A database with a webhook for `url` added. Returns the webhook's ID
+    fn subscribed(url: &str, events: &[&str]) -> (Mutex<AspirinEatsDb>, i64) {
+        let db = AspirinEatsDb::in_memory()
+            .unwrap()
+            .with_private_webhooks(true);
+        let id = db.add_webhook(&webhook(0, url, events)).unwrap();
+        (Mutex::new(db), id)
+    }
+
+    fn quiet() -> Logger {
+        Logger::from_writer("webhooks", Box::new(std::io::sink()))
+    }
+
+    /// A lane for `webhook_id` with every delivery of the event queued on it
+    fn lane(db: &Mutex<AspirinEatsDb>, webhook_id: i64, policy: RetryPolicy) -> Lane {
+        let mut lane = Lane::new(webhook_id, policy);
+        let db = db.lock().unwrap();
+        for delivery in enqueue(&db, &event(OrderEventKind::StatusChanged), 0).unwrap() {
+            lane.push(delivery);
+        }
+        lane
+    }
+
+    #[test]
+    fn test_enqueue_saves_deliveries() {
+        let (db, id) = subscribed("http://partner.example/", &["status_changed"]);
+        let db = db.into_inner().unwrap();
+
+        assert!(enqueue(&db, &event(OrderEventKind::Created), 100)
+            .unwrap()
+            .is_empty());
+        let deliveries = enqueue(&db, &event(OrderEventKind::StatusChanged), 100).unwrap();
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].webhook_id, id);
+        assert_eq!(deliveries[0].event, "status_changed");
+        assert_eq!(deliveries[0].due_at, 100);
+        assert_eq!(db.deliveries().unwrap(), deliveries);
+    }
+
+    #[test]
+    fn test_enqueue_only_for_covered_orders() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let customer = db
+            .add_customer(&crate::food::Customer {
+                id: None,
+                uuid: None,
+                name: "Amit".to_string(),
+                contact: None,
+            })
+            .unwrap();
+        let customer_uuid = db.get_customer(customer).unwrap().unwrap().uuid.unwrap();
+        let other = Webhook {
+            orders: vec![Uuid::new_v4()],
+            ..webhook(0, "http://partner.example/", &[])
+        };
+        db.add_webhook(&other).unwrap();
+        let by_customer = db
+            .add_webhook(&Webhook {
+                orders: Vec::new(),
+                customers: vec![customer_uuid],
+                ..other.clone()
+            })
+            .unwrap();
+
+        let mut event = event(OrderEventKind::Created);
+        event.order.customer_id = Some(customer);
+        let deliveries = enqueue(&db, &event, 0).unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].webhook_id, by_customer);
+
+        event.order.customer_id = None;
+        assert!(enqueue(&db, &event, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lane_retries_until_delivered() {
+        let (url, received) = receiver(&[500, 503, 204]);
+        let (db, id) = subscribed(&url, &[]);
+        let mut lane = lane(&db, id, policy());
+
+        for _ in 0..3 {
+            assert!(lane.deliver_due(&db, Instant::now(), &quiet()));
+        }
+        assert!(lane.next_due().is_none());
+        assert!(db.lock().unwrap().deliveries().unwrap().is_empty());
+
+        let requests: Vec<HttpRequest> = received.iter().take(3).collect();
+        let delivery_id = requests[0].header(DELIVERY_HEADER).unwrap();
+        for request in &requests {
+            assert_eq!(request.route(), "/hooks/aspirin");
+            assert_eq!(request.header(EVENT_HEADER), Some("status_changed"));
+            assert_eq!(request.header(DELIVERY_HEADER), Some(delivery_id));
+            let body = request.body.as_deref().unwrap();
+            let timestamp = request.header(TIMESTAMP_HEADER).unwrap().parse().unwrap();
+            assert!(verify(
+                "s3cret",
+                timestamp,
+                body,
+                request.header(SIGNATURE_HEADER).unwrap()
+            ));
+            let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(payload["id"], delivery_id);
+            assert_eq!(payload["event"], "status_changed");
+            assert_eq!(payload["order"]["customer"], "Amit");
+        }
+    }
+
+    #[test]
+    fn test_lane_gives_up_after_max_attempts() {
+        // Nothing listens on a port that was just released
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/", listener.local_addr().unwrap())
+        };
+        let (db, id) = subscribed(&url, &[]);
+        let mut lane = lane(
+            &db,
+            id,
+            RetryPolicy {
+                initial_backoff: Duration::from_secs(60),
+                max_backoff: Duration::from_secs(60),
+                ..policy()
+            },
+        );
+
+        assert!(lane.deliver_due(&db, Instant::now(), &quiet()));
+        // Not due again for a minute, and saved that way
+        let saved = db.lock().unwrap().deliveries().unwrap();
+        assert_eq!(saved[0].attempts, 1);
+        assert!(saved[0].due_at >= clock::unix_now() + 59);
+        assert!(lane.deliver_due(&db, Instant::now(), &quiet()));
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(lane.deliver_due(&db, later, &quiet()));
+        assert!(lane.deliver_due(&db, later + Duration::from_secs(60), &quiet()));
+
+        let db = db.into_inner().unwrap();
+        assert!(db.deliveries().unwrap().is_empty());
+        let dead = db.dead_letters().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].webhook_id, id);
+        assert_eq!(dead[0].event, "status_changed");
+        assert_eq!(dead[0].attempts, 3);
+        assert!(dead[0].payload.contains(&dead[0].delivery_id));
+        assert!(lane.next_due().is_none());
+    }
+
+    #[test]
+    fn test_lane_ends_when_its_webhook_is_removed() {
+        let (url, received) = receiver(&[500]);
+        let (db, id) = subscribed(&url, &[]);
+        let mut lane = lane(&db, id, policy());
+
+        assert!(lane.deliver_due(&db, Instant::now(), &quiet()));
+        received.recv().unwrap();
+        assert!(lane.next_due().is_some());
+
+        db.lock().unwrap().remove_webhook(id).unwrap();
+        assert!(!lane.deliver_due(&db, Instant::now(), &quiet()));
+        assert!(lane.next_due().is_none());
+    }
+
+    #[test]
+    fn test_worker_resumes_saved_deliveries_around_dead_receivers() {
+        // A receiver that accepts connections but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (url, received) = receiver(&[204, 204]);
+        let db = AspirinEatsDb::in_memory()
+            .unwrap()
+            .with_private_webhooks(true);
+        let dead = db
+            .add_webhook(&webhook(
+                0,
+                &format!("http://{}/", silent.local_addr().unwrap()),
+                &[],
+            ))
+            .unwrap();
+        let live = db.add_webhook(&webhook(0, &url, &[])).unwrap();
+        // Saved before a restart
+        let saved = Delivery {
+            id: None,
+            webhook_id: live,
+            delivery_id: "saved".to_string(),
+            event: "created".to_string(),
+            payload: "{}".to_string(),
+            attempts: 1,
+            due_at: 0,
+        };
+        db.add_delivery(&saved).unwrap();
+        db.add_delivery(&Delivery {
+            webhook_id: dead,
+            ..saved.clone()
+        })
+        .unwrap();
+
+        let db = std::sync::Arc::new(Mutex::new(db));
+        let (events, receiver) = mpsc::channel();
+        let shared = db.clone();
+        thread::spawn(move || {
+            let policy = RetryPolicy {
+                timeout: Duration::from_secs(5),
+                ..policy()
+            };
+            Worker::new(policy).run(&shared, receiver, &quiet())
+        });
+        events.send(event(OrderEventKind::Created)).unwrap();
+
+        let wait = Duration::from_secs(2);
+        let first = received.recv_timeout(wait).unwrap();
+        assert_eq!(first.header(DELIVERY_HEADER), Some("saved"));
+        let second = received.recv_timeout(wait).unwrap();
+        assert_eq!(second.header(EVENT_HEADER), Some("created"));
+        drop(silent);
+    }
+
+    #[test]
+    fn test_deliver_reports_failures() {
+        let (url, _received) = receiver(&[410]);
+        let webhook = webhook(1, &url, &[]);
+        let timeout = Duration::from_secs(2);
+        assert!(matches!(
+            deliver(&webhook, "id", "created", "{}", timeout, true),
+            Err(AspirinEatsError::UnexpectedStatus(410))
+        ));
+        // The receiver is on loopback, so it is never connected to without the flag
+        assert!(matches!(
+            deliver(&webhook, "id", "created", "{}", timeout, false),
+            Err(AspirinEatsError::InvalidRequest)
+        ));
+
+        let unsigned = Webhook {
+            secret: None,
+            ..webhook
+        };
+        assert!(deliver(&unsigned, "id", "created", "{}", timeout, true).is_err());
+    }
+
+    #[test]
+    fn test_deliver_bounds_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                http::read_request(&mut BufReader::new(stream.try_clone().unwrap())).unwrap();
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n");
+            }
+        });
+
+        let webhook = webhook(1, &url, &[]);
+        assert!(matches!(
+            deliver(
+                &webhook,
+                "id",
+                "created",
+                "{}",
+                Duration::from_secs(2),
+                true
+            ),
+            Err(AspirinEatsError::PayloadTooLarge)
+        ));
+    }
+}
@@ -0,0 +1,1177 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use display_json::{DisplayAsJson, FromStrAsJson};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+use crate::api;
+use crate::cors::Cors;
+use crate::error::AspirinEatsError;
+use crate::events::{EventBus, OrderEvent};
+use crate::food::{Order, OrderStatus};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::log::Logger;
+use crate::store::OrderStore;
+
+/// Appended to a client's key before it is hashed into the accept key (RFC 6455, section 1.3)
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only version of the protocol spoken, as sent in `Sec-WebSocket-Version`
+pub const VERSION: &str = "13";
+
+/// How long a socket may stay quiet before the client is pinged. A client that stays quiet
+/// for as long again is disconnected
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Largest payload a control frame may carry
+const MAX_CONTROL_PAYLOAD: u64 = 125;
+
+/// Close code for a connection that is done with
+pub const NORMAL_CLOSURE: u16 = 1000;
+
+/// Close code for a peer that is going away, or has stopped answering
+pub const GOING_AWAY: u16 = 1001;
+
+/// Close code for a peer that broke the framing rules
+pub const PROTOCOL_ERROR: u16 = 1002;
+
+/// Close code for a message of a kind that is not accepted, like binary messages here
+pub const UNSUPPORTED_DATA: u16 = 1003;
+
+/// Close code for a text message that is not valid UTF-8
+pub const INVALID_DATA: u16 = 1007;
+
+/// Close code for a message too large to accept
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Compute the `Sec-WebSocket-Accept` value that answers a client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+/// Whether a comma separated header lists the given token, ignoring case
+fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.is_some_and(|value| {
+        value
+            .split(',')
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+/// Whether a socket may be opened for the page at the request's `Origin`. Browsers send one,
+/// but do not apply CORS to WebSockets, so it is checked here. Requests without one (which
+/// are not from a browser) and from the server's own origin are allowed; other origins only
+/// if `cors` allows them
+pub fn allows_origin(request: &HttpRequest, cors: Option<&Cors>) -> bool {
+    let Some(origin) = request.header("Origin") else {
+        return true;
+    };
+    let own = request.header("Host").is_some_and(|host| {
+        origin
+            .split_once("://")
+            .is_some_and(|(_, authority)| authority.eq_ignore_ascii_case(host))
+    });
+    own || cors.is_some_and(|cors| cors.allows_origin(origin))
+}
+
+/// Answer a client's opening handshake. Returns `101 Switching Protocols` if the socket can
+/// be opened, `426 Upgrade Required` if the request is not an upgrade to a WebSocket of the
+/// version spoken here, `400 Bad Request` if its key is malformed, and `403 Forbidden` if
+/// its origin is not allowed by `cors` (see `allows_origin`)
+pub fn handshake(request: &HttpRequest, cors: Option<&Cors>) -> HttpResponse {
+    if request.method.as_deref() != Some("GET")
+        || !has_token(request.header("Upgrade"), "websocket")
+        || !has_token(request.header("Connection"), "Upgrade")
+        || request.header("Sec-WebSocket-Version") != Some(VERSION)
+    {
+        return HttpResponse::new(426, "Upgrade Required", "Upgrade Required")
+            .with_header("Upgrade", "websocket")
+            .with_header("Sec-WebSocket-Version", VERSION);
+    }
+    let key = request.header("Sec-WebSocket-Key").unwrap_or("");
+    if !matches!(STANDARD.decode(key), Ok(nonce) if nonce.len() == 16) {
+        return HttpResponse::from(AspirinEatsError::InvalidRequest);
+    }
+    if !allows_origin(request, cors) {
+        return HttpResponse::new(403, "Forbidden", "Cross-origin socket not allowed");
+    }
+    HttpResponse::new(101, "Switching Protocols", "")
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", &accept_key(key))
+}
+
+/// Kind of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    /// Whether frames of this kind manage the connection rather than carry a message
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// A single frame, with its payload unmasked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Whether this is the last frame of its message
+    pub fin: bool,
+
+    pub opcode: Opcode,
+
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// A whole text message in a single frame
+    pub fn text(text: &str) -> Self {
+        Frame {
+            fin: true,
+            opcode: Opcode::Text,
+            payload: text.as_bytes().to_vec(),
+        }
+    }
+
+    /// A control frame: a ping, a pong, or a close without a status code
+    pub fn control(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+
+    /// A close frame with a status code and reason
+    pub fn close(code: u16, reason: &str) -> Self {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        Frame::control(Opcode::Close, payload)
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// What the first two bytes of a frame say about it
+struct FrameHead {
+    fin: bool,
+    opcode: Opcode,
+    /// The 7 bit payload length, which is 126 or 127 if a longer length follows
+    length: u8,
+}
+
+impl FrameHead {
+    fn parse(head: [u8; 2], masked: bool) -> Result<Self, AspirinEatsError> {
+        // No extensions are negotiated, so none of the reserved bits may be set
+        if head[0] & 0x70 != 0 || (head[1] & 0x80 != 0) != masked {
+            return Err(AspirinEatsError::WebSocket(PROTOCOL_ERROR));
+        }
+        Ok(FrameHead {
+            fin: head[0] & 0x80 != 0,
+            opcode: Opcode::from_bits(head[0] & 0x0f)
+                .ok_or(AspirinEatsError::WebSocket(PROTOCOL_ERROR))?,
+            length: head[1] & 0x7f,
+        })
+    }
+
+    /// How many bytes of payload length follow the head
+    fn extended_length(&self) -> usize {
+        match self.length {
+            126 => 2,
+            127 => 8,
+            _ => 0,
+        }
+    }
+
+    /// The length of the payload, given the `extended_length` bytes that followed the head,
+    /// if a frame of this kind may carry that much
+    fn payload_length(
+        &self,
+        extended: &[u8],
+        max_payload: usize,
+    ) -> Result<usize, AspirinEatsError> {
+        let length = match extended {
+            [] => self.length as u64,
+            bytes => bytes
+                .iter()
+                .fold(0, |length, byte| (length << 8) | *byte as u64),
+        };
+        if self.opcode.is_control() && (!self.fin || length > MAX_CONTROL_PAYLOAD) {
+            return Err(AspirinEatsError::WebSocket(PROTOCOL_ERROR));
+        }
+        if length > max_payload as u64 {
+            return Err(AspirinEatsError::WebSocket(MESSAGE_TOO_BIG));
+        }
+        Ok(length as usize)
+    }
+
+    fn frame(self, mut payload: Vec<u8>, mask: Option<[u8; 4]>) -> Frame {
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+        Frame {
+            fin: self.fin,
+            opcode: self.opcode,
+            payload,
+        }
+    }
+}
+
+/// Read a single frame. `masked` is whether the peer masks its frames, as clients must and
+/// servers must not. Frames that break the protocol, or carry more than `max_payload`
+/// bytes, fail with `AspirinEatsError::WebSocket` and the code to close the connection with
+pub fn read_frame<R: Read>(
+    reader: &mut R,
+    max_payload: usize,
+    masked: bool,
+) -> Result<Frame, AspirinEatsError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let head = FrameHead::parse(head, masked)?;
+    let mut extended = [0; 8];
+    let extended = &mut extended[..head.extended_length()];
+    reader.read_exact(extended)?;
+    let length = head.payload_length(extended, max_payload)?;
+
+    let mask = if masked {
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask)?;
+        Some(mask)
+    } else {
+        None
+    };
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(head.frame(payload, mask))
+}
+
+/// Read a single frame from an async reader, as `read_frame` does
+#[cfg(feature = "async")]
+pub async fn read_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_payload: usize,
+    masked: bool,
+) -> Result<Frame, AspirinEatsError> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).await?;
+    let head = FrameHead::parse(head, masked)?;
+    let mut extended = [0; 8];
+    let extended = &mut extended[..head.extended_length()];
+    reader.read_exact(extended).await?;
+    let length = head.payload_length(extended, max_payload)?;
+
+    let mask = if masked {
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask).await?;
+        Some(mask)
+    } else {
+        None
+    };
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await?;
+    Ok(head.frame(payload, mask))
+}
+
+/// Write a single frame, masked with `mask` if given. Clients must mask every frame they
+/// send, and servers must not mask any
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    frame: &Frame,
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    let mut message = Vec::with_capacity(frame.payload.len() + 14);
+    message.push((u8::from(frame.fin) << 7) | frame.opcode.bits());
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match frame.payload.len() {
+        length @ 0..=125 => message.push(mask_bit | length as u8),
+        length @ 126..=0xffff => {
+            message.push(mask_bit | 126);
+            message.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            message.push(mask_bit | 127);
+            message.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    if let Some(mask) = mask {
+        message.extend_from_slice(&mask);
+    }
+    let start = message.len();
+    message.extend_from_slice(&frame.payload);
+    if let Some(mask) = mask {
+        apply_mask(&mut message[start..], mask);
+    }
+    writer.write_all(&message)
+}
+
+/// A whole message, put back together from its frames
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The peer is closing the connection, with the status code and reason it gave, if any
+    Close(Option<u16>, String),
+}
+
+/// Reads whole messages from a peer, putting fragmented ones back together. Control
+/// frames may arrive between the fragments of a message; they are returned as they come
+pub struct MessageReader<R> {
+    reader: R,
+    max_message: usize,
+    masked: bool,
+    partial: Option<(Opcode, Vec<u8>)>,
+}
+
+impl<R> MessageReader<R> {
+    /// Read messages of up to `max_message` bytes. `masked` is as for `read_frame`
+    pub fn new(reader: R, max_message: usize, masked: bool) -> Self {
+        MessageReader {
+            reader,
+            max_message,
+            masked,
+            partial: None,
+        }
+    }
+
+    /// Take in the next frame, returning the message it completes, if any
+    fn assemble(&mut self, frame: Frame) -> Result<Option<Message>, AspirinEatsError> {
+        let fin = frame.fin;
+        match (frame.opcode, &mut self.partial) {
+            (Opcode::Ping, _) => return Ok(Some(Message::Ping(frame.payload))),
+            (Opcode::Pong, _) => return Ok(Some(Message::Pong(frame.payload))),
+            (Opcode::Close, _) => return close_message(&frame.payload).map(Some),
+            (Opcode::Text | Opcode::Binary, None) => {
+                self.partial = Some((frame.opcode, frame.payload))
+            }
+            (Opcode::Continuation, Some((_, data))) => {
+                if data.len() + frame.payload.len() > self.max_message {
+                    return Err(AspirinEatsError::WebSocket(MESSAGE_TOO_BIG));
+                }
+                data.extend_from_slice(&frame.payload);
+            }
+            // A new message before the last one ended, or a continuation of nothing
+            _ => return Err(AspirinEatsError::WebSocket(PROTOCOL_ERROR)),
+        }
+
+        if !fin {
+            return Ok(None);
+        }
+        let (opcode, data) = self.partial.take().expect("a data frame was just read");
+        match opcode {
+            Opcode::Text => String::from_utf8(data)
+                .map(|text| Some(Message::Text(text)))
+                .map_err(|_| AspirinEatsError::WebSocket(INVALID_DATA)),
+            _ => Ok(Some(Message::Binary(data))),
+        }
+    }
+}
+
+impl<R: Read> MessageReader<R> {
+    /// Read the next message. Fails like `read_frame`, and also with
+    /// `AspirinEatsError::WebSocket` for fragments out of order and text that is not UTF-8
+    pub fn read_message(&mut self) -> Result<Message, AspirinEatsError> {
+        loop {
+            let frame = read_frame(&mut self.reader, self.max_message, self.masked)?;
+            if let Some(message) = self.assemble(frame)? {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    /// Read the next message from an async reader, as `read_message` does
+    pub async fn read_message_async(&mut self) -> Result<Message, AspirinEatsError> {
+        loop {
+            let frame = read_frame_async(&mut self.reader, self.max_message, self.masked).await?;
+            if let Some(message) = self.assemble(frame)? {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+/// Whether nothing more is read after a message: the peer is closing, or broke off
+fn is_last(message: &Result<Message, AspirinEatsError>) -> bool {
+    !matches!(
+        message,
+        Ok(Message::Text(_) | Message::Binary(_) | Message::Ping(_) | Message::Pong(_))
+    )
+}
+
+fn close_message(payload: &[u8]) -> Result<Message, AspirinEatsError> {
+    match payload {
+        [] => Ok(Message::Close(None, String::new())),
+        [_] => Err(AspirinEatsError::WebSocket(PROTOCOL_ERROR)),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            // Codes that are reserved, or only for reporting closes that had no frame
+            if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+                return Err(AspirinEatsError::WebSocket(PROTOCOL_ERROR));
+            }
+            let reason = String::from_utf8(reason.to_vec())
+                .map_err(|_| AspirinEatsError::WebSocket(INVALID_DATA))?;
+            Ok(Message::Close(Some(code), reason))
+        }
+    }
+}
+
+/// Message a client sends over an order socket, as JSON text
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Receive changes to an order, or to every order if `order` is left out
+    Subscribe {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        order: Option<Uuid>,
+    },
+
+    /// Stop receiving changes to an order, or to any order if `order` is left out.
+    /// Unsubscribing from one order leaves a subscription to every order in place
+    Unsubscribe {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        order: Option<Uuid>,
+    },
+
+    /// Change the status of an order
+    SetStatus { order: Uuid, status: OrderStatus },
+}
+
+/// Message the server sends over an order socket, as JSON text
+#[derive(
+    Serialize, Deserialize, JsonSchema, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone,
+)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The client's `subscribe` took effect
+    Subscribed {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        order: Option<Uuid>,
+    },
+
+    /// The client's `unsubscribe` took effect
+    Unsubscribed {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        order: Option<Uuid>,
+    },
+
+    /// An order the client is subscribed to changed. `id` and `event` are as on the event
+    /// stream
+    Event {
+        id: u64,
+        event: String,
+        order: Order,
+    },
+
+    /// The order whose status the client changed, after the change
+    StatusSet { order: Order },
+
+    /// A message from the client could not be acted on
+    Error { message: String },
+}
+
+impl From<OrderEvent> for ServerMessage {
+    fn from(event: OrderEvent) -> Self {
+        ServerMessage::Event {
+            id: event.id,
+            event: event.kind.name().to_string(),
+            order: event.order,
+        }
+    }
+}
+
+/// Something a session has to act on
+enum Input {
+    Message(Result<Message, AspirinEatsError>),
+    Event(OrderEvent),
+}
+
+/// What a session does after acting on an input
+enum Step {
+    /// Send a frame and carry on
+    Send(Frame),
+    /// Send a frame and end the session
+    Close(Frame),
+    /// Carry on without sending anything
+    Wait,
+    /// End the session without sending anything
+    End,
+}
+
+/// A client's session on an order socket: the orders it is watching, and what it needs to
+/// act on its messages
+pub(crate) struct Session {
+    store: Arc<Mutex<dyn OrderStore + Send>>,
+    logger: Arc<Logger>,
+    request_id: String,
+    keep_alive: Duration,
+    all_orders: bool,
+    orders: HashSet<Uuid>,
+    /// Whether the client was pinged, and has not been heard from since
+    pinged: bool,
+}
+
+impl Session {
+    pub(crate) fn new(
+        store: Arc<Mutex<dyn OrderStore + Send>>,
+        logger: Arc<Logger>,
+        request_id: &str,
+        keep_alive: Duration,
+    ) -> Self {
+        Session {
+            store,
+            logger,
+            request_id: request_id.to_string(),
+            keep_alive,
+            all_orders: false,
+            orders: HashSet::new(),
+            pinged: false,
+        }
+    }
+
+    /// Serve the socket until either side closes it, the client breaks the protocol, or it
+    /// stops answering pings. Messages are read on a thread of their own, so that events
+    /// can be written while the client is quiet; whoever owns the connection must shut it
+    /// down afterwards to stop that thread. Events are passed on by the bus itself, which
+    /// drops them once the session is over
+    pub(crate) fn run<R, W>(
+        mut self,
+        mut reader: MessageReader<R>,
+        writer: &mut W,
+        events: &EventBus,
+    ) -> Result<(), AspirinEatsError>
+    where
+        R: Read + Send + 'static,
+        W: Write,
+    {
+        let (sender, inputs) = mpsc::channel();
+        let messages = sender.clone();
+        thread::spawn(move || loop {
+            let message = reader.read_message();
+            let last = is_last(&message);
+            if messages.send(Input::Message(message)).is_err() || last {
+                return;
+            }
+        });
+        events.subscribe_with(None, move |event| {
+            sender.send(Input::Event(event.clone())).is_ok()
+        });
+
+        loop {
+            let step = match inputs.recv_timeout(self.keep_alive) {
+                Ok(Input::Message(message)) => self.on_message(message)?,
+                Ok(Input::Event(event)) => self.on_event(event),
+                Err(RecvTimeoutError::Timeout) => self.on_silence(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+            match step {
+                Step::Send(frame) => send(writer, &frame)?,
+                Step::Close(frame) => return send(writer, &frame),
+                Step::Wait => {}
+                Step::End => return Ok(()),
+            }
+        }
+    }
+
+    /// Serve the socket as `run` does, but on tasks. Messages are read by a task of their
+    /// own, which is aborted when the session ends, and messages that may touch the
+    /// database are acted on in the blocking pool. Writing a frame fails after
+    /// `write_timeout`
+    #[cfg(feature = "async")]
+    pub(crate) async fn run_async<R, W>(
+        mut self,
+        mut reader: MessageReader<R>,
+        writer: &mut W,
+        events: &EventBus,
+        write_timeout: Duration,
+    ) -> Result<(), AspirinEatsError>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin,
+    {
+        let (sender, mut inputs) = tokio::sync::mpsc::unbounded_channel();
+        let messages = sender.clone();
+        let _reading = AbortOnDrop(tokio::spawn(async move {
+            loop {
+                let message = reader.read_message_async().await;
+                let last = is_last(&message);
+                if messages.send(Input::Message(message)).is_err() || last {
+                    return;
+                }
+            }
+        }));
+        events.subscribe_with(None, move |event| {
+            sender.send(Input::Event(event.clone())).is_ok()
+        });
+
+        loop {
+            let step = match tokio::time::timeout(self.keep_alive, inputs.recv()).await {
+                Ok(Some(Input::Message(message @ Ok(Message::Text(_))))) => {
+                    let (session, step) = tokio::task::spawn_blocking(move || {
+                        let step = self.on_message(message);
+                        (self, step)
+                    })
+                    .await
+                    .map_err(io::Error::other)?;
+                    self = session;
+                    step?
+                }
+                Ok(Some(Input::Message(message))) => self.on_message(message)?,
+                Ok(Some(Input::Event(event))) => self.on_event(event),
+                Ok(None) => return Ok(()),
+                Err(_) => self.on_silence(),
+            };
+            match step {
+                Step::Send(frame) => send_async(writer, &frame, write_timeout).await?,
+                Step::Close(frame) => return send_async(writer, &frame, write_timeout).await,
+                Step::Wait => {}
+                Step::End => return Ok(()),
+            }
+        }
+    }
+
+    fn on_message(
+        &mut self,
+        message: Result<Message, AspirinEatsError>,
+    ) -> Result<Step, AspirinEatsError> {
+        self.pinged = false;
+        Ok(match message {
+            Ok(Message::Text(text)) => Step::Send(Frame::text(&self.handle(&text).to_string())),
+            Ok(Message::Binary(_)) => {
+                Step::Close(Frame::close(UNSUPPORTED_DATA, "Messages must be JSON text"))
+            }
+            Ok(Message::Ping(payload)) => Step::Send(Frame::control(Opcode::Pong, payload)),
+            Ok(Message::Pong(_)) => Step::Wait,
+            Ok(Message::Close(code, _)) => Step::Close(match code {
+                Some(code) => Frame::close(code, ""),
+                None => Frame::control(Opcode::Close, Vec::new()),
+            }),
+            Err(AspirinEatsError::WebSocket(code)) => Step::Close(Frame::close(code, "")),
+            // The client went away without closing the socket
+            Err(AspirinEatsError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => Step::End,
+            Err(e) => return Err(e),
+        })
+    }
+
+    fn on_event(&self, event: OrderEvent) -> Step {
+        if self.wants(&event) {
+            Step::Send(Frame::text(&ServerMessage::from(event).to_string()))
+        } else {
+            Step::Wait
+        }
+    }
+
+    /// The client has been quiet for `keep_alive`: ping it, or give up on it if it was
+    /// already pinged
+    fn on_silence(&mut self) -> Step {
+        if self.pinged {
+            return Step::Close(Frame::close(GOING_AWAY, "No answer to ping"));
+        }
+        self.pinged = true;
+        Step::Send(Frame::control(Opcode::Ping, Vec::new()))
+    }
+
+    /// Act on a message from the client, returning the reply
+    fn handle(&mut self, text: &str) -> ServerMessage {
+        ClientMessage::from_str(text)
+            .map_err(AspirinEatsError::from)
+            .and_then(|message| self.apply(message))
+            .unwrap_or_else(|e| {
+                self.logger.error(Some(&self.request_id), &e);
+                ServerMessage::Error {
+                    message: e.to_string(),
+                }
+            })
+    }
+
+    fn apply(&mut self, message: ClientMessage) -> Result<ServerMessage, AspirinEatsError> {
+        let store = || self.store.lock().expect("store lock poisoned");
+        match message {
+            ClientMessage::Subscribe { order } => {
+                match order {
+                    Some(uuid) => {
+                        let store = store();
+                        store
+                            .get_order(api::order_id(&*store, uuid)?)?
+                            .ok_or(AspirinEatsError::NotFound)?;
+                        self.orders.insert(uuid);
+                    }
+                    None => self.all_orders = true,
+                }
+                Ok(ServerMessage::Subscribed { order })
+            }
+            ClientMessage::Unsubscribe { order } => {
+                match order {
+                    Some(uuid) => {
+                        self.orders.remove(&uuid);
+                    }
+                    None => {
+                        self.all_orders = false;
+                        self.orders.clear();
+                    }
+                }
+                Ok(ServerMessage::Unsubscribed { order })
+            }
+            ClientMessage::SetStatus { order, status } => {
+                let store = store();
+                let order = store
+                    .update_order_status(api::order_id(&*store, order)?, status)?
+                    .ok_or(AspirinEatsError::NotFound)?;
+                Ok(ServerMessage::StatusSet { order })
+            }
+        }
+    }
+
+    fn wants(&self, event: &OrderEvent) -> bool {
+        self.all_orders
+            || event
+                .order
+                .uuid
+                .is_some_and(|uuid| self.orders.contains(&uuid))
+    }
+}
+
+fn send<W: Write>(writer: &mut W, frame: &Frame) -> Result<(), AspirinEatsError> {
+    write_frame(writer, frame, None)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+async fn send_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    frame: &Frame,
+    write_timeout: Duration,
+) -> Result<(), AspirinEatsError> {
+    let mut message = Vec::new();
+    write_frame(&mut message, frame, None)?;
+    tokio::time::timeout(write_timeout, async {
+        writer.write_all(&message).await?;
+        writer.flush().await
+    })
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    Ok(())
+}
+
+/// Aborts a task when dropped, so that it does not outlive whoever spawned it
+#[cfg(feature = "async")]
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+#[cfg(feature = "async")]
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::AspirinEatsDb;
+    use crate::food::{MenuItem, OrderRequest};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    fn masked(frames: &[Frame]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for frame in frames {
+            write_frame(&mut bytes, frame, Some(MASK)).unwrap();
+        }
+        bytes
+    }
+
+    fn fragment(opcode: Opcode, fin: bool, payload: &[u8]) -> Frame {
+        Frame {
+            fin,
+            opcode,
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn close_code(result: Result<Message, AspirinEatsError>) -> u16 {
+        match result {
+            Err(AspirinEatsError::WebSocket(code)) => code,
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let request = |headers: &str| -> HttpRequest {
+            format!("GET /orders/socket HTTP/1.1\r\n{}\r\n", headers)
+                .parse()
+                .unwrap()
+        };
+        let valid = "Upgrade: WebSocket\r\nConnection: keep-alive, Upgrade\r\n\
+                     Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+        let response = handshake(&request(valid), None);
+        assert_eq!(response.status_code(), 101);
+        assert_eq!(
+            response.header("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        let response = handshake(&request(""), None);
+        assert_eq!(response.status_code(), 426);
+        assert_eq!(response.header("Upgrade"), Some("websocket"));
+        let old_version = valid.replace("Version: 13", "Version: 8");
+        assert_eq!(handshake(&request(&old_version), None).status_code(), 426);
+        let short_key = valid.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ=");
+        assert_eq!(handshake(&request(&short_key), None).status_code(), 400);
+    }
+
+    #[test]
+    fn test_handshake_checks_origin() {
+        let request = |origin: &str| -> HttpRequest {
+            format!(
+                "GET /orders/socket HTTP/1.1\r\nHost: eats.example:8080\r\n{origin}\
+                 Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+            )
+            .parse()
+            .unwrap()
+        };
+        let cors = Cors::new().with_origin("https://order.example");
+        let status =
+            |origin: &str, cors: Option<&Cors>| handshake(&request(origin), cors).status_code();
+
+        // Not from a browser, or from the server's own pages
+        assert_eq!(status("", None), 101);
+        assert_eq!(status("Origin: http://eats.example:8080\r\n", None), 101);
+        // From pages elsewhere, only if the policy allows them
+        assert_eq!(status("Origin: https://evil.example\r\n", None), 403);
+        assert_eq!(status("Origin: https://evil.example\r\n", Some(&cors)), 403);
+        assert_eq!(status("Origin: https://order.example\r\n", None), 403);
+        assert_eq!(
+            status("Origin: https://order.example\r\n", Some(&cors)),
+            101
+        );
+        let any = Cors::new().with_any_origin();
+        assert_eq!(status("Origin: https://evil.example\r\n", Some(&any)), 101);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        for length in [0, 125, 126, 0xffff, 0x10000] {
+            let frame = fragment(Opcode::Binary, true, &vec![0xab; length]);
+            let mut unmasked = Vec::new();
+            write_frame(&mut unmasked, &frame, None).unwrap();
+            assert_eq!(
+                read_frame(&mut unmasked.as_slice(), usize::MAX, false).unwrap(),
+                frame
+            );
+
+            let bytes = masked(std::slice::from_ref(&frame));
+            assert_eq!(bytes.len(), unmasked.len() + 4);
+            assert_eq!(
+                read_frame(&mut bytes.as_slice(), usize::MAX, true).unwrap(),
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn test_masking_matches_rfc_example() {
+        // A masked "Hello" from a client (RFC 6455, section 5.7)
+        let bytes = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(masked(&[Frame::text("Hello")]), bytes);
+        let frame = read_frame(&mut &bytes[..], 125, true).unwrap();
+        assert_eq!(frame, Frame::text("Hello"));
+    }
+
+    #[test]
+    fn test_fragments_are_reassembled_around_control_frames() {
+        let bytes = masked(&[
+            fragment(Opcode::Text, false, b"{\"type\":"),
+            Frame::control(Opcode::Ping, b"still there?".to_vec()),
+            fragment(Opcode::Continuation, false, b"\"subscribe\""),
+            fragment(Opcode::Continuation, true, b"}"),
+            Frame::close(NORMAL_CLOSURE, "bye"),
+        ]);
+        let mut reader = MessageReader::new(bytes.as_slice(), 64, true);
+        assert_eq!(
+            reader.read_message().unwrap(),
+            Message::Ping(b"still there?".to_vec())
+        );
+        assert_eq!(
+            reader.read_message().unwrap(),
+            Message::Text("{\"type\":\"subscribe\"}".to_string())
+        );
+        assert_eq!(
+            reader.read_message().unwrap(),
+            Message::Close(Some(NORMAL_CLOSURE), "bye".to_string())
+        );
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let read = |bytes: Vec<u8>| MessageReader::new(bytes.as_slice(), 16, true).read_message();
+
+        let mut unmasked = Vec::new();
+        write_frame(&mut unmasked, &Frame::text("hi"), None).unwrap();
+        assert_eq!(close_code(read(unmasked)), PROTOCOL_ERROR);
+
+        let mut reserved_bit = masked(&[Frame::text("hi")]);
+        reserved_bit[0] |= 0x40;
+        assert_eq!(close_code(read(reserved_bit)), PROTOCOL_ERROR);
+
+        let mut unknown_opcode = masked(&[Frame::text("hi")]);
+        unknown_opcode[0] = 0x83;
+        assert_eq!(close_code(read(unknown_opcode)), PROTOCOL_ERROR);
+
+        let fragmented_ping = masked(&[fragment(Opcode::Ping, false, b"")]);
+        assert_eq!(close_code(read(fragmented_ping)), PROTOCOL_ERROR);
+
+        let stray_continuation = masked(&[fragment(Opcode::Continuation, true, b"")]);
+        assert_eq!(close_code(read(stray_continuation)), PROTOCOL_ERROR);
+
+        let interrupted = masked(&[
+            fragment(Opcode::Text, false, b"a"),
+            fragment(Opcode::Text, true, b"b"),
+        ]);
+        assert_eq!(close_code(read(interrupted)), PROTOCOL_ERROR);
+
+        let too_big = masked(&[Frame::text(&"a".repeat(17))]);
+        assert_eq!(close_code(read(too_big)), MESSAGE_TOO_BIG);
+
+        let too_big_in_pieces = masked(&[
+            fragment(Opcode::Text, false, &[b'a'; 10]),
+            fragment(Opcode::Continuation, true, &[b'a'; 10]),
+        ]);
+        assert_eq!(close_code(read(too_big_in_pieces)), MESSAGE_TOO_BIG);
+
+        // A length far beyond the limit is refused before anything is allocated for it
+        let mut huge = vec![0x81, 0xff];
+        huge.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(close_code(read(huge)), MESSAGE_TOO_BIG);
+
+        let not_utf8 = masked(&[fragment(Opcode::Text, true, &[0xc3, 0x28])]);
+        assert_eq!(close_code(read(not_utf8)), INVALID_DATA);
+
+        let reserved_close = masked(&[Frame::close(1005, "")]);
+        assert_eq!(close_code(read(reserved_close)), PROTOCOL_ERROR);
+    }
+
+    #[test]
+    fn test_messages_serialize_as_tagged_json() {
+        assert_eq!(
+            ClientMessage::from_str(r#"{"type":"subscribe"}"#).unwrap(),
+            ClientMessage::Subscribe { order: None }
+        );
+        let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!(
+            ClientMessage::from_str(&format!(
+                r#"{{"type":"set_status","order":"{uuid}","status":"Ready"}}"#
+            ))
+            .unwrap(),
+            ClientMessage::SetStatus {
+                order: Uuid::parse_str(uuid).unwrap(),
+                status: OrderStatus::Ready
+            }
+        );
+        assert_eq!(
+            ServerMessage::Subscribed {
+                order: Some(Uuid::parse_str(uuid).unwrap())
+            }
+            .to_string(),
+            format!(r#"{{"type":"subscribed","order":"{uuid}"}}"#)
+        );
+        // Orders are only named by their public ID
+        assert!(ClientMessage::from_str(r#"{"type":"subscribe","order":3}"#).is_err());
+    }
+
+    /// Run a session over a connection the client frames were sent on, and which the client
+    /// keeps open. Returns what the server wrote back
+    fn session(
+        db: &Arc<Mutex<AspirinEatsDb>>,
+        frames: &[Frame],
+        keep_alive: Duration,
+    ) -> Vec<Frame> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(&masked(frames)).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+
+        let logger = Arc::new(Logger::from_writer("origin", Box::new(io::sink())));
+        let events = db.lock().unwrap().events();
+        let reader = MessageReader::new(connection.try_clone().unwrap(), 1024, true);
+        let mut written = Vec::new();
+        Session::new(db.clone(), logger, "test", keep_alive)
+            .run(reader, &mut written, &events)
+            .unwrap();
+        connection.shutdown(Shutdown::Both).unwrap();
+
+        let mut written = written.as_slice();
+        let mut frames = Vec::new();
+        while !written.is_empty() {
+            frames.push(read_frame(&mut written, usize::MAX, false).unwrap());
+        }
+        frames
+    }
+
+    fn reply(frame: &Frame) -> ServerMessage {
+        ServerMessage::from_str(std::str::from_utf8(&frame.payload).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_session_answers_messages() {
+        let db = Arc::new(Mutex::new(AspirinEatsDb::in_memory().unwrap()));
+        let uuid = {
+            let db = db.lock().unwrap();
+            let id = db
+                .add_order(Order::from(OrderRequest {
+                    customer: "Amit".to_string(),
+                    customer_uuid: None,
+                    food: vec![MenuItem::Fries],
+                }))
+                .unwrap();
+            db.get_order(id).unwrap().unwrap().uuid
+        };
+
+        let written = session(
+            &db,
+            &[
+                Frame::text(&ClientMessage::Subscribe { order: uuid }.to_string()),
+                Frame::text(
+                    &ClientMessage::Subscribe {
+                        order: Some(Uuid::new_v4()),
+                    }
+                    .to_string(),
+                ),
+                Frame::text("not json"),
+                Frame::control(Opcode::Ping, b"hi".to_vec()),
+                Frame::text(
+                    &ClientMessage::SetStatus {
+                        order: uuid.unwrap(),
+                        status: OrderStatus::Ready,
+                    }
+                    .to_string(),
+                ),
+                Frame::close(NORMAL_CLOSURE, ""),
+            ],
+            KEEP_ALIVE_INTERVAL,
+        );
+
+        assert_eq!(
+            reply(&written[0]),
+            ServerMessage::Subscribed { order: uuid }
+        );
+        assert_eq!(
+            reply(&written[1]),
+            ServerMessage::Error {
+                message: "Resource not found".to_string()
+            }
+        );
+        assert_eq!(
+            reply(&written[2]),
+            ServerMessage::Error {
+                message: "Failed to parse request".to_string()
+            }
+        );
+        assert_eq!(written[3], Frame::control(Opcode::Pong, b"hi".to_vec()));
+        let ServerMessage::StatusSet { order } = reply(&written[4]) else {
+            panic!("expected the updated order, got {:?}", written[4]);
+        };
+        assert_eq!(order.status, OrderStatus::Ready);
+        // The status change may or may not be pushed before the close is answered
+        assert_eq!(written.last().unwrap(), &Frame::close(NORMAL_CLOSURE, ""));
+    }
+
+    #[test]
+    fn test_session_closes_on_bad_frames_and_silence() {
+        let db = Arc::new(Mutex::new(AspirinEatsDb::in_memory().unwrap()));
+
+        let written = session(
+            &db,
+            &[fragment(Opcode::Binary, true, &[1, 2, 3])],
+            KEEP_ALIVE_INTERVAL,
+        );
+        assert_eq!(
+            written,
+            [Frame::close(UNSUPPORTED_DATA, "Messages must be JSON text")]
+        );
+
+        // A client that stops talking is pinged, then dropped
+        let written = session(
+            &db,
+            &[Frame::control(Opcode::Pong, Vec::new())],
+            Duration::from_millis(20),
+        );
+        assert_eq!(
+            written,
+            [
+                Frame::control(Opcode::Ping, Vec::new()),
+                Frame::close(GOING_AWAY, "No answer to ping")
+            ]
+        );
+    }
+}
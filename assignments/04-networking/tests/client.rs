@@ -0,0 +1,263 @@
+use std::io::{BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use aspirin_eats::client::Client;
+use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::error::AspirinEatsError;
+use aspirin_eats::food::{
+    Bun, Burger, CustomerRequest, MenuItem, OrderRequest, OrderStatus, Patty, Topping,
+};
+use aspirin_eats::http;
+use aspirin_eats::server::OriginServer;
+use aspirin_eats::store::{FileStore, MemoryStore};
+
+fn spawn_client() -> Client {
+    let db = AspirinEatsDb::in_memory().unwrap();
+    let addr = OriginServer::new(db).spawn("127.0.0.1:0").unwrap();
+    Client::new(&addr.to_string()).read_timeout(Some(Duration::from_secs(5)))
+}
+
+fn get_order_request() -> OrderRequest {
+    OrderRequest {
+        customer: "Amit".to_string(),
+        customer_uuid: None,
+        food: vec![
+            MenuItem::Burger(Burger::new(
+                Bun::Sesame,
+                Patty::Chicken,
+                vec![Topping::Lettuce, Topping::Bacon],
+            )),
+            MenuItem::Fries,
+        ],
+    }
+}
+
+#[test]
+fn test_client_round_trip() {
+    let mut client = spawn_client();
+
+    let order = client.create_order(&get_order_request()).unwrap();
+    assert_eq!(order.total, 15.0);
+    let uuid = order.uuid.unwrap();
+    assert_eq!(client.get_order(uuid).unwrap(), order);
+
+    let updated = client
+        .update_status(uuid, OrderStatus::Transporting)
+        .unwrap();
+    assert_eq!(updated.status, OrderStatus::Transporting);
+    assert_eq!(client.list_orders().unwrap(), vec![updated]);
+
+    client.delete_order(uuid).unwrap();
+    assert!(client.list_orders().unwrap().is_empty());
+}
+
+#[test]
+fn test_client_round_trip_without_sqlite() {
+    let path = std::env::temp_dir().join(format!("aspirin-eats-{}.log", Uuid::new_v4()));
+    let servers = [
+        OriginServer::from_store(MemoryStore::new()),
+        OriginServer::from_store(FileStore::open(&path).unwrap()),
+    ];
+    for server in servers {
+        let addr = server.spawn("127.0.0.1:0").unwrap();
+        let mut client = Client::new(&addr.to_string());
+
+        let order = client.create_order(&get_order_request()).unwrap();
+        let uuid = order.uuid.unwrap();
+        assert_eq!(client.get_order(uuid).unwrap(), order);
+        let updated = client.update_status(uuid, OrderStatus::Ready).unwrap();
+        assert_eq!(client.list_orders().unwrap(), vec![updated]);
+        client.delete_order(uuid).unwrap();
+        assert!(client.list_orders().unwrap().is_empty());
+
+        // Customers are only kept by a database
+        assert!(matches!(
+            client.customer_orders(Uuid::new_v4()),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_client_customer_orders() {
+    let mut client = spawn_client();
+
+    let customer = client
+        .create_customer(&CustomerRequest {
+            name: "Amit".to_string(),
+            contact: Some("amit@example.com".to_string()),
+        })
+        .unwrap();
+    let mut request = get_order_request();
+    request.customer_uuid = customer.uuid;
+    let order = client.create_order(&request).unwrap();
+    client.create_order(&get_order_request()).unwrap();
+
+    assert_eq!(
+        client.customer_orders(customer.uuid.unwrap()).unwrap(),
+        vec![order]
+    );
+}
+
+#[test]
+fn test_client_maps_errors() {
+    let mut client = spawn_client();
+
+    assert!(matches!(
+        client.get_order(Uuid::new_v4()),
+        Err(AspirinEatsError::NotFound)
+    ));
+    assert!(matches!(
+        client.send("PATCH", "/orders", None),
+        Err(AspirinEatsError::MethodNotAllowed)
+    ));
+    assert!(matches!(
+        client.send("POST", "/orders", Some("not json")),
+        Err(AspirinEatsError::InvalidRequest)
+    ));
+    // The connection survives error responses
+    assert!(client.list_orders().unwrap().is_empty());
+}
+
+#[test]
+fn test_client_connect_error() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let mut client = Client::new(&addr.to_string()).connect_timeout(Duration::from_secs(1));
+    assert!(matches!(client.list_orders(), Err(AspirinEatsError::Io(_))));
+}
+
+/// Start a server that answers the first request on every connection and then hangs up:
+/// straight away, or after reading one more request if `read_before_hanging_up`. Returns its
+/// address and the request lines it read, one list per connection
+fn hanging_up_server(read_before_hanging_up: bool) -> (String, Arc<Mutex<Vec<Vec<String>>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let connections = seen.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let index = {
+                let mut connections = connections.lock().unwrap();
+                connections.push(Vec::new());
+                connections.len() - 1
+            };
+            let requests = if read_before_hanging_up { 2 } else { 1 };
+            for n in 0..requests {
+                let Ok(Some(request)) = http::read_request(&mut reader) else {
+                    break;
+                };
+                let line = format!("{} {}", request.method.unwrap(), request.path.unwrap());
+                connections.lock().unwrap()[index].push(line);
+                if n == 0 {
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]")
+                        .unwrap();
+                }
+            }
+        }
+    });
+    (addr, seen)
+}
+
+#[test]
+fn test_client_retries_idempotent_requests_the_server_dropped() {
+    let (addr, seen) = hanging_up_server(true);
+    let mut client = Client::new(&addr);
+
+    assert!(client.list_orders().unwrap().is_empty());
+    assert!(client.list_orders().unwrap().is_empty());
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [vec!["GET /orders", "GET /orders"], vec!["GET /orders"]]
+    );
+}
+
+#[test]
+fn test_client_never_sends_a_post_twice() {
+    let (addr, seen) = hanging_up_server(true);
+    let mut client = Client::new(&addr);
+
+    client.list_orders().unwrap();
+    // The server reads the order and hangs up without answering. It may have placed the
+    // order, so the client must not place it again
+    assert!(matches!(
+        client.create_order(&get_order_request()),
+        Err(AspirinEatsError::BadGateway)
+    ));
+
+    assert_eq!(*seen.lock().unwrap(), [vec!["GET /orders", "POST /orders"]]);
+}
+
+#[test]
+fn test_client_reconnects_before_posting_on_a_closed_connection() {
+    let (addr, seen) = hanging_up_server(false);
+    let mut client = Client::new(&addr);
+
+    client.list_orders().unwrap();
+    // Let the server's hang-up reach the client
+    thread::sleep(Duration::from_millis(100));
+    client.send("POST", "/orders", Some("{}")).unwrap();
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [vec!["GET /orders"], vec!["POST /orders"]]
+    );
+}
+
+/// Start a server that reads a request on every connection and answers with `response`,
+/// one byte every `pause`
+fn trickling_server(response: &'static [u8], pause: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                http::read_request(&mut reader).unwrap();
+                for byte in response {
+                    if stream.write_all(&[*byte]).is_err() {
+                        return;
+                    }
+                    thread::sleep(pause);
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_client_bounds_responses() {
+    // Every byte comes quickly, but the response as a whole never does
+    let addr = trickling_server(&[b' '; 1000], Duration::from_millis(20));
+    let mut client = Client::new(&addr).read_timeout(Some(Duration::from_millis(200)));
+    let started = std::time::Instant::now();
+    assert!(matches!(
+        client.list_orders(),
+        Err(AspirinEatsError::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut
+    ));
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let addr = trickling_server(
+        b"HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n[]",
+        Duration::ZERO,
+    );
+    let mut client = Client::new(&addr).max_body(1024);
+    assert!(matches!(
+        client.list_orders(),
+        Err(AspirinEatsError::PayloadTooLarge)
+    ));
+}
@@ -0,0 +1,673 @@
+//! Behavior every origin server runtime must share. `origin_suite!` expands to one `#[test]`
+//! per check, run against servers started by the given spawn function, which takes the
+//! configured `OriginServer` to run
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aspirin_eats::cors::Cors;
+use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::error::AspirinEatsError;
+use aspirin_eats::food::{Order, OrderStatus};
+use aspirin_eats::http::{self, HttpRequest, HttpResponse, Limits};
+use aspirin_eats::middleware::Middleware;
+use aspirin_eats::server::OriginServer;
+use aspirin_eats::websocket::{self, ClientMessage, Frame, Opcode, ServerMessage, NORMAL_CLOSURE};
+
+const ORDER_REQUEST: &str = r#"{"customer":"Amit","food":["Fries"]}"#;
+
+macro_rules! origin_suite {
+    ($spawn:expr) => {
+        #[test]
+        fn crud_round_trip() {
+            crate::common::crud_round_trip($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn errors_map_to_status_codes() {
+            crate::common::errors_map_to_status_codes($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn keep_alive_serves_many_requests() {
+            crate::common::keep_alive_serves_many_requests($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn orders_stream_pushes_changes() {
+            crate::common::orders_stream_pushes_changes($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn order_stream_resumes_from_last_event_id() {
+            crate::common::order_stream_resumes_from_last_event_id($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn order_socket_pushes_changes_and_sets_status() {
+            crate::common::order_socket_pushes_changes_and_sets_status($spawn(
+                crate::common::origin(),
+            ));
+        }
+
+        #[test]
+        fn order_socket_handshake_is_checked() {
+            crate::common::order_socket_handshake_is_checked($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn order_socket_checks_origin() {
+            crate::common::order_socket_checks_origin($spawn(
+                crate::common::origin().with_cors(crate::common::cors()),
+            ));
+        }
+
+        #[test]
+        fn responses_are_compressed_when_accepted() {
+            crate::common::responses_are_compressed_when_accepted($spawn(crate::common::origin()));
+        }
+
+        #[test]
+        fn cors_preflight_and_simple_requests() {
+            crate::common::cors_preflight_and_simple_requests($spawn(
+                crate::common::origin().with_cors(crate::common::cors()),
+            ));
+        }
+
+        #[test]
+        fn middleware_wraps_handlers_and_streams() {
+            crate::common::middleware_wraps_handlers_and_streams($spawn(
+                crate::common::origin().with_middleware(crate::common::ReadOnly),
+            ));
+        }
+
+        #[test]
+        fn slow_writers_time_out() {
+            crate::common::slow_writers_time_out($spawn(
+                crate::common::origin().with_limits(crate::common::tight_limits()),
+            ));
+        }
+
+        #[test]
+        fn idle_connections_are_closed() {
+            crate::common::idle_connections_are_closed($spawn(
+                crate::common::origin().with_limits(crate::common::tight_limits()),
+            ));
+        }
+
+        #[test]
+        fn oversized_requests_are_rejected() {
+            crate::common::oversized_requests_are_rejected($spawn(
+                crate::common::origin().with_limits(crate::common::tight_limits()),
+            ));
+        }
+    };
+}
+pub(crate) use origin_suite;
+
+/// An origin server with an empty database and the default configuration
+pub fn origin() -> OriginServer {
+    OriginServer::new(AspirinEatsDb::in_memory().unwrap())
+}
+
+/// Send one request on a fresh connection and read the whole response
+pub fn send(addr: SocketAddr, method: &str, path: &str, body: &str) -> HttpResponse {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write_request(&mut stream, method, path, body, true);
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.parse().unwrap()
+}
+
+fn write_request<W: Write>(writer: &mut W, method: &str, path: &str, body: &str, close: bool) {
+    let connection = if close { "Connection: close\r\n" } else { "" };
+    write!(
+        writer,
+        "{} {} HTTP/1.1\r\nContent-Length: {}\r\n{}\r\n{}",
+        method,
+        path,
+        body.len(),
+        connection,
+        body
+    )
+    .unwrap();
+}
+
+/// Read SSE lines until a blank line ends the next event
+fn read_event<R: BufRead>(reader: &mut R) -> Vec<String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            if lines.is_empty() {
+                continue;
+            }
+            return lines;
+        }
+        lines.push(line);
+    }
+}
+
+pub fn crud_round_trip(addr: SocketAddr) {
+    let response = send(addr, "POST", "/orders", ORDER_REQUEST);
+    assert_eq!(response.status_code(), 201);
+    let order: Order = response.body().parse().unwrap();
+    let path = format!("/orders/{}", order.uuid.unwrap());
+
+    let response = send(addr, "GET", &path, "");
+    assert_eq!(response.body().parse::<Order>().unwrap(), order);
+
+    let response = send(addr, "PUT", &format!("{path}/status"), "\"Completed\"");
+    let updated: Order = response.body().parse().unwrap();
+    assert_eq!(updated.status, OrderStatus::Completed);
+
+    let response = send(addr, "GET", "/orders", "");
+    let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+    assert_eq!(orders, vec![updated]);
+
+    assert_eq!(send(addr, "DELETE", "/orders", "").status_code(), 200);
+    assert_eq!(send(addr, "GET", &path, "").status_code(), 404);
+    assert_eq!(
+        send(addr, "POST", &format!("{path}/restore"), "").status_code(),
+        200
+    );
+}
+
+pub fn errors_map_to_status_codes(addr: SocketAddr) {
+    assert_eq!(
+        send(addr, "GET", "/", "").body(),
+        "Welcome to Aspirin Eats!"
+    );
+    assert_eq!(send(addr, "GET", "/menu", "").status_code(), 404);
+    assert_eq!(send(addr, "PATCH", "/orders", "").status_code(), 405);
+    assert_eq!(send(addr, "POST", "/orders", "{").status_code(), 400);
+    assert_eq!(send(addr, "GET", "/orders/abc", "").status_code(), 400);
+    // Internal order IDs are not accepted in paths
+    assert_eq!(send(addr, "GET", "/orders/1", "").status_code(), 400);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"garbage\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+}
+
+pub fn keep_alive_serves_many_requests(addr: SocketAddr) {
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    for _ in 0..3 {
+        write_request(&mut writer, "POST", "/orders", ORDER_REQUEST, false);
+        let response: HttpResponse = http::read_message(&mut reader)
+            .unwrap()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(response.status_code(), 201);
+        assert!(response.body().parse::<Order>().unwrap().uuid.is_some());
+        assert!(response.header("X-Request-Id").is_some());
+    }
+
+    write_request(&mut writer, "GET", "/orders", "", true);
+    let response: HttpResponse = http::read_message(&mut reader)
+        .unwrap()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(response.header("Connection"), Some("close"));
+    let orders: Vec<Order> = serde_json::from_str(response.body()).unwrap();
+    assert_eq!(orders.len(), 3);
+}
+
+pub fn orders_stream_pushes_changes(addr: SocketAddr) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /orders/stream HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let head = read_event(&mut reader);
+    assert_eq!(head[0], "HTTP/1.1 200 OK");
+    assert!(head.contains(&"Content-Type: text/event-stream".to_string()));
+
+    let response = send(addr, "POST", "/orders", ORDER_REQUEST);
+    assert_eq!(response.status_code(), 201);
+    let uuid = response.body().parse::<Order>().unwrap().uuid.unwrap();
+    send(
+        addr,
+        "PUT",
+        &format!("/orders/{uuid}/status"),
+        "\"Preparing\"",
+    );
+
+    let created = read_event(&mut reader);
+    assert_eq!(created[0], "id: 1");
+    assert_eq!(created[1], "event: created");
+    let changed = read_event(&mut reader);
+    assert_eq!(changed[1], "event: status_changed");
+    assert!(changed[2].contains("Preparing"));
+}
+
+pub fn order_stream_resumes_from_last_event_id(addr: SocketAddr) {
+    send(addr, "POST", "/orders", ORDER_REQUEST);
+    let response = send(addr, "POST", "/orders", ORDER_REQUEST);
+    let uuid = response.body().parse::<Order>().unwrap().uuid.unwrap();
+    send(addr, "DELETE", &format!("/orders/{uuid}"), "");
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET /orders/{uuid}/stream HTTP/1.1\r\nLast-Event-ID: 1\r\n\r\n"
+    )
+    .unwrap();
+    let mut reader = BufReader::new(stream);
+    read_event(&mut reader);
+
+    assert_eq!(read_event(&mut reader)[..2], ["id: 2", "event: created"]);
+    assert_eq!(read_event(&mut reader)[..2], ["id: 3", "event: deleted"]);
+}
+
+const SOCKET_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+fn socket_handshake(key: &str) -> String {
+    format!(
+        "GET /orders/socket HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n",
+        key
+    )
+}
+
+/// Send a message from the client side of a socket, masked as clients must
+fn send_message<W: Write>(writer: &mut W, message: &ClientMessage) {
+    let frame = Frame::text(&message.to_string());
+    websocket::write_frame(writer, &frame, Some([1, 2, 3, 4])).unwrap();
+}
+
+fn next_message<R: Read>(reader: &mut R) -> ServerMessage {
+    let frame = websocket::read_frame(reader, usize::MAX, false).unwrap();
+    assert_eq!(frame.opcode, Opcode::Text);
+    String::from_utf8(frame.payload).unwrap().parse().unwrap()
+}
+
+pub fn order_socket_pushes_changes_and_sets_status(addr: SocketAddr) {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    // The first message is sent right behind the handshake, before it is answered
+    let mut opening = socket_handshake(SOCKET_KEY).into_bytes();
+    send_message(&mut opening, &ClientMessage::Subscribe { order: None });
+    writer.write_all(&opening).unwrap();
+
+    let head = read_event(&mut reader);
+    assert_eq!(head[0], "HTTP/1.1 101 Switching Protocols");
+    assert!(head.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+    assert_eq!(
+        next_message(&mut reader),
+        ServerMessage::Subscribed { order: None }
+    );
+
+    assert_eq!(
+        send(addr, "POST", "/orders", ORDER_REQUEST).status_code(),
+        201
+    );
+    let ServerMessage::Event { id, event, order } = next_message(&mut reader) else {
+        panic!("expected an event");
+    };
+    assert_eq!((id, event.as_str()), (1, "created"));
+    let uuid = order.uuid.unwrap();
+
+    send_message(
+        &mut writer,
+        &ClientMessage::SetStatus {
+            order: uuid,
+            status: OrderStatus::Preparing,
+        },
+    );
+    let ServerMessage::StatusSet { order } = next_message(&mut reader) else {
+        panic!("expected the updated order");
+    };
+    assert_eq!(order.status, OrderStatus::Preparing);
+    let ServerMessage::Event { event, order, .. } = next_message(&mut reader) else {
+        panic!("expected an event");
+    };
+    assert_eq!(event, "status_changed");
+    assert_eq!(order.status, OrderStatus::Preparing);
+    let current: Order = send(addr, "GET", &format!("/orders/{}", uuid), "")
+        .body()
+        .parse()
+        .unwrap();
+    assert_eq!(current.status, OrderStatus::Preparing);
+
+    let ping = Frame::control(Opcode::Ping, b"board".to_vec());
+    websocket::write_frame(&mut writer, &ping, Some([9, 9, 9, 9])).unwrap();
+    assert_eq!(
+        websocket::read_frame(&mut reader, usize::MAX, false).unwrap(),
+        Frame::control(Opcode::Pong, b"board".to_vec())
+    );
+
+    let close = Frame::close(NORMAL_CLOSURE, "done");
+    websocket::write_frame(&mut writer, &close, Some([9, 9, 9, 9])).unwrap();
+    assert_eq!(
+        websocket::read_frame(&mut reader, usize::MAX, false).unwrap(),
+        Frame::close(NORMAL_CLOSURE, "")
+    );
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+pub fn order_socket_handshake_is_checked(addr: SocketAddr) {
+    let response = send(addr, "GET", "/orders/socket", "");
+    assert_eq!(response.status_code(), 426);
+    assert_eq!(response.header("Upgrade"), Some("websocket"));
+    assert_eq!(response.header("Sec-WebSocket-Version"), Some("13"));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(socket_handshake("too short").as_bytes())
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    assert_eq!(read_event(&mut reader)[0], "HTTP/1.1 400 Bad Request");
+
+    assert_eq!(send(addr, "POST", "/orders/socket", "").status_code(), 405);
+}
+
+/// Open a socket from a page at `origin`, returning the status line of the answer
+fn socket_from(addr: SocketAddr, origin: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let handshake =
+        socket_handshake(SOCKET_KEY).replacen("\r\n", &format!("\r\nOrigin: {}\r\n", origin), 1);
+    stream.write_all(handshake.as_bytes()).unwrap();
+    let mut reader = BufReader::new(stream);
+    read_event(&mut reader).remove(0)
+}
+
+pub fn order_socket_checks_origin(addr: SocketAddr) {
+    assert_eq!(
+        socket_from(addr, WEB_ORIGIN),
+        "HTTP/1.1 101 Switching Protocols"
+    );
+    assert_eq!(
+        socket_from(addr, "https://evil.example"),
+        "HTTP/1.1 403 Forbidden"
+    );
+}
+
+/// Limits short enough for tests to run into
+pub fn tight_limits() -> Limits {
+    Limits {
+        read_header_timeout: Duration::from_millis(300),
+        read_body_timeout: Duration::from_millis(300),
+        write_timeout: Duration::from_secs(1),
+        idle_timeout: Duration::from_millis(300),
+        max_request_line: 64,
+        max_headers: 4,
+        max_body: 32,
+    }
+}
+
+/// Write the chunks with a pause before each, then stop sending and read the response
+fn trickle(addr: SocketAddr, chunks: &[&str]) -> (String, Duration) {
+    let started = Instant::now();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    for chunk in chunks {
+        thread::sleep(Duration::from_millis(50));
+        stream.write_all(chunk.as_bytes()).unwrap();
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    (response, started.elapsed())
+}
+
+pub fn slow_writers_time_out(addr: SocketAddr) {
+    // Every byte arrives well within any per-read timeout, but the head never finishes
+    let (response, elapsed) = trickle(addr, &["G", "E", "T", " ", "/", " ", "H"]);
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout"),
+        "{}",
+        response
+    );
+    assert!(elapsed < Duration::from_secs(5));
+
+    let head = "POST /orders HTTP/1.1\r\nContent-Length: 20\r\n\r\n";
+    let (response, _) = trickle(addr, &[head, "{\"cust", "omer\""]);
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout"),
+        "{}",
+        response
+    );
+}
+
+pub fn idle_connections_are_closed(addr: SocketAddr) {
+    // A finished request does not keep the connection open forever either
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    write_request(&mut writer, "GET", "/", "", false);
+    let response = http::read_message(&mut reader).unwrap().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+    let started = Instant::now();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+pub fn oversized_requests_are_rejected(addr: SocketAddr) {
+    let path = format!("/{}", "a".repeat(100));
+    let (response, _) = trickle(addr, &[&format!("GET {} HTTP/1.1\r\n\r\n", path)]);
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+    let headers = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\nE: 5\r\n\r\n";
+    let (response, _) = trickle(addr, &[headers]);
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+    // The body is never sent: the server answers as soon as it sees the length
+    let head = "POST /orders HTTP/1.1\r\nContent-Length: 1000\r\n\r\n";
+    let (response, _) = trickle(addr, &[head]);
+    assert!(
+        response.starts_with("HTTP/1.1 413 Payload Too Large"),
+        "{}",
+        response
+    );
+}
+
+/// Read one response whose body may not be text, returning its head and body bytes
+fn read_raw_response<R: BufRead>(reader: &mut R) -> (HttpResponse, Vec<u8>) {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        head.push_str(&line);
+        if line == "\r\n" {
+            break;
+        }
+    }
+    let head: HttpResponse = head.parse().unwrap();
+    let length = head.header("Content-Length").unwrap().parse().unwrap();
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (head, body)
+}
+
+pub fn responses_are_compressed_when_accepted(addr: SocketAddr) {
+    let mut uuid = None;
+    for _ in 0..20 {
+        let response = send(addr, "POST", "/orders", ORDER_REQUEST);
+        uuid = response.body().parse::<Order>().unwrap().uuid;
+    }
+    let plain = send(addr, "GET", "/orders", "");
+    assert_eq!(plain.header("Content-Encoding"), None);
+    assert_eq!(plain.header("Vary"), Some("Accept-Encoding"));
+
+    // Both responses share one connection, so the compressed body must be framed exactly
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    for encoding in ["gzip", "deflate"] {
+        write!(
+            writer,
+            "GET /orders HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n",
+            encoding
+        )
+        .unwrap();
+        let (head, body) = read_raw_response(&mut reader);
+        assert_eq!(head.header("Content-Encoding"), Some(encoding));
+        assert_eq!(head.header("Vary"), Some("Accept-Encoding"));
+        assert!(body.len() < plain.body().len());
+
+        let mut decoded = String::new();
+        if encoding == "gzip" {
+            flate2::read::GzDecoder::new(&body[..])
+                .read_to_string(&mut decoded)
+                .unwrap();
+        } else {
+            flate2::read::ZlibDecoder::new(&body[..])
+                .read_to_string(&mut decoded)
+                .unwrap();
+        }
+        assert_eq!(decoded, plain.body());
+    }
+
+    // Small bodies are not worth compressing
+    write!(
+        writer,
+        "GET /orders/{} HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+        uuid.unwrap()
+    )
+    .unwrap();
+    let (head, body) = read_raw_response(&mut reader);
+    assert_eq!(head.header("Content-Encoding"), None);
+    assert!(body.starts_with(b"{"));
+}
+
+const WEB_ORIGIN: &str = "https://order.aspirin-eats.com";
+
+/// Policy for a web ordering page on another origin
+pub fn cors() -> Cors {
+    Cors::new().with_origin(WEB_ORIGIN).with_credentials(true)
+}
+
+/// Send raw request head lines on a fresh connection and read the whole response
+fn send_head(addr: SocketAddr, head: &str) -> HttpResponse {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "{}Connection: close\r\n\r\n", head).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.parse().unwrap()
+}
+
+pub fn cors_preflight_and_simple_requests(addr: SocketAddr) {
+    let preflight = send_head(
+        addr,
+        &format!(
+            "OPTIONS /orders HTTP/1.1\r\nOrigin: {}\r\nAccess-Control-Request-Method: POST\r\n\
+             Access-Control-Request-Headers: Content-Type\r\n",
+            WEB_ORIGIN
+        ),
+    );
+    assert_eq!(preflight.status_code(), 204);
+    assert_eq!(
+        preflight.header("Access-Control-Allow-Origin"),
+        Some(WEB_ORIGIN)
+    );
+    assert_eq!(
+        preflight.header("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+    assert!(preflight
+        .header("Access-Control-Allow-Methods")
+        .unwrap()
+        .contains("POST"));
+    // Preflights never reach the API
+    assert_eq!(send(addr, "GET", "/orders", "").body(), "[]");
+
+    let refused = send_head(
+        addr,
+        "OPTIONS /orders HTTP/1.1\r\nOrigin: https://evil.example\r\n\
+         Access-Control-Request-Method: DELETE\r\n",
+    );
+    assert_eq!(refused.status_code(), 403);
+    assert_eq!(refused.header("Access-Control-Allow-Origin"), None);
+
+    let simple = send_head(
+        addr,
+        &format!("GET /orders HTTP/1.1\r\nOrigin: {}\r\n", WEB_ORIGIN),
+    );
+    assert_eq!(simple.status_code(), 200);
+    assert_eq!(
+        simple.header("Access-Control-Allow-Origin"),
+        Some(WEB_ORIGIN)
+    );
+    assert_eq!(simple.header("Vary"), Some("Origin"));
+
+    let other = send_head(
+        addr,
+        "GET /orders HTTP/1.1\r\nOrigin: https://evil.example\r\n",
+    );
+    assert_eq!(other.status_code(), 200);
+    assert_eq!(other.header("Access-Control-Allow-Origin"), None);
+
+    // Event streams are read cross-origin with EventSource
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET /orders/stream HTTP/1.1\r\nOrigin: {}\r\n\r\n",
+        WEB_ORIGIN
+    )
+    .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).unwrap();
+    }
+    assert!(head.contains(&format!("Access-Control-Allow-Origin: {}", WEB_ORIGIN)));
+}
+
+/// Refuses everything but reads, and event streams as well, and labels every response
+pub struct ReadOnly;
+
+impl Middleware for ReadOnly {
+    fn before(&self, request: &mut HttpRequest) -> Result<Option<HttpResponse>, AspirinEatsError> {
+        if request.method.as_deref() != Some("GET") {
+            return Err(AspirinEatsError::MethodNotAllowed);
+        }
+        Ok(request
+            .route()
+            .ends_with("/stream")
+            .then(|| HttpResponse::new(403, "Forbidden", "")))
+    }
+
+    fn after(
+        &self,
+        _request: &HttpRequest,
+        response: &mut HttpResponse,
+    ) -> Result<(), AspirinEatsError> {
+        response.set_header("X-Read-Only", "true");
+        Ok(())
+    }
+}
+
+pub fn middleware_wraps_handlers_and_streams(addr: SocketAddr) {
+    let response = send(addr, "GET", "/orders", "");
+    assert_eq!(response.body(), "[]");
+    assert_eq!(response.header("X-Read-Only"), Some("true"));
+
+    let response = send(addr, "POST", "/orders", ORDER_REQUEST);
+    assert_eq!(response.status_code(), 405);
+    assert_eq!(response.header("X-Read-Only"), Some("true"));
+    assert_eq!(send(addr, "GET", "/orders", "").body(), "[]");
+
+    let response = send(addr, "GET", "/orders/stream", "");
+    assert_eq!(response.status_code(), 403);
+    assert_eq!(response.header("X-Read-Only"), Some("true"));
+}
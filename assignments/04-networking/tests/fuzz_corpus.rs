@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::Path;
+
+use aspirin_eats::fuzz;
+
+/// Run every input in a fuzz target's corpus through its check. The corpus holds seeds and
+/// the inputs that once broke something, so this keeps them fixed without a fuzzer
+fn replay(target: &str, check: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/corpus")
+        .join(target);
+    let mut inputs = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let data = fs::read(&path).unwrap();
+        if let Err(panic) = std::panic::catch_unwind(|| check(&data)) {
+            panic!("{} failed on {}: {:?}", target, path.display(), panic);
+        }
+        inputs += 1;
+    }
+    assert!(inputs > 0, "{} has no corpus", dir.display());
+}
+
+#[test]
+fn request_parsing_corpus() {
+    replay("request_parsing", fuzz::request_parsing);
+}
+
+#[test]
+fn response_serialization_corpus() {
+    replay("response_serialization", fuzz::response_serialization);
+}
+
+#[test]
+fn proxy_forward_corpus() {
+    replay("proxy_forward", fuzz::proxy_forward);
+}
+
+#[test]
+fn proxy_request_corpus() {
+    replay("proxy_request", fuzz::proxy_request);
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use aspirin_eats::server::OriginServer;
+
+mod threaded {
+    use super::*;
+
+    fn spawn(server: OriginServer) -> SocketAddr {
+        server.spawn("127.0.0.1:0").unwrap()
+    }
+
+    crate::common::origin_suite!(spawn);
+}
+
+#[cfg(feature = "async")]
+mod tokio_runtime {
+    use super::*;
+    use aspirin_eats::async_server::AsyncOriginServer;
+
+    fn spawn(server: OriginServer) -> SocketAddr {
+        AsyncOriginServer::new(server).spawn("127.0.0.1:0").unwrap()
+    }
+
+    crate::common::origin_suite!(spawn);
+}
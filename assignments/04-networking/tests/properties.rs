@@ -0,0 +1,165 @@
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use uuid::Uuid;
+
+use aspirin_eats::food::{Bun, Burger, MenuItem, Order, OrderRequest, OrderStatus, Patty, Topping};
+use aspirin_eats::http::{HttpRequest, HttpResponse};
+
+fn token() -> impl Strategy<Value = String> {
+    "[A-Za-z][A-Za-z0-9!#$%&'*+.^_`|~-]{0,15}"
+}
+
+/// Header values as they come out of the parser: printable, without surrounding whitespace
+fn header_value() -> impl Strategy<Value = String> {
+    "([!-~]+( [!-~]+)*)?"
+}
+
+fn headers() -> impl Strategy<Value = Vec<(String, String)>> {
+    vec((token(), header_value()), 0..8)
+}
+
+fn request() -> impl Strategy<Value = HttpRequest> {
+    (token(), "/[!-~]{0,40}", headers(), option::of(".+")).prop_map(
+        |(method, path, headers, body)| HttpRequest {
+            method: Some(method),
+            path: Some(path),
+            headers,
+            body,
+        },
+    )
+}
+
+fn response() -> impl Strategy<Value = HttpResponse> {
+    (any::<u16>(), "[ -~]{0,20}", headers(), ".*").prop_map(|(code, text, headers, body)| {
+        headers.iter().fold(
+            HttpResponse::new(code, &text, &body),
+            |response, (name, value)| response.with_header(name, value),
+        )
+    })
+}
+
+fn menu_item() -> impl Strategy<Value = MenuItem> {
+    let bun = prop_oneof![Just(Bun::Sesame), Just(Bun::Plain), Just(Bun::GlutenFree)];
+    let patty = prop_oneof![Just(Patty::Beef), Just(Patty::Chicken), Just(Patty::Veggie)];
+    let topping = prop_oneof![
+        Just(Topping::Lettuce),
+        Just(Topping::Tomato),
+        Just(Topping::Onion),
+        Just(Topping::Pickle),
+        Just(Topping::Cheese),
+        Just(Topping::Bacon),
+    ];
+    prop_oneof![
+        (bun, patty, vec(topping, 0..6))
+            .prop_map(|(bun, patty, toppings)| MenuItem::Burger(Burger::new(bun, patty, toppings))),
+        Just(MenuItem::Fries),
+        Just(MenuItem::Drink),
+    ]
+}
+
+fn order() -> impl Strategy<Value = Order> {
+    let status = prop_oneof![
+        Just(OrderStatus::Pending),
+        Just(OrderStatus::Preparing),
+        Just(OrderStatus::Ready),
+        Just(OrderStatus::Transporting),
+        Just(OrderStatus::Completed),
+        Just(OrderStatus::Cancelled),
+    ];
+    (
+        ".*",
+        vec(menu_item(), 0..6),
+        status,
+        option::of(any::<i64>()),
+        option::of(any::<u128>()),
+        option::of(any::<i64>()),
+        option::of(any::<i64>()),
+    )
+        .prop_map(
+            |(customer, food, status, id, uuid, customer_id, estimated_ready_at)| Order {
+                id,
+                uuid: uuid.map(Uuid::from_u128),
+                customer_id,
+                status,
+                estimated_ready_at,
+                ..Order::from(OrderRequest {
+                    customer,
+                    customer_uuid: None,
+                    food,
+                })
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn requests_round_trip(request in request()) {
+        let serialized = request.to_string();
+        prop_assert_eq!(serialized.parse::<HttpRequest>().unwrap(), request);
+    }
+
+    #[test]
+    fn responses_round_trip(response in response()) {
+        let serialized = response.to_string();
+        prop_assert_eq!(serialized.parse::<HttpResponse>().unwrap(), response);
+    }
+
+    #[test]
+    fn orders_round_trip_as_json(order in order()) {
+        // Everything but the internal IDs, which are never serialized
+        let public = Order { id: None, customer_id: None, ..order.clone() };
+        prop_assert_eq!(order.to_string().parse::<Order>().unwrap(), public);
+    }
+}
+
+/// The checks the fuzz targets run, on generated inputs
+#[cfg(feature = "fuzz")]
+mod fuzz_checks {
+    use aspirin_eats::fuzz;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn requests_from_the_network_round_trip(request in request()) {
+            fuzz::request_parsing(request.to_string().as_bytes());
+        }
+
+        #[test]
+        fn responses_survive_framing(response in response()) {
+            fuzz::response_serialization(response.to_string().as_bytes());
+        }
+
+        #[test]
+        fn arbitrary_requests_do_not_panic(data in vec(any::<u8>(), 0..512)) {
+            fuzz::request_parsing(&data);
+        }
+
+        #[test]
+        fn arbitrary_responses_do_not_panic(data in vec(any::<u8>(), 0..512)) {
+            fuzz::response_serialization(&data);
+        }
+
+        #[test]
+        fn arbitrary_origins_are_relayed_whole(data in vec(any::<u8>(), 0..512)) {
+            fuzz::proxy_forward(&data);
+        }
+
+        #[test]
+        fn origin_responses_are_relayed_whole(response in response()) {
+            fuzz::proxy_forward(response.to_string().as_bytes());
+        }
+
+        #[test]
+        fn arbitrary_exchanges_through_the_proxy_do_not_panic(
+            request in request(),
+            data in vec(any::<u8>(), 0..512),
+        ) {
+            let mut exchange = request.to_string().into_bytes();
+            exchange.push(0);
+            exchange.extend_from_slice(&data);
+            fuzz::proxy_request(&exchange);
+        }
+    }
+}
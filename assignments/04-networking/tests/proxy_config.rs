@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use aspirin_eats::http::HttpResponse;
+use aspirin_eats::log::Logger;
+use aspirin_eats::proxy::ReverseProxy;
+use aspirin_eats::routing::ProxyConfig;
+
+/// Start an upstream that answers every request with its name. If `gate` is given, each
+/// request is announced on its sender, and answered once its receiver gets a message
+fn upstream(name: &'static str, gate: Option<(Sender<()>, Receiver<()>)>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let gate = Arc::new(Mutex::new(gate));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let gate = gate.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    if line != "\r\n" {
+                        continue;
+                    }
+                    if let Some((arrived, release)) = &*gate.lock().unwrap() {
+                        arrived.send(()).unwrap();
+                        release.recv().unwrap();
+                    }
+                    let response = HttpResponse::new(200, "OK", name)
+                        .with_header("Content-Length", &name.len().to_string());
+                    writer.write_all(response.to_string().as_bytes()).unwrap();
+                }
+            });
+        }
+    });
+    addr
+}
+
+fn spawn_proxy(proxy: &ReverseProxy) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = proxy.clone();
+    thread::spawn(move || proxy.serve(listener));
+    addr
+}
+
+fn get(proxy: SocketAddr, path: &str) -> HttpResponse {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: proxy\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.parse().unwrap()
+}
+
+/// A configuration that sends everything to `upstream`, tagging responses with `version`
+fn routes_to(upstream: SocketAddr, version: u32) -> serde_json::Value {
+    json!({
+        "upstreams": { "main": { "servers": [upstream.to_string()] } },
+        "routes": [{
+            "prefix": "/",
+            "upstream": "main",
+            "response_headers": { "set": { "X-Config-Version": version.to_string() } }
+        }]
+    })
+}
+
+fn config_file(config: &serde_json::Value) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("aspirin-proxy-{}.json", uuid::Uuid::new_v4()));
+    fs::write(&path, config.to_string()).unwrap();
+    path
+}
+
+fn quiet_proxy(path: &Path) -> ReverseProxy {
+    ReverseProxy::from_config(ProxyConfig::load(path).unwrap())
+        .unwrap()
+        .with_logger(Logger::from_writer("proxy", Box::new(io::sink())))
+}
+
+/// Wait for the proxy to answer `/` from `upstream`
+fn wait_for(proxy: SocketAddr, upstream: &str) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while get(proxy, "/").body() != upstream {
+        assert!(
+            Instant::now() < deadline,
+            "proxy never switched to {}",
+            upstream
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn file_changes_are_reloaded_and_invalid_ones_rejected() {
+    let (a, b) = (upstream("a", None), upstream("b", None));
+    let path = config_file(&routes_to(a, 1));
+    let proxy = quiet_proxy(&path);
+    proxy.watch_config(path.clone(), Duration::from_millis(20));
+    let addr = spawn_proxy(&proxy);
+
+    let response = get(addr, "/orders");
+    assert_eq!(response.body(), "a");
+    assert_eq!(response.header("X-Config-Version"), Some("1"));
+
+    fs::write(&path, routes_to(b, 22).to_string()).unwrap();
+    wait_for(addr, "b");
+    assert_eq!(get(addr, "/").header("X-Config-Version"), Some("22"));
+
+    let mut unknown_upstream = routes_to(a, 333);
+    unknown_upstream["routes"][0]["upstream"] = json!("missing");
+    for invalid in [unknown_upstream.to_string(), "{\"upstreams\":".to_string()] {
+        fs::write(&path, invalid).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let response = get(addr, "/");
+        assert_eq!(response.body(), "b");
+        assert_eq!(response.header("X-Config-Version"), Some("22"));
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn in_flight_requests_keep_their_routing() {
+    let (arrived, arrival) = mpsc::channel();
+    let (release, released) = mpsc::channel();
+    let slow = upstream("slow", Some((arrived, released)));
+    let b = upstream("b", None);
+    let path = config_file(&routes_to(slow, 1));
+    let proxy = quiet_proxy(&path);
+    let addr = spawn_proxy(&proxy);
+
+    let in_flight = thread::spawn(move || get(addr, "/"));
+    arrival.recv().unwrap();
+    fs::write(&path, routes_to(b, 2).to_string()).unwrap();
+    proxy.reload(&path).unwrap();
+    assert_eq!(get(addr, "/").body(), "b");
+
+    release.send(()).unwrap();
+    let response = in_flight.join().unwrap();
+    assert_eq!(response.body(), "slow");
+    assert_eq!(response.header("X-Config-Version"), Some("1"));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn sighup_reloads() {
+    let (a, b) = (upstream("a", None), upstream("b", None));
+    let path = config_file(&routes_to(a, 1));
+    let proxy = quiet_proxy(&path);
+    proxy.reload_on_hangup(path.clone()).unwrap();
+    let addr = spawn_proxy(&proxy);
+    assert_eq!(get(addr, "/").body(), "a");
+
+    fs::write(&path, routes_to(b, 2).to_string()).unwrap();
+    signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+
+    wait_for(addr, "b");
+    fs::remove_file(&path).unwrap();
+}
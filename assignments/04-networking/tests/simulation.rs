@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use aspirin_eats::client::Client;
+use aspirin_eats::clock::ManualClock;
+use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::dispatch::{Courier, Location};
+use aspirin_eats::food::{MenuItem, OrderRequest, OrderStatus};
+use aspirin_eats::server::OriginServer;
+
+#[test]
+fn test_order_moves_from_kitchen_to_doorstep() {
+    let clock = Arc::new(ManualClock::new(1_000_000));
+    let db = AspirinEatsDb::in_memory()
+        .unwrap()
+        .with_couriers(vec![Courier::new(1, "Ada", Location::RESTAURANT, 1)]);
+    let server = OriginServer::new(db).with_clock(clock.clone());
+    let addr = server.clone().spawn("127.0.0.1:0").unwrap();
+    let mut client = Client::new(&addr.to_string());
+
+    let order = client
+        .create_order(&OrderRequest {
+            customer: "Amit".to_string(),
+            customer_uuid: None,
+            food: vec![MenuItem::Fries],
+        })
+        .unwrap();
+    let uuid = order.uuid.unwrap();
+    // The estimate is made on the server's clock, not the system's
+    assert_eq!(order.estimated_ready_at, Some(1_000_180));
+
+    server.step_simulation().unwrap();
+    let order = client.get_order(uuid).unwrap();
+    assert_eq!(order.status, OrderStatus::Preparing);
+    assert_eq!(order.estimated_ready_at, Some(1_000_180));
+
+    clock.advance(Duration::from_secs(180));
+    server.step_simulation().unwrap();
+    assert_eq!(
+        client.get_order(uuid).unwrap().status,
+        OrderStatus::Transporting
+    );
+    let couriers = client.list_couriers().unwrap();
+    let assignment = &couriers[0].assignments[0];
+    assert_eq!(
+        assignment.destination,
+        Location::of_order(assignment.order_id)
+    );
+
+    clock.set(assignment.delivered_at);
+    server.step_simulation().unwrap();
+    assert_eq!(
+        client.get_order(uuid).unwrap().status,
+        OrderStatus::Completed
+    );
+    assert!(client.list_couriers().unwrap()[0].assignments.is_empty());
+}
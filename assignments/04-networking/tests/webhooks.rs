@@ -0,0 +1,254 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::http::{self, HttpRequest, HttpResponse};
+use aspirin_eats::log::Logger;
+use aspirin_eats::server::OriginServer;
+use aspirin_eats::webhooks::{
+    self, DeadLetter, RetryPolicy, Webhook, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_HEADER,
+    TIMESTAMP_HEADER,
+};
+use uuid::Uuid;
+
+fn send(addr: SocketAddr, method: &str, path: &str, body: &str) -> HttpResponse {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response.parse().unwrap()
+}
+
+/// Start an origin with a webhook worker that retries quickly. Receivers in these tests are
+/// on loopback, so private targets are allowed
+fn origin(max_attempts: u32) -> SocketAddr {
+    let db = AspirinEatsDb::in_memory()
+        .unwrap()
+        .with_private_webhooks(true);
+    let server =
+        OriginServer::new(db).with_logger(Logger::from_writer("origin", Box::new(std::io::sink())));
+    server.start_webhook_worker(RetryPolicy {
+        max_attempts,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+        timeout: Duration::from_secs(2),
+    });
+    server.spawn("127.0.0.1:0").unwrap()
+}
+
+/// A partner's endpoint. Every request received is passed on over the channel and answered
+/// with `status`
+fn receiver(status: u16) -> (String, Receiver<HttpRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/aspirin", listener.local_addr().unwrap());
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let Ok(Some(request)) = http::read_request(&mut reader) else {
+                continue;
+            };
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\n\r\n",
+                status
+            )
+            .unwrap();
+            if sender.send(request).is_err() {
+                return;
+            }
+        }
+    });
+    (url, received)
+}
+
+/// Create a customer, returning its public ID
+fn customer(addr: SocketAddr, name: &str) -> String {
+    let body = format!(r#"{{"name":"{}"}}"#, name);
+    let response = send(addr, "POST", "/customers", &body);
+    let customer: serde_json::Value = response.body().parse().unwrap();
+    customer["uuid"].as_str().unwrap().to_string()
+}
+
+/// Place an order for a customer, returning the response
+fn order(addr: SocketAddr, customer: &str) -> HttpResponse {
+    let body = format!(
+        r#"{{"customer":"","customer_uuid":"{}","food":["Fries"]}}"#,
+        customer
+    );
+    send(addr, "POST", "/orders", &body)
+}
+
+/// Subscribe to the orders of a customer
+fn subscribe(addr: SocketAddr, url: &str, events: &str, customer: &str) -> Webhook {
+    let response = send(
+        addr,
+        "POST",
+        "/webhooks",
+        &format!(
+            r#"{{"url":"{}","events":{},"customers":["{}"]}}"#,
+            url, events, customer
+        ),
+    );
+    assert_eq!(response.status_code(), 201);
+    response.body().parse().unwrap()
+}
+
+fn next(received: &Receiver<HttpRequest>) -> HttpRequest {
+    received.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn order_changes_are_delivered_signed() {
+    let addr = origin(3);
+    let (url, received) = receiver(200);
+    let amit = customer(addr, "Amit");
+    let webhook = subscribe(
+        addr,
+        &url,
+        r#"["created","status_changed","deleted"]"#,
+        &amit,
+    );
+    let secret = webhook.secret.clone().unwrap();
+
+    let listed: Vec<Webhook> =
+        serde_json::from_str(send(addr, "GET", "/webhooks", "").body()).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].secret, None);
+    assert!(listed[0].customers.is_empty());
+
+    // Orders of other customers are not delivered
+    send(
+        addr,
+        "POST",
+        "/orders",
+        r#"{"customer":"Alice","food":["Fries"]}"#,
+    );
+    let created = order(addr, &amit);
+    let uuid = created.body().parse::<serde_json::Value>().unwrap()["uuid"].clone();
+    let path = format!("/orders/{}", uuid.as_str().unwrap());
+    send(addr, "PUT", &format!("{path}/status"), r#""Completed""#);
+    send(addr, "DELETE", &path, "");
+
+    let mut delivery_ids = Vec::new();
+    for event in ["created", "status_changed", "deleted"] {
+        let request = next(&received);
+        assert_eq!(request.route(), "/aspirin");
+        assert_eq!(request.header(EVENT_HEADER), Some(event));
+        let body = request.body.as_deref().unwrap();
+        let timestamp = request.header(TIMESTAMP_HEADER).unwrap().parse().unwrap();
+        let signature = request.header(SIGNATURE_HEADER).unwrap();
+        assert!(webhooks::verify(&secret, timestamp, body, signature));
+        assert!(!webhooks::verify("wrong", timestamp, body, signature));
+
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["event"], event);
+        assert_eq!(payload["order"]["uuid"], uuid);
+        assert_eq!(payload["order"].get("id"), None);
+        assert_eq!(payload["id"], request.header(DELIVERY_HEADER).unwrap());
+        delivery_ids.push(payload["id"].clone());
+    }
+    delivery_ids.dedup();
+    assert_eq!(delivery_ids.len(), 3);
+
+    // Removed webhooks hear nothing more
+    let removed = send(
+        addr,
+        "DELETE",
+        &format!("/webhooks/{}", webhook.id.unwrap()),
+        "",
+    );
+    assert_eq!(removed.status_code(), 200);
+    order(addr, &amit);
+    assert!(received.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+#[test]
+fn failed_deliveries_are_retried_then_dead_lettered() {
+    let addr = origin(3);
+    let (url, received) = receiver(500);
+    let amit = customer(addr, "Amit");
+    let webhook = subscribe(addr, &url, r#"["created"]"#, &amit);
+
+    order(addr, &amit);
+    let attempts: Vec<HttpRequest> = (0..3).map(|_| next(&received)).collect();
+    let delivery_id = attempts[0].header(DELIVERY_HEADER).unwrap();
+    assert!(attempts
+        .iter()
+        .all(|attempt| attempt.header(DELIVERY_HEADER) == Some(delivery_id)));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let dead = loop {
+        let dead: Vec<DeadLetter> =
+            serde_json::from_str(send(addr, "GET", "/webhooks/dead-letters", "").body()).unwrap();
+        if !dead.is_empty() || Instant::now() > deadline {
+            break dead;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].webhook_id, webhook.id.unwrap());
+    assert_eq!(dead[0].delivery_id, delivery_id);
+    assert_eq!(dead[0].event, "created");
+    assert_eq!(dead[0].attempts, 3);
+    assert_eq!(dead[0].error, "Unexpected response status 500");
+    assert_eq!(dead[0].payload, attempts[0].body.clone().unwrap());
+    assert!(received.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn private_targets_are_refused_by_default() {
+    let server = OriginServer::new(AspirinEatsDb::in_memory().unwrap())
+        .with_logger(Logger::from_writer("origin", Box::new(std::io::sink())));
+    let addr = server.spawn("127.0.0.1:0").unwrap();
+    let amit = customer(addr, "Amit");
+    for url in [
+        "http://127.0.0.1:9000/",
+        "http://10.0.0.1/",
+        "http://169.254.169.254/latest/meta-data/",
+        "http://[::1]:9000/",
+    ] {
+        let body = format!(r#"{{"url":"{url}","customers":["{amit}"]}}"#);
+        assert_eq!(
+            send(addr, "POST", "/webhooks", &body).status_code(),
+            400,
+            "{url}"
+        );
+    }
+}
+
+#[test]
+fn invalid_subscriptions_are_rejected() {
+    let addr = origin(1);
+    let amit = customer(addr, "Amit");
+    for body in [
+        format!(r#"{{"url":"https://partner.example/","customers":["{amit}"]}}"#),
+        format!(r#"{{"url":"http://partner.example/","events":["eaten"],"customers":["{amit}"]}}"#),
+        format!(r#"{{"events":["created"],"customers":["{amit}"]}}"#),
+        // Covering nothing, or an order or customer that does not exist
+        r#"{"url":"http://127.0.0.1:9/"}"#.to_string(),
+        format!(r#"{{"url":"http://127.0.0.1:9/","orders":["{amit}"]}}"#),
+        format!(
+            r#"{{"url":"http://127.0.0.1:9/","customers":["{}"]}}"#,
+            Uuid::nil()
+        ),
+    ] {
+        assert_eq!(
+            send(addr, "POST", "/webhooks", &body).status_code(),
+            400,
+            "{body}"
+        );
+    }
+    assert_eq!(send(addr, "DELETE", "/webhooks/42", "").status_code(), 404);
+}